@@ -0,0 +1,20 @@
+//! Writes the C header of the `ffi` feature to `include/indexer.h`
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    ffi_header();
+}
+
+#[cfg(feature = "ffi")]
+fn ffi_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let config =
+        cbindgen::Config::from_file("cbindgen.toml").expect("Failed to read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/ffi.rs")
+        .generate()
+        .expect("Failed to generate the C header")
+        .write_to_file("include/indexer.h");
+}
@@ -0,0 +1,87 @@
+#ifndef INDEXER_H
+#define INDEXER_H
+
+/* Generated from src/ffi.rs by cbindgen, don't edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of the functions which can fail, see [`indexer_last_error`] for the message
+typedef enum IndexerStatus {
+  INDEXER_STATUS_OK = 0,
+  // A required pointer was null
+  INDEXER_STATUS_NULL_ARGUMENT = 1,
+  // A string wasn't valid UTF-8
+  INDEXER_STATUS_INVALID_UTF8 = 2,
+  // The query couldn't be parsed
+  INDEXER_STATUS_INVALID_QUERY = 3,
+  // The index couldn't be read
+  INDEXER_STATUS_IO = 4,
+} IndexerStatus;
+
+// An index opened with [`indexer_index_open`]
+typedef struct IndexerIndex IndexerIndex;
+
+// The message of the last error on this thread, null if there was none
+//
+// The string stays valid until the next failing call on the same thread.
+const char *indexer_last_error(void);
+
+// Load an index saved by `indexer index build`, null on failure
+//
+// # Safety
+//
+// `path` must be null or a nul-terminated string.
+struct IndexerIndex *indexer_index_open(const char *path);
+
+// Free an index, null is ignored
+//
+// # Safety
+//
+// `index` must be null or returned by [`indexer_index_open`] and not freed yet.
+void indexer_index_free(struct IndexerIndex *index);
+
+// Number of posts in the index
+//
+// # Safety
+//
+// `index` must be returned by [`indexer_index_open`] and not freed yet.
+size_t indexer_index_len(const struct IndexerIndex *index);
+
+// Run a query, storing the ids of at most `limit` matching posts (all of them if `limit` is 0)
+// in result order
+//
+// On success `*ids` points to `*len` ids, which have to be freed with [`indexer_ids_free`]. On
+// failure `*ids` is null and `*len` is 0.
+//
+// # Safety
+//
+// `index` must be returned by [`indexer_index_open`] and not freed yet, `query` must be a
+// nul-terminated string, and `ids` and `len` must be valid for writes.
+enum IndexerStatus indexer_query(const struct IndexerIndex *index,
+                                 const char *query,
+                                 size_t limit,
+                                 uint32_t **ids,
+                                 size_t *len);
+
+// Free the ids returned by [`indexer_query`], null is ignored
+//
+// # Safety
+//
+// `ids` and `len` must be exactly as returned by [`indexer_query`], and not freed yet.
+void indexer_ids_free(uint32_t *ids, size_t len);
+
+// Count the posts matching a query into `*count`
+//
+// # Safety
+//
+// `index` must be returned by [`indexer_index_open`] and not freed yet, `query` must be a
+// nul-terminated string, and `count` must be valid for writes.
+enum IndexerStatus indexer_count(const struct IndexerIndex *index,
+                                 const char *query,
+                                 uint64_t *count);
+
+#endif  /* INDEXER_H */
@@ -0,0 +1,113 @@
+use typed_builder::TypedBuilder;
+
+use super::{
+    models::{ApiError, ApiPost, ApiPostResponse, ApiTag, ApiTagResponse},
+    utils::{with_date_formats, DateFormats},
+};
+
+/// A synchronous variant of [`ApiClient`](super::client::ApiClient) for small tools and scripts
+/// that don't want to run inside a tokio runtime
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct ApiClientBlocking {
+    #[builder(default)]
+    pub client: reqwest::blocking::Client,
+
+    #[builder(setter(into, strip_option))]
+    pub api_key: Option<String>,
+
+    #[builder(setter(into, strip_option))]
+    pub user_id: Option<String>,
+
+    #[builder(setter(into))]
+    pub endpoint: String,
+
+    /// Formats tried on the dates of the responses, see [`DateFormats`]
+    #[builder(default)]
+    pub date_formats: DateFormats,
+}
+
+impl ApiClientBlocking {
+    /// Parse a response with the date formats of the client
+    fn parse<T: serde::de::DeserializeOwned>(
+        &self,
+        response: reqwest::blocking::Response,
+    ) -> Result<T, ApiError> {
+        let body = response.bytes()?;
+        Ok(with_date_formats(&self.date_formats, || {
+            serde_json::from_slice(&body)
+        })?)
+    }
+
+    /// Add the api_key and user_id to the request
+    fn add_credentials(
+        &self,
+        req: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        let mut params = Vec::new();
+
+        if let Some(api_key) = &self.api_key {
+            params.push(("api_key", api_key));
+        }
+
+        if let Some(user_id) = &self.user_id {
+            params.push(("user_id", user_id));
+        }
+
+        req.query(&params)
+    }
+
+    /// Fetch a single post by its id
+    pub fn get_post(&self, id: u64) -> Result<Option<ApiPost>, ApiError> {
+        let req = self.client.get(&self.endpoint).query(&[
+            ("page", "dapi"),
+            ("s", "post"),
+            ("q", "index"),
+            ("json", "1"),
+            ("id", &format!("{id}")),
+        ]);
+
+        let req = self.add_credentials(req);
+
+        let response: ApiPostResponse = self.parse(req.send()?)?;
+        Ok(response.posts.into_iter().next())
+    }
+
+    /// Fetch a single post by its id with a backoff strategy
+    pub fn get_post_backoff(&self, id: u64) -> Result<Option<ApiPost>, ApiError> {
+        backoff::retry(backoff::ExponentialBackoff::default(), || {
+            Ok(self.get_post(id)?)
+        })
+        .map_err(into_api_error)
+    }
+
+    /// Look up a single tag by its exact name
+    pub fn get_tag(&self, name: &str) -> Result<Option<ApiTag>, ApiError> {
+        let req = self.client.get(&self.endpoint).query(&[
+            ("page", "dapi"),
+            ("s", "tag"),
+            ("q", "index"),
+            ("json", "1"),
+            ("name", name),
+        ]);
+
+        let req = self.add_credentials(req);
+
+        let response: ApiTagResponse = self.parse(req.send()?)?;
+        Ok(response.tags.into_iter().next())
+    }
+
+    /// Look up a single tag by its exact name with a backoff strategy
+    pub fn get_tag_backoff(&self, name: &str) -> Result<Option<ApiTag>, ApiError> {
+        backoff::retry(backoff::ExponentialBackoff::default(), || {
+            Ok(self.get_tag(name)?)
+        })
+        .map_err(into_api_error)
+    }
+}
+
+fn into_api_error(error: backoff::Error<ApiError>) -> ApiError {
+    match error {
+        backoff::Error::Permanent(e) => e,
+        backoff::Error::Transient { err, .. } => err,
+    }
+}
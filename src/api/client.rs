@@ -1,9 +1,21 @@
 use std::ops::Range;
 
+use chrono::{TimeDelta, Utc};
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use serde::de::DeserializeOwned;
 use typed_builder::TypedBuilder;
 
-use super::models::{ApiError, ApiPostResponse, ApiTagResponse};
+use super::{
+    models::{
+        ApiError, ApiPost, ApiPostResponse, ApiTag, ApiTagResponse, ApiUser, ApiUserResponse,
+        PostSort, SortDirection, SortField,
+    },
+    throttle::Throttle,
+    utils::{with_date_formats, DateFormats},
+};
 
+/// Cool-down after a 429 without a `Retry-After`
+const DEFAULT_RETRY_AFTER_SECS: u64 = 60;
 
 #[derive(Debug, Clone, TypedBuilder)]
 pub struct ApiClient {
@@ -12,15 +24,49 @@ pub struct ApiClient {
 
     #[builder(setter(into, strip_option))]
     pub api_key: Option<String>,
-    
+
     #[builder(setter(into, strip_option))]
     pub user_id: Option<String>,
 
     #[builder(setter(into))]
     pub endpoint: String,
+
+    /// Requests wait for its deadline, which a throttled request sets
+    #[builder(default)]
+    pub throttle: Throttle,
+
+    /// Formats tried on the dates of the responses, see [`DateFormats`]
+    #[builder(default)]
+    pub date_formats: DateFormats,
 }
 
 impl ApiClient {
+    /// Share the cool-down of `throttle`, e.g. the one kept in the scrape state
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Send the request once the cool-down is over, starting a new one if it is throttled
+    async fn send<T: DeserializeOwned>(&self, req: reqwest::RequestBuilder) -> Result<T, ApiError> {
+        self.throttle.wait().await;
+        let response = req.send().await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(DEFAULT_RETRY_AFTER_SECS);
+            self.throttle
+                .hold_until(Utc::now() + TimeDelta::seconds(retry_after as i64));
+            return Err(ApiError::Throttled(retry_after));
+        }
+        let body = response.bytes().await?;
+        Ok(with_date_formats(&self.date_formats, || {
+            serde_json::from_slice(&body)
+        })?)
+    }
 
     /// Add the api_key and user_id to the request
     fn add_credentials(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
@@ -37,8 +83,12 @@ impl ApiClient {
         req.query(&params)
     }
 
-    /// Query the posts
-    async fn query_posts(&self, id: Range<u64>) -> Result<ApiPostResponse, ApiError> {
+    /// Query the posts, optionally ordering the results
+    async fn query_posts(
+        &self,
+        id: Range<u64>,
+        sort: Option<PostSort>,
+    ) -> Result<ApiPostResponse, ApiError> {
         let req = self.client.get(&self.endpoint).query(&[
             ("page", "dapi"),
             ("s", "post"),
@@ -51,22 +101,45 @@ impl ApiClient {
         let req = self.add_credentials(req);
 
         // Create the id range part of the request
-        let tags = format!("id:>={} id:<{}", id.start, id.end);
+        let mut tags = format!("id:>={} id:<{}", id.start, id.end);
+        if let Some(sort) = sort {
+            tags.push(' ');
+            tags.push_str(&sort.as_tag());
+        }
         let request = req.query(&[("tags", tags)]);
 
-        Ok(request.send().await?.json().await?)
+        self.send(request).await
     }
 
     /// Query the posts with a backoff strategy
     pub async fn query_posts_backoff(&self, id: Range<u64>) -> Result<ApiPostResponse, ApiError> {
+        self.query_posts_sorted_backoff(id, None).await
+    }
+
+    /// Query the posts in the given order with a backoff strategy
+    #[tracing::instrument(name = "post_page", skip(self, id, sort), fields(start = id.start, end = id.end))]
+    pub async fn query_posts_sorted_backoff(
+        &self,
+        id: Range<u64>,
+        sort: Option<PostSort>,
+    ) -> Result<ApiPostResponse, ApiError> {
         backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
-            Ok(self.query_posts(id.clone()).await?)
-        }).await
+            Ok(self.query_posts(id.clone(), sort).await?)
+        })
+        .await
     }
-    
+
+    /// Fetch the newest posts without retrying, e.g. to check the credentials
+    ///
+    /// The response contains the total number of posts and the highest post id.
+    pub async fn latest_posts(&self) -> Result<ApiPostResponse, ApiError> {
+        let sort = PostSort::new(SortField::Id, SortDirection::Desc);
+        self.query_posts(0..u64::MAX, Some(sort)).await
+    }
+
     /// Query the tags
     async fn query_tags(&self, after_id: u64) -> Result<ApiTagResponse, ApiError> {
-        let req = self.client.get("https://gelbooru.com/index.php").query(&[
+        let req = self.client.get(&self.endpoint).query(&[
             ("page", "dapi"),
             ("s", "tag"),
             ("q", "index"),
@@ -79,14 +152,63 @@ impl ApiClient {
 
         let req = self.add_credentials(req);
 
-        Ok(req.send().await?.json().await?)
+        self.send(req).await
     }
 
     /// Query the tags with a backoff strategy
+    #[tracing::instrument(name = "tag_page", skip(self))]
     pub async fn query_tags_backoff(&self, after_id: u64) -> Result<ApiTagResponse, ApiError> {
         backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
             Ok(self.query_tags(after_id).await?)
-        }).await
+        })
+        .await
+    }
+
+    /// Fetch a single post by its id
+    pub async fn get_post(&self, id: u64) -> Result<Option<ApiPost>, ApiError> {
+        let req = self.client.get(&self.endpoint).query(&[
+            ("page", "dapi"),
+            ("s", "post"),
+            ("q", "index"),
+            ("json", "1"),
+            ("id", &format!("{id}")),
+        ]);
+
+        let req = self.add_credentials(req);
+
+        let response: ApiPostResponse = self.send(req).await?;
+        Ok(response.posts.into_iter().next())
+    }
+
+    /// Look up a single tag by its exact name
+    pub async fn get_tag(&self, name: &str) -> Result<Option<ApiTag>, ApiError> {
+        let req = self.client.get(&self.endpoint).query(&[
+            ("page", "dapi"),
+            ("s", "tag"),
+            ("q", "index"),
+            ("json", "1"),
+            ("name", name),
+        ]);
+
+        let req = self.add_credentials(req);
+
+        let response: ApiTagResponse = self.send(req).await?;
+        Ok(response.tags.into_iter().next())
     }
 
-}
\ No newline at end of file
+    /// Look up an account by its id, `None` if it doesn't exist or the site doesn't list users
+    pub async fn get_user(&self, id: u64) -> Result<Option<ApiUser>, ApiError> {
+        let req = self.client.get(&self.endpoint).query(&[
+            ("page", "dapi"),
+            ("s", "user"),
+            ("q", "index"),
+            ("json", "1"),
+            ("id", &format!("{id}")),
+        ]);
+
+        let req = self.add_credentials(req);
+
+        let response: ApiUserResponse = self.send(req).await?;
+        Ok(response.users.into_iter().next())
+    }
+}
@@ -1,3 +1,8 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "scraper")]
 pub mod client;
 pub mod models;
+#[cfg(feature = "scraper")]
+pub mod throttle;
 pub mod utils;
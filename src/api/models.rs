@@ -1,14 +1,22 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{Map, Value};
 use thiserror::Error;
 
-use crate::api::utils::{api_bool, api_date, api_option_str, api_option_u32, api_option_u64};
+use crate::api::utils::{
+    api_bool, api_date, api_option_str, api_option_u32, api_option_u64, ApiDate,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiPostResponse {
     #[serde(rename = "@attributes")]
     pub attributes: ApiAttributes,
-    #[serde(default, rename = "post")]
+    #[serde(default, rename = "post", deserialize_with = "api_posts")]
     pub posts: Vec<ApiPost>,
 }
 
@@ -19,77 +27,339 @@ pub struct ApiAttributes {
     pub count: u64,
 }
 
+/// A post as the API sent it
+///
+/// Sites add and drop fields over time. Only the id, date, md5 and tags are needed, the other
+/// fields get their default if a post comes without them (see [`ApiPost::missing`]), and fields
+/// this struct doesn't know are kept in [`ApiPost::extra`], so neither fails the page.
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiPost {
     pub id: u64,
-    #[serde(deserialize_with = "api_date")]
-    pub created_at: DateTime<Utc>,
+    /// Kept as it was sent if no date format matches, so the rest of the post isn't lost
+    pub created_at: ApiDate,
+    #[serde(default)]
     pub score: i32,
+    #[serde(default)]
     pub width: u32,
+    #[serde(default)]
     pub height: u32,
     pub md5: String,
+    #[serde(default)]
     pub directory: String,
+    #[serde(default)]
     pub image: String,
+    #[serde(default)]
     pub rating: String,
-    #[serde(deserialize_with = "api_option_str")]
+    #[serde(default, deserialize_with = "api_option_str")]
     pub source: Option<String>,
+    #[serde(default)]
     pub change: u64,
+    #[serde(default)]
     pub owner: String,
+    #[serde(default)]
     pub creator_id: u64,
-    #[serde(deserialize_with = "api_option_u64")]
+    #[serde(default, deserialize_with = "api_option_u64")]
     pub parent_id: Option<u64>,
-    #[serde(deserialize_with = "api_bool")]
+    #[serde(default, deserialize_with = "api_bool")]
     pub sample: bool,
+    #[serde(default)]
     pub preview_height: u32,
+    #[serde(default)]
     pub preview_width: u32,
     pub tags: String,
-    #[serde(deserialize_with = "api_option_str")]
+    #[serde(default, deserialize_with = "api_option_str")]
     pub title: Option<String>,
-    #[serde(deserialize_with = "api_bool")]
+    #[serde(default, deserialize_with = "api_bool")]
     pub has_notes: bool,
-    #[serde(deserialize_with = "api_bool")]
+    #[serde(default, deserialize_with = "api_bool")]
     pub has_comments: bool,
+    #[serde(default)]
     pub file_url: String,
+    #[serde(default)]
     pub preview_url: String,
-    #[serde(deserialize_with = "api_option_str")]
+    #[serde(default, deserialize_with = "api_option_str")]
     pub sample_url: Option<String>,
-    #[serde(deserialize_with = "api_option_u32")]
+    #[serde(default, deserialize_with = "api_option_u32")]
     pub sample_height: Option<u32>,
-    #[serde(deserialize_with = "api_option_u32")]
+    #[serde(default, deserialize_with = "api_option_u32")]
     pub sample_width: Option<u32>,
+    #[serde(default)]
     pub status: String,
-    #[serde(deserialize_with = "api_bool")]
+    #[serde(default, deserialize_with = "api_bool")]
     pub post_locked: bool,
-    #[serde(deserialize_with = "api_bool")]
+    #[serde(default, deserialize_with = "api_bool")]
     pub has_children: bool,
+    /// The fields of the post which aren't fields of this struct, carried over into
+    /// [`Post::extra`](crate::models::Post::extra)
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+    /// The fields of [`ApiPost::OPTIONAL_FIELDS`] the post came without, only set for the posts
+    /// of an [`ApiPostResponse`]
+    #[serde(skip)]
+    pub missing: Vec<&'static str>,
+}
+
+impl ApiPost {
+    /// The fields which get their default if a post comes without them
+    pub const OPTIONAL_FIELDS: &[&str] = &[
+        "score",
+        "width",
+        "height",
+        "directory",
+        "image",
+        "rating",
+        "source",
+        "change",
+        "owner",
+        "creator_id",
+        "parent_id",
+        "sample",
+        "preview_height",
+        "preview_width",
+        "title",
+        "has_notes",
+        "has_comments",
+        "file_url",
+        "preview_url",
+        "sample_url",
+        "sample_height",
+        "sample_width",
+        "status",
+        "post_locked",
+        "has_children",
+    ];
+}
+
+/// The posts of a page, noting the optional fields each of them is missing
+fn api_posts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ApiPost>, D::Error> {
+    Vec::<Map<String, Value>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|fields| {
+            let missing = ApiPost::OPTIONAL_FIELDS
+                .iter()
+                .copied()
+                .filter(|field| !fields.contains_key(*field))
+                .collect();
+            let mut post =
+                ApiPost::deserialize(Value::Object(fields)).map_err(de::Error::custom)?;
+            post.missing = missing;
+            Ok(post)
+        })
+        .collect()
+}
+
+/// How the posts of a run differed from [`ApiPost`], to be logged once the run is over
+#[derive(Debug, Default, Clone)]
+pub struct SchemaDrift {
+    /// Posts counted
+    pub posts: u64,
+    /// Posts per field [`ApiPost`] doesn't know
+    pub unknown: BTreeMap<String, u64>,
+    /// Posts per optional field they came without
+    pub missing: BTreeMap<String, u64>,
+}
+
+impl SchemaDrift {
+    pub fn record(&mut self, post: &ApiPost) {
+        self.posts += 1;
+        for field in post.extra.keys() {
+            *self.unknown.entry(field.clone()).or_default() += 1;
+        }
+        for field in &post.missing {
+            *self.missing.entry(field.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unknown.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// e.g. `unknown fields: rating_v2 (1000 of 1000 posts); missing fields: title (12 of 1000 posts)`
+impl fmt::Display for SchemaDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            ("unknown fields", &self.unknown),
+            ("missing fields", &self.missing),
+        ]
+        .into_iter()
+        .filter(|(_, fields)| !fields.is_empty())
+        .map(|(label, fields)| {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, count)| format!("{field} ({count} of {} posts)", self.posts))
+                .collect();
+            format!("{label}: {}", fields.join(", "))
+        })
+        .collect();
+        f.write_str(&parts.join("; "))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiTagResponse {
     #[serde(rename = "@attributes")]
     pub attributes: ApiAttributes,
-    #[serde(rename = "tag")]
+    #[serde(default, rename = "tag")]
     pub tags: Vec<ApiTag>,
 }
 
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApiTag {
     pub id: u64,
     pub name: String,
     pub count: u64,
-    #[serde(rename="type")]
+    #[serde(rename = "type")]
     pub tag_type: u32,
     #[serde(deserialize_with = "api_bool")]
     pub ambiguous: bool,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiUserResponse {
+    #[serde(rename = "@attributes")]
+    pub attributes: ApiAttributes,
+    #[serde(default, rename = "user")]
+    pub users: Vec<ApiUser>,
+}
+
+/// An account, only its current display name is needed
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiUser {
+    pub id: u64,
+    #[serde(alias = "username")]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiCommentResponse {
+    #[serde(rename = "@attributes")]
+    pub attributes: ApiAttributes,
+    #[serde(default, rename = "comment")]
+    pub comments: Vec<ApiComment>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiComment {
+    pub id: u64,
+    pub post_id: u64,
+    #[serde(deserialize_with = "api_date")]
+    pub created_at: DateTime<Utc>,
+    pub creator: String,
+    pub creator_id: u64,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiNoteResponse {
+    #[serde(rename = "@attributes")]
+    pub attributes: ApiAttributes,
+    #[serde(default, rename = "note")]
+    pub notes: Vec<ApiNote>,
+}
+
+/// A translation or annotation box placed over part of a post
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiNote {
+    pub id: u64,
+    pub post_id: u64,
+    #[serde(deserialize_with = "api_date")]
+    pub created_at: DateTime<Utc>,
+    #[serde(deserialize_with = "api_date")]
+    pub updated_at: DateTime<Utc>,
+    pub creator_id: u64,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub body: String,
+    #[serde(deserialize_with = "api_bool")]
+    pub is_active: bool,
+    pub version: u32,
+}
+
+/// A named, ordered group of posts, e.g. the pages of a comic
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiPool {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub post_ids: Vec<u64>,
+    #[serde(deserialize_with = "api_date")]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Field used to order the results of a post query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Id,
+    Score,
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PostSort {
+    pub field: SortField,
+    pub direction: SortDirection,
+}
+
+impl PostSort {
+    pub fn new(field: SortField, direction: SortDirection) -> Self {
+        Self { field, direction }
+    }
+
+    /// Render the sort as a search metatag, e.g. `sort:score:desc`
+    pub fn as_tag(&self) -> String {
+        let field = match self.field {
+            SortField::Id => "id",
+            SortField::Score => "score",
+            SortField::Updated => "updated",
+        };
+        let direction = match self.direction {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        };
+        format!("sort:{field}:{direction}")
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ApiError {
+    #[cfg(feature = "scraper")]
     #[error("Reqwest Error: `{0}`")]
     Reqwest(#[from] reqwest::Error),
     #[error("Serde Error: `{0}`")]
     Serde(#[from] serde_json::Error),
+    #[error("Throttled by the API, retrying in {0} seconds")]
+    Throttled(u64),
     #[error("Other")]
-    Other
-}
\ No newline at end of file
+    Other,
+}
+
+impl ApiError {
+    /// A stable name of the kind of error, logged as `error_kind`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "scraper")]
+            ApiError::Reqwest(e) if e.is_timeout() => "timeout",
+            #[cfg(feature = "scraper")]
+            ApiError::Reqwest(e) if e.is_connect() => "connect",
+            #[cfg(feature = "scraper")]
+            ApiError::Reqwest(e) if e.is_status() => "status",
+            #[cfg(feature = "scraper")]
+            ApiError::Reqwest(e) if e.is_decode() => "decode",
+            #[cfg(feature = "scraper")]
+            ApiError::Reqwest(_) => "request",
+            ApiError::Serde(_) => "decode",
+            ApiError::Throttled(_) => "throttled",
+            ApiError::Other => "other",
+        }
+    }
+}
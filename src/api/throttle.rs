@@ -0,0 +1,58 @@
+//! A cool-down after the API throttled a request, see [`Throttle`]
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use tracing::info;
+
+/// The time until which no request is sent, shared by the clones of a client
+///
+/// The site answers too many requests with a 429, and its `Retry-After` sets the deadline. The
+/// [`StateManager`](crate::scraper::state_manager::StateManager) keeps the deadline in the state
+/// file, so a scraper restarted right away waits for it too instead of being throttled again.
+#[derive(Debug, Clone, Default)]
+pub struct Throttle {
+    until: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl Throttle {
+    /// A throttle which holds requests until `until`, if it is given
+    pub fn new(until: Option<DateTime<Utc>>) -> Self {
+        Self {
+            until: Arc::new(Mutex::new(until)),
+        }
+    }
+
+    /// The deadline, `None` once it has passed
+    pub fn until(&self) -> Option<DateTime<Utc>> {
+        let until = *self
+            .until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        until.filter(|until| *until > Utc::now())
+    }
+
+    /// Hold the requests until `until`, an earlier deadline doesn't shorten the current one
+    pub fn hold_until(&self, until: DateTime<Utc>) {
+        let mut current = self
+            .until
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if current.is_none_or(|current| current < until) {
+            *current = Some(until);
+        }
+    }
+
+    /// Wait for the deadline, returns right away if there is none
+    pub async fn wait(&self) {
+        // The deadline may be extended while waiting
+        while let Some(until) = self.until() {
+            let wait = (until - Utc::now()).to_std().unwrap_or_default();
+            info!(
+                cooldown_secs = wait.as_secs(),
+                "Waiting for the API's cool-down"
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
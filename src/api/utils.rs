@@ -1,27 +1,200 @@
-/// Utility functions for deserializing API responses
+//! Utility functions for deserializing API responses
 
-use chrono::{DateTime, Utc};
-use serde::{de::Visitor, Deserializer};
+use std::cell::RefCell;
 
-pub fn api_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
-    struct ApiDateVisitor;
-    impl Visitor<'_> for ApiDateVisitor {
-        type Value = DateTime<Utc>;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer,
+};
 
-        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-            formatter.write_str("a date string in the following format: `%a %b %d %T %z %Y`")
+/// The format of Gelbooru dates, e.g. `Sat Jan 04 17:00:00 -0600 2025`
+pub const GELBOORU_DATE: &str = "%a %b %d %T %z %Y";
+
+/// One way a site writes its dates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DateFormat {
+    /// A `strftime` pattern, dates without an offset are taken as UTC
+    Pattern(String),
+    /// ISO 8601 / RFC 3339, e.g. `2025-01-04T17:00:00.000+01:00`
+    Rfc3339,
+    /// Seconds since the unix epoch, as a number or a string of digits
+    Unix,
+    /// Milliseconds since the unix epoch
+    UnixMillis,
+}
+
+impl DateFormat {
+    fn parse_str(&self, value: &str) -> Option<DateTime<Utc>> {
+        match self {
+            DateFormat::Pattern(pattern) => DateTime::parse_from_str(value, pattern)
+                .map(|date| date.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(value, pattern).map(|date| date.and_utc())
+                })
+                .ok(),
+            DateFormat::Rfc3339 => DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|date| date.with_timezone(&Utc)),
+            DateFormat::Unix | DateFormat::UnixMillis => self.parse_int(value.trim().parse().ok()?),
         }
+    }
 
-        fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-        where
-            E: serde::de::Error,
-        {
-            DateTime::parse_from_str(v, "%a %b %d %T %z %Y")
-                .map_err(E::custom)
-                .map(|dt| dt.with_timezone(&Utc))
+    fn parse_int(&self, value: i64) -> Option<DateTime<Utc>> {
+        match self {
+            DateFormat::Unix => DateTime::from_timestamp(value, 0),
+            DateFormat::UnixMillis => DateTime::from_timestamp_millis(value),
+            _ => None,
+        }
+    }
+}
+
+/// `rfc3339`, `unix` and `unix_ms` name the formats of the same name, anything else is a
+/// `strftime` pattern
+impl From<&str> for DateFormat {
+    fn from(value: &str) -> Self {
+        match value {
+            "rfc3339" | "iso8601" => DateFormat::Rfc3339,
+            "unix" => DateFormat::Unix,
+            "unix_ms" => DateFormat::UnixMillis,
+            pattern => DateFormat::Pattern(pattern.to_string()),
         }
     }
-    deserializer.deserialize_str(ApiDateVisitor)
+}
+
+impl<'de> Deserialize<'de> for DateFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(DateFormat::from(
+            String::deserialize(deserializer)?.as_str(),
+        ))
+    }
+}
+
+/// The formats a client tries on every date of a response, in order
+///
+/// The default covers Gelbooru and the sites answering with ISO 8601 dates or unix timestamps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateFormats(pub Vec<DateFormat>);
+
+impl Default for DateFormats {
+    fn default() -> Self {
+        Self(vec![
+            DateFormat::Pattern(GELBOORU_DATE.to_string()),
+            DateFormat::Rfc3339,
+            DateFormat::Unix,
+        ])
+    }
+}
+
+impl DateFormats {
+    /// `formats` tried before the default ones
+    pub fn with_extra<I: IntoIterator<Item = DateFormat>>(formats: I) -> Self {
+        let mut all: Vec<DateFormat> = formats.into_iter().collect();
+        all.extend(Self::default().0);
+        Self(all)
+    }
+
+    pub fn parse_str(&self, value: &str) -> Option<DateTime<Utc>> {
+        self.0.iter().find_map(|format| format.parse_str(value))
+    }
+
+    pub fn parse_int(&self, value: i64) -> Option<DateTime<Utc>> {
+        self.0.iter().find_map(|format| format.parse_int(value))
+    }
+}
+
+thread_local! {
+    static DATE_FORMATS: RefCell<Option<DateFormats>> = const { RefCell::new(None) };
+}
+
+/// Deserialize with `formats` for the dates, instead of the default ones
+///
+/// Serde can't pass settings to a deserializer, so the formats are set for the current thread
+/// while `f` runs. Clients call this around the parsing of every response.
+pub fn with_date_formats<T>(formats: &DateFormats, f: impl FnOnce() -> T) -> T {
+    let previous = DATE_FORMATS.with(|current| current.replace(Some(formats.clone())));
+    let result = f();
+    DATE_FORMATS.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+fn current_formats<T>(f: impl FnOnce(&DateFormats) -> T) -> T {
+    DATE_FORMATS.with(|current| match &*current.borrow() {
+        Some(formats) => f(formats),
+        None => f(&DateFormats::default()),
+    })
+}
+
+/// A date of a post as the API sent it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiDate {
+    Parsed(DateTime<Utc>),
+    /// None of the date formats matched, the raw value is kept so the post still gets saved
+    Unparsed(String),
+}
+
+impl ApiDate {
+    /// The date, or the unix epoch if it couldn't be parsed
+    pub fn or_epoch(&self) -> DateTime<Utc> {
+        match self {
+            ApiDate::Parsed(date) => *date,
+            ApiDate::Unparsed(_) => DateTime::UNIX_EPOCH,
+        }
+    }
+
+    pub fn unparsed(&self) -> Option<&str> {
+        match self {
+            ApiDate::Parsed(_) => None,
+            ApiDate::Unparsed(value) => Some(value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ApiDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ApiDateVisitor;
+        impl Visitor<'_> for ApiDateVisitor {
+            type Value = ApiDate;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a date string or a unix timestamp")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(current_formats(|formats| formats.parse_str(v))
+                    .map_or_else(|| ApiDate::Unparsed(v.to_string()), ApiDate::Parsed))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(current_formats(|formats| formats.parse_int(v))
+                    .map_or_else(|| ApiDate::Unparsed(v.to_string()), ApiDate::Parsed))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                match i64::try_from(v) {
+                    Ok(v) => self.visit_i64(v),
+                    Err(_) => Ok(ApiDate::Unparsed(v.to_string())),
+                }
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                self.visit_i64(v as i64)
+            }
+        }
+        deserializer.deserialize_any(ApiDateVisitor)
+    }
+}
+
+/// A date in one of the formats of the client, see [`with_date_formats`]
+///
+/// Unlike [`ApiDate`] this fails if no format matches.
+pub fn api_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    match ApiDate::deserialize(deserializer)? {
+        ApiDate::Parsed(date) => Ok(date),
+        ApiDate::Unparsed(value) => Err(de::Error::custom(format!(
+            "`{value}` doesn't match any of the date formats"
+        ))),
+    }
 }
 
 pub fn api_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
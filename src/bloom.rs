@@ -0,0 +1,62 @@
+//! Bloom filters over post ids, see [`BloomFilter`]
+//!
+//! The index keeps one for each of its most used tags. When a query has narrowed its result down
+//! to a few posts, checking those against a filter answers most of them without touching the
+//! containers of the tag's bitmap, and only the possible members are looked up in the bitmap.
+
+use serde::{Deserialize, Serialize};
+
+/// A set of post ids which may report ids it doesn't contain, but never misses one it does
+///
+/// The hashes are fixed, so a filter saved with the index answers the same after loading it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for `items` ids with about a 1% false positive rate
+    pub fn with_capacity(items: u64) -> Self {
+        // 10 bits and 7 hashes per item give a false positive rate just below 1%
+        let words = (items.max(1) * 10).div_ceil(64);
+        Self {
+            bits: vec![0; words as usize],
+            hashes: 7,
+        }
+    }
+
+    pub fn insert(&mut self, id: u32) {
+        for bit in self.bit_indexes(id) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// `false` if the id was never inserted, `true` if it probably was
+    pub fn may_contain(&self, id: u32) -> bool {
+        self.bit_indexes(id)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Size of the filter in bytes
+    pub fn byte_len(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// The bits of an id, by double hashing the two halves of a mixed 64 bit hash
+    fn bit_indexes(&self, id: u32) -> impl Iterator<Item = usize> {
+        let hash = mix(id as u64);
+        let (first, second) = (hash as u32 as u64, (hash >> 32) | 1);
+        let len = self.bits.len() as u64 * 64;
+        (0..self.hashes as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+}
+
+/// The finalizer of splitmix64
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,65 @@
+use clap::Args;
+use indexer::{config::Config, index::RatingFinding};
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+}
+
+/// Result of `audit`
+#[derive(Debug, Serialize)]
+pub struct AuditOutput {
+    /// Indexed posts
+    pub posts: usize,
+    /// Rules from `[[audit.ratings]]`
+    pub rules: usize,
+    pub findings: Vec<RatingFinding>,
+}
+
+pub fn run(
+    args: AuditArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let rules = &config.audit.ratings;
+    if rules.is_empty() {
+        return Err("no rating rules configured, add `[[audit.ratings]]` to the config".into());
+    }
+    let index = args.index.load(&config)?;
+    if index.rating_to_post_id.is_empty() {
+        return Err("the index has no ratings, rebuild it with `index build`".into());
+    }
+
+    let output = AuditOutput {
+        posts: index.post_id_to_post.len(),
+        rules: rules.len(),
+        findings: index.audit_ratings(rules),
+    };
+    format.print(&output, |output| {
+        for finding in &output.findings {
+            println!(
+                "{}\t{}\t{}",
+                finding.post_id,
+                finding.rating,
+                finding.tags.join(" ")
+            );
+        }
+        eprintln!(
+            "{} of {} posts contradict the {} rating rules, `sync` requests them again",
+            output.findings.len(),
+            output.posts,
+            output.rules
+        );
+    });
+    match output.findings.is_empty() {
+        true => Ok(Status::Success),
+        false => Ok(Status::Partial),
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use indexer::{config::Config, query::Query};
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+/// The system allocator, counting allocations so `bench` can report them
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size as u64, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn allocations() -> (u64, u64) {
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Workload file with one query per line, empty lines and lines starting with `#` are skipped
+    #[arg(long)]
+    pub queries: PathBuf,
+
+    /// How often every query is run
+    #[arg(long, default_value_t = 100)]
+    pub iterations: u32,
+
+    /// Runs of every query before measuring, e.g. to warm up the caches
+    #[arg(long, default_value_t = 3)]
+    pub warmup: u32,
+}
+
+/// Latency percentiles in microseconds
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Latency {
+    pub p50_us: f64,
+    pub p95_us: f64,
+    pub p99_us: f64,
+}
+
+impl Latency {
+    /// Nearest rank percentiles of `durations`, which must be sorted
+    fn from_sorted(durations: &[Duration]) -> Self {
+        let percentile = |p: f64| {
+            let rank = ((p / 100.0) * durations.len() as f64).ceil() as usize;
+            durations
+                .get(rank.saturating_sub(1))
+                .map_or(0.0, |duration| duration.as_secs_f64() * 1e6)
+        };
+        Self {
+            p50_us: percentile(50.0),
+            p95_us: percentile(95.0),
+            p99_us: percentile(99.0),
+        }
+    }
+}
+
+/// Measurements of a single query of the workload
+#[derive(Debug, Serialize)]
+pub struct QueryBench {
+    pub query: String,
+    pub results: u64,
+    #[serde(flatten)]
+    pub latency: Latency,
+    /// Allocations per run
+    pub allocations: u64,
+    /// Bytes allocated per run
+    pub allocated_bytes: u64,
+}
+
+/// Result of `bench`
+#[derive(Debug, Serialize)]
+pub struct BenchOutput {
+    pub load_ms: u128,
+    pub iterations: u32,
+    pub queries: Vec<QueryBench>,
+    /// Percentiles over the runs of every query
+    pub overall: Latency,
+}
+
+pub fn run(
+    args: BenchArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let mut queries = Vec::new();
+    for (number, line) in BufReader::new(File::open(&args.queries)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let query = Query::parse(line)
+            .map_err(|e| format!("{}:{}: {}", args.queries.display(), number + 1, e))?;
+        queries.push(query);
+    }
+
+    let start = Instant::now();
+    let index = args.index.load(&config)?;
+    let load_ms = start.elapsed().as_millis();
+
+    let iterations = args.iterations.max(1);
+    let mut all_durations = Vec::with_capacity(queries.len() * iterations as usize);
+    let mut results = Vec::with_capacity(queries.len());
+    for query in &queries {
+        for _ in 0..args.warmup {
+            std::hint::black_box(index.search(query));
+        }
+
+        let mut durations = Vec::with_capacity(iterations as usize);
+        let mut count = 0;
+        let (allocations_before, bytes_before) = allocations();
+        for _ in 0..iterations {
+            let start = Instant::now();
+            let found = std::hint::black_box(index.search(query));
+            durations.push(start.elapsed());
+            count = found.len();
+        }
+        let (allocations_after, bytes_after) = allocations();
+
+        durations.sort_unstable();
+        all_durations.extend_from_slice(&durations);
+        results.push(QueryBench {
+            query: query.to_string(),
+            results: count,
+            latency: Latency::from_sorted(&durations),
+            allocations: (allocations_after - allocations_before) / iterations as u64,
+            allocated_bytes: (bytes_after - bytes_before) / iterations as u64,
+        });
+    }
+    all_durations.sort_unstable();
+
+    let output = BenchOutput {
+        load_ms,
+        iterations,
+        queries: results,
+        overall: Latency::from_sorted(&all_durations),
+    };
+    format.print(&output, |output| {
+        println!(
+            "loaded the index in {}ms, {} runs per query",
+            output.load_ms, output.iterations
+        );
+        println!(
+            "{:>10} {:>10} {:>10} {:>10} {:>8} {:>10}  query",
+            "results", "p50 us", "p95 us", "p99 us", "allocs", "bytes"
+        );
+        for query in &output.queries {
+            println!(
+                "{:>10} {:>10.1} {:>10.1} {:>10.1} {:>8} {:>10}  {}",
+                query.results,
+                query.latency.p50_us,
+                query.latency.p95_us,
+                query.latency.p99_us,
+                query.allocations,
+                query.allocated_bytes,
+                query.query
+            );
+        }
+        println!(
+            "overall: p50 {:.1}us, p95 {:.1}us, p99 {:.1}us",
+            output.overall.p50_us, output.overall.p95_us, output.overall.p99_us
+        );
+    });
+    Ok(Status::Success)
+}
@@ -0,0 +1,158 @@
+//! Dynamic shell completion of query terms
+//!
+//! The shell calls the binary with `COMPLETE=<shell>` set for every completion, so tag names are
+//! looked up in the saved index at `index.path` of the config (honouring `INDEXER_CONFIG` and
+//! `INDEXER_PROFILE`, but not `--index`).
+
+use std::{ffi::OsStr, path::PathBuf};
+
+use clap_complete::CompletionCandidate;
+use indexer::{
+    config::{Config, CONFIG_FILE},
+    index::Index,
+};
+
+/// Maximum number of tags offered at once
+const LIMIT: usize = 50;
+
+/// Prefixes of terms matching a typed tag, see [`indexer::query`]
+const TYPE_KEYS: [&str; 6] = [
+    "artist",
+    "character",
+    "copyright",
+    "metadata",
+    "meta",
+    "general",
+];
+
+const RATINGS: [&str; 4] = ["safe", "sensitive", "questionable", "explicit"];
+
+const MEDIA: [&str; 3] = ["image", "animated", "video"];
+
+const FILES: [&str; 2] = ["downloaded", "missing"];
+
+/// Complete the last term of a query with the most frequent tags starting with it
+///
+/// `download --query` takes the whole query as a single value, so everything before the last
+/// word is kept as it is. The `-`/`~` operators and `rating:`/`media:`/`file:`/`pool:`/`source:`/`uploader:`/`snapshot:`/`artist:`-style
+/// keys are kept too.
+pub fn query_terms(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(current) = current.to_str() else {
+        return Vec::new();
+    };
+    let split = current
+        .rfind(char::is_whitespace)
+        .map_or(0, |position| position + 1);
+    let (head, word) = current.split_at(split);
+    let (operator, term) = word.split_at(word.starts_with(['-', '~']) as usize);
+    let (key, value) = match term.split_once(':') {
+        Some((key, _))
+            if matches!(
+                key,
+                "rating" | "media" | "file" | "pool" | "source" | "snapshot"
+            ) || TYPE_KEYS.contains(&key) =>
+        {
+            term.split_at(key.len() + 1)
+        }
+        _ => ("", term),
+    };
+    let value = value.to_lowercase();
+
+    let values = match key {
+        "rating:" => Some(RATINGS.as_slice()),
+        "media:" => Some(MEDIA.as_slice()),
+        "file:" => Some(FILES.as_slice()),
+        _ => None,
+    };
+    if let Some(values) = values {
+        return values
+            .iter()
+            .filter(|candidate| candidate.starts_with(&value))
+            .map(|candidate| CompletionCandidate::new(format!("{head}{operator}{key}{candidate}")))
+            .collect();
+    }
+
+    let Some(index) = load_index() else {
+        return Vec::new();
+    };
+    if key == "pool:" {
+        let mut pools: Vec<(&String, u64)> = index
+            .pool_to_post_id
+            .iter()
+            .filter(|(pool, _)| pool.starts_with(&value))
+            .map(|(pool, post_ids)| (pool, post_ids.len()))
+            .collect();
+        pools.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        return pools
+            .into_iter()
+            .take(LIMIT)
+            .map(|(pool, count)| {
+                CompletionCandidate::new(format!("{head}{operator}{key}{pool}"))
+                    .help(Some(format!("{count} posts").into()))
+            })
+            .collect();
+    }
+    if key == "snapshot:" {
+        return index
+            .snapshots
+            .iter()
+            .filter(|(name, _)| name.starts_with(&value))
+            .take(LIMIT)
+            .map(|(name, snapshot)| {
+                CompletionCandidate::new(format!("{head}{operator}{key}{name}"))
+                    .help(Some(format!("{} posts", snapshot.post_ids.len()).into()))
+            })
+            .collect();
+    }
+    if key == "uploader:" {
+        let mut uploaders: Vec<(&String, u64)> = index
+            .creator_names
+            .iter()
+            .filter(|(_, name)| name.starts_with(&value))
+            .filter_map(|(id, name)| Some((name, index.creator_to_post_id.get(id)?.len())))
+            .collect();
+        uploaders.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        return uploaders
+            .into_iter()
+            .take(LIMIT)
+            .map(|(name, count)| {
+                CompletionCandidate::new(format!("{head}{operator}{key}{name}"))
+                    .help(Some(format!("{count} posts").into()))
+            })
+            .collect();
+    }
+    if key == "source:" {
+        let mut domains: Vec<(&String, u64)> = index
+            .source_to_post_id
+            .iter()
+            .filter(|(domain, _)| domain.starts_with(&value))
+            .map(|(domain, post_ids)| (domain, post_ids.len()))
+            .collect();
+        domains.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        return domains
+            .into_iter()
+            .take(LIMIT)
+            .map(|(domain, count)| {
+                CompletionCandidate::new(format!("{head}{operator}{key}{domain}"))
+                    .help(Some(format!("{count} posts").into()))
+            })
+            .collect();
+    }
+    index
+        .suggest_tags(&value, LIMIT)
+        .into_iter()
+        .map(|(tag, count)| {
+            CompletionCandidate::new(format!("{head}{operator}{key}{tag}"))
+                .help(Some(format!("{count} posts").into()))
+        })
+        .collect()
+}
+
+fn load_index() -> Option<Index> {
+    let config_path = std::env::var_os("INDEXER_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+    let profile = std::env::var("INDEXER_PROFILE").ok();
+    let config = Config::load(config_path, profile.as_deref()).ok()?;
+    Index::load(&config.index.path).ok()
+}
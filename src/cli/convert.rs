@@ -0,0 +1,216 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use clap::{Args, ValueEnum};
+use indexer::{
+    models::{
+        envelope::{parse_record, Record},
+        Post, Tag,
+    },
+    sink::Sink,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{info, warn};
+
+use super::output::{Format, Status};
+
+/// Number of records between two progress messages
+const PROGRESS_INTERVAL: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Parquet,
+    Csv,
+    Sqlite,
+    Msgpack,
+}
+
+impl Target {
+    /// Used both as the file extension and as the cargo feature the sink is gated behind
+    fn as_str(&self) -> &'static str {
+        match self {
+            Target::Parquet => "parquet",
+            Target::Csv => "csv",
+            Target::Sqlite => "sqlite",
+            Target::Msgpack => "msgpack",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Posts,
+    Tags,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Posts => "posts",
+            Kind::Tags => "tags",
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// JSON lines file written by `scrape`
+    pub input: PathBuf,
+
+    /// Format to convert into
+    #[arg(long, value_enum)]
+    pub to: Target,
+
+    /// Whether the input holds posts or tags
+    #[arg(long, value_enum, default_value_t = Kind::Posts)]
+    pub kind: Kind,
+
+    /// Output file, defaults to the input with the extension of the target format
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+}
+
+/// Result of `convert`
+#[derive(Debug, Serialize)]
+pub struct ConvertOutput {
+    pub kind: Kind,
+    pub to: Target,
+    pub path: PathBuf,
+    pub records: u64,
+    pub malformed: u64,
+    pub duration_ms: u128,
+}
+
+pub fn run(args: ConvertArgs, format: Format) -> Result<Status, Box<dyn std::error::Error>> {
+    let out = args
+        .out
+        .unwrap_or_else(|| args.input.with_extension(args.to.as_str()));
+    let reader = BufReader::new(File::open(&args.input)?);
+
+    let start = Instant::now();
+    let (records, malformed) = match args.kind {
+        Kind::Posts => convert::<Post>(reader, post_sink(args.to, &out)?)?,
+        Kind::Tags => convert::<Tag>(reader, tag_sink(args.to, &out)?)?,
+    };
+
+    let output = ConvertOutput {
+        kind: args.kind,
+        to: args.to,
+        path: out,
+        records,
+        malformed,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    format.print(&output, |output| {
+        println!(
+            "converted {} {} into {} in {}ms",
+            output.records,
+            output.kind.as_str(),
+            output.path.display(),
+            output.duration_ms
+        );
+        if output.malformed > 0 {
+            println!("skipped {} malformed lines", output.malformed);
+        }
+    });
+
+    match malformed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
+
+/// Stream every record of `reader` into `sink`, returning the number of records written and the
+/// number of malformed lines skipped
+fn convert<T: Record + DeserializeOwned>(
+    reader: impl BufRead,
+    mut sink: Box<dyn Sink<T>>,
+) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let mut records = 0;
+    let mut malformed = 0;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match parse_record::<T>(&line) {
+            Ok(record) => {
+                sink.write(record)?;
+                records += 1;
+                if records % PROGRESS_INTERVAL == 0 {
+                    info!("Converted {} records", records);
+                }
+            }
+            Err(e) => {
+                warn!("Skipping malformed line {}: {}", number + 1, e);
+                malformed += 1;
+            }
+        }
+    }
+
+    sink.flush()?;
+    Ok((records, malformed))
+}
+
+pub fn post_sink(
+    target: Target,
+    path: &Path,
+) -> Result<Box<dyn Sink<Post>>, Box<dyn std::error::Error>> {
+    match target {
+        #[cfg(feature = "parquet")]
+        Target::Parquet => Ok(Box::new(
+            indexer::sink::parquet::ParquetSink::<Post>::create(path)?,
+        )),
+        #[cfg(feature = "csv")]
+        Target::Csv => Ok(Box::new(indexer::sink::csv::CsvPostSink::new(
+            std::io::BufWriter::new(File::create(path)?),
+        ))),
+        #[cfg(feature = "sqlite")]
+        Target::Sqlite => Ok(Box::new(indexer::sink::sqlite::SqliteSink::open(path)?)),
+        #[cfg(feature = "msgpack")]
+        Target::Msgpack => Ok(Box::new(indexer::sink::compact::MessagePackSink::new(
+            std::io::BufWriter::new(File::create(path)?),
+        ))),
+        #[allow(unreachable_patterns)]
+        target => Err(unsupported(target, path)),
+    }
+}
+
+fn tag_sink(target: Target, path: &Path) -> Result<Box<dyn Sink<Tag>>, Box<dyn std::error::Error>> {
+    match target {
+        #[cfg(feature = "parquet")]
+        Target::Parquet => Ok(Box::new(
+            indexer::sink::parquet::ParquetSink::<Tag>::create(path)?,
+        )),
+        #[cfg(feature = "csv")]
+        Target::Csv => Ok(Box::new(indexer::sink::csv::CsvTagSink::new(
+            std::io::BufWriter::new(File::create(path)?),
+        ))),
+        #[cfg(feature = "sqlite")]
+        Target::Sqlite => Ok(Box::new(indexer::sink::sqlite::SqliteSink::open(path)?)),
+        #[cfg(feature = "msgpack")]
+        Target::Msgpack => Ok(Box::new(indexer::sink::compact::MessagePackSink::new(
+            std::io::BufWriter::new(File::create(path)?),
+        ))),
+        #[allow(unreachable_patterns)]
+        target => Err(unsupported(target, path)),
+    }
+}
+
+fn unsupported(target: Target, path: &Path) -> Box<dyn std::error::Error> {
+    format!(
+        "cannot write `{}`: converting to {} requires the `{}` feature",
+        path.display(),
+        target.as_str(),
+        target.as_str()
+    )
+    .into()
+}
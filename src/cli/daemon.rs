@@ -0,0 +1,371 @@
+//! Scraping and serving in one long-running process
+//!
+//! The scraper appends to the configured output files like `scrape` does, while the server answers
+//! queries from the saved index. Every newly scraped post is also published to `/feed`.
+//!
+//! With jobs in `[schedule]` the daemon runs them on their cron schedules instead of scraping
+//! once: catching up with the new posts, refreshing the tags, syncing edits and deletions,
+//! updating and reloading the index and compacting the posts file. Every job reads or rewrites the
+//! output files or the index, so they run one at a time; the status of every job is kept in the
+//! state file.
+//!
+//! `/health` reports the cursors, failed ranges and queued records of the scrape, and answers with
+//! a 503 once a running post sweep received no page for `--stall-after` seconds.
+//!
+//! On ctrl-c or SIGTERM the daemon stops serving, lets the running scrape or job finish its
+//! writes, saves the state and exits successfully, so it can run as a systemd service.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use clap::Args;
+use indexer::{
+    api::client::ApiClient,
+    config::Config,
+    maintenance::compact::compact_posts,
+    schedule::{JobResult, Scheduler},
+    scraper::{
+        blacklist::Blacklist, post_scraper::PostScraper, processor::Pipeline,
+        state_manager::StateManager, tag_scraper::TagScraper,
+    },
+    server::{health::Health, AppState, Feed},
+    sink::{
+        tee::{ErrorPolicy, TeeSink},
+        writer::spawn_writer,
+    },
+};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{error, info};
+
+use super::{
+    index::{build_index, BuildArgs},
+    output::Status,
+    progress::Progress,
+    scrape::{api_client, create_client, open_output, open_posts_output, recover_post_pages},
+    serve::{serve, ServeArgs},
+    sync::{sync, SyncArgs},
+    systemd, IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    #[command(flatten)]
+    pub serve: ServeArgs,
+
+    /// Posts buffered for every `/feed` client, a slower client misses the oldest ones
+    #[arg(long, default_value_t = 1024)]
+    pub feed_capacity: usize,
+
+    /// Seconds without a post page after which `/health` reports a running post sweep as stalled
+    #[arg(long, default_value_t = 900)]
+    pub stall_after: u64,
+}
+
+pub async fn run(args: DaemonArgs, config: Config) -> Result<Status, Box<dyn std::error::Error>> {
+    let feed = Feed::new(args.feed_capacity);
+    let state = AppState::load(args.serve.index.path(&config))?
+        .with_namespaces(&config.server.namespaces)?
+        .with_feed(feed.clone());
+    if !config.schedule.is_empty() {
+        return run_scheduled(args, config, state, feed).await;
+    }
+
+    let output = &config.output;
+    let state_path = output.state.to_string_lossy().to_string();
+    let state_manager = StateManager::new(&state_path).expect("Failed to load state file");
+    let api_client = api_client(&config)?.with_throttle(state_manager.throttle());
+    let health =
+        Health::new(state_manager.clone()).with_stall_after(Duration::from_secs(args.stall_after));
+    let state = state.with_health(health.clone());
+    let seen = recover_post_pages(&state_manager, &output.posts).await?;
+
+    // The feed only logs its errors, a broken subscriber must not stop the posts file
+    let capacity = config.scraper.channel_capacity;
+    let posts = TeeSink::new()
+        .with(
+            "posts",
+            open_posts_output(&config.scraper, output)?,
+            ErrorPolicy::Fail,
+        )
+        .with("feed", feed, ErrorPolicy::Log);
+    let (post_output, post_writer) = spawn_writer(posts, capacity);
+    let (tag_output, tag_writer) = spawn_writer(open_output(&output.tags), capacity);
+    health.track_queue("posts", post_output.depth());
+    health.track_queue("tags", tag_output.depth());
+    let tag_scraper = TagScraper::new(tag_output, state_manager.clone(), api_client.clone())
+        .with_requests_per_second(config.scraper.requests_per_second);
+    let blacklist = Arc::new(Blacklist::new(&config.scraper.blacklist));
+    let pipeline = Arc::new(Pipeline::from_config(
+        &config.scraper.processors,
+        create_client(),
+    ));
+    let post_scraper = PostScraper::new(post_output, state_manager.clone(), api_client)
+        .with_requests_per_second(config.scraper.requests_per_second)
+        .with_parallel_requests(config.scraper.parallel_requests)
+        .with_blacklist(blacklist.clone())
+        .with_pipeline(pipeline.clone())
+        .with_seen(seen);
+
+    let scrape = async move {
+        let (posts, tags) = tokio::join!(post_scraper.run(), tag_scraper.run());
+        for result in [posts, tags] {
+            if let Err(e) = result {
+                error!("Scraper failed: {}", e);
+            }
+        }
+    };
+
+    // The server stops on ctrl-c, which also ends the scrape. If the scrape ends first the
+    // server keeps running.
+    let server = serve(&args.serve, &config, state);
+    tokio::pin!(server);
+    let mut served = None;
+    tokio::select! {
+        result = &mut server => served = Some(result),
+        _ = scrape => {
+            info!("Finished Scraping");
+            systemd::status("Finished scraping, serving the index");
+        }
+    }
+
+    // The scrapers have been dropped, save the state and wait for the writers to flush
+    systemd::stopping();
+    state_manager.save_state(&state_path).await?;
+    tag_writer.await??;
+    post_writer.await??;
+    let filtered = blacklist.stats();
+    if filtered.total() > 0 {
+        info!("Blacklisted {} posts", filtered.total());
+    }
+    let processed = pipeline.stats();
+    if processed.dropped + processed.failed > 0 {
+        info!(
+            "Processors dropped {} posts and failed {} times",
+            processed.dropped, processed.failed
+        );
+    }
+    match served {
+        Some(result) => result?,
+        None => server.await?,
+    }
+    Ok(Status::Success)
+}
+
+async fn run_scheduled(
+    args: DaemonArgs,
+    config: Config,
+    state: AppState,
+    feed: Feed,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let state_path = config.output.state.to_string_lossy().to_string();
+    let state_manager = StateManager::new(&state_path).expect("Failed to load state file");
+    // Without a posts job no pages are expected, so the scrape can't stall
+    let mut health = Health::new(state_manager.clone());
+    if config.schedule.posts.is_some() {
+        health = health.with_stall_after(Duration::from_secs(args.stall_after));
+    }
+    let state = state.with_health(health.clone());
+    let jobs = Jobs {
+        api_client: api_client(&config)?.with_throttle(state_manager.throttle()),
+        state_manager,
+        files: Arc::new(Mutex::new(())),
+        stopping: Arc::new(AtomicBool::new(false)),
+        blacklist: Arc::new(Blacklist::new(&config.scraper.blacklist)),
+        pipeline: Arc::new(Pipeline::from_config(
+            &config.scraper.processors,
+            create_client(),
+        )),
+        feed,
+        health,
+        app: state.clone(),
+        config: Arc::new(config.clone()),
+    };
+
+    let schedule = config.schedule.clone();
+    let mut scheduler = Scheduler::new(jobs.state_manager.clone(), state_path.clone());
+    if let Some(cron) = schedule.posts {
+        let jobs = jobs.clone();
+        scheduler = scheduler.with_job("posts", cron, move || jobs.clone().posts());
+    }
+    if let Some(cron) = schedule.tags {
+        let jobs = jobs.clone();
+        scheduler = scheduler.with_job("tags", cron, move || jobs.clone().tags());
+    }
+    if let Some(cron) = schedule.sync {
+        let jobs = jobs.clone();
+        scheduler = scheduler.with_job("sync", cron, move || jobs.clone().sync());
+    }
+    if let Some(cron) = schedule.index {
+        let jobs = jobs.clone();
+        scheduler = scheduler.with_job("index", cron, move || jobs.clone().index());
+    }
+    if let Some(cron) = schedule.compact {
+        let jobs = jobs.clone();
+        scheduler = scheduler.with_job("compact", cron, move || jobs.clone().compact());
+    }
+
+    systemd::status("Serving the index and running the scheduled jobs");
+    tokio::select! {
+        result = serve(&args.serve, &config, state) => result?,
+        _ = scheduler.run() => {}
+    }
+
+    // A running job keeps going in its own task, wait for it instead of cutting off its writes.
+    // Jobs which were waiting for it give up.
+    systemd::stopping();
+    jobs.stopping.store(true, Ordering::Release);
+    let _files = jobs.files.lock().await;
+    jobs.state_manager.save_state(&state_path).await?;
+    Ok(Status::Success)
+}
+
+/// What the scheduled jobs share
+#[derive(Clone)]
+struct Jobs {
+    config: Arc<Config>,
+    api_client: ApiClient,
+    state_manager: StateManager,
+    /// Held by every job while it runs
+    files: Arc<Mutex<()>>,
+    /// Set on shutdown, jobs which didn't start yet are skipped
+    stopping: Arc<AtomicBool>,
+    blacklist: Arc<Blacklist>,
+    pipeline: Arc<Pipeline>,
+    feed: Feed,
+    health: Health,
+    app: AppState,
+}
+
+impl Jobs {
+    /// Wait for the other jobs, fails if the daemon is shutting down in the meantime
+    async fn lock(&self) -> Result<MutexGuard<'_, ()>, String> {
+        let files = self.files.lock().await;
+        match self.stopping.load(Ordering::Acquire) {
+            true => Err(String::from("the daemon is shutting down")),
+            false => Ok(files),
+        }
+    }
+
+    async fn posts(self) -> JobResult {
+        let _files = self.lock().await?;
+        let config = &self.config;
+        let seen = recover_post_pages(&self.state_manager, &config.output.posts).await?;
+        let posts = TeeSink::new()
+            .with(
+                "posts",
+                open_posts_output(&config.scraper, &config.output)?,
+                ErrorPolicy::Fail,
+            )
+            .with("feed", self.feed.clone(), ErrorPolicy::Log);
+        let (post_output, post_writer) = spawn_writer(posts, config.scraper.channel_capacity);
+        self.health.track_queue("posts", post_output.depth());
+        let before = self.state_manager.last_post_id().await;
+        let post_scraper = PostScraper::new(
+            post_output,
+            self.state_manager.clone(),
+            self.api_client.clone(),
+        )
+        .with_requests_per_second(config.scraper.requests_per_second)
+        .with_parallel_requests(config.scraper.parallel_requests)
+        .with_blacklist(self.blacklist.clone())
+        .with_pipeline(self.pipeline.clone())
+        .with_seen(seen);
+        let result = post_scraper.catch_up().await.map_err(|e| e.to_string());
+        drop(post_scraper);
+        post_writer.await??;
+        result?;
+
+        let after = self.state_manager.last_post_id().await;
+        if after == before {
+            return Ok(String::from("no new posts"));
+        }
+        Ok(format!("scraped posts {} to {}", before + 1, after))
+    }
+
+    async fn tags(self) -> JobResult {
+        let _files = self.lock().await?;
+        let config = &self.config;
+        let (tag_output, tag_writer) = spawn_writer(
+            open_output(&config.output.tags),
+            config.scraper.channel_capacity,
+        );
+        self.health.track_queue("tags", tag_output.depth());
+        let tag_scraper = TagScraper::new(
+            tag_output,
+            self.state_manager.clone(),
+            self.api_client.clone(),
+        )
+        .with_requests_per_second(config.scraper.requests_per_second);
+        let result = tag_scraper.refresh().await.map_err(|e| e.to_string());
+        drop(tag_scraper);
+        tag_writer.await??;
+        Ok(format!("refreshed {} tags", result?))
+    }
+
+    async fn sync(self) -> JobResult {
+        let _files = self.lock().await?;
+        let args = SyncArgs {
+            index: IndexArgs { index: None },
+            since: None,
+            posts: None,
+            sqlite: None,
+            dry_run: false,
+        };
+        let output = sync(args, &self.config, Some(&self.state_manager))
+            .await
+            .map_err(|e| e.to_string())?;
+        if output.index_updated {
+            self.app.reload().await?;
+        }
+        let report = output.report;
+        Ok(format!(
+            "checked {} posts: {} added, {} updated, {} deleted, {} ranges failed",
+            report.checked, report.added, report.updated, report.deleted, report.failed
+        ))
+    }
+
+    async fn index(self) -> JobResult {
+        let _files = self.lock().await?;
+        let config = (*self.config).clone();
+        let output = tokio::task::spawn_blocking(move || {
+            let args = BuildArgs {
+                posts: None,
+                tags: None,
+                pools: None,
+                notes: None,
+                hashes: None,
+                inventory: None,
+                out: None,
+                incremental: true,
+                keep: None,
+                histogram_tags: None,
+                bloom_tags: None,
+            };
+            build_index(args, config, Progress::new(false)).map_err(|e| e.to_string())
+        })
+        .await??;
+        self.app.reload().await?;
+        Ok(format!(
+            "indexed {} post lines, {} posts in total",
+            output.post_lines, output.posts
+        ))
+    }
+
+    async fn compact(self) -> JobResult {
+        let _files = self.lock().await?;
+        let posts = self.config.output.posts.clone();
+        let stats = tokio::task::spawn_blocking(move || {
+            compact_posts(&posts, false).map_err(|e| e.to_string())
+        })
+        .await??;
+        Ok(format!(
+            "kept {} of {} lines, dropped {} duplicates and {} tombstones",
+            stats.kept, stats.lines, stats.duplicates, stats.tombstones
+        ))
+    }
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use indexer::{
+    config::Config,
+    export::dataset::{export_dataset, DatasetFormat, DatasetOptions, DatasetStats},
+    query::Query,
+};
+use serde::Serialize;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct DatasetArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Export the posts matching this query, e.g. `"cat -dog"`
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        add = ArgValueCompleter::new(complete::query_terms)
+    )]
+    pub query: String,
+
+    /// Directory the manifests are written to
+    #[arg(short, long, default_value = "dataset")]
+    pub out: PathBuf,
+
+    /// Ratios of the train, validation and test splits
+    #[arg(long, value_delimiter = ',', default_values_t = [0.8, 0.1, 0.1])]
+    pub split: Vec<f64>,
+
+    /// The same seed always assigns a post to the same split
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Format of the manifests: jsonl or csv
+    #[arg(long, default_value = "jsonl")]
+    pub manifest: DatasetFormat,
+
+    /// The `--dest` of `download`, the paths of the downloaded files are included
+    #[arg(long)]
+    pub downloads: Option<PathBuf>,
+}
+
+/// Result of `dataset`
+#[derive(Debug, Serialize)]
+pub struct DatasetOutput {
+    pub query: String,
+    pub out: PathBuf,
+    #[serde(flatten)]
+    pub stats: DatasetStats,
+}
+
+pub fn run(
+    args: DatasetArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let [train, val, test] = args.split[..] else {
+        return Err("--split takes three ratios, e.g. 0.8,0.1,0.1".into());
+    };
+    let index = args.index.load(&config)?;
+    let query = Query::parse(&args.query)?;
+    let post_ids = index.search(&query);
+
+    let options = DatasetOptions {
+        train,
+        val,
+        test,
+        seed: args.seed,
+        format: args.manifest,
+        downloads: args.downloads,
+    };
+    let stats = export_dataset(&index, &post_ids, &options, &args.out)?;
+
+    let output = DatasetOutput {
+        query: query.to_string(),
+        out: args.out,
+        stats,
+    };
+    format.print(&output, |output| {
+        println!(
+            "Wrote {} train, {} val and {} test posts to {}",
+            output.stats.train,
+            output.stats.val,
+            output.stats.test,
+            output.out.display()
+        );
+        if options.downloads.is_some() {
+            println!("{} of them were downloaded", output.stats.downloaded);
+        }
+    });
+    Ok(Status::Success)
+}
@@ -0,0 +1,179 @@
+use std::{fs::File, io::BufReader, path::PathBuf, sync::Arc};
+
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use futures::StreamExt;
+use indexer::{
+    config::Config,
+    download::{DownloadJob, DownloadStats, Downloader, Variant},
+    index::read_posts,
+    models::HashKind,
+    query::Query,
+    scraper::state_manager::StateManager,
+    stream::QueryStream,
+};
+use indicatif::{HumanBytes, ProgressBar};
+use roaring::RoaringBitmap;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    progress::Progress,
+    scrape::create_client,
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct DownloadArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Download the posts matching this query, e.g. `"artist:foo rating:safe"`
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        add = ArgValueCompleter::new(complete::query_terms)
+    )]
+    pub query: String,
+
+    /// Which file to download: original, sample or preview
+    #[arg(long, default_value = "original")]
+    pub variant: Variant,
+
+    /// Directory the files are stored in
+    #[arg(long, default_value = "files")]
+    pub dest: PathBuf,
+
+    /// Only download the first `limit` matching posts
+    #[arg(long)]
+    pub limit: Option<u64>,
+
+    /// Number of files downloaded concurrently
+    #[arg(long, default_value_t = 4)]
+    pub parallel: usize,
+
+    /// Build the file urls from the index records and `site.urls` instead of reading the scraped
+    /// urls from the posts file
+    #[arg(long)]
+    pub from_index: bool,
+
+    /// Append a perceptual hash (phash or dhash) of every downloaded image to `output.hashes`,
+    /// for `duplicates`. Requires the `phash` feature.
+    #[arg(long, num_args = 0..=1, default_missing_value = "phash")]
+    pub phash: Option<HashKind>,
+
+    /// Keep the progress of the downloads in this state file, as its `downloads` task. Use a
+    /// file of its own, the scrape state is rewritten by running scrapes and the daemon.
+    #[arg(long)]
+    pub progress_state: Option<PathBuf>,
+}
+
+pub async fn run(
+    args: DownloadArgs,
+    config: Config,
+    format: Format,
+    progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let index = args.index.load(&config)?;
+    let query = Query::parse(&args.query)?;
+
+    let mut post_ids = index.search(&query);
+    if let Some(limit) = args.limit {
+        post_ids = post_ids
+            .iter()
+            .take(limit as usize)
+            .collect::<RoaringBitmap>();
+    }
+
+    let progress_state = match &args.progress_state {
+        Some(path) => {
+            let path = path.to_string_lossy().to_string();
+            Some((StateManager::new(&path)?, path))
+        }
+        None => None,
+    };
+    let downloader = Downloader::builder()
+        .client(create_client())
+        .state_manager(
+            progress_state
+                .as_ref()
+                .map(|(state_manager, _)| state_manager.clone()),
+        )
+        .dest(args.dest)
+        .variant(args.variant)
+        .urls(config.site.urls)
+        .requests_per_second(config.scraper.requests_per_second)
+        .parallel_downloads(args.parallel);
+    #[cfg(feature = "phash")]
+    let downloader = match args.phash {
+        Some(kind) => downloader
+            .hashes(config.output.hashes.clone())
+            .hash_kind(kind)
+            .build(),
+        None => downloader.build(),
+    };
+    #[cfg(not(feature = "phash"))]
+    let downloader = match args.phash {
+        Some(_) => return Err("hashing the files requires the `phash` feature".into()),
+        None => downloader.build(),
+    };
+    let template = "{spinner} {bar:30} {pos}/{len} files, {msg} (eta {eta})";
+    let stats = if args.from_index {
+        // Jobs are made from the index as download slots free up, not all up front
+        let posts = QueryStream::new(Arc::new(index), query, post_ids);
+        let bar = progress.bar(posts.len(), template);
+        let jobs = posts.flat_map(|batch| {
+            let jobs: Vec<DownloadJob> = batch
+                .posts()
+                .map(|post| downloader.index_job(post))
+                .collect();
+            futures::stream::iter(jobs)
+        });
+        let stats = downloader
+            .download_job_stream_with_progress(jobs, |stats| update_bar(&bar, stats))
+            .await?;
+        bar.finish_and_clear();
+        stats
+    } else {
+        // The index doesn't keep the file urls, read them from the scraped posts
+        let jobs: Vec<DownloadJob> =
+            read_posts(BufReader::new(File::open(&config.output.posts)?), &post_ids)?
+                .iter()
+                .map(|post| downloader.job(post))
+                .collect();
+        drop(index);
+
+        let bar = progress.bar(jobs.len() as u64, template);
+        let stats = downloader
+            .download_jobs_with_progress(jobs, |stats| update_bar(&bar, stats))
+            .await?;
+        bar.finish_and_clear();
+        stats
+    };
+
+    if let Some((state_manager, path)) = &progress_state {
+        state_manager.save_state(path).await?;
+    }
+
+    format.print(&stats, |stats| {
+        println!(
+            "Downloaded {} files ({} bytes), skipped {} existing, {} failed",
+            stats.downloaded, stats.bytes, stats.skipped, stats.failed
+        );
+        if stats.hashed + stats.unhashable > 0 {
+            println!(
+                "Hashed {} images, {} files couldn't be hashed",
+                stats.hashed, stats.unhashable
+            );
+        }
+    });
+    match stats.failed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
+
+fn update_bar(bar: &ProgressBar, stats: &DownloadStats) {
+    bar.set_position(stats.downloaded + stats.skipped + stats.failed);
+    bar.set_message(HumanBytes(stats.bytes).to_string());
+}
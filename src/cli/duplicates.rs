@@ -0,0 +1,81 @@
+use clap::Args;
+use indexer::config::Config;
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct DuplicatesArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Maximum number of differing bits of the perceptual hashes of two duplicates
+    #[arg(long, default_value_t = 6)]
+    pub threshold: u32,
+}
+
+/// A post of a group of near duplicates
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub id: u32,
+    pub md5: String,
+}
+
+/// Result of `duplicates`
+#[derive(Debug, Serialize)]
+pub struct DuplicatesOutput {
+    /// Posts with a perceptual hash
+    pub hashed: usize,
+    pub groups: Vec<Vec<Duplicate>>,
+}
+
+pub fn run(
+    args: DuplicatesArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let index = args.index.load(&config)?;
+    if index.perceptual_hashes.is_empty() {
+        return Err(
+            "the index has no perceptual hashes, run `download --phash` and `index build` first"
+                .into(),
+        );
+    }
+
+    let groups = index
+        .near_duplicates(args.threshold)
+        .into_iter()
+        .map(|group| {
+            group
+                .into_iter()
+                .map(|id| Duplicate {
+                    id,
+                    md5: hex::encode(index.post_id_to_post[&id].md5),
+                })
+                .collect()
+        })
+        .collect();
+    let output = DuplicatesOutput {
+        hashed: index.perceptual_hashes.len(),
+        groups,
+    };
+
+    format.print(&output, |output| {
+        for group in &output.groups {
+            let posts: Vec<String> = group
+                .iter()
+                .map(|post| format!("{} ({})", post.id, post.md5))
+                .collect();
+            println!("{}", posts.join("\t"));
+        }
+        eprintln!(
+            "{} groups of near duplicates among {} hashed posts",
+            output.groups.len(),
+            output.hashed
+        );
+    });
+    Ok(Status::Success)
+}
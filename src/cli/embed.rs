@@ -0,0 +1,110 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use indexer::{
+    config::Config,
+    export::embedding::{ppmi, EmbeddingStats},
+    query::Query,
+};
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct EmbedArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Only count the posts matching this query, defaults to every post
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        add = ArgValueCompleter::new(complete::query_terms)
+    )]
+    pub query: Option<String>,
+
+    /// File the vectors are written to, in the word2vec text format
+    #[arg(short, long, default_value = "tags.vec")]
+    pub out: PathBuf,
+
+    /// Number of most used tags which get a vector
+    #[arg(long, default_value_t = 10_000)]
+    pub vocabulary: usize,
+
+    /// Leave out tags on fewer posts
+    #[arg(long, default_value_t = 5)]
+    pub min_count: u64,
+
+    /// Length of the vectors
+    #[arg(long, default_value_t = 64)]
+    pub dimensions: usize,
+
+    /// Rounds of the subspace iteration, more give more accurate vectors
+    #[arg(long, default_value_t = 10)]
+    pub iterations: usize,
+
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Also write the PPMI matrix as `tag_a tag_b weight` lines to this file
+    #[arg(long)]
+    pub matrix: Option<PathBuf>,
+}
+
+/// Result of `embed`
+#[derive(Debug, Serialize)]
+pub struct EmbedOutput {
+    pub out: PathBuf,
+    #[serde(flatten)]
+    pub stats: EmbeddingStats,
+}
+
+pub fn run(
+    args: EmbedArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let index = args.index.load(&config)?;
+    let post_ids = match &args.query {
+        Some(query) => index.search(&Query::parse(query)?),
+        None => index
+            .post_id_to_post
+            .keys()
+            .copied()
+            .collect::<RoaringBitmap>(),
+    };
+
+    let matrix = ppmi(&index, &post_ids, args.vocabulary, args.min_count);
+    if let Some(path) = &args.matrix {
+        matrix.write(BufWriter::new(File::create(path)?))?;
+    }
+    let embeddings = matrix.factorize(args.dimensions, args.iterations, args.seed);
+    embeddings.write_word2vec(BufWriter::new(File::create(&args.out)?))?;
+
+    let output = EmbedOutput {
+        out: args.out,
+        stats: EmbeddingStats {
+            posts: post_ids.len(),
+            tags: matrix.tags.len() as u64,
+            pairs: matrix.pairs(),
+            dimensions: args.dimensions as u64,
+        },
+    };
+    format.print(&output, |output| {
+        println!(
+            "Wrote {} tag vectors of {} dimensions to {} ({} posts, {} tag pairs)",
+            output.stats.tags,
+            output.stats.dimensions,
+            output.out.display(),
+            output.stats.posts,
+            output.stats.pairs
+        );
+    });
+    Ok(Status::Success)
+}
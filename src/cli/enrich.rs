@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    maintenance::enrich::{enrich_posts, load_tag_types, EnrichStats},
+};
+use serde::Serialize;
+
+use super::output::{Format, Status};
+
+#[derive(Debug, Args)]
+pub struct EnrichArgs {
+    /// Defaults to `output.posts` from the config
+    #[arg(long)]
+    pub posts: Option<PathBuf>,
+
+    /// Defaults to `output.tags` from the config
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+
+    /// Where the posts are written, defaults to replacing the posts file
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+}
+
+/// Result of `enrich`
+#[derive(Debug, Serialize)]
+pub struct EnrichOutput {
+    pub path: PathBuf,
+    /// Tags in the tags file
+    pub known_tags: usize,
+    #[serde(flatten)]
+    pub stats: EnrichStats,
+}
+
+pub fn run(
+    args: EnrichArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let posts = args.posts.unwrap_or(config.output.posts);
+    let tags = args.tags.unwrap_or(config.output.tags);
+    let out = args.out.unwrap_or_else(|| posts.clone());
+
+    let tag_types = load_tag_types(&tags)?;
+    let stats = enrich_posts(&posts, &tag_types, &out)?;
+
+    let output = EnrichOutput {
+        path: out,
+        known_tags: tag_types.len(),
+        stats,
+    };
+    format.print(&output, |output| {
+        println!(
+            "Resolved the tags of {} posts into {}, {} tags are unknown",
+            output.stats.posts,
+            output.path.display(),
+            output.stats.unresolved
+        );
+        if output.stats.malformed > 0 {
+            println!("Dropped {} malformed lines", output.stats.malformed);
+        }
+    });
+
+    match stats.malformed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    fs::File,
+    io::{Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use chrono::Utc;
+use clap::{Args, Subcommand};
+use indexer::{
+    cluster::{ClusterOptions, TagCluster},
+    config::Config,
+    index::{BuildReport, Index},
+    input::{self, Compression},
+    inventory::Inventory,
+    maintenance::rename::reconcile_tag_names,
+    models::{PostField, PostFields, Tag},
+    sink::Sink,
+};
+use serde::Serialize;
+use tracing::warn;
+
+use super::{
+    output::{Format, Status},
+    progress::Progress,
+    scrape::{api_client, open_output},
+    IndexArgs,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum IndexCommand {
+    /// Build an index from the scraped posts and tags
+    Build(BuildArgs),
+    /// Find the tags renamed on the site and rename them in the index, keeping the old names as
+    /// aliases
+    RenameTags(RenameTagsArgs),
+    /// Cluster the most used tags into topics by how often they are used together, and save the
+    /// clusters in the index
+    Clusters(ClustersArgs),
+    /// List the segments of the index, the points in its history `query --at` can go back to
+    Segments(SegmentsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct BuildArgs {
+    /// Defaults to `output.posts` from the config
+    #[arg(long)]
+    pub posts: Option<PathBuf>,
+
+    /// Defaults to `output.tags` from the config
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+
+    /// Defaults to `output.pools` from the config, skipped if the file doesn't exist
+    #[arg(long)]
+    pub pools: Option<PathBuf>,
+
+    /// Defaults to `output.notes` from the config, skipped if the file doesn't exist
+    #[arg(long)]
+    pub notes: Option<PathBuf>,
+
+    /// Defaults to `output.hashes` from the config, skipped if the file doesn't exist
+    #[arg(long)]
+    pub hashes: Option<PathBuf>,
+
+    /// Defaults to `output.inventory` from the config, skipped if the file doesn't exist
+    #[arg(long)]
+    pub inventory: Option<PathBuf>,
+
+    /// Where the built index is saved, defaults to `index.path` from the config
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+
+    /// Update an existing index with the lines written since it was built, instead of building
+    /// it from scratch
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Optional post fields to keep in the index (score, rating, dimensions, parent_id),
+    /// defaults to `index.keep` from the config
+    #[arg(long, value_delimiter = ',')]
+    pub keep: Option<Vec<PostField>>,
+
+    /// Number of most used tags with a precomputed score histogram, defaults to
+    /// `index.histogram_tags` from the config
+    #[arg(long)]
+    pub histogram_tags: Option<usize>,
+
+    /// Number of most used tags with a bloom filter, defaults to `index.bloom_tags` from the
+    /// config
+    #[arg(long)]
+    pub bloom_tags: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct RenameTagsArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// The renamed tags are appended here, defaults to `output.tags` from the config
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+
+    /// Only report the renames, without changing the index or the tags file
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ClustersArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Number of most used tags which are clustered
+    #[arg(long, default_value_t = 5_000)]
+    pub vocabulary: usize,
+
+    /// Leave out tags on fewer posts
+    #[arg(long, default_value_t = 20)]
+    pub min_count: u64,
+
+    /// Smallest cosine similarity of the posts of two tags to link them, from 0 to 1
+    #[arg(long, default_value_t = 0.2)]
+    pub min_similarity: f32,
+
+    /// Drop clusters with fewer tags
+    #[arg(long, default_value_t = 3)]
+    pub min_size: usize,
+
+    /// Number of clusters printed, all of them are saved
+    #[arg(long, default_value_t = 20)]
+    pub show: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct SegmentsArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+}
+
+/// Result of `index clusters`
+#[derive(Debug, Serialize)]
+pub struct ClustersOutput {
+    /// Number of clusters found
+    pub count: usize,
+    /// The largest clusters
+    pub clusters: Vec<TagCluster>,
+    pub duration_ms: u128,
+}
+
+/// Result of `index build`
+#[derive(Debug, Serialize)]
+pub struct BuildOutput {
+    pub posts: usize,
+    pub tags: usize,
+    pub pools: usize,
+    /// Distinct words in the notes
+    pub note_words: usize,
+    /// Tags with a precomputed score histogram
+    pub histogram_tags: usize,
+    /// Tags with a bloom filter
+    pub bloom_tags: usize,
+    /// Posts with a perceptual hash
+    pub hashes: usize,
+    /// Posts whose file is in the inventory
+    pub downloaded: u64,
+    /// Whether an existing index was updated
+    pub incremental: bool,
+    /// The segment the build added, `None` if there were no new lines
+    pub segment: Option<u32>,
+    /// Post lines read in this run
+    pub post_lines: u64,
+    /// Tag lines read in this run
+    pub tag_lines: u64,
+    /// The lines which were skipped and the duration of every phase
+    pub report: BuildReport,
+    pub path: PathBuf,
+    pub duration_ms: u128,
+}
+
+pub async fn run(
+    command: IndexCommand,
+    config: Config,
+    format: Format,
+    progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    match command {
+        IndexCommand::Build(args) => build(args, config, format, progress),
+        IndexCommand::RenameTags(args) => rename_tags(args, config, format).await,
+        IndexCommand::Clusters(args) => clusters(args, config, format),
+        IndexCommand::Segments(args) => segments(args, config, format),
+    }
+}
+
+fn build(
+    args: BuildArgs,
+    config: Config,
+    format: Format,
+    progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let output = build_index(args, config, progress)?;
+    format.print(&output, |output| {
+        if output.incremental {
+            println!(
+                "Added {} post lines and {} tag lines to {}",
+                output.post_lines,
+                output.tag_lines,
+                output.path.display()
+            );
+        }
+        println!(
+            "Indexed {} posts and {} tags into {} in {}ms",
+            output.posts,
+            output.tags,
+            output.path.display(),
+            output.duration_ms
+        );
+        if let Some(segment) = output.segment {
+            println!("Finished segment {segment}");
+        }
+        print_report(&output.report);
+    });
+    Ok(Status::Success)
+}
+
+fn print_report(report: &BuildReport) {
+    for (kind, stats) in [("tag", &report.tags), ("post", &report.posts)] {
+        if stats.failed == 0 {
+            continue;
+        }
+        println!(
+            "Skipped {} {kind} lines which couldn't be parsed:",
+            stats.failed
+        );
+        for failure in &stats.failures {
+            println!("  line {}: {}", failure.line, failure.error);
+        }
+        if stats.failed > stats.failures.len() as u64 {
+            println!("  ...");
+        }
+    }
+    if report.posts.incomplete > 0 {
+        println!(
+            "{} posts were indexed without {} tags missing from the tags file",
+            report.posts.incomplete, report.posts.unknown_tags
+        );
+    }
+    let phases: Vec<String> = report
+        .phases
+        .iter()
+        .map(|phase| format!("{} {}ms", phase.name, phase.duration_ms))
+        .collect();
+    println!("Phases: {}", phases.join(", "));
+}
+
+/// Build or update the index and save it, also used by the scheduled rebuilds of `daemon`
+#[tracing::instrument(skip_all, fields(incremental = args.incremental))]
+pub fn build_index(
+    args: BuildArgs,
+    config: Config,
+    progress: Progress,
+) -> Result<BuildOutput, Box<dyn std::error::Error>> {
+    let posts = args.posts.unwrap_or(config.output.posts);
+    let tags = args.tags.unwrap_or(config.output.tags);
+    let pools = args.pools.unwrap_or(config.output.pools);
+    let notes = args.notes.unwrap_or(config.output.notes);
+    let hashes = args.hashes.unwrap_or(config.output.hashes);
+    let inventory = args.inventory.unwrap_or(config.output.inventory);
+    let path = args.out.unwrap_or(config.index.path);
+    let post_fields: PostFields = args.keep.unwrap_or(config.index.keep).into_iter().collect();
+    let histogram_tags = args.histogram_tags.unwrap_or(config.index.histogram_tags);
+    let bloom_tags = args.bloom_tags.unwrap_or(config.index.bloom_tags);
+
+    let start = std::time::Instant::now();
+    let mut posts_file = File::open(&posts)?;
+    let mut tags_file = File::open(&tags)?;
+    let posts_len = posts_file.metadata()?.len();
+    let tags_len = tags_file.metadata()?.len();
+
+    // Compressed files can't be read from the middle, they are always indexed from the start
+    let compressed =
+        Compression::of(&posts) != Compression::None || Compression::of(&tags) != Compression::None;
+    let mut index = Index::with_post_fields(post_fields);
+    if args.incremental && compressed {
+        warn!("Compressed inputs can't be indexed incrementally, rebuilding the index");
+    } else if args.incremental && path.exists() {
+        let saved = Index::load(&path)?;
+        // Files which are shorter than the watermark have been rewritten, e.g. by compaction
+        if saved.watermark.posts > posts_len || saved.watermark.tags > tags_len {
+            warn!(
+                "The output files were rewritten since {} was built, rebuilding it",
+                path.display()
+            );
+        } else if saved.post_fields != post_fields {
+            warn!(
+                "{} keeps different post fields, rebuilding it",
+                path.display()
+            );
+        } else {
+            index = saved;
+        }
+    }
+    let mut report = BuildReport::default();
+    let incremental = index.watermark.posts > 0 || index.watermark.tags > 0;
+
+    posts_file.seek(SeekFrom::Start(index.watermark.posts))?;
+    tags_file.seek(SeekFrom::Start(index.watermark.tags))?;
+    let bar = progress.bar(
+        posts_len - index.watermark.posts + tags_len - index.watermark.tags,
+        "{spinner} {bar:30} {bytes}/{total_bytes} ({bytes_per_sec}, eta {eta})",
+    );
+    // Tags first, posts are only linked to tags which are already known
+    report.tags = report.timed("tags", || {
+        index.ingest_tags(input::decode(
+            bar.wrap_read(tags_file),
+            Compression::of(&tags),
+        )?)
+    })?;
+    report.posts = report.timed("posts", || {
+        index.ingest_posts(input::decode(
+            bar.wrap_read(posts_file),
+            Compression::of(&posts),
+        )?)
+    })?;
+    bar.finish_and_clear();
+    if report.failed() > 0 {
+        warn!(
+            "Skipped {} tag lines and {} post lines which couldn't be parsed",
+            report.tags.failed, report.posts.failed
+        );
+    }
+    report.timed("histograms", || {
+        index.update_score_histograms(histogram_tags)
+    });
+    report.timed("bloom filters", || index.update_bloom_filters(bloom_tags));
+    if pools.exists() {
+        report.timed("pools", || index.ingest_pools(input::open(&pools)?))?;
+    }
+    if notes.exists() {
+        report.timed("notes", || index.ingest_notes(input::open(&notes)?))?;
+    }
+    if hashes.exists() {
+        report.timed("hashes", || index.ingest_hashes(input::open(&hashes)?))?;
+    }
+    if inventory.exists() {
+        report.timed("inventory", || {
+            Ok::<_, Box<dyn std::error::Error>>(
+                index.apply_inventory(&Inventory::load(&inventory)?),
+            )
+        })?;
+    }
+    let segment = index.finish_segment(Utc::now());
+    report.timed("save", || index.save(&path))?;
+
+    Ok(BuildOutput {
+        posts: index.post_id_to_post.len(),
+        tags: index.tag_str_to_id.len(),
+        pools: index.pool_order.len(),
+        note_words: index
+            .note_to_post_id
+            .keys()
+            .filter(|key| !key.contains(' '))
+            .count(),
+        histogram_tags: index.score_histograms.tags.len(),
+        bloom_tags: index.tag_id_to_bloom.len(),
+        hashes: index.perceptual_hashes.len(),
+        downloaded: index.downloaded.len(),
+        incremental,
+        segment,
+        post_lines: report.posts.lines,
+        tag_lines: report.tags.lines,
+        report,
+        path,
+        duration_ms: start.elapsed().as_millis(),
+    })
+}
+
+async fn rename_tags(
+    args: RenameTagsArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let path = args.index.path(&config).clone();
+    let mut index = Index::load(&path)?;
+    let client = api_client(&config)?;
+
+    let report =
+        reconcile_tag_names(&client, &mut index, config.scraper.requests_per_second).await?;
+    if !args.dry_run && !report.renames.is_empty() {
+        index.save(&path)?;
+        let tags = args.tags.unwrap_or(config.output.tags);
+        let mut output = open_output(&tags);
+        for tag in report.tags.iter().cloned() {
+            output.write(tag)?;
+        }
+        Sink::<Tag>::flush(&mut output)?;
+    }
+
+    format.print(&report, |report| {
+        for rename in &report.renames {
+            println!("{}\t{}\t{}", rename.id, rename.from, rename.to);
+        }
+        eprintln!(
+            "Checked {} tags, {} were renamed",
+            report.checked,
+            report.renames.len()
+        );
+    });
+    Ok(Status::Success)
+}
+
+fn clusters(
+    args: ClustersArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let path = args.index.path(&config).clone();
+    let mut index = Index::load(&path)?;
+    let options = ClusterOptions {
+        vocabulary: args.vocabulary,
+        min_count: args.min_count,
+        min_similarity: args.min_similarity,
+        min_size: args.min_size,
+    };
+
+    let start = std::time::Instant::now();
+    index.update_tag_clusters(options);
+    index.save(&path)?;
+
+    let clusters = index.tag_clusters();
+    let output = ClustersOutput {
+        count: clusters.len(),
+        clusters: clusters.iter().take(args.show).cloned().collect(),
+        duration_ms: start.elapsed().as_millis(),
+    };
+    format.print(&output, |output| {
+        for cluster in &output.clusters {
+            println!(
+                "{} posts, {} tags: {}",
+                cluster.posts,
+                cluster.tags.len(),
+                cluster.tags.join(" ")
+            );
+        }
+        eprintln!(
+            "Found {} clusters in {}ms, saved to {}",
+            output.count,
+            output.duration_ms,
+            path.display()
+        );
+    });
+    Ok(Status::Success)
+}
+
+fn segments(
+    args: SegmentsArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let index = args.index.load(&config)?;
+    format.print(&index.segments, |segments| {
+        for segment in segments {
+            println!(
+                "{}\t{}\t{} posts\tposts file up to byte {}",
+                segment.id,
+                segment.built_at.format("%Y-%m-%d %H:%M:%S"),
+                segment.posts,
+                segment.watermark.posts
+            );
+        }
+        if segments.is_empty() {
+            eprintln!("The index has no segments, it was built before they were recorded");
+        }
+    });
+    Ok(Status::Success)
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    index::Index,
+    inventory::{Inventory, ScanStats},
+};
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct InventoryArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Downloads directory to scan, subdirectories included
+    #[arg(long, default_value = "files")]
+    pub dir: PathBuf,
+
+    /// Where the inventory is saved, defaults to `output.inventory` from the config
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+/// Result of `inventory`
+#[derive(Debug, Serialize)]
+pub struct InventoryOutput {
+    #[serde(flatten)]
+    pub scan: ScanStats,
+    pub path: PathBuf,
+    /// Indexed posts whose file is present, `None` without an index
+    pub downloaded: Option<u64>,
+    /// Indexed posts whose file is missing
+    pub missing: Option<u64>,
+    /// Files which don't belong to any indexed post
+    pub orphans: Option<u64>,
+}
+
+pub fn run(
+    args: InventoryArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let path = args.out.unwrap_or(config.output.inventory.clone());
+    let (inventory, scan) = Inventory::scan(&args.dir)?;
+    inventory.save(&path)?;
+
+    let mut output = InventoryOutput {
+        scan,
+        path,
+        downloaded: None,
+        missing: None,
+        orphans: None,
+    };
+    let index_path = args.index.path(&config);
+    if index_path.exists() {
+        let mut index = Index::load(index_path)?;
+        let downloaded = index.apply_inventory(&inventory);
+        output.downloaded = Some(downloaded);
+        output.missing = Some(index.post_id_to_post.len() as u64 - downloaded);
+        output.orphans = Some(inventory.orphans(&index).len() as u64);
+        index.save(index_path)?;
+    }
+
+    format.print(&output, |output| {
+        println!(
+            "Recorded {} files ({}) in {}",
+            output.scan.files,
+            HumanBytes(output.scan.bytes),
+            output.path.display()
+        );
+        if output.scan.skipped > 0 {
+            println!("Skipped {} files not named by an md5", output.scan.skipped);
+        }
+        if let (Some(downloaded), Some(missing), Some(orphans)) =
+            (output.downloaded, output.missing, output.orphans)
+        {
+            println!(
+                "{} indexed posts downloaded, {} missing, {} files without an indexed post",
+                downloaded, missing, orphans
+            );
+        }
+    });
+    Ok(Status::Success)
+}
@@ -0,0 +1,83 @@
+use std::{fs::File, path::PathBuf};
+
+use clap::Args;
+use indexer::maintenance::merge::{merge_posts, merge_states, merge_tags, MergeStats};
+use serde::Serialize;
+
+use super::{
+    convert::Kind,
+    output::{Format, Status},
+};
+
+#[derive(Debug, Args)]
+pub struct MergeArgs {
+    /// JSON lines files written by `scrape`, e.g. on different machines
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Merged output file, may be one of the inputs
+    #[arg(long, short)]
+    pub out: PathBuf,
+
+    /// Whether the inputs hold posts or tags
+    #[arg(long, value_enum, default_value_t = Kind::Posts)]
+    pub kind: Kind,
+
+    /// State file to merge, can be given several times
+    #[arg(long = "state", requires = "state_out")]
+    pub states: Vec<PathBuf>,
+
+    /// Where the merged state is written
+    #[arg(long, requires = "states")]
+    pub state_out: Option<PathBuf>,
+}
+
+/// Result of `merge`
+#[derive(Debug, Serialize)]
+pub struct MergeOutput {
+    pub kind: Kind,
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub stats: MergeStats,
+    pub state: Option<PathBuf>,
+}
+
+pub fn run(args: MergeArgs, format: Format) -> Result<Status, Box<dyn std::error::Error>> {
+    let stats = match args.kind {
+        Kind::Posts => merge_posts(&args.inputs, &args.out)?,
+        Kind::Tags => merge_tags(&args.inputs, &args.out)?,
+    };
+
+    if let Some(state_out) = &args.state_out {
+        let state = merge_states(&args.states)?;
+        serde_json::to_writer(File::create(state_out)?, &state)?;
+    }
+
+    let output = MergeOutput {
+        kind: args.kind,
+        path: args.out,
+        stats,
+        state: args.state_out,
+    };
+    format.print(&output, |output| {
+        println!(
+            "merged {} lines into {} {} in {}, dropped {} duplicates",
+            output.stats.lines,
+            output.stats.kept,
+            output.kind.as_str(),
+            output.path.display(),
+            output.stats.duplicates
+        );
+        if output.stats.malformed > 0 {
+            println!("skipped {} malformed lines", output.stats.malformed);
+        }
+        if let Some(state) = &output.state {
+            println!("merged state written to {}", state.display());
+        }
+    });
+
+    match stats.malformed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
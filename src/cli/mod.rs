@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use indexer::{
+    config::{Config, CONFIG_FILE},
+    index::Index,
+};
+use output::Format;
+
+pub mod audit;
+pub mod bench;
+pub mod complete;
+pub mod convert;
+pub mod daemon;
+pub mod dataset;
+pub mod download;
+pub mod duplicates;
+pub mod embed;
+pub mod enrich;
+pub mod index;
+pub mod inventory;
+pub mod merge;
+pub mod output;
+pub mod pack;
+pub mod progress;
+pub mod query;
+pub mod repair;
+pub mod repl;
+pub mod scrape;
+pub mod serve;
+pub mod sheet;
+pub mod snapshot;
+pub mod stats;
+pub mod sync;
+pub mod sync_to;
+pub mod systemd;
+pub mod tag_drift;
+pub mod telemetry;
+pub mod unpack;
+pub mod uploaders;
+pub mod verify;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "indexer",
+    version,
+    about = "Scrape posts and tags and query them with a bitmap index"
+)]
+pub struct Cli {
+    /// Settings file, see `indexer::config` for the format
+    #[arg(long, global = true, env = "INDEXER_CONFIG", default_value = CONFIG_FILE)]
+    pub config: PathBuf,
+
+    /// Use the named profile from the config, e.g. one per site
+    #[arg(long, global = true, env = "INDEXER_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Output format, `json` prints a single JSON document for scripts
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// Also write logs to rotating files in this directory, see `[log]` in the config
+    #[arg(long, global = true)]
+    pub log_dir: Option<PathBuf>,
+
+    /// Don't show progress bars, they are also hidden if stderr isn't a terminal
+    #[arg(long, global = true)]
+    pub no_progress: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Scrape posts and tags into JSON lines files
+    Scrape(scrape::ScrapeArgs),
+    /// Build and manage the index
+    #[command(subcommand)]
+    Index(index::IndexCommand),
+    /// Find the posts matching a query
+    Query(query::QueryArgs),
+    /// Convert a posts or tags file into another format
+    Convert(convert::ConvertArgs),
+    /// Download the files of the posts matching a query
+    Download(download::DownloadArgs),
+    /// Compose a contact sheet of the previews of a sample of the posts matching a query
+    Sheet(sheet::SheetArgs),
+    /// Write train, validation and test manifests of the posts matching a query
+    Dataset(dataset::DatasetArgs),
+    /// Group the downloaded posts whose images look the same but have different md5s
+    Duplicates(duplicates::DuplicatesArgs),
+    /// Compute tag vectors from the co-occurrence of tags, in the word2vec text format
+    Embed(embed::EmbedArgs),
+    /// Record which posts have their file in the downloads directory, for `file:` queries
+    Inventory(inventory::InventoryArgs),
+    /// Add the types of their tags to the scraped posts, from the tags file
+    Enrich(enrich::EnrichArgs),
+    /// Combine the posts or tags files of several scrapes, keeping the newest record per id
+    Merge(merge::MergeArgs),
+    /// Measure the latency and allocations of a workload of queries
+    Bench(bench::BenchArgs),
+    /// Serve search requests over HTTP from an index held in memory
+    Serve(serve::ServeArgs),
+    /// Scrape continuously while serving the index, with a live feed of the new posts
+    Daemon(daemon::DaemonArgs),
+    /// Print an overview of the scraped dataset
+    Stats(stats::StatsArgs),
+    /// Explore an index interactively
+    Repl(repl::ReplArgs),
+    /// Bring the local posts and the index in line with the site, writing edits and deletions
+    Sync(sync::SyncArgs),
+    /// Send the new and changed files of the dataset to another copy of it
+    SyncTo(sync_to::SyncToArgs),
+    /// Retry the post ranges and tag pages which failed during earlier scrapes
+    Repair,
+    /// Check the output files for malformed lines, duplicates, gaps and a stale state file
+    Verify(verify::VerifyArgs),
+    /// Compare the tags files of two scrapes: new, deleted, renamed and retyped tags and large
+    /// count changes
+    TagDrift(tag_drift::TagDriftArgs),
+    /// Find posts whose rating contradicts their tags, per the rules of `[[audit.ratings]]`
+    Audit(audit::AuditArgs),
+    /// Bundle the index, the tags and optionally the scraped files into a single `.booru` file
+    Pack(pack::PackArgs),
+    /// Extract the files of a `.booru` bundle
+    Unpack(unpack::UnpackArgs),
+    /// Freeze query results under a name, so later `snapshot:name` queries match the same posts
+    #[command(subcommand)]
+    Snapshot(snapshot::SnapshotCommand),
+    /// List the uploader accounts with the most posts, optionally of the posts matching a query
+    Uploaders(uploaders::UploadersArgs),
+}
+
+/// Arguments shared by every command reading a saved index
+#[derive(Debug, clap::Args)]
+pub struct IndexArgs {
+    /// Path of the saved index, defaults to `index.path` from the config
+    #[arg(long)]
+    pub index: Option<PathBuf>,
+}
+
+impl IndexArgs {
+    pub fn path<'a>(&'a self, config: &'a Config) -> &'a PathBuf {
+        self.index.as_ref().unwrap_or(&config.index.path)
+    }
+
+    /// Load the index, from a bundle written by `pack` if the path ends in `.booru`
+    pub fn load(&self, config: &Config) -> Result<Index, Box<dyn std::error::Error>> {
+        let path = self.path(config);
+        match path
+            .extension()
+            .is_some_and(|extension| extension == "booru")
+        {
+            true => Index::open_bundle(path),
+            false => Index::load(path),
+        }
+    }
+}
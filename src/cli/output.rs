@@ -0,0 +1,57 @@
+//! Output formats and exit codes shared by every command
+
+use std::process::ExitCode;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Human readable output
+    Text,
+    /// A single JSON document on stdout, logs stay on stderr
+    Json,
+}
+
+/// How a command finished, when it didn't fail outright
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    /// The command finished, but some of the work failed, e.g. scrape errors or verify problems
+    Partial,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        match status {
+            Status::Success => ExitCode::SUCCESS,
+            Status::Partial => ExitCode::from(PARTIAL),
+        }
+    }
+}
+
+/// Exit code of a command that failed
+pub const FAILURE: u8 = 1;
+/// Exit code of a command that only partially succeeded
+pub const PARTIAL: u8 = 2;
+
+impl Format {
+    /// Print `value` as JSON, or call `text` to print it for humans
+    pub fn print<T: Serialize>(&self, value: &T, text: impl FnOnce(&T)) {
+        match self {
+            Format::Text => text(value),
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string(value).expect("Failed to serialize output")
+            ),
+        }
+    }
+
+    /// Report an error which stopped the command
+    pub fn print_error(&self, error: &dyn std::error::Error) {
+        match self {
+            Format::Text => eprintln!("Error: {}", error),
+            Format::Json => println!("{}", serde_json::json!({ "error": error.to_string() })),
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    maintenance::bundle::{self, manifest_entry, BundleEntry, BundleWriter, Compression},
+};
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct PackArgs {
+    /// Where the bundle is written, e.g. `dataset.booru`
+    pub out: PathBuf,
+
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Also bundle the posts, pools and notes files from `[output]`, those which exist
+    #[arg(long)]
+    pub sources: bool,
+
+    /// Compress the files with zstd, requires the `zstd` feature
+    #[arg(long)]
+    pub compress: bool,
+}
+
+/// Result of `pack`
+#[derive(Debug, Serialize)]
+pub struct PackOutput {
+    pub path: PathBuf,
+    pub entries: Vec<BundleEntry>,
+    /// Size of the bundle
+    pub bytes: u64,
+}
+
+pub fn run(
+    args: PackArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let compression = match args.compress {
+        true => Compression::Zstd,
+        false => Compression::None,
+    };
+
+    let index = args.index.path(&config).clone();
+    let mut files = vec![(bundle::TAGS, config.output.tags)];
+    if args.sources {
+        let sources = [
+            (bundle::POSTS, config.output.posts),
+            (bundle::POOLS, config.output.pools),
+            (bundle::NOTES, config.output.notes),
+        ];
+        files.extend(sources.into_iter().filter(|(_, path)| path.exists()));
+    }
+
+    let mut writer = BundleWriter::create(&args.out)?;
+    writer.add_file(bundle::INDEX, &index, compression)?;
+    for (name, path) in &files {
+        writer.add_file(name, path, compression)?;
+    }
+    let manifest = manifest_entry(&files)?;
+    writer.add_reader(bundle::MANIFEST, manifest.as_slice(), Compression::None)?;
+    let contents = writer.finish()?;
+
+    let output = PackOutput {
+        bytes: args.out.metadata()?.len(),
+        path: args.out,
+        entries: contents.entries,
+    };
+    format.print(&output, |output| {
+        for entry in &output.entries {
+            println!(
+                "{}\t{} bytes\t{} stored",
+                entry.name, entry.bytes, entry.stored_bytes
+            );
+        }
+        println!(
+            "Packed {} files into {} ({} bytes)",
+            output.entries.len(),
+            output.path.display(),
+            output.bytes
+        );
+    });
+    Ok(Status::Success)
+}
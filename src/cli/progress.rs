@@ -0,0 +1,74 @@
+//! Progress bars on stderr
+//!
+//! The bars are hidden with `--no-progress` and whenever stderr isn't a terminal, so logs
+//! redirected to a file or read by another program stay clean.
+
+use std::time::Duration;
+
+use indexer::sink::{Sink, SinkError};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    multi: MultiProgress,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Self {
+        let target = match enabled {
+            true => ProgressDrawTarget::stderr(),
+            false => ProgressDrawTarget::hidden(),
+        };
+        Self {
+            multi: MultiProgress::with_draw_target(target),
+        }
+    }
+
+    /// Whether nothing will be drawn, e.g. to skip requests only needed for a bar
+    pub fn is_hidden(&self) -> bool {
+        self.multi.is_hidden()
+    }
+
+    /// A bar for `len` units, see [`ProgressStyle::with_template`] for the template syntax
+    pub fn bar(&self, len: u64, template: &str) -> ProgressBar {
+        let bar = ProgressBar::new(len).with_style(
+            ProgressStyle::with_template(template)
+                .expect("Invalid progress template")
+                .progress_chars("=> "),
+        );
+        self.multi.add(bar)
+    }
+
+    /// A counter for work of unknown size
+    pub fn spinner(&self, template: &str) -> ProgressBar {
+        let spinner = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::with_template(template).expect("Invalid progress template"));
+        let spinner = self.multi.add(spinner);
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        spinner
+    }
+}
+
+/// Advances a progress bar for every record written to the wrapped sink
+pub struct ProgressSink<S> {
+    inner: S,
+    bar: ProgressBar,
+}
+
+impl<S> ProgressSink<S> {
+    pub fn new(inner: S, bar: ProgressBar) -> Self {
+        Self { inner, bar }
+    }
+}
+
+impl<T, S: Sink<T>> Sink<T> for ProgressSink<S> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.inner.write(record)?;
+        self.bar.inc(1);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.inner.flush()
+    }
+}
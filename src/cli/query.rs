@@ -0,0 +1,301 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use indexer::{
+    config::Config,
+    index::{stream_posts, Index},
+    models::{Post, PostSimplified},
+    query::{Query, Term},
+    sink::{json::JsonLinesSink, Sink},
+};
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use super::{
+    complete,
+    convert::{post_sink, Target},
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Only print the first `limit` results
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Only print the number of results
+    #[arg(long)]
+    pub count: bool,
+
+    /// Write the full records of the matching posts to this file instead of printing them, as
+    /// CSV, JSON lines or Parquet by the extension (`.csv`, `.jsonl` or `.parquet`)
+    #[arg(long, conflicts_with = "count")]
+    pub export: Option<PathBuf>,
+
+    /// Read the records for `--export` from a database written by the SQLite sink instead of the
+    /// posts file. Requires the `sqlite` feature.
+    #[arg(long, requires = "export")]
+    pub sqlite: Option<PathBuf>,
+
+    /// Query the index as it was when this segment was built (see `index segments`), rebuilt
+    /// from the beginning of the posts and tags files
+    #[arg(long, value_name = "SEGMENT")]
+    pub at: Option<u32>,
+
+    /// The query, e.g. `cat -dog ~red ~blue`, options have to come before it
+    #[arg(
+        required = true,
+        allow_hyphen_values = true,
+        add = ArgValueCompleter::new(complete::query_terms)
+    )]
+    pub query: Vec<String>,
+}
+
+/// A query result as printed by the json format
+#[derive(Debug, Serialize)]
+pub struct QueryResult {
+    pub id: u32,
+    pub md5: String,
+    pub extension: String,
+    pub created_at: DateTime<Utc>,
+    /// Only set if the index keeps the field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<u64>,
+}
+
+impl From<&PostSimplified> for QueryResult {
+    fn from(post: &PostSimplified) -> Self {
+        let details = post.details.as_deref().cloned().unwrap_or_default();
+        Self {
+            id: post.id,
+            md5: hex::encode(post.md5),
+            extension: post.extension.as_str().to_string(),
+            created_at: post.created_at,
+            score: details.score,
+            rating: details.rating.map(|rating| rating.as_str().to_string()),
+            width: details.width,
+            height: details.height,
+            parent_id: details.parent_id,
+        }
+    }
+}
+
+/// Result of `query`, `results` is left out with `--count` and `--export`
+#[derive(Debug, Serialize)]
+pub struct QueryOutput {
+    pub query: String,
+    pub count: u64,
+    pub duration_us: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<QueryResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub export: Option<ExportOutput>,
+    /// Corrections of the unknown tags of a query without results
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub did_you_mean: Vec<DidYouMean>,
+}
+
+/// Tags close to an unknown tag of the query, see [`Index::did_you_mean`]
+#[derive(Debug, Serialize)]
+pub struct DidYouMean {
+    pub tag: String,
+    pub corrections: Vec<String>,
+}
+
+/// Corrections shown per unknown tag
+const CORRECTIONS: usize = 5;
+
+/// Where `--export` wrote the records
+#[derive(Debug, Serialize)]
+pub struct ExportOutput {
+    pub path: PathBuf,
+    /// Posts written, matching posts missing from the posts file or database are left out
+    pub posts: u64,
+}
+
+pub fn run(
+    args: QueryArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let mut index = args.index.load(&config)?;
+    if let Some(segment) = args.at {
+        index = index.at(segment, &config.output.posts, &config.output.tags)?;
+    }
+    let query = Query::parse(&args.query.join(" "))?;
+
+    let start = std::time::Instant::now();
+    let results = index.search(&query);
+    let duration = start.elapsed();
+
+    if let Some(path) = args.export {
+        let post_ids: RoaringBitmap = match args.limit {
+            Some(limit) => index.ordered(&query, &results, None).take(limit).collect(),
+            None => results.clone(),
+        };
+        drop(index);
+        let posts = export(&post_ids, &path, args.sqlite.as_deref(), &config)?;
+
+        let output = QueryOutput {
+            query: query.to_string(),
+            count: results.len(),
+            duration_us: duration.as_micros(),
+            results: None,
+            export: Some(ExportOutput { path, posts }),
+            did_you_mean: Vec::new(),
+        };
+        format.print(&output, |output| {
+            if let Some(export) = &output.export {
+                println!(
+                    "Exported {} of {} matching posts to {}",
+                    export.posts,
+                    output.count,
+                    export.path.display()
+                );
+            }
+        });
+        return Ok(Status::Success);
+    }
+
+    let output = QueryOutput {
+        query: query.to_string(),
+        count: results.len(),
+        duration_us: duration.as_micros(),
+        export: None,
+        did_you_mean: match results.is_empty() {
+            true => did_you_mean(&index, &query),
+            false => Vec::new(),
+        },
+        results: (!args.count).then(|| {
+            index
+                .ordered(&query, &results, None)
+                .take(args.limit.unwrap_or(usize::MAX))
+                .filter_map(|id| index.post_id_to_post.get(&id))
+                .map(QueryResult::from)
+                .collect()
+        }),
+    };
+
+    format.print(&output, |output| {
+        match &output.results {
+            Some(results) => results.iter().for_each(print_result),
+            None => println!("{}", output.count),
+        }
+        eprintln!("{} results in {:?}", output.count, duration);
+        for suggestion in &output.did_you_mean {
+            eprintln!(
+                "No tag `{}`, did you mean {}?",
+                suggestion.tag,
+                suggestion.corrections.join(", ")
+            );
+        }
+    });
+    Ok(Status::Success)
+}
+
+/// Corrections of the tags of `query` the index doesn't know, excluded tags can't empty a result
+fn did_you_mean(index: &Index, query: &Query) -> Vec<DidYouMean> {
+    query
+        .include
+        .iter()
+        .chain(&query.any)
+        .filter_map(|term| match term {
+            Term::Tag(tag) | Term::TypedTag(_, tag) if index.tag_id(tag).is_none() => Some(tag),
+            _ => None,
+        })
+        .filter_map(|tag| {
+            let corrections: Vec<String> = index
+                .did_you_mean(tag)
+                .into_iter()
+                .take(CORRECTIONS)
+                .map(|correction| correction.name)
+                .collect();
+            (!corrections.is_empty()).then(|| DidYouMean {
+                tag: tag.clone(),
+                corrections,
+            })
+        })
+        .collect()
+}
+
+/// Write the full records of `post_ids` to `path`, read from the posts file or a SQLite database
+fn export(
+    post_ids: &RoaringBitmap,
+    path: &Path,
+    sqlite: Option<&Path>,
+    config: &Config,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let mut sink: Box<dyn Sink<Post>> = match extension {
+        "jsonl" | "json" => Box::new(JsonLinesSink::new(BufWriter::new(File::create(path)?))),
+        "csv" => post_sink(Target::Csv, path)?,
+        "parquet" => post_sink(Target::Parquet, path)?,
+        _ => {
+            return Err(format!(
+                "unknown export format `{extension}`, expected csv, jsonl or parquet"
+            )
+            .into())
+        }
+    };
+
+    let posts = match sqlite {
+        #[cfg(feature = "sqlite")]
+        Some(database) => {
+            let conn = rusqlite::Connection::open(database)?;
+            let (mut posts, mut error) = (0, None);
+            indexer::sink::sqlite::for_each_post(&conn, |post| {
+                let wanted = u32::try_from(post.id).is_ok_and(|id| post_ids.contains(id));
+                if wanted && error.is_none() {
+                    match sink.write(post) {
+                        Ok(()) => posts += 1,
+                        Err(e) => error = Some(e),
+                    }
+                }
+            })?;
+            if let Some(e) = error {
+                return Err(e.into());
+            }
+            posts
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some(_) => return Err("exporting from a database requires the `sqlite` feature".into()),
+        None => {
+            let reader = BufReader::new(File::open(&config.output.posts)?);
+            stream_posts(reader, post_ids, |post| sink.write(post))?
+        }
+    };
+    sink.flush()?;
+    Ok(posts)
+}
+
+fn print_result(result: &QueryResult) {
+    println!(
+        "{}\t{}.{}\t{}",
+        result.id,
+        result.md5,
+        result.extension,
+        result.created_at.to_rfc3339()
+    );
+}
+
+pub fn print_post(post: &PostSimplified) {
+    print_result(&QueryResult::from(post));
+}
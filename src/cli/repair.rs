@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use super::{
+    output::{Format, Status},
+    scrape::{api_client, create_client, open_output},
+};
+use indexer::{
+    config::Config,
+    scraper::{
+        blacklist::Blacklist, processor::Pipeline, repair::Repairer, state_manager::StateManager,
+    },
+    sink::writer::spawn_writer,
+};
+
+pub async fn run(config: Config, format: Format) -> Result<Status, Box<dyn std::error::Error>> {
+    let output = &config.output;
+    let capacity = config.scraper.channel_capacity;
+    let (post_output, post_writer) = spawn_writer(open_output(&output.posts), capacity);
+    let (tag_output, tag_writer) = spawn_writer(open_output(&output.tags), capacity);
+
+    let state_path = output.state.to_string_lossy().to_string();
+    let state_manager = StateManager::new(&state_path).expect("Failed to load state file");
+    let repairer = Repairer::new(
+        post_output,
+        tag_output,
+        state_manager.clone(),
+        api_client(&config)?.with_throttle(state_manager.throttle()),
+    )
+    .with_blacklist(Arc::new(Blacklist::new(&config.scraper.blacklist)))
+    .with_pipeline(Arc::new(Pipeline::from_config(
+        &config.scraper.processors,
+        create_client(),
+    )));
+
+    let stats = repairer.run().await;
+    drop(repairer);
+
+    // Only drop the resolved errors once the recovered records are written
+    post_writer.await??;
+    tag_writer.await??;
+    state_manager.save_state(&state_path).await?;
+
+    format.print(&stats, |stats| {
+        println!(
+            "Resolved {} errors ({} posts, {} tags), {} still failing",
+            stats.resolved, stats.posts, stats.tags, stats.failed
+        )
+    });
+    match stats.failed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
@@ -0,0 +1,189 @@
+use clap::Args;
+use indexer::{config::Config, index::Index, query::Query};
+use roaring::RoaringBitmap;
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+
+use super::{output::Status, query::print_post, IndexArgs};
+
+const HELP: &str = "\
+<query>          run a query, e.g. `cat -dog ~red ~blue`
+<enter>          show the next page of the last query
+:count <query>   only count the results
+:explain <query> show how the query is evaluated
+:page <size>     set the number of results per page
+:help            show this help
+:quit            exit";
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Number of results shown per page
+    #[arg(long, default_value_t = 20)]
+    pub page_size: usize,
+}
+
+/// Completes tag names from the index, most used tags first
+struct TagCompleter<'a> {
+    index: &'a Index,
+}
+
+impl Completer for TagCompleter<'_> {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        if word.starts_with(':') {
+            return Ok((start, Vec::new()));
+        }
+
+        // Keep the `-`/`~` operator in front of the completed tag
+        let operator = word.len() - word.trim_start_matches(['-', '~']).len();
+        let candidates = self
+            .index
+            .suggest_tags(&word[operator..], 50)
+            .into_iter()
+            .map(|(tag, freq)| Pair {
+                display: format!("{} ({})", tag, freq),
+                replacement: format!("{}{}", &word[..operator], tag),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for TagCompleter<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for TagCompleter<'_> {}
+
+impl Validator for TagCompleter<'_> {}
+
+impl Helper for TagCompleter<'_> {}
+
+/// The results of the last query which haven't been shown yet
+struct Pages {
+    results: Vec<u32>,
+    shown: usize,
+}
+
+pub fn run(args: ReplArgs, config: Config) -> Result<Status, Box<dyn std::error::Error>> {
+    let index = args.index.load(&config)?;
+    let mut page_size = args.page_size.max(1);
+    println!(
+        "Loaded {} posts and {} tags, type :help for help",
+        index.post_id_to_post.len(),
+        index.tag_str_to_id.len()
+    );
+
+    let mut editor = Editor::new()?;
+    editor.set_helper(Some(TagCompleter { index: &index }));
+    let mut pages: Option<Pages> = None;
+
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if !line.is_empty() {
+            editor.add_history_entry(line)?;
+        }
+
+        let (command, rest) = match line.strip_prefix(':') {
+            Some(command) => command.split_once(' ').unwrap_or((command, "")),
+            None => ("", line),
+        };
+
+        match command {
+            "" if rest.is_empty() => match &mut pages {
+                Some(pages) => print_page(&index, pages, page_size),
+                None => println!("No query to page through"),
+            },
+            "" => {
+                let Some((query, results, duration)) = search(&index, rest) else {
+                    continue;
+                };
+                println!("{} results in {:?}", results.len(), duration);
+                let mut next = Pages {
+                    results: index.ordered(&query, &results, None).collect(),
+                    shown: 0,
+                };
+                print_page(&index, &mut next, page_size);
+                pages = Some(next);
+            }
+            "count" => {
+                if let Some((_, results, duration)) = search(&index, rest) {
+                    println!("{} results in {:?}", results.len(), duration);
+                }
+            }
+            "explain" => match Query::parse(rest) {
+                Ok(query) => {
+                    for (step, remaining) in index.explain(&query) {
+                        println!("{:<60}-> {}", step, remaining);
+                    }
+                }
+                Err(e) => println!("{}", e),
+            },
+            "page" => match rest.trim().parse::<usize>() {
+                Ok(size) if size > 0 => page_size = size,
+                _ => println!("Expected a page size above 0"),
+            },
+            "help" => println!("{}", HELP),
+            "quit" | "q" | "exit" => break,
+            _ => println!("Unknown command `:{}`, type :help for help", command),
+        }
+    }
+
+    Ok(Status::Success)
+}
+
+fn search(index: &Index, query: &str) -> Option<(Query, RoaringBitmap, std::time::Duration)> {
+    let query = match Query::parse(query) {
+        Ok(query) => query,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let results = index.search(&query);
+    let duration = start.elapsed();
+    Some((query, results, duration))
+}
+
+fn print_page(index: &Index, pages: &mut Pages, page_size: usize) {
+    let end = (pages.shown + page_size).min(pages.results.len());
+    for id in &pages.results[pages.shown..end] {
+        if let Some(post) = index.post_id_to_post.get(id) {
+            print_post(post);
+        }
+    }
+    pages.shown = end;
+
+    if end < pages.results.len() {
+        println!(
+            "-- {} of {}, press enter for more --",
+            end,
+            pages.results.len()
+        );
+    }
+}
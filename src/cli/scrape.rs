@@ -0,0 +1,432 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::Args;
+use indexer::{
+    api::{client::ApiClient, utils::DateFormats},
+    config::{Config, ConfigError, OutputConfig, ScraperConfig},
+    maintenance::{
+        enrich::{load_tag_types, EnrichSink},
+        manifest::Manifest,
+    },
+    models::Post,
+    scraper::{
+        blacklist::{Blacklist, BlacklistStats},
+        plan::ScrapePlan,
+        post_scraper::PostScraper,
+        processor::{Pipeline, PipelineStats},
+        seen::SeenPosts,
+        state_manager::StateManager,
+        tag_scraper::TagScraper,
+    },
+    sink::{json::JsonLinesSink, writer::spawn_writer, Sink},
+};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE, USER_AGENT};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use super::{
+    output::{Format, Status},
+    progress::{Progress, ProgressSink},
+    systemd,
+};
+
+#[derive(Debug, Args)]
+pub struct ScrapeArgs {
+    /// Scraped posts are appended to this file
+    #[arg(long)]
+    pub posts: Option<PathBuf>,
+
+    /// Scraped tags are appended to this file
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+
+    /// Progress is saved to and resumed from this file
+    #[arg(long)]
+    pub state: Option<PathBuf>,
+
+    /// A manifest of the output files is written here after the run
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Check the config and credentials with a single request and print the plan, without
+    /// writing any output or state
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Create a reqwest client with the necessary headers
+pub fn create_client() -> reqwest::Client {
+    let mut headers = HeaderMap::default();
+    headers.insert(USER_AGENT, HeaderValue::from_str("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36").unwrap());
+    headers.insert(
+        ACCEPT_LANGUAGE,
+        HeaderValue::from_str("en-US,en;q=0.9").unwrap(),
+    );
+
+    reqwest::Client::builder()
+        .brotli(true)
+        .gzip(true)
+        .deflate(true)
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+/// Create the api client for the configured site
+pub fn api_client(config: &Config) -> Result<ApiClient, ConfigError> {
+    Ok(ApiClient {
+        client: create_client(),
+        endpoint: config.endpoint()?.to_string(),
+        api_key: config.site.api_key.clone(),
+        user_id: config.site.user_id.clone(),
+        throttle: Default::default(),
+        date_formats: DateFormats::with_extra(config.site.date_formats.iter().cloned()),
+    })
+}
+
+/// Mark the pages as completed which a scrape that crashed before saving the state wrote to
+/// `posts`, returning the posts it wrote after the watermark so they aren't written twice
+pub async fn recover_post_pages(
+    state_manager: &StateManager,
+    posts: &Path,
+) -> std::io::Result<Arc<SeenPosts>> {
+    let last_post_id = state_manager.last_post_id().await;
+    let Some(seen) = SeenPosts::load(posts, last_post_id)? else {
+        return Ok(Arc::default());
+    };
+    let pages = state_manager.recover_post_pages(&seen).await;
+    warn!(
+        posts = seen.len(),
+        pages, "The posts file has posts after the saved state, skipping the pages they finished"
+    );
+    Ok(Arc::new(seen))
+}
+
+/// Open a JSON lines output file for appending
+pub fn open_output(path: &PathBuf) -> JsonLinesSink<BufWriter<File>> {
+    JsonLinesSink::new(BufWriter::new(
+        File::options()
+            .append(true)
+            .create(true)
+            .open(path)
+            .unwrap_or_else(|e| panic!("Failed to open {}: {}", path.display(), e)),
+    ))
+    .with_envelope()
+}
+
+/// The posts file of `output`, resolving the tag types of every post first if
+/// `scraper.typed_tags` is set
+///
+/// Only the tags already in the tags file are known, tags first seen in this run stay untyped.
+pub fn open_posts_output(
+    scraper: &ScraperConfig,
+    output: &OutputConfig,
+) -> std::io::Result<Box<dyn Sink<Post> + Send>> {
+    let posts = open_output(&output.posts);
+    if !scraper.typed_tags {
+        return Ok(Box::new(posts));
+    }
+    let tag_types = match output.tags.exists() {
+        true => load_tag_types(&output.tags)?,
+        false => HashMap::new(),
+    };
+    info!("Resolving tags against {} known tags", tag_types.len());
+    Ok(Box::new(EnrichSink::new(posts, tag_types)))
+}
+
+/// Result of `scrape`
+#[derive(Debug, Serialize)]
+pub struct ScrapeOutput {
+    pub last_post_id: u64,
+    pub last_tag_id: u64,
+    /// Number of ranges and pages which failed and are left for `repair`
+    pub errors: usize,
+    /// Post id ranges above `last_post_id` which weren't scraped yet, the next scrape requests
+    /// them again
+    pub gaps: Vec<std::ops::Range<u64>>,
+    /// Posts dropped by `scraper.blacklist`
+    pub filtered: BlacklistStats,
+    /// Posts run through `scraper.processors`
+    pub processed: PipelineStats,
+    pub manifest: PathBuf,
+    /// The post pages planned at the start and how many of them were scraped
+    pub plan: ScrapePlan,
+    pub pages_done: u64,
+    /// Whether every planned page was scraped, i.e. the posts caught up with the site
+    pub caught_up: bool,
+}
+
+pub async fn run(
+    args: ScrapeArgs,
+    config: Config,
+    format: Format,
+    progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let api_client = api_client(&config)?;
+
+    // Command line flags take precedence over the config
+    let output = OutputConfig {
+        posts: args.posts.unwrap_or(config.output.posts),
+        tags: args.tags.unwrap_or(config.output.tags),
+        pools: config.output.pools,
+        notes: config.output.notes,
+        hashes: config.output.hashes,
+        inventory: config.output.inventory,
+        state: args.state.unwrap_or(config.output.state),
+        manifest: args.manifest.unwrap_or(config.output.manifest),
+    };
+
+    if args.dry_run {
+        return dry_run(&api_client, &config.scraper, &output, format).await;
+    }
+
+    // Listen for ctrl-c and SIGTERM
+    let ctrl_c_task = systemd::shutdown_signal();
+
+    let state_path = output.state.to_string_lossy().to_string();
+    let state_manager = StateManager::new(&state_path).expect("Failed to load state file");
+    // Waits out a cool-down left by an earlier run which was throttled
+    let api_client = api_client.with_throttle(state_manager.throttle());
+    let seen = recover_post_pages(&state_manager, &output.posts).await?;
+
+    // Each output is owned by its own writer task, which also counts the written records
+    let capacity = config.scraper.channel_capacity;
+    let post_bar = progress.spinner("{spinner} {pos} posts ({per_sec})");
+    let tag_bar = progress.spinner("{spinner} {pos} tags ({per_sec})");
+    let (tag_output, tag_writer) = spawn_writer(
+        ProgressSink::new(open_output(&output.tags), tag_bar.clone()),
+        capacity,
+    );
+    let (post_output, post_writer) = spawn_writer(
+        ProgressSink::new(
+            open_posts_output(&config.scraper, &output)?,
+            post_bar.clone(),
+        ),
+        capacity,
+    );
+    // The newest post bounds the run, so it ends once it caught up and the progress has a total
+    let plan = ScrapePlan::fetch(&api_client, state_manager.last_post_id().await + 1)
+        .await?
+        .with_completed(state_manager.completed_posts().await);
+    info!(
+        pages = plan.pages,
+        max_post_id = plan.max_post_id,
+        "Planned the post pages"
+    );
+    let page_task = track_pages(&plan, &progress);
+    let tag_scraper = TagScraper::new(tag_output, state_manager.clone(), api_client.clone())
+        .with_requests_per_second(config.scraper.requests_per_second);
+    let blacklist = Arc::new(Blacklist::new(&config.scraper.blacklist));
+    let pipeline = Arc::new(Pipeline::from_config(
+        &config.scraper.processors,
+        create_client(),
+    ));
+    let post_scraper = PostScraper::new(post_output, state_manager.clone(), api_client.clone())
+        .with_requests_per_second(config.scraper.requests_per_second)
+        .with_parallel_requests(config.scraper.parallel_requests)
+        .with_blacklist(blacklist.clone())
+        .with_pipeline(pipeline.clone())
+        .with_plan(plan.clone())
+        .with_seen(seen);
+
+    let tag_scraper_task = async move {
+        tag_scraper.run().await.unwrap();
+    };
+
+    let post_scraper_task = async move {
+        post_scraper.run().await.unwrap();
+    };
+
+    tokio::select! {
+        _ = post_scraper_task => {
+            info!("Finished Scraping Posts");
+        }
+        _ = tag_scraper_task => {
+            info!("Finished Scraping Tags");
+        }
+        _ = ctrl_c_task => {
+            info!("Saving State");
+        }
+    }
+    // The other scraper was cancelled
+    state_manager.stop_tasks().await;
+    state_manager.save_state(&state_path).await?;
+
+    // The scrapers have been dropped, wait for the writers to flush everything
+    tag_writer.await??;
+    post_writer.await??;
+    if let Some(page_task) = page_task {
+        page_task.abort();
+    }
+    post_bar.finish_and_clear();
+    tag_bar.finish_and_clear();
+
+    // Record what was produced so mirrors can be synced and verified
+    Manifest::build(&[&output.posts, &output.tags])?.save(&output.manifest)?;
+
+    let state = state_manager.get_state();
+    let state = state.lock().await;
+    let result = ScrapeOutput {
+        last_post_id: state.last_post_id,
+        last_tag_id: state.last_tag_id,
+        errors: state.errors.len(),
+        gaps: state
+            .completed_posts
+            .gaps(state.last_post_id + 1..state.completed_posts.end()),
+        filtered: blacklist.stats(),
+        processed: pipeline.stats(),
+        manifest: output.manifest,
+        pages_done: plan.pages_done(),
+        caught_up: plan.is_complete(),
+        plan,
+    };
+    format.print(&result, |result| {
+        println!(
+            "Scraped up to post {} and tag {}, {} errors left for `indexer repair`",
+            result.last_post_id, result.last_tag_id, result.errors
+        );
+        if !result.gaps.is_empty() {
+            println!(
+                "{} post id ranges after {} are missing and will be scraped again",
+                result.gaps.len(),
+                result.last_post_id
+            );
+        }
+        match result.caught_up {
+            true => println!(
+                "Caught up with post {}, the newest when the scrape started",
+                result.plan.max_post_id
+            ),
+            false => println!(
+                "Scraped {} of {} planned pages up to post {}",
+                result.pages_done, result.plan.pages, result.plan.max_post_id
+            ),
+        }
+        let filtered = &result.filtered;
+        if filtered.total() > 0 {
+            println!(
+                "Blacklisted {} posts: {} by tag, {} by rating, {} by uploader",
+                filtered.total(),
+                filtered.tags,
+                filtered.ratings,
+                filtered.uploaders
+            );
+        }
+        let processed = &result.processed;
+        if processed.dropped + processed.failed > 0 {
+            println!(
+                "Processors dropped {} posts and failed {} times",
+                processed.dropped, processed.failed
+            );
+        }
+    });
+    match result.errors {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
+
+/// Show the post pages scraped so far out of the planned ones
+///
+/// The returned task polls the plan until it is aborted.
+fn track_pages(plan: &ScrapePlan, progress: &Progress) -> Option<tokio::task::JoinHandle<()>> {
+    if progress.is_hidden() {
+        return None;
+    }
+    let pages = progress.bar(
+        plan.pages,
+        "{spinner} {bar:30} {pos}/{len} pages ({per_sec}, eta {eta})",
+    );
+    let plan = plan.clone();
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(500));
+        loop {
+            interval.tick().await;
+            pages.set_position(plan.pages_done());
+        }
+    }))
+}
+
+/// Result of `scrape --dry-run`
+#[derive(Debug, Serialize)]
+pub struct DryRunOutput {
+    pub endpoint: String,
+    pub total_posts: u64,
+    pub max_post_id: u64,
+    /// The post id the scrape would continue after
+    pub last_post_id: u64,
+    pub last_tag_id: u64,
+    pub pending_errors: usize,
+    pub post_pages: u64,
+    pub requests_per_second: u32,
+    pub parallel_requests: usize,
+    /// Lower bound for the post scrape, assuming the rate limit is the bottleneck
+    pub estimated_seconds: u64,
+}
+
+async fn dry_run(
+    api_client: &ApiClient,
+    scraper: &ScraperConfig,
+    output: &OutputConfig,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    // Only read the state, a missing file is fine for a first run
+    let state = StateManager::new(&output.state).expect("Failed to load state file");
+    let last_post_id = state.last_post_id().await;
+
+    let plan = ScrapePlan::fetch(api_client, last_post_id + 1)
+        .await?
+        .with_completed(state.completed_posts().await);
+    let post_pages = plan.pages;
+    let requests_per_second = scraper.requests_per_second.get();
+    let result = DryRunOutput {
+        endpoint: api_client.endpoint.clone(),
+        total_posts: plan.total_posts,
+        max_post_id: plan.max_post_id,
+        last_post_id,
+        last_tag_id: state.last_tag_id().await,
+        pending_errors: state.get_state().lock().await.errors.len(),
+        post_pages,
+        requests_per_second,
+        parallel_requests: scraper.parallel_requests,
+        estimated_seconds: post_pages.div_ceil(requests_per_second as u64),
+    };
+
+    format.print(&result, |result| {
+        println!(
+            "{} is reachable: {} posts, highest id {}",
+            result.endpoint, result.total_posts, result.max_post_id
+        );
+        println!(
+            "posts: continue after id {}, {} pages at {} requests/s ({} in parallel), at least {}",
+            result.last_post_id,
+            result.post_pages,
+            result.requests_per_second,
+            result.parallel_requests,
+            format_duration(result.estimated_seconds)
+        );
+        println!("tags: continue after id {}", result.last_tag_id);
+        if result.pending_errors > 0 {
+            println!(
+                "{} errors from earlier runs, retry them with `indexer repair`",
+                result.pending_errors
+            );
+        }
+    });
+    Ok(Status::Success)
+}
+
+fn format_duration(seconds: u64) -> String {
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,78 @@
+use std::{net::SocketAddr, time::Duration};
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    server::{router, thumbnail::ThumbnailCache, AppState, Auth},
+};
+use tracing::{info, warn};
+
+use super::{output::Status, scrape::create_client, systemd, IndexArgs};
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Address the server listens on, defaults to `server.listen` from the config
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+
+    /// Reload the index when its file changes, checking every this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub watch: Option<u64>,
+}
+
+pub async fn run(args: ServeArgs, config: Config) -> Result<Status, Box<dyn std::error::Error>> {
+    let state =
+        AppState::load(args.index.path(&config))?.with_namespaces(&config.server.namespaces)?;
+    serve(&args, &config, state).await?;
+    Ok(Status::Success)
+}
+
+/// Serve `state` until ctrl-c is pressed or SIGTERM is received
+///
+/// Systemd is notified once the server is listening.
+pub async fn serve(
+    args: &ServeArgs,
+    config: &Config,
+    mut state: AppState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(seconds) = args.watch {
+        state.watch(Duration::from_secs(seconds.max(1)));
+    }
+
+    let server = &config.server;
+    let thumbnails = &server.thumbnails;
+    if thumbnails.url.is_some() || thumbnails.downloads.is_some() {
+        state = state.with_thumbnails(ThumbnailCache::new(
+            thumbnails,
+            &config.site.urls,
+            create_client(),
+        )?);
+    }
+    let auth = (!server.api_keys.is_empty()).then(|| {
+        Auth::new(
+            server.api_keys.iter().cloned(),
+            server.requests_per_second,
+            server.burst,
+        )
+    });
+    let listen = args.listen.unwrap_or(server.listen);
+    if auth.is_none() && !listen.ip().is_loopback() {
+        warn!(
+            "Serving on {} without API keys, anyone who can reach it can query the index",
+            listen
+        );
+    }
+    let health = state.health().cloned();
+    let app = router(state, auth);
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    info!("Listening on {}", listener.local_addr()?);
+    systemd::ready(health);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(systemd::shutdown_signal())
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use indexer::config::Config;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    progress::Progress,
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct SheetArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// A tag or query whose posts are sampled, e.g. `"cat -dog"`
+    #[arg(allow_hyphen_values = true, add = ArgValueCompleter::new(complete::query_terms))]
+    pub query: String,
+
+    /// Where the sheet is written, the format follows the extension (jpg, png or webp)
+    #[arg(long, default_value = "sheet.jpg")]
+    pub out: PathBuf,
+
+    /// Number of posts on the sheet
+    #[arg(long, default_value_t = 48)]
+    pub count: usize,
+
+    /// Number of thumbnails per row
+    #[arg(long, default_value_t = 8)]
+    pub columns: u32,
+
+    /// Side of the square cell of every thumbnail, in pixels
+    #[arg(long, default_value_t = 160)]
+    pub cell: u32,
+
+    /// Seed of the sample, the same seed picks the same posts. Random if not given.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Directory the previews are downloaded to, and reused from on the next sheet
+    #[arg(long, default_value = "previews")]
+    pub cache: PathBuf,
+
+    /// Build the preview urls from the index records and `site.urls` instead of reading the
+    /// scraped urls from the posts file
+    #[arg(long)]
+    pub from_index: bool,
+}
+
+/// Result of `sheet`
+#[cfg(feature = "sheet")]
+#[derive(Debug, serde::Serialize)]
+pub struct SheetOutput {
+    pub path: PathBuf,
+    /// Posts matching the query
+    pub matched: u64,
+    pub seed: u64,
+    /// Posts on the sheet, in grid order
+    pub posts: Vec<u32>,
+    /// Sampled posts whose preview couldn't be downloaded or decoded
+    pub missing: Vec<u32>,
+}
+
+#[cfg(feature = "sheet")]
+pub async fn run(
+    args: SheetArgs,
+    config: Config,
+    format: Format,
+    progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    use std::{collections::HashSet, fs::File, io::BufReader};
+
+    use indexer::{
+        download::{DownloadJob, Downloader, Variant},
+        index::read_posts,
+        query::Query,
+        sheet::{compose, sample},
+    };
+    use roaring::RoaringBitmap;
+
+    use super::scrape::create_client;
+
+    let index = args.index.load(&config)?;
+    let query = Query::parse(&args.query)?;
+    let post_ids = index.search(&query);
+    let seed = args
+        .seed
+        .unwrap_or_else(|| chrono::Utc::now().timestamp_micros() as u64);
+    let sampled = sample(&post_ids, args.count, seed);
+
+    let downloader = Downloader::builder()
+        .client(create_client())
+        .dest(args.cache)
+        .variant(Variant::Preview)
+        .urls(config.site.urls)
+        .requests_per_second(config.scraper.requests_per_second)
+        .build();
+    let mut jobs: Vec<DownloadJob> = if args.from_index {
+        sampled
+            .iter()
+            .filter_map(|id| index.post_id_to_post.get(id))
+            .map(|post| downloader.index_job(post))
+            .collect()
+    } else {
+        let ids: RoaringBitmap = sampled.iter().copied().collect();
+        read_posts(BufReader::new(File::open(&config.output.posts)?), &ids)?
+            .iter()
+            .map(|post| downloader.job(post))
+            .collect()
+    };
+    // Keep the order of the sample, which doesn't follow the ids
+    jobs.sort_by_key(|job| sampled.iter().position(|id| *id as u64 == job.post_id));
+    drop(index);
+
+    let bar = progress.bar(jobs.len() as u64, "{spinner} {bar:30} {pos}/{len} previews");
+    downloader
+        .download_jobs_with_progress(jobs.clone(), |stats| {
+            bar.set_position(stats.downloaded + stats.skipped + stats.failed);
+        })
+        .await?;
+    bar.finish_and_clear();
+
+    let files: Vec<(u32, PathBuf)> = jobs
+        .into_iter()
+        .filter(|job| job.path.exists())
+        .map(|job| (job.post_id as u32, job.path))
+        .collect();
+    let sheet = compose(&files, args.columns, args.cell);
+    sheet.image.save(&args.out)?;
+
+    let placed: HashSet<u32> = sheet.placed.iter().copied().collect();
+    let output = SheetOutput {
+        path: args.out,
+        matched: post_ids.len(),
+        seed,
+        missing: sampled
+            .into_iter()
+            .filter(|id| !placed.contains(id))
+            .collect(),
+        posts: sheet.placed,
+    };
+    format.print(&output, |output| {
+        println!(
+            "Wrote {} of {} matching posts to {} (seed {})",
+            output.posts.len(),
+            output.matched,
+            output.path.display(),
+            output.seed
+        );
+        if !output.missing.is_empty() {
+            println!(
+                "{} previews couldn't be downloaded or decoded",
+                output.missing.len()
+            );
+        }
+    });
+    match output.missing.len() {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
+
+#[cfg(not(feature = "sheet"))]
+pub async fn run(
+    _args: SheetArgs,
+    _config: Config,
+    _format: Format,
+    _progress: Progress,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    Err("contact sheets require the `sheet` feature".into())
+}
@@ -0,0 +1,169 @@
+use clap::{Args, Subcommand};
+use clap_complete::ArgValueCompleter;
+use indexer::{
+    config::Config,
+    query::Query,
+    snapshot::{Snapshot, Snapshots},
+};
+use serde::Serialize;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotCommand {
+    /// Freeze the posts matching a query under a name, for `snapshot:name` queries
+    Create(CreateArgs),
+    /// List the snapshots of the index
+    List(ListArgs),
+    /// Delete a snapshot
+    Delete(DeleteArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct CreateArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Replace a snapshot with the same name
+    #[arg(long)]
+    pub force: bool,
+
+    /// Name of the snapshot, matched case-insensitively like every query term
+    pub name: String,
+
+    /// The query whose current result is frozen, e.g. `cat -dog`, options have to come before it
+    #[arg(
+        required = true,
+        allow_hyphen_values = true,
+        add = ArgValueCompleter::new(complete::query_terms)
+    )]
+    pub query: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DeleteArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    pub name: String,
+}
+
+/// A snapshot as listed by `snapshot`
+#[derive(Debug, Serialize)]
+pub struct SnapshotOutput {
+    pub name: String,
+    pub query: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub posts: u64,
+}
+
+impl SnapshotOutput {
+    fn new(name: &str, snapshot: &Snapshot) -> Self {
+        Self {
+            name: name.to_string(),
+            query: snapshot.query.clone(),
+            created_at: snapshot.created_at,
+            posts: snapshot.post_ids.len(),
+        }
+    }
+}
+
+pub fn run(
+    command: SnapshotCommand,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    match command {
+        SnapshotCommand::Create(args) => create(args, config, format),
+        SnapshotCommand::List(args) => list(args, config, format),
+        SnapshotCommand::Delete(args) => delete(args, config, format),
+    }
+}
+
+fn create(
+    args: CreateArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let name = args.name.to_lowercase();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid snapshot name `{}`", args.name).into());
+    }
+    let mut index = args.index.load(&config)?;
+    if index.snapshots.get(&name).is_some() && !args.force {
+        return Err(format!("snapshot `{name}` already exists, pass --force to replace it").into());
+    }
+
+    let query = Query::parse(&args.query.join(" "))?;
+    let snapshot = Snapshot {
+        query: query.to_string(),
+        created_at: chrono::Utc::now(),
+        post_ids: index.search(&query),
+    };
+    let output = SnapshotOutput::new(&name, &snapshot);
+    index.snapshots.insert(name, snapshot);
+    index.snapshots.save(args.index.path(&config))?;
+
+    format.print(&output, |output| {
+        println!(
+            "Froze {} posts of `{}` as snapshot:{}",
+            output.posts, output.query, output.name
+        );
+    });
+    Ok(Status::Success)
+}
+
+fn list(
+    args: ListArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let snapshots = Snapshots::load(args.index.path(&config))?;
+    let output: Vec<SnapshotOutput> = snapshots
+        .iter()
+        .map(|(name, snapshot)| SnapshotOutput::new(name, snapshot))
+        .collect();
+
+    format.print(&output, |output| {
+        for snapshot in output {
+            println!(
+                "{}\t{}\t{}\t{}",
+                snapshot.name,
+                snapshot.posts,
+                snapshot.created_at.format("%Y-%m-%d %H:%M"),
+                snapshot.query
+            );
+        }
+    });
+    Ok(Status::Success)
+}
+
+fn delete(
+    args: DeleteArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let path = args.index.path(&config);
+    let name = args.name.to_lowercase();
+    let mut snapshots = Snapshots::load(path)?;
+    let Some(snapshot) = snapshots.remove(&name) else {
+        return Err(format!("no snapshot named `{name}`").into());
+    };
+    snapshots.save(path)?;
+
+    let output = SnapshotOutput::new(&name, &snapshot);
+    format.print(&output, |output| {
+        println!("Deleted snapshot:{} ({} posts)", output.name, output.posts);
+    });
+    Ok(Status::Success)
+}
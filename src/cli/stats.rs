@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    input,
+    stats::{DatasetReport, DatasetStats},
+};
+
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Stream the posts and tags files instead of loading the index
+    ///
+    /// This is slower, but also works without an index and includes the rating distribution.
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Posts file read by `--stream`, defaults to `output.posts` from the config. `.gz` and
+    /// `.zst` files are decompressed while they are read.
+    #[arg(long, requires = "stream")]
+    pub posts: Option<PathBuf>,
+
+    /// Tags file read by `--stream`, defaults to `output.tags` from the config
+    #[arg(long, requires = "stream")]
+    pub tags: Option<PathBuf>,
+
+    /// Number of most used tags and source domains to print
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Also compute the detailed report: ratings per month, top artists, average score by tag
+    /// and uploads per day
+    ///
+    /// Scores are only known for an index built with `--fields score`, or with `--stream`.
+    #[arg(long)]
+    pub report: bool,
+
+    /// Render the report as an HTML page to this file, implies `--report`
+    #[arg(long)]
+    pub html: Option<PathBuf>,
+}
+
+/// Size of an output file, `None` if it doesn't exist
+#[derive(Debug, Serialize)]
+pub struct FileSize {
+    pub path: PathBuf,
+    pub bytes: Option<u64>,
+}
+
+/// Result of `stats`
+#[derive(Debug, Serialize)]
+pub struct StatsOutput {
+    #[serde(flatten)]
+    pub stats: DatasetStats,
+    pub id_coverage: Option<f64>,
+    pub files: Vec<FileSize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<DatasetReport>,
+}
+
+pub fn run(
+    args: StatsArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let with_report = args.report || args.html.is_some();
+    let posts = args.posts.as_ref().unwrap_or(&config.output.posts);
+    let tags = args.tags.as_ref().unwrap_or(&config.output.tags);
+    let (stats, report) = if args.stream {
+        let open = || -> std::io::Result<_> { Ok((input::open(posts)?, input::open(tags)?)) };
+        let (posts, tags) = open()?;
+        let stats = DatasetStats::from_json_lines(posts, tags, args.top)?;
+        let report = if with_report {
+            let (posts, tags) = open()?;
+            Some(DatasetReport::from_json_lines(posts, tags, args.top)?)
+        } else {
+            None
+        };
+        (stats, report)
+    } else {
+        let index = args.index.load(&config)?;
+        (
+            DatasetStats::from_index(&index, args.top),
+            with_report.then(|| DatasetReport::from_index(&index, args.top)),
+        )
+    };
+
+    if let (Some(path), Some(report)) = (&args.html, &report) {
+        std::fs::write(path, report.to_html())?;
+    }
+
+    let files = [posts, tags, &config.output.state, args.index.path(&config)]
+        .into_iter()
+        .map(|path| FileSize {
+            path: path.clone(),
+            bytes: std::fs::metadata(path).ok().map(|metadata| metadata.len()),
+        })
+        .collect();
+
+    let output = StatsOutput {
+        id_coverage: stats.id_coverage(),
+        stats,
+        files,
+        report,
+    };
+    format.print(&output, print_text);
+    Ok(Status::Success)
+}
+
+fn print_text(output: &StatsOutput) {
+    let stats = &output.stats;
+    println!("posts: {}", stats.posts);
+    println!("tags:  {}", stats.tags);
+    if let (Some(min), Some(max), Some(coverage)) =
+        (stats.min_post_id, stats.max_post_id, output.id_coverage)
+    {
+        println!(
+            "ids:   {}..={} ({:.1}% present)",
+            min,
+            max,
+            coverage * 100.0
+        );
+    }
+
+    if !stats.ratings.is_empty() {
+        println!("\nratings:");
+        for (rating, count) in &stats.ratings {
+            println!("  {:<14}{:>10}", rating, count);
+        }
+    }
+
+    println!("\ntop tags:");
+    for (tag, count) in &stats.top_tags {
+        println!("  {:<40}{:>10}", tag, count);
+    }
+
+    if !stats.top_sources.is_empty() {
+        println!("\ntop sources:");
+        for (domain, count) in &stats.top_sources {
+            println!("  {:<40}{:>10}", domain, count);
+        }
+    }
+
+    println!("\nposts per month:");
+    for (month, count) in &stats.posts_per_month {
+        println!("  {}{:>10}", month, count);
+    }
+
+    println!("\nfiles:");
+    for file in &output.files {
+        let size = file.bytes.map_or(String::from("missing"), human_size);
+        println!("  {:<40}{:>10}", file.path.display(), size);
+    }
+
+    let Some(report) = &output.report else {
+        return;
+    };
+    println!("\ntop artists:");
+    for (artist, count) in &report.top_artists {
+        println!("  {:<40}{:>10}", artist, count);
+    }
+
+    if !report.tag_scores.is_empty() {
+        println!("\naverage score by tag:");
+        for score in &report.tag_scores {
+            println!("  {:<40}{:>10.2}", score.tag, score.average_score);
+        }
+    }
+
+    let busiest = report.posts_per_day.iter().max_by_key(|(_, count)| **count);
+    if let Some((day, count)) = busiest {
+        println!(
+            "\nuploads: {} days, busiest {} with {} posts",
+            report.posts_per_day.len(),
+            day,
+            count
+        );
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use clap::Args;
+use indexer::{
+    config::{Config, OutputConfig},
+    index::Index,
+    maintenance::sync::{
+        latest_records, local_posts, sync_posts, tombstone, LocalPost, SyncChange, SyncReport,
+    },
+    models::Post,
+    scraper::{
+        blacklist::Blacklist,
+        processor::{prepare_post, Pipeline},
+        state_manager::StateManager,
+    },
+    sink::Sink,
+};
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    scrape::{api_client, create_client, open_posts_output},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Only sync the posts created since this time, e.g. `2024-06-01T00:00:00Z`
+    #[arg(long)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Posts file to sync, defaults to `output.posts` from the config
+    #[arg(long)]
+    pub posts: Option<PathBuf>,
+
+    /// Sync a database written by the SQLite sink instead of the posts file. Requires the
+    /// `sqlite` feature.
+    #[arg(long, conflicts_with = "posts")]
+    pub sqlite: Option<PathBuf>,
+
+    /// Only report the changes, without writing them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Result of `sync`
+#[derive(Debug, Serialize)]
+pub struct SyncOutput {
+    #[serde(flatten)]
+    pub report: SyncReport,
+    /// Whether the changes were also applied to the saved index
+    pub index_updated: bool,
+}
+
+/// The local store of the posts being synced
+enum Store {
+    Json(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+impl Store {
+    fn local_posts(&self) -> Result<HashMap<u64, LocalPost>, Box<dyn std::error::Error>> {
+        match self {
+            Store::Json(path) => Ok(local_posts(BufReader::new(File::open(path)?))?),
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(path) => {
+                let conn = rusqlite::Connection::open(path)?;
+                let mut posts = HashMap::new();
+                indexer::sink::sqlite::for_each_post(&conn, |post| {
+                    posts.insert(post.id, LocalPost::from(&post));
+                })?;
+                Ok(posts)
+            }
+        }
+    }
+
+    fn latest_records(
+        &self,
+        ids: &HashSet<u64>,
+    ) -> Result<HashMap<u64, Post>, Box<dyn std::error::Error>> {
+        match self {
+            Store::Json(path) => Ok(latest_records(BufReader::new(File::open(path)?), ids)?),
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(path) => {
+                let conn = rusqlite::Connection::open(path)?;
+                let mut posts = HashMap::new();
+                indexer::sink::sqlite::for_each_post(&conn, |post| {
+                    if ids.contains(&post.id) {
+                        posts.insert(post.id, post);
+                    }
+                })?;
+                Ok(posts)
+            }
+        }
+    }
+
+    fn sink(&self, config: &Config) -> Result<Box<dyn Sink<Post>>, Box<dyn std::error::Error>> {
+        match self {
+            Store::Json(path) => {
+                let output = OutputConfig {
+                    posts: path.clone(),
+                    ..config.output.clone()
+                };
+                Ok(open_posts_output(&config.scraper, &output)?)
+            }
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(path) => Ok(Box::new(indexer::sink::sqlite::SqliteSink::open(path)?)),
+        }
+    }
+}
+
+pub async fn run(
+    args: SyncArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let output = sync(args, &config, None).await?;
+    format.print(&output, |output| {
+        let report = &output.report;
+        println!(
+            "Checked {} posts in {} ranges: {} added, {} updated, {} deleted",
+            report.checked, report.ranges, report.added, report.updated, report.deleted
+        );
+        if report.failed > 0 {
+            println!("{} ranges failed and were left as they are", report.failed);
+        }
+        if output.index_updated {
+            println!("Applied the changes to the index");
+        }
+    });
+    match output.report.failed {
+        0 => Ok(Status::Success),
+        _ => Ok(Status::Partial),
+    }
+}
+
+/// Sync the posts and write the changes, also used by the scheduled syncs of `daemon`, which
+/// records their progress in its `state_manager`
+pub async fn sync(
+    args: SyncArgs,
+    config: &Config,
+    state_manager: Option<&StateManager>,
+) -> Result<SyncOutput, Box<dyn std::error::Error>> {
+    let store = match args.sqlite {
+        #[cfg(feature = "sqlite")]
+        Some(path) => Store::Sqlite(path),
+        #[cfg(not(feature = "sqlite"))]
+        Some(_) => return Err("syncing a database requires the `sqlite` feature".into()),
+        None => Store::Json(args.posts.unwrap_or(config.output.posts.clone())),
+    };
+    let client = api_client(config)?;
+    let local = store.local_posts()?;
+
+    let mut changed = Vec::new();
+    let mut updated = HashSet::new();
+    let mut deleted = HashSet::new();
+    let mut report = sync_posts(
+        &client,
+        &local,
+        args.since,
+        config.scraper.requests_per_second,
+        config.scraper.parallel_requests,
+        state_manager,
+        |change| match change {
+            SyncChange::Added(post) => changed.push(post),
+            SyncChange::Updated(post) => {
+                updated.insert(post.id);
+                changed.push(post);
+            }
+            SyncChange::Deleted(id) => {
+                deleted.insert(id);
+            }
+        },
+    )
+    .await;
+    drop(local);
+
+    let mut index_updated = false;
+    if !args.dry_run {
+        // New posts are filtered like while scraping, posts already written are kept up to date
+        let blacklist = Blacklist::new(&config.scraper.blacklist);
+        let pipeline = Pipeline::from_config(&config.scraper.processors, create_client());
+        let mut kept = Vec::with_capacity(changed.len());
+        for post in changed {
+            if updated.contains(&post.id) {
+                kept.push(post);
+            } else if let Some(post) = prepare_post(Some(&blacklist), Some(&pipeline), post).await {
+                kept.push(post);
+            } else {
+                report.added -= 1;
+            }
+        }
+        changed = kept;
+
+        // The versions being replaced, so the index only has to unlink what changed
+        let mut previous = store.latest_records(&(&deleted | &updated))?;
+        // A deleted post missing from the store can't be written, it isn't counted
+        let tombstones: Vec<Post> = deleted
+            .iter()
+            .filter_map(|id| previous.get(id))
+            .map(|post| tombstone(post.clone(), report.as_of))
+            .collect();
+        report.deleted = tombstones.len() as u64;
+        changed.extend(tombstones);
+        changed.sort_unstable_by_key(|post| post.id);
+
+        let mut sink = store.sink(config)?;
+        for post in &changed {
+            sink.write(post.clone())?;
+        }
+        sink.flush()?;
+
+        let index_path = args.index.path(config);
+        if index_path.exists() && !changed.is_empty() {
+            let mut index = Index::load(index_path)?;
+            for post in changed {
+                index.upsert_post(previous.remove(&post.id).as_ref(), post);
+            }
+            index.save(index_path)?;
+            index_updated = true;
+        }
+    }
+
+    Ok(SyncOutput {
+        report,
+        index_updated,
+    })
+}
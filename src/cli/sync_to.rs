@@ -0,0 +1,94 @@
+use clap::Args;
+use indexer::{
+    config::Config,
+    maintenance::mirror::{plan, remote_manifest, Action, Remote, Transfer},
+};
+use serde::Serialize;
+
+use super::{
+    output::{Format, Status},
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct SyncToArgs {
+    /// The other copy: a directory, `ssh://host/path`, `s3://bucket/prefix` (requires the `s3`
+    /// feature) or an `http(s)://` url accepting `PUT` requests
+    pub target: String,
+
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Leave the index out, e.g. when the other copy builds its own with `index build`
+    #[arg(long)]
+    pub no_index: bool,
+
+    /// Only report what would be sent
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Result of `sync-to`
+#[derive(Debug, Serialize)]
+pub struct SyncToOutput {
+    pub target: String,
+    pub files: Vec<Transfer>,
+    /// Bytes sent, or which would be sent with `--dry-run`
+    pub bytes: u64,
+    pub dry_run: bool,
+}
+
+pub async fn run(
+    args: SyncToArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let remote = Remote::parse(&args.target)?;
+    let output = &config.output;
+    // The index is sent after the files it was built from, the manifest after everything
+    let mut files = vec![
+        ("tags.json", &output.tags),
+        ("posts.json", &output.posts),
+        ("pools.json", &output.pools),
+        ("notes.json", &output.notes),
+    ];
+    if !args.no_index {
+        files.push(("index.json", args.index.path(&config)));
+    }
+    files.retain(|(_, path)| path.exists());
+
+    let manifest = remote.manifest().await?;
+    let transfers = plan(&files, manifest.as_ref(), remote.can_append())?;
+    if !args.dry_run {
+        for transfer in &transfers {
+            remote.send(transfer).await?;
+        }
+        remote
+            .save_manifest(&remote_manifest(manifest.as_ref(), &transfers))
+            .await?;
+    }
+
+    let output = SyncToOutput {
+        bytes: transfers.iter().map(|transfer| transfer.bytes).sum(),
+        target: args.target,
+        files: transfers,
+        dry_run: args.dry_run,
+    };
+    format.print(&output, |output| {
+        for file in &output.files {
+            match file.action {
+                Action::Unchanged => println!("{}\tunchanged", file.name),
+                Action::Append { offset } => {
+                    println!("{}\tappend {} bytes at {}", file.name, file.bytes, offset)
+                }
+                Action::Replace => println!("{}\treplace {} bytes", file.name, file.bytes),
+            }
+        }
+        let verb = match output.dry_run {
+            true => "Would send",
+            false => "Sent",
+        };
+        println!("{verb} {} bytes to {}", output.bytes, output.target);
+    });
+    Ok(Status::Success)
+}
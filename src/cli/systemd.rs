@@ -0,0 +1,124 @@
+//! Running as a systemd service with `Type=notify`
+//!
+//! The readiness, stopping and watchdog messages are sent to `$NOTIFY_SOCKET` like `sd_notify`
+//! does, and are skipped when the variable isn't set, e.g. when started from a shell.
+
+use std::time::Duration;
+
+use indexer::server::health::Health;
+use tracing::{info, warn};
+
+/// Wait for ctrl-c or, on unix, SIGTERM, which is how systemd stops a service
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received ctrl-c, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
+
+/// Tell systemd the service is up, and start the watchdog pings if `WatchdogSec` is set
+///
+/// With the `health` of a scraper running in the process the pings stop while its scrape is
+/// stalled, so systemd restarts a daemon whose scrape hangs and not only one which hangs as a
+/// whole.
+pub fn ready(health: Option<Health>) {
+    send("READY=1");
+    if let Some(interval) = watchdog_interval() {
+        // Pinging twice per interval leaves room for a slow tick
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval / 2);
+            let mut live = true;
+            loop {
+                interval.tick().await;
+                let was_live = live;
+                live = match &health {
+                    Some(health) => health.is_live().await,
+                    None => true,
+                };
+                if live {
+                    send("WATCHDOG=1");
+                } else if was_live {
+                    warn!("The scrape stalled, stopped the watchdog pings");
+                }
+            }
+        });
+    }
+}
+
+/// Tell systemd the service is shutting down, e.g. while it flushes its files
+pub fn stopping() {
+    send("STOPPING=1");
+}
+
+/// A line shown by `systemctl status`
+pub fn status(status: &str) {
+    send(&format!("STATUS={}", status));
+}
+
+/// `WATCHDOG_USEC`, if the watchdog is enabled for this process
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+fn send(message: &str) {
+    if let Err(e) = notify(message) {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn notify(message: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+    // A leading `@` names a socket in the abstract namespace
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on linux",
+            ))
+        }
+        None => {
+            socket.send_to(message.as_bytes(), &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn notify(_message: &str) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    maintenance::drift::{read_tags, tag_drift, DriftThresholds, TagDrift},
+};
+
+use super::output::{Format, Status};
+
+#[derive(Debug, Args)]
+pub struct TagDriftArgs {
+    /// Tags file of the earlier scrape
+    pub old: PathBuf,
+
+    /// Tags file of the later scrape, defaults to `output.tags` from the config
+    pub new: Option<PathBuf>,
+
+    /// Only report count changes of at least this many posts
+    #[arg(long, default_value_t = 100)]
+    pub min_change: u64,
+
+    /// Only report count changes of at least this share of the old count, e.g. `0.5` for 50%
+    #[arg(long, default_value_t = 0.5)]
+    pub min_ratio: f64,
+}
+
+pub fn run(
+    args: TagDriftArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let new_path = args.new.unwrap_or(config.output.tags);
+    let old = read_tags(BufReader::new(File::open(&args.old)?))?;
+    let new = read_tags(BufReader::new(File::open(&new_path)?))?;
+    let thresholds = DriftThresholds {
+        min_change: args.min_change,
+        min_ratio: args.min_ratio,
+    };
+
+    let drift = tag_drift(&old, &new, thresholds);
+    format.print(&drift, print_drift);
+    Ok(Status::Success)
+}
+
+fn print_drift(drift: &TagDrift) {
+    for tag in &drift.added {
+        println!("+ {} ({}, {} posts)", tag.name, tag.tag_type, tag.count);
+    }
+    for tag in &drift.deleted {
+        println!("- {} ({}, {} posts)", tag.name, tag.tag_type, tag.count);
+    }
+    for rename in &drift.renamed {
+        println!("renamed {} -> {}", rename.from, rename.to);
+    }
+    for change in &drift.retyped {
+        println!("retyped {}: {} -> {}", change.name, change.from, change.to);
+    }
+    for change in &drift.counts {
+        println!("count {}: {} -> {}", change.name, change.from, change.to);
+    }
+    println!(
+        "{} -> {} tags: {} added, {} deleted, {} renamed, {} retyped, {} large count changes",
+        drift.old,
+        drift.new,
+        drift.added.len(),
+        drift.deleted.len(),
+        drift.renamed.len(),
+        drift.retyped.len(),
+        drift.counts.len()
+    );
+}
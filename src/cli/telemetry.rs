@@ -0,0 +1,78 @@
+//! Exporting the tracing spans over OTLP, see `[log.otlp]`
+//!
+//! Every page request of the scrapers, every phase of an index build and every query of the
+//! server is a span, so a trace viewer shows where the time goes. The spans are batched and sent
+//! from a background thread, the [`Guard`] flushes the last batch when the command is done.
+
+use indexer::config::OtlpConfig;
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+/// Flushes and stops the exporter when dropped
+pub struct Guard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to export the last spans: {}", e);
+            }
+        }
+    }
+}
+
+/// The layer exporting the spans, `None` if `config` is
+#[cfg(feature = "otlp")]
+pub fn layer<S>(
+    config: Option<&OtlpConfig>,
+) -> Result<(Option<impl Layer<S>>, Guard), Box<dyn std::error::Error>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+    use tracing_subscriber::EnvFilter;
+
+    let Some(config) = config else {
+        return Ok((None, Guard { provider: None }));
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("indexer"))
+        .with_filter(EnvFilter::try_new(&config.level)?);
+    Ok((
+        Some(layer),
+        Guard {
+            provider: Some(provider),
+        },
+    ))
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn layer<S>(
+    config: Option<&OtlpConfig>,
+) -> Result<(Option<impl Layer<S>>, Guard), Box<dyn std::error::Error>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match config {
+        Some(_) => Err("exporting spans requires the `otlp` feature".into()),
+        None => Ok((None::<tracing_subscriber::layer::Identity>, Guard {})),
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fs::File, io::BufWriter, path::PathBuf};
+
+use clap::Args;
+use indexer::maintenance::bundle::{Bundle, BundleEntry};
+use serde::Serialize;
+
+use super::output::{Format, Status};
+
+#[derive(Debug, Args)]
+pub struct UnpackArgs {
+    /// A bundle written by `pack`
+    pub bundle: PathBuf,
+
+    /// Directory the files are extracted to, created if it doesn't exist
+    #[arg(long, default_value = ".")]
+    pub dir: PathBuf,
+
+    /// Only list the files of the bundle
+    #[arg(long)]
+    pub list: bool,
+
+    /// Replace files which already exist in the directory
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// Result of `unpack`
+#[derive(Debug, Serialize)]
+pub struct UnpackOutput {
+    pub entries: Vec<BundleEntry>,
+    /// `None` with `--list`
+    pub dir: Option<PathBuf>,
+}
+
+pub fn run(args: UnpackArgs, format: Format) -> Result<Status, Box<dyn std::error::Error>> {
+    let bundle = Bundle::open(&args.bundle)?;
+    let entries = bundle.contents().entries.clone();
+
+    if !args.list {
+        std::fs::create_dir_all(&args.dir)?;
+        // Check every file first, so an existing dataset isn't half overwritten
+        if !args.force {
+            if let Some(entry) = entries.iter().find(|e| args.dir.join(&e.name).exists()) {
+                return Err(format!(
+                    "{} already exists, pass --force to replace it",
+                    args.dir.join(&entry.name).display()
+                )
+                .into());
+            }
+        }
+        for entry in &entries {
+            // Written next to the target and renamed, a failed checksum leaves no partial file
+            let path = args.dir.join(&entry.name);
+            let tmp_path = path.with_extension("tmp");
+            let result = bundle.extract(&entry.name, BufWriter::new(File::create(&tmp_path)?));
+            if let Err(e) = result {
+                std::fs::remove_file(&tmp_path)?;
+                return Err(e.into());
+            }
+            std::fs::rename(&tmp_path, &path)?;
+        }
+    }
+
+    let output = UnpackOutput {
+        entries,
+        dir: (!args.list).then_some(args.dir),
+    };
+    format.print(&output, |output| {
+        for entry in &output.entries {
+            println!(
+                "{}\t{} bytes\t{:?}\t{}",
+                entry.name, entry.bytes, entry.compression, entry.sha256
+            );
+        }
+        if let Some(dir) = &output.dir {
+            println!(
+                "Extracted {} files into {}",
+                output.entries.len(),
+                dir.display()
+            );
+        }
+    });
+    Ok(Status::Success)
+}
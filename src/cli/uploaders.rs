@@ -0,0 +1,112 @@
+use clap::Args;
+use clap_complete::ArgValueCompleter;
+use indexer::{config::Config, index::UploaderStats, query::Query};
+use serde::Serialize;
+use tracing::warn;
+
+use super::{
+    complete,
+    output::{Format, Status},
+    scrape::api_client,
+    IndexArgs,
+};
+
+#[derive(Debug, Args)]
+pub struct UploadersArgs {
+    #[command(flatten)]
+    pub index: IndexArgs,
+
+    /// Number of uploaders to list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Fetch the current display names of the listed uploaders from the API and save them in the
+    /// index, for sites where accounts get renamed
+    #[arg(long)]
+    pub refresh_names: bool,
+
+    /// Only count the posts matching this query, e.g. `cat -dog`, options have to come before it
+    #[arg(allow_hyphen_values = true, add = ArgValueCompleter::new(complete::query_terms))]
+    pub query: Vec<String>,
+}
+
+/// Result of `uploaders`
+#[derive(Debug, Serialize)]
+pub struct UploadersOutput {
+    /// Posts the uploaders are counted in
+    pub posts: u64,
+    pub uploaders: Vec<UploaderStats>,
+    /// Names changed by `--refresh-names`
+    pub renamed: u64,
+}
+
+pub async fn run(
+    args: UploadersArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let path = args.index.path(&config);
+    if args.refresh_names && path.extension().is_some_and(|e| e == "booru") {
+        return Err("--refresh-names can't update the index of a `.booru` bundle".into());
+    }
+    let mut index = args.index.load(&config)?;
+    let post_ids = match args.query.is_empty() {
+        true => index.all_post_ids(),
+        false => index.search(&Query::parse(&args.query.join(" "))?),
+    };
+
+    let mut status = Status::Success;
+    let mut renamed = 0;
+    if args.refresh_names {
+        let client = api_client(&config)?;
+        for stats in index.uploader_stats(&post_ids, args.top) {
+            match client.get_user(stats.creator_id).await {
+                Ok(Some(user)) => {
+                    let name = user.name.to_lowercase();
+                    if index.creator_names.get(&user.id) != Some(&name) {
+                        index.creator_names.insert(user.id, name);
+                        renamed += 1;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Failed to fetch uploader {}: {e}", stats.creator_id);
+                    status = Status::Partial;
+                }
+            }
+        }
+        if renamed > 0 {
+            index.save(path)?;
+        }
+    }
+
+    let output = UploadersOutput {
+        posts: post_ids.len(),
+        uploaders: index.uploader_stats(&post_ids, args.top),
+        renamed,
+    };
+    format.print(&output, |output| {
+        for uploader in &output.uploaders {
+            let dates = match (uploader.first_post, uploader.last_post) {
+                (Some(first), Some(last)) => {
+                    format!("{} - {}", first.format("%Y-%m-%d"), last.format("%Y-%m-%d"))
+                }
+                _ => String::new(),
+            };
+            println!(
+                "{}\t{}\t{} posts\t{}\tscore {}",
+                uploader.creator_id,
+                uploader.name.as_deref().unwrap_or("?"),
+                uploader.posts,
+                dates,
+                uploader
+                    .mean_score
+                    .map_or("?".to_string(), |score| format!("{score:.1}"))
+            );
+        }
+        if output.renamed > 0 {
+            println!("Updated {} uploader names", output.renamed);
+        }
+    });
+    Ok(status)
+}
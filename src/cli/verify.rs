@@ -0,0 +1,94 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use indexer::{
+    config::Config,
+    maintenance::verify::{verify, verify_file, Problem, VerifyReport},
+    models::{Post, Tag},
+};
+use serde::Serialize;
+
+use super::output::{Format, Status};
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Only report gaps spanning more than this many ids
+    #[arg(long, default_value_t = 100)]
+    pub min_gap: u64,
+
+    /// Check this posts file instead of `output.posts`, e.g. a `.gz` or `.zst` dump; the state
+    /// file is only checked against the output files of the config
+    #[arg(long)]
+    pub posts: Option<PathBuf>,
+
+    /// Check this tags file instead of `output.tags`
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProblemOutput<'a> {
+    pub message: String,
+    #[serde(flatten)]
+    pub problem: &'a Problem,
+}
+
+/// Result of `verify`
+#[derive(Debug, Serialize)]
+pub struct VerifyOutput<'a> {
+    pub posts: u64,
+    pub tags: u64,
+    pub problems: Vec<ProblemOutput<'a>>,
+}
+
+pub fn run(
+    args: VerifyArgs,
+    config: Config,
+    format: Format,
+) -> Result<Status, Box<dyn std::error::Error>> {
+    let output = &config.output;
+    let report = match (&args.posts, &args.tags) {
+        (None, None) => verify(&output.posts, &output.tags, &output.state, args.min_gap)?,
+        (posts, tags) => VerifyReport {
+            posts: verify_file::<Post, _>(
+                posts.as_ref().unwrap_or(&output.posts),
+                |post| post.id,
+                args.min_gap,
+            )?,
+            tags: verify_file::<Tag, _>(
+                tags.as_ref().unwrap_or(&output.tags),
+                |tag| tag.id,
+                args.min_gap,
+            )?,
+            state: Vec::new(),
+        },
+    };
+
+    let output = VerifyOutput {
+        posts: report.posts.records,
+        tags: report.tags.records,
+        problems: report
+            .problems()
+            .map(|problem| ProblemOutput {
+                message: problem.to_string(),
+                problem,
+            })
+            .collect(),
+    };
+    format.print(&output, |output| {
+        for problem in &output.problems {
+            println!("{}", problem.message);
+        }
+        println!(
+            "checked {} posts and {} tags, found {} problems",
+            output.posts,
+            output.tags,
+            output.problems.len()
+        );
+    });
+
+    match report.is_ok() {
+        true => Ok(Status::Success),
+        false => Ok(Status::Partial),
+    }
+}
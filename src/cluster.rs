@@ -0,0 +1,178 @@
+//! Topics of tags found in their co-occurrence, see [`cluster_tags`]
+//!
+//! The most used tags are the nodes of a graph with an edge between every two tags whose posts
+//! overlap more than chance would have them, weighted by that excess relative to the size of their
+//! post sets, see [`ClusterOptions::min_similarity`]. Communities of that graph are found by label
+//! propagation: every tag repeatedly takes the label carrying the most edge weight among its
+//! neighbours, until no label changes. Tags of a community are used together far
+//! more than their frequency suggests, e.g. the characters, outfits and locations of a series,
+//! which makes the clusters a map of the sub-collections of a large dataset.
+
+use std::collections::{BTreeMap, HashMap};
+
+use rayon::prelude::*;
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+use crate::index::Index;
+
+/// Rounds of label propagation after which the labels are taken as they are
+const MAX_ROUNDS: usize = 50;
+
+/// Which tags are clustered and how strongly they have to be linked
+#[derive(Debug, Clone, Copy)]
+pub struct ClusterOptions {
+    /// Number of most used tags in the graph
+    pub vocabulary: usize,
+    /// Tags on fewer posts are left out
+    pub min_count: u64,
+    /// Smallest similarity of two tags for an edge between them
+    ///
+    /// The similarity is the cosine similarity of their post sets minus what it would be if the
+    /// tags were used independently, so tags on most posts (`solo`, `highres`) don't link
+    /// unrelated topics.
+    pub min_similarity: f32,
+    /// Smaller clusters are dropped
+    pub min_size: usize,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        Self {
+            vocabulary: 5_000,
+            min_count: 20,
+            min_similarity: 0.2,
+            min_size: 3,
+        }
+    }
+}
+
+/// A group of tags used together, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagCluster {
+    /// The tags of the cluster, most used first
+    pub tags: Vec<String>,
+    /// Posts with at least one of the tags
+    pub posts: u64,
+}
+
+/// Cluster the most used tags of the index, largest clusters first
+pub fn cluster_tags(index: &Index, options: ClusterOptions) -> Vec<TagCluster> {
+    let names: HashMap<u32, &str> = index
+        .tag_str_to_id
+        .iter()
+        .map(|(name, id)| (*id, name.as_str()))
+        .collect();
+    let mut tags: Vec<(&str, &RoaringBitmap)> = index
+        .tag_id_to_post_id
+        .iter()
+        .filter(|(_, posts)| posts.len() >= options.min_count.max(1))
+        .filter_map(|(id, posts)| Some((*names.get(id)?, posts)))
+        .collect();
+    tags.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+    tags.truncate(options.vocabulary);
+
+    let total = index.post_id_to_post.len() as f64;
+    let edges = similarity_graph(&tags, total, options.min_similarity);
+    let labels = propagate_labels(&edges);
+
+    // Nodes are ordered by use, so the members of a community are too
+    let mut communities: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    for (node, label) in labels.into_iter().enumerate() {
+        communities.entry(label).or_default().push(node);
+    }
+    let mut clusters: Vec<TagCluster> = communities
+        .into_values()
+        .filter(|members| members.len() >= options.min_size.max(2))
+        .map(|members| {
+            let mut posts = RoaringBitmap::new();
+            for node in &members {
+                posts |= tags[*node].1;
+            }
+            TagCluster {
+                tags: members
+                    .iter()
+                    .map(|node| tags[*node].0.to_string())
+                    .collect(),
+                posts: posts.len(),
+            }
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.posts.cmp(&a.posts).then(a.tags.cmp(&b.tags)));
+    clusters
+}
+
+/// Neighbours and edge weights of every tag
+fn similarity_graph(
+    tags: &[(&str, &RoaringBitmap)],
+    total: f64,
+    min_similarity: f32,
+) -> Vec<Vec<(u32, f32)>> {
+    // The index only maps tags to posts, the pairs need the tags of every post
+    let mut post_tags: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (node, (_, posts)) in tags.iter().enumerate() {
+        for post_id in *posts {
+            post_tags.entry(post_id).or_default().push(node as u32);
+        }
+    }
+
+    tags.par_iter()
+        .enumerate()
+        .map(|(node, (_, posts))| {
+            let mut counts = vec![0u32; tags.len()];
+            for post_id in *posts {
+                for other in &post_tags[&post_id] {
+                    counts[*other as usize] += 1;
+                }
+            }
+            counts[node] = 0;
+            counts
+                .into_iter()
+                .enumerate()
+                .filter(|(_, count)| *count > 0)
+                .filter_map(|(other, count)| {
+                    let (a, b) = (posts.len() as f64, tags[other].1.len() as f64);
+                    let expected = a * b / total.max(1.0);
+                    let similarity = ((count as f64 - expected) / (a * b).sqrt()) as f32;
+                    (similarity >= min_similarity).then_some((other as u32, similarity))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The community label of every node
+///
+/// Nodes are visited in order and the labels change in place, which converges faster than
+/// updating them all at once and doesn't oscillate on bipartite parts of the graph. Ties keep the
+/// current label, or else go to the lowest, so the result only depends on the graph.
+fn propagate_labels(edges: &[Vec<(u32, f32)>]) -> Vec<u32> {
+    let mut labels: Vec<u32> = (0..edges.len() as u32).collect();
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for (node, neighbours) in edges.iter().enumerate() {
+            let mut weights: BTreeMap<u32, f32> = BTreeMap::new();
+            for (other, weight) in neighbours {
+                *weights.entry(labels[*other as usize]).or_default() += weight;
+            }
+            let current = labels[node];
+            let Some(best) = weights.values().copied().reduce(f32::max) else {
+                continue;
+            };
+            if weights.get(&current) == Some(&best) {
+                continue;
+            }
+            let label = weights
+                .iter()
+                .find(|(_, weight)| **weight == best)
+                .map(|(label, _)| *label)
+                .unwrap_or(current);
+            labels[node] = label;
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+    labels
+}
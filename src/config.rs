@@ -0,0 +1,599 @@
+//! Settings loaded from `indexer.toml`, with environment variable overrides
+//!
+//! Every value has a default, so the file is optional. Environment variables (also read from a
+//! `.env` file) take precedence over the file, which keeps secrets out of it:
+//!
+//! ```toml
+//! [site]
+//! endpoint = "https://example.com/index.php"
+//! api_key_env = "EXAMPLE_API_KEY"
+//! user_id_env = "EXAMPLE_USER_ID"
+//! date_formats = ["%Y-%m-%d %H:%M:%S"] # tried before the Gelbooru, rfc3339 and unix formats
+//!
+//! [site.urls]
+//! original = "https://img.example.com/images/{dir}/{md5}.{ext}"
+//! preview = "https://img.example.com/thumbnails/{dir}/thumbnail_{md5}.jpg"
+//!
+//! [scraper]
+//! requests_per_second = 8
+//! parallel_requests = 2
+//!
+//! [scraper.blacklist]
+//! tags = ["guro"]
+//! ratings = ["explicit"]
+//! uploaders = ["spammer", "12345"]
+//!
+//! [[scraper.processors]] # run on every post in this order before it is written
+//! kind = "normalize_tags"
+//!
+//! [[scraper.processors]]
+//! kind = "http_tagger"
+//! url = "http://127.0.0.1:5000/tag"
+//! timeout_secs = 10
+//!
+//! [output]
+//! posts = "example/posts.json"
+//! tags = "example/tags.json"
+//! state = "example/state.json"
+//!
+//! [index]
+//! path = "example/index.json"
+//! keep = ["score", "rating"]
+//!
+//! [[audit.ratings]] # posts with any of the tags must be rated at least questionable
+//! tags = ["nude", "nipples"]
+//! min = "questionable"
+//!
+//! [log]
+//! dir = "logs"
+//! rotation = "daily"
+//! max_files = 14
+//! format = "json"
+//!
+//! [server]
+//! listen = "0.0.0.0:3000"
+//! api_keys = ["a-long-random-key"]
+//! requests_per_second = 10
+//!
+//! [server.thumbnails]
+//! downloads = "files"
+//!
+//! [server.namespaces] # more indexes, queried as `art:cat -dog`
+//! art = "art/index.json"
+//!
+//! [schedule] # jobs of `daemon`, in UTC
+//! posts = "*/10 * * * *"
+//! tags = "@daily"
+//! sync = "0 4 * * 0"
+//! index = "*/15 * * * *"
+//! compact = "30 4 * * 0"
+//! ```
+//!
+//! Several sites can share one file through named profiles. A section given in a profile replaces
+//! the top level section of the same name:
+//!
+//! ```toml
+//! [profiles.safebooru.site]
+//! endpoint = "https://safebooru.example/index.php"
+//!
+//! [profiles.safebooru.output]
+//! posts = "safebooru/posts.json"
+//! tags = "safebooru/tags.json"
+//! state = "safebooru/state.json"
+//! manifest = "safebooru/manifest.json"
+//! ```
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    api::utils::DateFormat,
+    models::{PostField, RatingRule, UrlTemplates},
+    schedule::Cron,
+};
+
+/// Default location of the config file
+pub const CONFIG_FILE: &str = "indexer.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("IO Error: `{0}`")]
+    Io(#[from] std::io::Error),
+    #[error("Toml Error: `{0}`")]
+    Toml(#[from] toml::de::Error),
+    #[error("Invalid value for `{0}`: `{1}`")]
+    InvalidEnv(&'static str, String),
+    #[error("`{0}` is not configured")]
+    Missing(&'static str),
+    #[error("Unknown profile `{0}`")]
+    UnknownProfile(String),
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub site: SiteConfig,
+    pub scraper: ScraperConfig,
+    pub output: OutputConfig,
+    pub index: IndexConfig,
+    pub audit: AuditConfig,
+    pub log: LogConfig,
+    pub server: ServerConfig,
+    pub schedule: ScheduleConfig,
+    pub profiles: HashMap<String, Profile>,
+}
+
+/// Settings for one site or account, replacing the top level sections it contains
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub site: Option<SiteConfig>,
+    pub scraper: Option<ScraperConfig>,
+    pub output: Option<OutputConfig>,
+    pub index: Option<IndexConfig>,
+    pub audit: Option<AuditConfig>,
+    pub log: Option<LogConfig>,
+    pub server: Option<ServerConfig>,
+    pub schedule: Option<ScheduleConfig>,
+}
+
+/// The site being scraped and how to authenticate against it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    pub endpoint: Option<String>,
+    pub api_key: Option<String>,
+    pub user_id: Option<String>,
+    /// Name of the environment variable holding the api key
+    pub api_key_env: String,
+    /// Name of the environment variable holding the user id
+    pub user_id_env: String,
+    /// Where the site stores files, to download posts from an index
+    pub urls: UrlTemplates,
+    /// Date formats of the site tried before the default ones, `strftime` patterns or `rfc3339`,
+    /// `unix` and `unix_ms`, see [`DateFormats`](crate::api::utils::DateFormats)
+    pub date_formats: Vec<DateFormat>,
+}
+
+impl Default for SiteConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            api_key: None,
+            user_id: None,
+            api_key_env: String::from("API_KEY"),
+            user_id_env: String::from("USER_ID"),
+            urls: UrlTemplates::default(),
+            date_formats: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScraperConfig {
+    pub requests_per_second: NonZeroU32,
+    /// Number of post pages requested concurrently
+    pub parallel_requests: usize,
+    /// Capacity of the channel between a scraper and its writer task
+    pub channel_capacity: usize,
+    /// Write the types of the tags along with every post, as far as the tags file knows them
+    pub typed_tags: bool,
+    pub blacklist: BlacklistConfig,
+    /// The post processing pipeline, see `scraper::processor`
+    pub processors: Vec<ProcessorConfig>,
+}
+
+/// Posts which are dropped while scraping
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlacklistConfig {
+    /// Posts with any of these tags
+    pub tags: Vec<String>,
+    /// Posts with any of these ratings, e.g. `explicit`
+    pub ratings: Vec<String>,
+    /// Posts uploaded by any of these users, by name or id
+    pub uploaders: Vec<String>,
+}
+
+/// A step of the post processing pipeline, selected by `kind`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProcessorConfig {
+    /// Lowercase the tags, replace spaces with underscores and remove duplicates
+    NormalizeTags,
+    /// Send every post to an external tagger and add the tags it returns
+    HttpTagger {
+        url: String,
+        #[serde(default = "default_tagger_timeout")]
+        timeout_secs: u64,
+    },
+}
+
+fn default_tagger_timeout() -> u64 {
+    30
+}
+
+impl Default for ScraperConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: NonZeroU32::new(8).unwrap(),
+            parallel_requests: 2,
+            channel_capacity: 10_000,
+            typed_tags: false,
+            blacklist: BlacklistConfig::default(),
+            processors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub posts: PathBuf,
+    pub tags: PathBuf,
+    /// Read by `index build` if it exists
+    pub pools: PathBuf,
+    /// Notes of the posts, read by `index build` for `note:` queries if it exists
+    pub notes: PathBuf,
+    /// Perceptual hashes written by `download --phash`, read by `index build` if it exists
+    pub hashes: PathBuf,
+    /// The files of the downloads directory written by `inventory`, read by `index build` if it
+    /// exists
+    pub inventory: PathBuf,
+    pub state: PathBuf,
+    pub manifest: PathBuf,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            posts: PathBuf::from("posts.json"),
+            tags: PathBuf::from("tags.json"),
+            pools: PathBuf::from("pools.json"),
+            notes: PathBuf::from("notes.json"),
+            hashes: PathBuf::from("hashes.json"),
+            inventory: PathBuf::from("inventory.json"),
+            state: PathBuf::from("state.json"),
+            manifest: PathBuf::from("manifest.json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexConfig {
+    /// Where the index is saved to and loaded from
+    pub path: PathBuf,
+    /// Optional post fields kept in the index, so results can show them without the posts file
+    pub keep: Vec<PostField>,
+    /// Number of most used tags whose score histogram is computed when the index is built, so
+    /// `top:1%:tag` queries on them don't have to count the scores
+    pub histogram_tags: usize,
+    /// Number of most used tags with a bloom filter of their posts, which speeds up queries
+    /// combining them with rare tags
+    pub bloom_tags: usize,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("index.json"),
+            keep: Vec::new(),
+            histogram_tags: 100,
+            bloom_tags: 50,
+        }
+    }
+}
+
+/// Consistency checks of the indexed posts, see `audit`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Tags which imply a range of ratings, checked by `audit`
+    pub ratings: Vec<RatingRule>,
+}
+
+/// Logging to rotating files, in addition to stderr
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Directory the log files are written to, file logging is off if not set
+    pub dir: Option<PathBuf>,
+    /// File names are this prefix followed by the date
+    pub prefix: String,
+    pub rotation: Rotation,
+    /// Older files are deleted once there are more than this many
+    pub max_files: Option<usize>,
+    /// Filter for the file, in `RUST_LOG` syntax
+    pub level: String,
+    /// Format of stderr and the files
+    pub format: LogFormat,
+    /// Export the tracing spans to an OpenTelemetry collector, needs the `otlp` feature
+    pub otlp: Option<OtlpConfig>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            prefix: String::from("indexer.log"),
+            rotation: Rotation::Daily,
+            max_files: Some(7),
+            level: String::from("info"),
+            format: LogFormat::Text,
+            otlp: None,
+        }
+    }
+}
+
+/// Span export over OTLP/HTTP, e.g. to Jaeger or Tempo
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// The traces endpoint of the collector
+    pub endpoint: String,
+    /// `service.name` of the exported spans
+    pub service_name: String,
+    /// Filter for the exported spans, in `RUST_LOG` syntax
+    pub level: String,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: String::from("http://localhost:4318/v1/traces"),
+            service_name: String::from("indexer"),
+            level: String::from("info"),
+        }
+    }
+}
+
+/// How log lines are written
+///
+/// `json` writes one object per line with the fields of the event at the top level, for log
+/// aggregation. Failed pages are logged with `range` (posts) or `after_id` (tags), `duration_ms`
+/// and `error_kind` (`timeout`, `connect`, `status`, `throttled`, `decode`, `request`, `write` or
+/// `other`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// How often a new log file is started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+/// Cron expressions of the jobs run by `daemon`, see [`crate::schedule`]
+///
+/// Without any job the daemon scrapes once, like `scrape`, while serving.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Scrape the posts newer than the state
+    pub posts: Option<Cron>,
+    /// Scrape every tag again, for changed counts and types
+    pub tags: Option<Cron>,
+    /// Look for edited and deleted posts, like `sync`
+    pub sync: Option<Cron>,
+    /// Update the index with the new lines and reload it
+    pub index: Option<Cron>,
+    /// Drop the outdated records and tombstones from the posts file, see
+    /// [`compact_posts`](crate::maintenance::compact::compact_posts)
+    pub compact: Option<Cron>,
+}
+
+impl ScheduleConfig {
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.posts,
+            &self.tags,
+            &self.sync,
+            &self.index,
+            &self.compact,
+        ]
+        .iter()
+        .all(|cron| cron.is_none())
+    }
+}
+
+/// Settings of the search server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// Bearer tokens accepted by the server, anyone can query it if empty
+    pub api_keys: Vec<String>,
+    /// Requests allowed per key and second
+    pub requests_per_second: NonZeroU32,
+    /// Requests a key may make at once after being idle
+    pub burst: NonZeroU32,
+    pub thumbnails: ThumbnailConfig,
+    /// Index files served next to the main index by namespace name, a query starting with
+    /// `name:` searches the index of the namespace
+    pub namespaces: HashMap<String, PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            api_keys: Vec::new(),
+            requests_per_second: NonZeroU32::new(10).unwrap(),
+            burst: NonZeroU32::new(20).unwrap(),
+            thumbnails: ThumbnailConfig::default(),
+            namespaces: HashMap::new(),
+        }
+    }
+}
+
+/// Settings of the thumbnail proxy of the server
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    /// Upstream URL of a preview, `{md5}`, `{dir}` (`ab/cd` from the md5), `{id}` and `{ext}`
+    /// are replaced. Defaults to `site.urls.preview`.
+    pub url: Option<String>,
+    /// Directory of downloaded files, which are served instead of fetching them, see `download`
+    pub downloads: Option<PathBuf>,
+    /// Fetched previews are kept here
+    pub cache_dir: PathBuf,
+    /// The least recently used previews are removed once the cache grows beyond this size
+    pub max_cache_bytes: u64,
+    /// How long clients may cache a preview, in seconds
+    pub max_age: u64,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            downloads: None,
+            cache_dir: PathBuf::from("thumbnails"),
+            max_cache_bytes: 512 * 1024 * 1024,
+            max_age: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`, select `profile` and apply the environment overrides
+    ///
+    /// A missing file is not an error, the defaults are used instead.
+    pub fn load<P: AsRef<Path>>(path: P, profile: Option<&str>) -> Result<Self, ConfigError> {
+        let mut config = match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(profile) = profile {
+            config.select_profile(profile)?;
+        }
+
+        // A missing .env file is fine, the variables may be set directly
+        dotenvy::dotenv().ok();
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Replace the top level sections with the ones given in the profile
+    pub fn select_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        let profile = self
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?;
+
+        if let Some(site) = profile.site {
+            self.site = site;
+        }
+        if let Some(scraper) = profile.scraper {
+            self.scraper = scraper;
+        }
+        if let Some(output) = profile.output {
+            self.output = output;
+        }
+        if let Some(index) = profile.index {
+            self.index = index;
+        }
+        if let Some(audit) = profile.audit {
+            self.audit = audit;
+        }
+        if let Some(log) = profile.log {
+            self.log = log;
+        }
+        if let Some(server) = profile.server {
+            self.server = server;
+        }
+        if let Some(schedule) = profile.schedule {
+            self.schedule = schedule;
+        }
+        Ok(())
+    }
+
+    /// Override values with the `INDEXER_*` environment variables
+    ///
+    /// The variables named by `api_key_env` and `user_id_env` are also read, as is `ENDPOINT` if
+    /// no endpoint is configured, so existing `.env` files keep working.
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(endpoint) = var("INDEXER_ENDPOINT") {
+            self.site.endpoint = Some(endpoint);
+        } else if self.site.endpoint.is_none() {
+            self.site.endpoint = var("ENDPOINT");
+        }
+        if let Some(api_key) = var("INDEXER_API_KEY").or_else(|| var(&self.site.api_key_env)) {
+            self.site.api_key = Some(api_key);
+        }
+        if let Some(user_id) = var("INDEXER_USER_ID").or_else(|| var(&self.site.user_id_env)) {
+            self.site.user_id = Some(user_id);
+        }
+
+        if let Some(value) = var("INDEXER_REQUESTS_PER_SECOND") {
+            self.scraper.requests_per_second = parse("INDEXER_REQUESTS_PER_SECOND", value)?;
+        }
+        if let Some(value) = var("INDEXER_PARALLEL_REQUESTS") {
+            self.scraper.parallel_requests = parse("INDEXER_PARALLEL_REQUESTS", value)?;
+        }
+
+        if let Some(posts) = var("INDEXER_POSTS") {
+            self.output.posts = posts.into();
+        }
+        if let Some(tags) = var("INDEXER_TAGS") {
+            self.output.tags = tags.into();
+        }
+        if let Some(state) = var("INDEXER_STATE") {
+            self.output.state = state.into();
+        }
+        if let Some(index) = var("INDEXER_INDEX") {
+            self.index.path = index.into();
+        }
+        if let Some(dir) = var("INDEXER_LOG_DIR") {
+            self.log.dir = Some(dir.into());
+        }
+        if let Some(value) = var("INDEXER_LISTEN") {
+            self.server.listen = parse("INDEXER_LISTEN", value)?;
+        }
+        // Comma separated, so keys can be kept out of the file
+        if let Some(keys) = var("INDEXER_SERVER_KEYS") {
+            self.server.api_keys = keys
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// The configured endpoint, which is required for anything talking to the api
+    pub fn endpoint(&self) -> Result<&str, ConfigError> {
+        self.site
+            .endpoint
+            .as_deref()
+            .ok_or(ConfigError::Missing("site.endpoint"))
+    }
+}
+
+fn var(key: &str) -> Option<String> {
+    dotenvy::var(key).ok().filter(|value| !value.is_empty())
+}
+
+fn parse<T: std::str::FromStr>(key: &'static str, value: String) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| ConfigError::InvalidEnv(key, value))
+}
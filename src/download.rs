@@ -0,0 +1,384 @@
+//! Download of post files into a local directory
+//!
+//! Files are stored as `{md5}.{extension}`, so a post that was downloaded before is skipped
+//! without a request. Every file is written to a `.part` file first and only renamed once it is
+//! complete (and, for originals, its md5 matches the post).
+//!
+//! With the `phash` feature and [`hashes`](DownloaderBuilder::hashes) set, a perceptual hash of
+//! every downloaded image is appended to a file, see [`crate::phash`].
+
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use futures::{Stream, StreamExt};
+use governor::{Quota, RateLimiter};
+use md5::{Digest, Md5};
+use serde::Serialize;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+use typed_builder::TypedBuilder;
+
+pub use crate::models::Variant;
+use crate::{
+    models::{Post, PostSimplified, UrlTemplates},
+    scraper::state_manager::{StateManager, Task},
+};
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("IO Error: `{0}`")]
+    Io(#[from] std::io::Error),
+    #[error("Reqwest Error: `{0}`")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("Checksum mismatch: expected `{expected}`, got `{got}`")]
+    Checksum { expected: String, got: String },
+}
+
+/// A single file to download
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub post_id: u64,
+    pub url: String,
+    pub path: PathBuf,
+    /// Expected md5 of the file, only known for originals
+    pub md5: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadOutcome {
+    Downloaded {
+        bytes: u64,
+    },
+    /// The file already exists
+    Skipped,
+}
+
+/// Counts collected while downloading
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DownloadStats {
+    pub downloaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+    /// Files whose perceptual hash was computed, zero unless hashing is enabled
+    pub hashed: u64,
+    /// Files which aren't images, or whose image couldn't be decoded
+    pub unhashable: u64,
+}
+
+#[derive(Debug, TypedBuilder)]
+pub struct Downloader {
+    #[builder(default)]
+    client: reqwest::Client,
+
+    /// Directory the files are stored in
+    #[builder(setter(into))]
+    dest: PathBuf,
+
+    #[builder(default)]
+    variant: Variant,
+
+    /// Used for posts read from an index, which doesn't keep the file urls
+    #[builder(default)]
+    urls: UrlTemplates,
+
+    #[builder(default = NonZeroU32::new(4).unwrap())]
+    requests_per_second: NonZeroU32,
+
+    /// Number of files downloaded concurrently
+    #[builder(default = 4)]
+    parallel_downloads: usize,
+
+    /// Append the perceptual hashes of the downloaded files to this file. Files downloaded
+    /// earlier are hashed as well, unless the file already has a hash of their post.
+    #[cfg(feature = "phash")]
+    #[builder(default, setter(strip_option, into))]
+    hashes: Option<PathBuf>,
+
+    #[cfg(feature = "phash")]
+    #[builder(default)]
+    hash_kind: crate::models::HashKind,
+
+    /// Record the progress of the downloads as its [`Task::Downloads`]
+    #[builder(default)]
+    state_manager: Option<StateManager>,
+}
+
+impl Downloader {
+    /// Where the file of `post` is stored
+    pub fn job(&self, post: &Post) -> DownloadJob {
+        self.job_for_url(post.id, &post.md5, self.variant.url(post))
+    }
+
+    /// Like [`job`](Self::job) for a post of an index, with the url filled in from the templates
+    pub fn index_job(&self, post: &PostSimplified) -> DownloadJob {
+        let url = post.file_url(self.variant, &self.urls);
+        self.job_for_url(post.id as u64, &hex::encode(post.md5), &url)
+    }
+
+    fn job_for_url(&self, post_id: u64, md5: &str, url: &str) -> DownloadJob {
+        let extension = url
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .filter(|extension| !extension.contains('/'))
+            .unwrap_or("bin");
+        let extension = extension.split(['?', '#']).next().unwrap_or(extension);
+
+        DownloadJob {
+            post_id,
+            url: url.to_string(),
+            path: self.dest.join(format!("{}.{}", md5, extension)),
+            md5: (self.variant == Variant::Original).then(|| md5.to_lowercase()),
+        }
+    }
+
+    /// Download a single file, retrying server and network errors with a backoff strategy
+    pub async fn download(&self, job: &DownloadJob) -> Result<DownloadOutcome, DownloadError> {
+        if tokio::fs::try_exists(&job.path).await? {
+            return Ok(DownloadOutcome::Skipped);
+        }
+
+        let bytes = backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
+            // Client errors (e.g. a deleted file) and a file not matching the post won't succeed
+            // on a retry
+            self.fetch(job).await.map_err(|e| match &e {
+                DownloadError::Reqwest(inner)
+                    if inner
+                        .status()
+                        .is_some_and(|status| status.is_client_error()) =>
+                {
+                    backoff::Error::permanent(e)
+                }
+                DownloadError::Checksum { .. } => backoff::Error::permanent(e),
+                _ => backoff::Error::transient(e),
+            })
+        })
+        .await?;
+
+        Ok(DownloadOutcome::Downloaded { bytes })
+    }
+
+    async fn fetch(&self, job: &DownloadJob) -> Result<u64, DownloadError> {
+        let part_path = part_path(&job.path);
+        let mut file = tokio::fs::File::create(&part_path).await?;
+        let mut hasher = Md5::new();
+        let mut bytes = 0;
+
+        let mut stream = self
+            .client
+            .get(&job.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            bytes += chunk.len() as u64;
+        }
+        file.flush().await?;
+        drop(file);
+
+        if let Some(expected) = &job.md5 {
+            let got = hex::encode(hasher.finalize());
+            if &got != expected {
+                tokio::fs::remove_file(&part_path).await?;
+                return Err(DownloadError::Checksum {
+                    expected: expected.clone(),
+                    got,
+                });
+            }
+        }
+
+        tokio::fs::rename(&part_path, &job.path).await?;
+        Ok(bytes)
+    }
+
+    /// Download the files of every post, skipping the ones that already exist
+    pub async fn download_all(
+        &self,
+        posts: impl IntoIterator<Item = Post>,
+    ) -> Result<DownloadStats, DownloadError> {
+        self.download_all_with_progress(posts, |_| {}).await
+    }
+
+    /// Like [`download_all`](Self::download_all), calling `progress` after every file
+    pub async fn download_all_with_progress(
+        &self,
+        posts: impl IntoIterator<Item = Post>,
+        progress: impl FnMut(&DownloadStats),
+    ) -> Result<DownloadStats, DownloadError> {
+        let jobs = posts.into_iter().map(|post| self.job(&post));
+        self.download_jobs_with_progress(jobs, progress).await
+    }
+
+    /// Download every job, skipping the files that already exist and calling `progress` after
+    /// every one
+    pub async fn download_jobs_with_progress(
+        &self,
+        jobs: impl IntoIterator<Item = DownloadJob>,
+        progress: impl FnMut(&DownloadStats),
+    ) -> Result<DownloadStats, DownloadError> {
+        self.download_job_stream_with_progress(futures::stream::iter(jobs), progress)
+            .await
+    }
+
+    /// Like [`download_jobs_with_progress`](Self::download_jobs_with_progress), taking the jobs
+    /// from a stream
+    ///
+    /// The next job is only pulled once a download slot is free, so the jobs can come from a
+    /// [`QueryStream`](crate::stream::QueryStream) without collecting them first.
+    pub async fn download_job_stream_with_progress(
+        &self,
+        jobs: impl Stream<Item = DownloadJob>,
+        mut progress: impl FnMut(&DownloadStats),
+    ) -> Result<DownloadStats, DownloadError> {
+        tokio::fs::create_dir_all(&self.dest).await?;
+        let limiter = RateLimiter::direct(Quota::per_second(self.requests_per_second));
+        let mut stats = DownloadStats::default();
+        if let Some(state_manager) = &self.state_manager {
+            // The number of jobs is only known if the stream knows it exactly, e.g. for a list
+            let total = match jobs.size_hint() {
+                (lower, Some(upper)) if lower == upper => Some(upper as u64),
+                _ => None,
+            };
+            state_manager.start_task(Task::Downloads, total).await;
+        }
+
+        let jobs = std::pin::pin!(jobs);
+        let mut results = jobs
+            .map(|job| {
+                let limiter = &limiter;
+                async move {
+                    if let Some(state_manager) = &self.state_manager {
+                        state_manager.start_unit(Task::Downloads).await;
+                    }
+                    if !tokio::fs::try_exists(&job.path).await.unwrap_or(false) {
+                        limiter.until_ready().await;
+                    }
+                    let result = self.download(&job).await;
+                    (job, result)
+                }
+            })
+            .buffer_unordered(self.parallel_downloads.max(1));
+
+        #[cfg(feature = "phash")]
+        let mut finished = Vec::new();
+        while let Some((job, result)) = results.next().await {
+            #[cfg(feature = "phash")]
+            if result.is_ok() {
+                finished.push(job.clone());
+            }
+            if let Some(state_manager) = &self.state_manager {
+                match result {
+                    Ok(DownloadOutcome::Downloaded { .. }) => {
+                        state_manager.complete_unit(Task::Downloads, 1).await
+                    }
+                    Ok(DownloadOutcome::Skipped) => {
+                        state_manager.complete_unit(Task::Downloads, 0).await
+                    }
+                    Err(_) => state_manager.fail_unit(Task::Downloads).await,
+                }
+            }
+            match result {
+                Ok(DownloadOutcome::Downloaded { bytes }) => {
+                    stats.downloaded += 1;
+                    stats.bytes += bytes;
+                    if stats.downloaded % 100 == 0 {
+                        info!("Downloaded {} files", stats.downloaded);
+                    }
+                }
+                Ok(DownloadOutcome::Skipped) => stats.skipped += 1,
+                Err(e) => {
+                    error!("Failed to download post {}: {}", job.post_id, e);
+                    stats.failed += 1;
+                }
+            }
+            progress(&stats);
+        }
+        drop(results);
+        if let Some(state_manager) = &self.state_manager {
+            state_manager.finish_task(Task::Downloads).await;
+        }
+
+        #[cfg(feature = "phash")]
+        if let Some(path) = &self.hashes {
+            self.hash_files(finished, path, &mut stats).await?;
+        }
+        Ok(stats)
+    }
+
+    /// Append the hashes of the files of `jobs` whose post isn't in the hashes file yet
+    #[cfg(feature = "phash")]
+    async fn hash_files(
+        &self,
+        jobs: Vec<DownloadJob>,
+        path: &Path,
+        stats: &mut DownloadStats,
+    ) -> Result<(), DownloadError> {
+        use std::{collections::HashSet, io::BufRead};
+
+        use crate::models::{
+            envelope::{parse_record, Envelope},
+            PerceptualHash,
+        };
+
+        let mut hashed = HashSet::new();
+        if let Ok(file) = std::fs::File::open(path) {
+            for line in std::io::BufReader::new(file).lines() {
+                if let Ok(hash) = parse_record::<PerceptualHash>(&line?) {
+                    if hash.kind == self.hash_kind {
+                        hashed.insert(hash.post_id);
+                    }
+                }
+            }
+        }
+        let mut out = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+
+        let kind = self.hash_kind;
+        let mut results = futures::stream::iter(
+            jobs.into_iter()
+                .filter(|job| !hashed.contains(&job.post_id)),
+        )
+        .map(|job| {
+            tokio::task::spawn_blocking(move || {
+                let hash = crate::phash::hash_post_file(job.post_id, &job.path, kind);
+                (job.post_id, hash)
+            })
+        })
+        .buffer_unordered(self.parallel_downloads.max(1));
+
+        while let Some(result) = results.next().await {
+            let (post_id, hash) = result.map_err(std::io::Error::other)?;
+            match hash {
+                Ok(hash) => {
+                    let mut line =
+                        serde_json::to_vec(&Envelope::new(hash)).map_err(std::io::Error::other)?;
+                    line.push(b'\n');
+                    out.write_all(&line).await?;
+                    stats.hashed += 1;
+                }
+                Err(e) => {
+                    tracing::debug!("Can't hash the file of post {}: {}", post_id, e);
+                    stats.unhashable += 1;
+                }
+            }
+        }
+        out.flush().await?;
+        Ok(())
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
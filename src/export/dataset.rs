@@ -0,0 +1,222 @@
+//! Export of train/validation/test manifests for machine learning
+//!
+//! Every post is assigned to a split by a hash of its id and the seed, so the same seed always
+//! gives the same splits, and a post keeps its split when the dataset is exported again from a
+//! grown index. The split sizes follow the ratios closely for large datasets, but not exactly.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use crate::index::Index;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatasetFormat {
+    #[default]
+    Jsonl,
+    /// Tags are joined with spaces
+    Csv,
+}
+
+impl FromStr for DatasetFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(DatasetFormat::Jsonl),
+            "csv" => Ok(DatasetFormat::Csv),
+            _ => Err(format!("unknown format `{s}`, expected jsonl or csv")),
+        }
+    }
+}
+
+impl DatasetFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DatasetFormat::Jsonl => "jsonl",
+            DatasetFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DatasetOptions {
+    /// Share of the posts in each split, normalized so they don't have to add up to 1
+    pub train: f64,
+    pub val: f64,
+    pub test: f64,
+    pub seed: u64,
+    pub format: DatasetFormat,
+    /// The `--dest` of `download`, the path of a post's file is included if it was downloaded
+    pub downloads: Option<PathBuf>,
+}
+
+impl Default for DatasetOptions {
+    fn default() -> Self {
+        Self {
+            train: 0.8,
+            val: 0.1,
+            test: 0.1,
+            seed: 0,
+            format: DatasetFormat::default(),
+            downloads: None,
+        }
+    }
+}
+
+/// One line of a manifest
+#[derive(Debug, Clone, Serialize)]
+pub struct DatasetEntry {
+    pub post_id: u32,
+    pub md5: String,
+    pub path: Option<PathBuf>,
+    pub tags: Vec<String>,
+    pub rating: Option<String>,
+}
+
+/// Number of posts written to every split
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DatasetStats {
+    pub train: u64,
+    pub val: u64,
+    pub test: u64,
+    /// Posts whose file was found in the downloads
+    pub downloaded: u64,
+}
+
+const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+/// Write `train`, `val` and `test` manifests of the posts in `post_ids` into `dir`
+pub fn export_dataset<P: AsRef<Path>>(
+    index: &Index,
+    post_ids: &RoaringBitmap,
+    options: &DatasetOptions,
+    dir: P,
+) -> std::io::Result<DatasetStats> {
+    let ratios = [options.train, options.val, options.test];
+    let total: f64 = ratios.iter().sum();
+    if ratios
+        .iter()
+        .any(|ratio| !ratio.is_finite() || *ratio < 0.0)
+        || total <= 0.0
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the split ratios must not be negative and add up to more than 0",
+        ));
+    }
+    let bounds = [options.train / total, (options.train + options.val) / total];
+
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+    let mut outputs = Vec::with_capacity(SPLITS.len());
+    for split in SPLITS {
+        let path = dir.join(format!("{split}.{}", options.format.extension()));
+        let mut output = BufWriter::new(File::create(path)?);
+        if options.format == DatasetFormat::Csv {
+            writeln!(output, "post_id,md5,path,tags,rating")?;
+        }
+        outputs.push(output);
+    }
+
+    let tags = index.tags_for_posts(post_ids);
+    let mut ratings: HashMap<u32, &str> = HashMap::new();
+    for (rating, ids) in &index.rating_to_post_id {
+        for id in ids & post_ids {
+            ratings.insert(id, rating);
+        }
+    }
+
+    let mut stats = DatasetStats::default();
+    for post_id in post_ids {
+        let Some(post) = index.post_id_to_post.get(&post_id) else {
+            continue;
+        };
+        let md5 = hex::encode(post.md5);
+        let path = options.downloads.as_ref().and_then(|downloads| {
+            // Originals keep their extension, samples and previews are stored as jpg
+            [post.extension.as_str(), "jpg"]
+                .iter()
+                .map(|extension| downloads.join(format!("{md5}.{extension}")))
+                .find(|path| path.exists())
+        });
+        if path.is_some() {
+            stats.downloaded += 1;
+        }
+        let entry = DatasetEntry {
+            post_id,
+            md5,
+            path,
+            tags: tags.get(&post_id).cloned().unwrap_or_default(),
+            rating: ratings.get(&post_id).map(|rating| rating.to_string()),
+        };
+
+        let position = unit(options.seed, post_id);
+        let split = bounds.iter().filter(|bound| position >= **bound).count();
+        match split {
+            0 => stats.train += 1,
+            1 => stats.val += 1,
+            _ => stats.test += 1,
+        }
+        write_entry(&mut outputs[split], &entry, options.format)?;
+    }
+
+    for output in &mut outputs {
+        output.flush()?;
+    }
+    Ok(stats)
+}
+
+fn write_entry(
+    output: &mut impl Write,
+    entry: &DatasetEntry,
+    format: DatasetFormat,
+) -> std::io::Result<()> {
+    match format {
+        DatasetFormat::Jsonl => {
+            serde_json::to_writer(&mut *output, entry)?;
+            writeln!(output)
+        }
+        DatasetFormat::Csv => {
+            let path = entry
+                .path
+                .as_ref()
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_default();
+            writeln!(
+                output,
+                "{},{},{},{},{}",
+                entry.post_id,
+                entry.md5,
+                csv_field(&path),
+                csv_field(&entry.tags.join(" ")),
+                entry.rating.as_deref().unwrap_or_default()
+            )
+        }
+    }
+}
+
+/// Quote a field if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A number in `0.0..1.0` derived from the seed and the post id (splitmix64)
+pub(crate) fn unit(seed: u64, post_id: u32) -> f64 {
+    let mut z = seed ^ (post_id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
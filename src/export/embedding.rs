@@ -0,0 +1,336 @@
+//! Tag embeddings from the co-occurrence of tags on posts
+//!
+//! [`ppmi`] counts how often every pair of the most used tags appears on the same post and weighs
+//! the counts by positive pointwise mutual information, so pairs seen together more often than
+//! their frequency suggests score high. [`PpmiMatrix::factorize`] reduces the sparse matrix to a
+//! dense vector per tag with a truncated eigendecomposition. Tags with a high cosine similarity of
+//! their vectors are used in the same contexts, which is what tag clustering and query expansion
+//! need.
+
+use std::{collections::HashMap, io::Write};
+
+use rayon::prelude::*;
+use roaring::RoaringBitmap;
+use serde::Serialize;
+
+use crate::index::Index;
+
+/// Sparse, symmetric PPMI weights of the tag pairs, see the [module docs](self)
+#[derive(Debug, Clone, Default)]
+pub struct PpmiMatrix {
+    /// The tags of the rows and columns, most used first
+    pub tags: Vec<String>,
+    /// Column and weight of the non-zero entries of every row, ordered by column
+    pub rows: Vec<Vec<(u32, f32)>>,
+}
+
+/// A vector per tag, see [`PpmiMatrix::factorize`]
+#[derive(Debug, Clone, Default)]
+pub struct Embeddings {
+    pub tags: Vec<String>,
+    pub dimensions: usize,
+    /// The vectors of the tags one after another, `dimensions` values each
+    pub vectors: Vec<f32>,
+}
+
+/// Size of the computed matrix, for reports
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct EmbeddingStats {
+    pub posts: u64,
+    pub tags: u64,
+    /// Non-zero entries of the PPMI matrix
+    pub pairs: u64,
+    pub dimensions: u64,
+}
+
+/// Weigh the co-occurrences of the `vocabulary` most used tags of the posts in `post_ids`
+///
+/// Tags on fewer than `min_count` of the posts are left out.
+pub fn ppmi(
+    index: &Index,
+    post_ids: &RoaringBitmap,
+    vocabulary: usize,
+    min_count: u64,
+) -> PpmiMatrix {
+    let names: HashMap<u32, &str> = index
+        .tag_str_to_id
+        .iter()
+        .map(|(name, id)| (*id, name.as_str()))
+        .collect();
+    let mut tags: Vec<(&str, RoaringBitmap)> = index
+        .tag_id_to_post_id
+        .iter()
+        .filter_map(|(id, bitmap)| {
+            let posts = bitmap & post_ids;
+            (posts.len() >= min_count.max(1)).then_some((*names.get(id)?, posts))
+        })
+        .collect();
+    tags.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+    tags.truncate(vocabulary);
+
+    // The index only maps tags to posts, the pairs need the tags of every post
+    let mut post_tags: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (column, (_, posts)) in tags.iter().enumerate() {
+        for post_id in posts {
+            post_tags.entry(post_id).or_default().push(column as u32);
+        }
+    }
+
+    let counts: Vec<Vec<(u32, u32)>> = tags
+        .par_iter()
+        .enumerate()
+        .map(|(row, (_, posts))| {
+            let mut dense = vec![0u32; tags.len()];
+            for post_id in posts {
+                for column in &post_tags[&post_id] {
+                    dense[*column as usize] += 1;
+                }
+            }
+            dense[row] = 0;
+            dense
+                .into_iter()
+                .enumerate()
+                .filter(|(_, count)| *count > 0)
+                .map(|(column, count)| (column as u32, count))
+                .collect()
+        })
+        .collect();
+
+    let row_sums: Vec<f64> = counts
+        .iter()
+        .map(|row| row.iter().map(|(_, count)| *count as f64).sum())
+        .collect();
+    let total: f64 = row_sums.iter().sum();
+    let rows = counts
+        .iter()
+        .enumerate()
+        .map(|(row, entries)| {
+            entries
+                .iter()
+                .filter_map(|(column, count)| {
+                    let pmi =
+                        (*count as f64 * total / (row_sums[row] * row_sums[*column as usize])).ln();
+                    (pmi > 0.0).then_some((*column, pmi as f32))
+                })
+                .collect()
+        })
+        .collect();
+
+    PpmiMatrix {
+        tags: tags.into_iter().map(|(name, _)| name.to_string()).collect(),
+        rows,
+    }
+}
+
+impl PpmiMatrix {
+    /// Number of non-zero entries
+    pub fn pairs(&self) -> u64 {
+        self.rows.iter().map(|row| row.len() as u64).sum()
+    }
+
+    /// Write the non-zero entries as `tag_a tag_b weight` lines, for tools doing their own
+    /// factorization
+    pub fn write<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        for (row, entries) in self.rows.iter().enumerate() {
+            for (column, weight) in entries {
+                writeln!(
+                    out,
+                    "{} {} {}",
+                    self.tags[row], self.tags[*column as usize], weight
+                )?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Vectors of `dimensions` values for every tag
+    ///
+    /// Finds the eigenvectors of the largest eigenvalues by subspace iteration started from
+    /// random vectors (`seed` makes it reproducible) and scales them by the square root of their
+    /// eigenvalue, like an SVD of the matrix. More `iterations` give more accurate vectors.
+    pub fn factorize(&self, dimensions: usize, iterations: usize, seed: u64) -> Embeddings {
+        let n = self.tags.len();
+        let k = dimensions.min(n);
+        if k == 0 {
+            return Embeddings {
+                tags: self.tags.clone(),
+                dimensions,
+                vectors: vec![0.0; n * dimensions],
+            };
+        }
+
+        // Column major, one basis vector of length n after another
+        let mut state = seed;
+        let mut basis: Vec<f64> = (0..n * k)
+            .map(|_| splitmix(&mut state) as f64 / u64::MAX as f64 - 0.5)
+            .collect();
+        orthonormalize(&mut basis, n);
+        for _ in 0..iterations {
+            basis = self.multiply(&basis);
+            orthonormalize(&mut basis, n);
+        }
+
+        // Rayleigh-Ritz: the eigenvectors of the projected k x k matrix rotate the basis onto the
+        // eigenvectors of the matrix
+        let product = self.multiply(&basis);
+        let mut projected = vec![0.0; k * k];
+        for a in 0..k {
+            for b in 0..k {
+                projected[a * k + b] =
+                    dot(&basis[a * n..(a + 1) * n], &product[b * n..(b + 1) * n]);
+            }
+        }
+        let (values, rotation) = jacobi_eigen(projected, k);
+        let mut order: Vec<usize> = (0..k).collect();
+        order.sort_unstable_by(|a, b| values[*b].abs().total_cmp(&values[*a].abs()));
+
+        let mut vectors = vec![0.0f32; n * dimensions];
+        for (dimension, component) in order.into_iter().enumerate() {
+            let scale = values[component].abs().sqrt();
+            for row in 0..n {
+                let value: f64 = (0..k)
+                    .map(|b| basis[b * n + row] * rotation[b * k + component])
+                    .sum();
+                vectors[row * dimensions + dimension] = (value * scale) as f32;
+            }
+        }
+        Embeddings {
+            tags: self.tags.clone(),
+            dimensions,
+            vectors,
+        }
+    }
+
+    /// Multiply the matrix with every column of a column major `n x k` matrix
+    fn multiply(&self, columns: &[f64]) -> Vec<f64> {
+        let n = self.tags.len();
+        let k = columns.len() / n;
+        let rows: Vec<Vec<f64>> = self
+            .rows
+            .par_iter()
+            .map(|entries| {
+                (0..k)
+                    .map(|b| {
+                        entries
+                            .iter()
+                            .map(|(column, weight)| {
+                                *weight as f64 * columns[b * n + *column as usize]
+                            })
+                            .sum()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut product = vec![0.0; n * k];
+        for (row, values) in rows.into_iter().enumerate() {
+            for (b, value) in values.into_iter().enumerate() {
+                product[b * n + row] = value;
+            }
+        }
+        product
+    }
+}
+
+impl Embeddings {
+    /// The vector of the tag at `row` of [`Embeddings::tags`]
+    pub fn vector(&self, row: usize) -> &[f32] {
+        &self.vectors[row * self.dimensions..(row + 1) * self.dimensions]
+    }
+
+    /// Write the vectors in the word2vec text format understood by gensim and fastText
+    pub fn write_word2vec<W: Write>(&self, mut out: W) -> std::io::Result<()> {
+        writeln!(out, "{} {}", self.tags.len(), self.dimensions)?;
+        for (row, tag) in self.tags.iter().enumerate() {
+            write!(out, "{tag}")?;
+            for value in self.vector(row) {
+                write!(out, " {value:.6}")?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    }
+}
+
+/// Modified Gram-Schmidt on the columns of a column major matrix with `n` rows
+///
+/// A column which is (nearly) dependent on the previous ones is zeroed, which happens if the
+/// matrix has a lower rank than the number of columns.
+fn orthonormalize(columns: &mut [f64], n: usize) {
+    let k = columns.len() / n;
+    for b in 0..k {
+        let (previous, rest) = columns.split_at_mut(b * n);
+        let column = &mut rest[..n];
+        for a in 0..b {
+            let other = &previous[a * n..(a + 1) * n];
+            let projection = dot(column, other);
+            column
+                .iter_mut()
+                .zip(other)
+                .for_each(|(x, y)| *x -= projection * y);
+        }
+        let norm = dot(column, column).sqrt();
+        if norm > 1e-12 {
+            column.iter_mut().for_each(|x| *x /= norm);
+        } else {
+            column.iter_mut().for_each(|x| *x = 0.0);
+        }
+    }
+}
+
+/// Eigenvalues and column major eigenvectors of a symmetric `k x k` matrix (cyclic Jacobi)
+fn jacobi_eigen(mut matrix: Vec<f64>, k: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut vectors = vec![0.0; k * k];
+    for i in 0..k {
+        vectors[i * k + i] = 1.0;
+    }
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..k)
+            .flat_map(|i| (0..k).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| matrix[i * k + j].powi(2))
+            .sum();
+        if off_diagonal < 1e-20 {
+            break;
+        }
+        for p in 0..k {
+            for q in p + 1..k {
+                let apq = matrix[p * k + q];
+                if apq.abs() < 1e-30 {
+                    continue;
+                }
+                let theta = (matrix[q * k + q] - matrix[p * k + p]) / (2.0 * apq);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for i in 0..k {
+                    let (aip, aiq) = (matrix[i * k + p], matrix[i * k + q]);
+                    matrix[i * k + p] = c * aip - s * aiq;
+                    matrix[i * k + q] = s * aip + c * aiq;
+                }
+                for i in 0..k {
+                    let (api, aqi) = (matrix[p * k + i], matrix[q * k + i]);
+                    matrix[p * k + i] = c * api - s * aqi;
+                    matrix[q * k + i] = s * api + c * aqi;
+                }
+                for i in 0..k {
+                    let (vip, viq) = (vectors[i * k + p], vectors[i * k + q]);
+                    vectors[i * k + p] = c * vip - s * viq;
+                    vectors[i * k + q] = s * vip + c * viq;
+                }
+            }
+        }
+    }
+    ((0..k).map(|i| matrix[i * k + i]).collect(), vectors)
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn splitmix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,52 @@
+//! Export of Hydrus-importable sidecar tag files
+//!
+//! For every post a `{md5}.{ext}.txt` file is written containing one tag per line, which Hydrus
+//! picks up when importing `{md5}.{ext}` from the same folder with sidecar import enabled.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use roaring::RoaringBitmap;
+
+use crate::index::Index;
+
+/// Counts collected while exporting sidecars
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HydrusExportStats {
+    pub files: u64,
+    pub tags: u64,
+}
+
+/// Write a sidecar tag file for every post in `post_ids` into `dir`
+///
+/// Underscores in tag names are replaced with spaces to match Hydrus' tag conventions.
+pub fn write_sidecars<P: AsRef<Path>>(
+    index: &Index,
+    post_ids: &RoaringBitmap,
+    dir: P,
+) -> Result<HydrusExportStats, std::io::Error> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)?;
+
+    let mut stats = HydrusExportStats::default();
+    let tags = index.tags_for_posts(post_ids);
+    for post_id in post_ids {
+        let Some(post) = index.post_id_to_post.get(&post_id) else {
+            continue;
+        };
+
+        let file_name = format!("{}.{}.txt", hex::encode(post.md5), post.extension.as_str());
+        let mut output = BufWriter::new(File::create(dir.join(file_name))?);
+        for tag in tags.get(&post_id).into_iter().flatten() {
+            writeln!(output, "{}", tag.replace('_', " "))?;
+            stats.tags += 1;
+        }
+        output.flush()?;
+        stats.files += 1;
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,7 @@
+//! Exporters turning index query results into formats understood by other applications
+
+pub mod dataset;
+pub mod embedding;
+pub mod hydrus;
+#[cfg(feature = "scraper")]
+pub mod szurubooru;
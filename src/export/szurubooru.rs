@@ -0,0 +1,144 @@
+//! Upload of posts into a self-hosted Szurubooru instance
+//!
+//! Posts are created through the REST API with a `contentUrl`, so Szurubooru fetches the files
+//! itself. The ids of uploaded posts are stored in a progress file, which lets an interrupted
+//! migration resume where it stopped.
+
+use std::{num::NonZeroU32, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use governor::{Quota, RateLimiter};
+use roaring::RoaringBitmap;
+use serde::Serialize;
+use tracing::{error, info};
+use typed_builder::TypedBuilder;
+
+use crate::models::{Post, Rating};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreatePost<'a> {
+    tags: &'a [String],
+    safety: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    content_url: &'a str,
+}
+
+/// Counts collected while uploading
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UploadStats {
+    pub uploaded: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, TypedBuilder)]
+pub struct SzurubooruUploader {
+    #[builder(default)]
+    client: reqwest::Client,
+
+    /// Base url of the instance, e.g. `https://booru.example.com`
+    #[builder(setter(into))]
+    url: String,
+
+    #[builder(setter(into))]
+    user: String,
+
+    /// A login token created in the Szurubooru account settings
+    #[builder(setter(into))]
+    token: String,
+
+    #[builder(default = NonZeroU32::new(2).unwrap())]
+    requests_per_second: NonZeroU32,
+
+    #[builder(default = PathBuf::from("szurubooru_progress.json"), setter(into))]
+    progress_file: PathBuf,
+}
+
+impl SzurubooruUploader {
+    fn load_progress(&self) -> RoaringBitmap {
+        std::fs::File::open(&self.progress_file)
+            .ok()
+            .and_then(|file| serde_json::from_reader(std::io::BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_progress(&self, uploaded: &RoaringBitmap) -> Result<(), Box<dyn std::error::Error>> {
+        let file = std::fs::File::create(&self.progress_file)?;
+        serde_json::to_writer(file, uploaded)?;
+        Ok(())
+    }
+
+    /// Create a single post
+    pub async fn upload(&self, post: &Post) -> Result<(), reqwest::Error> {
+        let safety = match post.rating {
+            Rating::Safe => "safe",
+            Rating::Sensitive | Rating::Questionable => "sketchy",
+            // Err on the safe side for ratings szurubooru doesn't know
+            Rating::Explicit | Rating::Other(_) => "unsafe",
+        };
+        let body = CreatePost {
+            tags: &post.tags,
+            safety,
+            source: post.source.as_deref(),
+            content_url: &post.original.url,
+        };
+        let auth = STANDARD.encode(format!("{}:{}", self.user, self.token));
+
+        self.client
+            .post(format!("{}/api/posts/", self.url.trim_end_matches('/')))
+            .header("Authorization", format!("Token {auth}"))
+            .header("Accept", "application/json")
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Upload every post that has not been uploaded by a previous run
+    pub async fn upload_all(
+        &self,
+        posts: impl IntoIterator<Item = Post>,
+    ) -> Result<UploadStats, Box<dyn std::error::Error>> {
+        let limiter = RateLimiter::direct(Quota::per_second(self.requests_per_second));
+        let mut uploaded = self.load_progress();
+        let mut stats = UploadStats::default();
+
+        for post in posts {
+            if uploaded.contains(post.id as u32) {
+                stats.skipped += 1;
+                continue;
+            }
+
+            limiter.until_ready().await;
+            let result = backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
+                // Client errors (e.g. an already uploaded file) won't succeed on a retry
+                self.upload(&post).await.map_err(|e| match e.status() {
+                    Some(status) if status.is_client_error() => backoff::Error::permanent(e),
+                    _ => backoff::Error::transient(e),
+                })
+            })
+            .await;
+
+            match result {
+                Ok(()) => {
+                    uploaded.insert(post.id as u32);
+                    stats.uploaded += 1;
+                    if stats.uploaded % 100 == 0 {
+                        self.save_progress(&uploaded)?;
+                        info!("Uploaded {} posts to szurubooru", stats.uploaded);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to upload post {}: {}", post.id, e);
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        self.save_progress(&uploaded)?;
+        Ok(stats)
+    }
+}
@@ -0,0 +1,213 @@
+//! A C API for the query engine, declared in `include/indexer.h`
+//!
+//! ```c
+//! IndexerIndex *index = indexer_index_open("index.json");
+//! uint32_t *ids;
+//! size_t len;
+//! if (indexer_query(index, "cat -dog", 100, &ids, &len) == INDEXER_STATUS_OK) {
+//!     // ids[0..len] in result order
+//!     indexer_ids_free(ids, len);
+//! } else {
+//!     fprintf(stderr, "%s\n", indexer_last_error());
+//! }
+//! indexer_index_free(index);
+//! ```
+//!
+//! The header is written by the build when the `ffi` feature is enabled, and the library is built
+//! with `cargo rustc --release --lib --no-default-features --features ffi --crate-type cdylib`
+//! (or `staticlib`). An opened index is immutable, so it can be queried from several threads.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    ptr,
+};
+
+use crate::{index::Index, query::Query};
+
+/// An index opened with [`indexer_index_open`]
+pub struct IndexerIndex {
+    index: Index,
+}
+
+/// Result of the functions which can fail, see [`indexer_last_error`] for the message
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexerStatus {
+    Ok = 0,
+    /// A required pointer was null
+    NullArgument = 1,
+    /// A string wasn't valid UTF-8
+    InvalidUtf8 = 2,
+    /// The query couldn't be parsed
+    InvalidQuery = 3,
+    /// The index couldn't be read
+    Io = 4,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(status: IndexerStatus, message: impl ToString) -> IndexerStatus {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+    status
+}
+
+/// The message of the last error on this thread, null if there was none
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn indexer_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| {
+        error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Load an index saved by `indexer index build`, null on failure
+///
+/// # Safety
+///
+/// `path` must be null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_index_open(path: *const c_char) -> *mut IndexerIndex {
+    if path.is_null() {
+        set_error(IndexerStatus::NullArgument, "path is null");
+        return ptr::null_mut();
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(e) => {
+            set_error(IndexerStatus::InvalidUtf8, e);
+            return ptr::null_mut();
+        }
+    };
+    match Index::load(path) {
+        Ok(index) => Box::into_raw(Box::new(IndexerIndex { index })),
+        Err(e) => {
+            set_error(IndexerStatus::Io, e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free an index, null is ignored
+///
+/// # Safety
+///
+/// `index` must be null or returned by [`indexer_index_open`] and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_index_free(index: *mut IndexerIndex) {
+    if !index.is_null() {
+        drop(Box::from_raw(index));
+    }
+}
+
+/// Number of posts in the index
+///
+/// # Safety
+///
+/// `index` must be returned by [`indexer_index_open`] and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_index_len(index: *const IndexerIndex) -> usize {
+    index
+        .as_ref()
+        .map_or(0, |index| index.index.post_id_to_post.len())
+}
+
+/// Parse a query and check the arguments shared by [`indexer_query`] and [`indexer_count`]
+unsafe fn parse_query<'a>(
+    index: *const IndexerIndex,
+    query: *const c_char,
+) -> Result<(&'a Index, Query), IndexerStatus> {
+    let (Some(index), false) = (index.as_ref(), query.is_null()) else {
+        return Err(set_error(
+            IndexerStatus::NullArgument,
+            "index or query is null",
+        ));
+    };
+    let query = CStr::from_ptr(query)
+        .to_str()
+        .map_err(|e| set_error(IndexerStatus::InvalidUtf8, e))?;
+    let query = Query::parse(query).map_err(|e| set_error(IndexerStatus::InvalidQuery, e))?;
+    Ok((&index.index, query))
+}
+
+/// Run a query, storing the ids of at most `limit` matching posts (all of them if `limit` is 0)
+/// in result order
+///
+/// On success `*ids` points to `*len` ids, which have to be freed with [`indexer_ids_free`]. On
+/// failure `*ids` is null and `*len` is 0.
+///
+/// # Safety
+///
+/// `index` must be returned by [`indexer_index_open`] and not freed yet, `query` must be a
+/// nul-terminated string, and `ids` and `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_query(
+    index: *const IndexerIndex,
+    query: *const c_char,
+    limit: usize,
+    ids: *mut *mut u32,
+    len: *mut usize,
+) -> IndexerStatus {
+    if ids.is_null() || len.is_null() {
+        return set_error(IndexerStatus::NullArgument, "ids or len is null");
+    }
+    *ids = ptr::null_mut();
+    *len = 0;
+    let (index, query) = match parse_query(index, query) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+
+    let post_ids = index.search(&query);
+    let limit = match limit {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let results: Box<[u32]> = index.ordered(&query, &post_ids, None).take(limit).collect();
+    *len = results.len();
+    *ids = Box::into_raw(results).cast();
+    IndexerStatus::Ok
+}
+
+/// Free the ids returned by [`indexer_query`], null is ignored
+///
+/// # Safety
+///
+/// `ids` and `len` must be exactly as returned by [`indexer_query`], and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_ids_free(ids: *mut u32, len: usize) {
+    if !ids.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(ids, len)));
+    }
+}
+
+/// Count the posts matching a query into `*count`
+///
+/// # Safety
+///
+/// `index` must be returned by [`indexer_index_open`] and not freed yet, `query` must be a
+/// nul-terminated string, and `count` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn indexer_count(
+    index: *const IndexerIndex,
+    query: *const c_char,
+    count: *mut u64,
+) -> IndexerStatus {
+    if count.is_null() {
+        return set_error(IndexerStatus::NullArgument, "count is null");
+    }
+    match parse_query(index, query) {
+        Ok((index, query)) => {
+            *count = index.search(&query).len();
+            IndexerStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
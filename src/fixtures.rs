@@ -0,0 +1,183 @@
+//! Synthetic posts and tags for tests and benchmarks
+//!
+//! [`Fixtures`] generates valid records from a seed, the same seed always gives the same records.
+//! Posts get ascending ids and take their tags from the tags generated before them, with the first
+//! tags picked far more often like the popular tags of a real site. Generate the tags first, e.g.
+//! `fixtures.tags(100)` followed by `fixtures.posts(1000)`.
+//!
+//! Every field is public, so a record can be adjusted after it was generated.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::models::{Post, Rating, Tag, TagType, UrlTemplates, Variant, Varient};
+
+const SYLLABLES: [&str; 16] = [
+    "ka", "ri", "to", "me", "su", "no", "ha", "ru", "mi", "ya", "ko", "ne", "sa", "chi", "re", "na",
+];
+
+const EXTENSIONS: [&str; 5] = ["jpg", "jpg", "png", "gif", "webm"];
+
+/// Generator of synthetic records, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct Fixtures {
+    /// State of a splitmix64 generator, good enough for test data and without a dependency
+    state: u64,
+    next_post_id: u64,
+    next_tag_id: u64,
+    tags: Vec<Tag>,
+    urls: UrlTemplates,
+}
+
+impl Fixtures {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed,
+            next_post_id: 1,
+            next_tag_id: 1,
+            tags: Vec::new(),
+            urls: UrlTemplates::default(),
+        }
+    }
+
+    /// Use these templates for the file urls of the posts instead of the default ones
+    pub fn with_urls(mut self, urls: UrlTemplates) -> Self {
+        self.urls = urls;
+        self
+    }
+
+    /// The tags generated so far, their `count` is the number of posts generated with them
+    pub fn generated_tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    /// A new tag with a unique name, mostly general tags
+    pub fn tag(&mut self) -> Tag {
+        let id = self.next_tag_id;
+        self.next_tag_id += 1;
+
+        let syllables = 2 + self.below(3) as usize;
+        let mut name: String = (0..syllables)
+            .map(|_| SYLLABLES[self.below(SYLLABLES.len() as u64) as usize])
+            .collect();
+        // The id keeps names unique without remembering the taken ones
+        name.push_str(&format!("_{id}"));
+
+        let tag_type = match self.below(20) {
+            0..=13 => TagType::Descriptive,
+            14 | 15 => TagType::Character,
+            16 | 17 => TagType::Artist,
+            18 => TagType::Copyright,
+            _ => TagType::Metadata,
+        };
+        let tag = Tag {
+            id,
+            name,
+            count: 0,
+            tag_type,
+            ambiguous: false,
+        };
+        self.tags.push(tag.clone());
+        tag
+    }
+
+    pub fn tags(&mut self, count: usize) -> Vec<Tag> {
+        (0..count).map(|_| self.tag()).collect()
+    }
+
+    /// A new post with the next id, tagged with up to 8 of the generated tags
+    pub fn post(&mut self) -> Post {
+        let id = self.next_post_id;
+        self.next_post_id += 1;
+
+        let md5 = format!("{:016x}{:016x}", self.next(), self.next());
+        let extension = EXTENSIONS[self.below(EXTENSIONS.len() as u64) as usize];
+        let rating = match self.below(10) {
+            0..=4 => Rating::Safe,
+            5 | 6 => Rating::Sensitive,
+            7 | 8 => Rating::Questionable,
+            _ => Rating::Explicit,
+        };
+        let width = 500 + self.below(1500) as u32;
+        let height = 500 + self.below(1500) as u32;
+
+        let mut tags = Vec::new();
+        if !self.tags.is_empty() {
+            for _ in 0..1 + self.below(8) {
+                // Squaring skews the picks towards the first tags
+                let pick = self.unit() * self.unit();
+                let len = self.tags.len();
+                let tag = &mut self.tags[((pick * len as f64) as usize).min(len - 1)];
+                if !tags.contains(&tag.name) {
+                    tag.count += 1;
+                    tags.push(tag.name.clone());
+                }
+            }
+        }
+
+        let mut post = Post {
+            id,
+            created_at: epoch() + Duration::minutes(id as i64),
+            score: self.below(100) as i32,
+            directory: format!("{}/{}", &md5[..2], &md5[2..4]),
+            image: format!("{md5}.{extension}"),
+            md5,
+            rating,
+            source: None,
+            change: id,
+            owner: String::from("fixture"),
+            creator_id: 1 + self.below(1000),
+            parent_id: None,
+            sample: None,
+            preview: Varient {
+                url: String::new(),
+                width: 150,
+                height: 150,
+            },
+            original: Varient {
+                url: String::new(),
+                width,
+                height,
+            },
+            tags,
+            title: None,
+            has_notes: false,
+            has_comments: false,
+            status: String::from("active"),
+            post_locked: false,
+            has_children: false,
+            typed_tags: Vec::new(),
+            raw_created_at: None,
+            extra: serde_json::Map::new(),
+        };
+        post.original.url = post.file_url(Variant::Original, &self.urls);
+        post.preview.url = post.file_url(Variant::Preview, &self.urls);
+        post
+    }
+
+    pub fn posts(&mut self, count: usize) -> Vec<Post> {
+        (0..count).map(|_| self.post()).collect()
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+
+    /// A number in `0.0..1.0`
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Creation time of the post with id 0
+fn epoch() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+}
@@ -0,0 +1,90 @@
+//! Score histograms with precomputed percentiles, see [`ScoreHistogram`]
+//!
+//! The index keeps the posts of every score in a bitmap, so the histogram of any set of posts can
+//! be counted from it. For all posts and the most used tags the histograms are computed when the
+//! index is built, which makes `top:1%:tag` queries a lookup instead of a pass over the posts.
+
+use std::collections::{BTreeMap, HashMap};
+
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+/// Number of posts per score, with the score thresholds of the top percents
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreHistogram {
+    /// Posts per score, scores without posts are left out
+    pub counts: BTreeMap<i32, u64>,
+    /// `percentiles[p - 1]` is the lowest score among the top `p`% of the posts, empty if there
+    /// are no posts
+    pub percentiles: Vec<i32>,
+}
+
+impl ScoreHistogram {
+    pub fn new(mut counts: BTreeMap<i32, u64>) -> Self {
+        counts.retain(|_, count| *count > 0);
+        let total: u64 = counts.values().sum();
+
+        let mut percentiles = Vec::new();
+        if total > 0 {
+            // Walk down from the highest score, the rank of the lowest post of the top `p`% is
+            // rounded up so every percent has at least one post
+            let mut scores = counts.iter().rev();
+            let (mut score, mut seen) = scores.next().map(|(s, c)| (*s, *c)).unwrap();
+            for percent in 1..=100 {
+                let rank = (total * percent).div_ceil(100);
+                while seen < rank {
+                    let (next, count) = scores.next().unwrap();
+                    score = *next;
+                    seen += count;
+                }
+                percentiles.push(score);
+            }
+        }
+        Self {
+            counts,
+            percentiles,
+        }
+    }
+
+    /// The histogram of `post_ids` in the per-score bitmaps of an index, or of every post if it is
+    /// `None`
+    pub fn from_bitmaps(
+        score_to_post_id: &BTreeMap<i32, RoaringBitmap>,
+        post_ids: Option<&RoaringBitmap>,
+    ) -> Self {
+        let counts = score_to_post_id
+            .iter()
+            .map(|(score, ids)| {
+                let count = match post_ids {
+                    Some(post_ids) => ids.intersection_len(post_ids),
+                    None => ids.len(),
+                };
+                (*score, count)
+            })
+            .collect();
+        Self::new(counts)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// The lowest score among the top `percent`% of the posts, `None` without posts
+    ///
+    /// Posts sharing the threshold score are all counted in, so slightly more than `percent`% of
+    /// the posts may reach it.
+    pub fn top_threshold(&self, percent: u8) -> Option<i32> {
+        let percent = percent.clamp(1, 100) as usize;
+        self.percentiles.get(percent - 1).copied()
+    }
+}
+
+/// The precomputed histograms of an index, see
+/// [`Index::update_score_histograms`](crate::index::Index::update_score_histograms)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScoreHistograms {
+    /// Every indexed post, `None` before the histograms were computed
+    pub all: Option<ScoreHistogram>,
+    /// The most used tags by tag id
+    pub tags: HashMap<u32, ScoreHistogram>,
+}
@@ -0,0 +1,283 @@
+//! Importer for Danbooru's published database dumps
+//!
+//! The dumps contain one JSON object per line (CSV exports can be read with the `csv` feature).
+//! Records are converted into [`Post`]s and [`Tag`]s and written to any [`Sink`], including an
+//! [`Index`](crate::index::Index) directly.
+
+use std::io::BufRead;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    models::{Post, Rating, Tag, TagType, TypedTag, Varient},
+    sink::{Sink, SinkError},
+};
+
+use super::ImportStats;
+
+const CDN: &str = "https://cdn.donmai.us";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DanbooruPost {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub score: i32,
+    #[serde(default)]
+    pub md5: Option<String>,
+    pub rating: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub tag_string: String,
+    #[serde(default)]
+    pub tag_string_general: String,
+    #[serde(default)]
+    pub tag_string_artist: String,
+    #[serde(default)]
+    pub tag_string_copyright: String,
+    #[serde(default)]
+    pub tag_string_character: String,
+    #[serde(default)]
+    pub tag_string_meta: String,
+    #[serde(default)]
+    pub file_ext: String,
+    #[serde(default)]
+    pub image_width: u32,
+    #[serde(default)]
+    pub image_height: u32,
+    #[serde(default)]
+    pub parent_id: Option<u64>,
+    #[serde(default)]
+    pub uploader_id: u64,
+    #[serde(default)]
+    pub has_children: bool,
+    #[serde(default)]
+    pub is_deleted: bool,
+    #[serde(default)]
+    pub is_pending: bool,
+    #[serde(default)]
+    pub is_flagged: bool,
+    #[serde(default)]
+    pub last_noted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_commented_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DanbooruTag {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub post_count: u64,
+    #[serde(default)]
+    pub category: u32,
+    #[serde(default)]
+    pub is_deprecated: bool,
+}
+
+impl DanbooruPost {
+    /// Convert into a [`Post`], returning `None` for posts without an md5 (e.g. banned posts)
+    pub fn into_post(self) -> Option<Post> {
+        let md5 = self.md5.filter(|md5| md5.len() == 32)?;
+        let directory = format!("{}/{}", &md5[0..2], &md5[2..4]);
+        let image = format!("{md5}.{}", self.file_ext);
+
+        let rating = match self.rating.as_str() {
+            "g" => Rating::Safe,
+            "s" => Rating::Sensitive,
+            "q" => Rating::Questionable,
+            "e" => Rating::Explicit,
+            other => Rating::Other(other.to_string()),
+        };
+
+        let status = if self.is_deleted {
+            "deleted"
+        } else if self.is_pending {
+            "pending"
+        } else if self.is_flagged {
+            "flagged"
+        } else {
+            "active"
+        };
+
+        // The dumps split the tags by category, so the types are known without the tags table
+        let typed_tags = [
+            (&self.tag_string_general, TagType::Descriptive),
+            (&self.tag_string_artist, TagType::Artist),
+            (&self.tag_string_copyright, TagType::Copyright),
+            (&self.tag_string_character, TagType::Character),
+            (&self.tag_string_meta, TagType::Metadata),
+        ]
+        .into_iter()
+        .flat_map(|(tags, tag_type)| {
+            tags.split_whitespace().map(move |name| TypedTag {
+                name: name.to_string(),
+                tag_type: Some(tag_type),
+            })
+        })
+        .collect();
+
+        Some(Post {
+            id: self.id,
+            created_at: self.created_at,
+            score: self.score,
+            directory: directory.clone(),
+            rating,
+            source: self.source.filter(|source| !source.is_empty()),
+            change: self
+                .updated_at
+                .unwrap_or(self.created_at)
+                .timestamp()
+                .max(0) as u64,
+            owner: String::new(),
+            creator_id: self.uploader_id,
+            parent_id: self.parent_id,
+            sample: None,
+            preview: Varient {
+                url: format!("{CDN}/180x180/{directory}/{md5}.jpg"),
+                width: 180,
+                height: 180,
+            },
+            original: Varient {
+                url: format!("{CDN}/original/{directory}/{image}"),
+                width: self.image_width,
+                height: self.image_height,
+            },
+            tags: self
+                .tag_string
+                .split_whitespace()
+                .map(|tag| tag.to_string())
+                .collect(),
+            title: None,
+            has_notes: self.last_noted_at.is_some(),
+            has_comments: self.last_commented_at.is_some(),
+            status: status.to_string(),
+            post_locked: false,
+            has_children: self.has_children,
+            md5,
+            image,
+            typed_tags,
+            raw_created_at: None,
+            extra: serde_json::Map::new(),
+        })
+    }
+}
+
+impl From<DanbooruTag> for Tag {
+    fn from(value: DanbooruTag) -> Self {
+        Tag {
+            id: value.id,
+            name: value.name,
+            count: value.post_count,
+            tag_type: TagType::from(value.category),
+            ambiguous: value.is_deprecated,
+        }
+    }
+}
+
+/// Import a posts dump with one JSON object per line
+pub fn import_posts<R: BufRead, S: Sink<Post>>(
+    reader: R,
+    sink: &mut S,
+) -> Result<ImportStats, SinkError> {
+    let mut stats = ImportStats::default();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<DanbooruPost>(&line).map(DanbooruPost::into_post) {
+            Ok(Some(post)) => {
+                sink.write(post)?;
+                stats.imported += 1;
+            }
+            Ok(None) => stats.skipped += 1,
+            Err(e) => {
+                warn!("Skipping malformed post on line {}: {}", line_number + 1, e);
+                stats.skipped += 1;
+            }
+        }
+    }
+    sink.flush()?;
+    Ok(stats)
+}
+
+/// Import a tags dump with one JSON object per line
+pub fn import_tags<R: BufRead, S: Sink<Tag>>(
+    reader: R,
+    sink: &mut S,
+) -> Result<ImportStats, SinkError> {
+    let mut stats = ImportStats::default();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<DanbooruTag>(&line) {
+            Ok(tag) => {
+                sink.write(tag.into())?;
+                stats.imported += 1;
+            }
+            Err(e) => {
+                warn!("Skipping malformed tag on line {}: {}", line_number + 1, e);
+                stats.skipped += 1;
+            }
+        }
+    }
+    sink.flush()?;
+    Ok(stats)
+}
+
+/// Import a posts dump exported as CSV with a header row
+#[cfg(feature = "csv")]
+pub fn import_posts_csv<R: std::io::Read, S: Sink<Post>>(
+    reader: R,
+    sink: &mut S,
+) -> Result<ImportStats, SinkError> {
+    let mut stats = ImportStats::default();
+    for record in csv::Reader::from_reader(reader).deserialize::<DanbooruPost>() {
+        match record.map(DanbooruPost::into_post) {
+            Ok(Some(post)) => {
+                sink.write(post)?;
+                stats.imported += 1;
+            }
+            Ok(None) => stats.skipped += 1,
+            Err(e) => {
+                warn!("Skipping malformed post: {}", e);
+                stats.skipped += 1;
+            }
+        }
+    }
+    sink.flush()?;
+    Ok(stats)
+}
+
+/// Import a tags dump exported as CSV with a header row
+#[cfg(feature = "csv")]
+pub fn import_tags_csv<R: std::io::Read, S: Sink<Tag>>(
+    reader: R,
+    sink: &mut S,
+) -> Result<ImportStats, SinkError> {
+    let mut stats = ImportStats::default();
+    for record in csv::Reader::from_reader(reader).deserialize::<DanbooruTag>() {
+        match record {
+            Ok(tag) => {
+                sink.write(tag.into())?;
+                stats.imported += 1;
+            }
+            Err(e) => {
+                warn!("Skipping malformed tag: {}", e);
+                stats.skipped += 1;
+            }
+        }
+    }
+    sink.flush()?;
+    Ok(stats)
+}
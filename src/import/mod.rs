@@ -0,0 +1,10 @@
+//! Importers converting third-party data dumps into the crate's records
+
+pub mod danbooru;
+
+/// Counts collected while importing a dump
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportStats {
+    pub imported: u64,
+    pub skipped: u64,
+}
@@ -1,10 +1,42 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, Read, Seek, SeekFrom, Write},
+    ops::Bound::{Excluded, Unbounded},
+    path::Path,
+    sync::OnceLock,
+};
 
+use chrono::{DateTime, Utc};
 use rayon::{iter::ParallelIterator, str::ParallelString};
 use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize};
 
-use crate::models::{Post, PostSimplified, Tag};
+use crate::{
+    bloom::BloomFilter,
+    cluster::{cluster_tags, ClusterOptions, TagCluster},
+    histogram::{ScoreHistogram, ScoreHistograms},
+    input::{self, Compression},
+    inventory::Inventory,
+    models::{
+        envelope::{parse_record, record_id, RecordError},
+        note_tokens, HashKind, Note, PerceptualHash, Pool, Post, PostFields, PostSimplified,
+        Rating, RatingRule, Tag, TagType,
+    },
+    query::{FileState, Query, Term},
+    sink::{Sink, SinkError},
+    snapshot::Snapshots,
+    symspell::{Correction, SymSpell},
+};
+
+/// Largest intermediate result checked against a bloom filter post by post
+const BLOOM_MAX_RESULT: u64 = 1024;
+/// How many times more posts a tag needs than the intermediate result to use its bloom filter
+const BLOOM_MIN_RATIO: u64 = 64;
+/// Unparseable lines reported with their line number, the rest are only counted
+pub const MAX_LINE_FAILURES: usize = 100;
+/// Post records indexed together, whose earlier versions are unlinked in one pass over the bitmaps
+const POST_BATCH: usize = 10_000;
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Index {
@@ -12,81 +44,979 @@ pub struct Index {
     pub tag_id_to_post_id: HashMap<u32, RoaringBitmap>,
     pub post_id_to_post: HashMap<u32, PostSimplified>,
     pub tag_id_freq: HashMap<u32, u32>,
+    /// Posts per rating, empty in indexes saved before ratings were indexed
+    #[serde(default)]
+    pub rating_to_post_id: HashMap<String, RoaringBitmap>,
+    #[serde(default)]
+    pub tag_id_to_type: HashMap<u32, TagType>,
+    /// Posts per `media:` value (`image`, `animated`, `video`), see
+    /// [`Extension::media`](crate::models::Extension::media), empty in indexes saved before media
+    /// types were indexed
+    #[serde(default)]
+    pub media_to_post_id: HashMap<String, RoaringBitmap>,
+    /// Posts per normalized source domain, see [`Post::source_domains`], empty in indexes saved
+    /// before sources were indexed
+    #[serde(default)]
+    pub source_to_post_id: HashMap<String, RoaringBitmap>,
+    /// Posts per uploader account id, empty in indexes saved before uploaders were indexed
+    #[serde(default)]
+    pub creator_to_post_id: HashMap<u64, RoaringBitmap>,
+    /// Display name of every uploader account, from its latest indexed post or from the API,
+    /// see [`creator_ids`](Self::creator_ids)
+    #[serde(default)]
+    pub creator_names: HashMap<u64, String>,
+    /// Former names of renamed tags, posts scraped before a rename still carry them
+    #[serde(default)]
+    pub tag_aliases: HashMap<String, u32>,
+    /// Indexed posts per pool name
+    #[serde(default)]
+    pub pool_to_post_id: HashMap<String, RoaringBitmap>,
+    /// The posts of every pool in pool order, including the ones which aren't indexed
+    #[serde(default)]
+    pub pool_order: HashMap<String, Vec<u32>>,
+    /// Indexed posts per word of their notes and per pair of adjacent words, so phrases are
+    /// matched in order, see [`ingest_notes`](Self::ingest_notes)
+    #[serde(default)]
+    pub note_to_post_id: HashMap<String, RoaringBitmap>,
+    /// Indexed posts per score, empty in indexes saved before scores were indexed
+    #[serde(default)]
+    pub score_to_post_id: BTreeMap<i32, RoaringBitmap>,
+    /// Score histograms of all posts and of the most used tags, see
+    /// [`update_score_histograms`](Self::update_score_histograms)
+    #[serde(default)]
+    pub score_histograms: ScoreHistograms,
+    /// Bloom filters of the posts of the most used tags, see
+    /// [`update_bloom_filters`](Self::update_bloom_filters)
+    #[serde(default)]
+    pub tag_id_to_bloom: HashMap<u32, BloomFilter>,
+    /// Perceptual hashes of the downloaded files by post id, see [`near_duplicates`](Self::near_duplicates)
+    #[serde(default)]
+    pub perceptual_hashes: HashMap<u32, PerceptualHash>,
+    /// Posts whose file is in the inventory of the downloads directory, see
+    /// [`apply_inventory`](Self::apply_inventory)
+    #[serde(default)]
+    pub downloaded: RoaringBitmap,
+    /// How much of the output files has been ingested, zero in indexes saved before it was tracked
+    #[serde(default)]
+    pub watermark: Watermark,
+    /// The optional fields kept for every post
+    #[serde(default)]
+    pub post_fields: PostFields,
+    /// The builds which ingested new lines since the index was first built, the views
+    /// [`at`](Self::at) can go back to
+    #[serde(default)]
+    pub segments: Vec<Segment>,
+    /// Topics of the most used tags, see [`update_tag_clusters`](Self::update_tag_clusters)
+    #[serde(default)]
+    pub clusters: Vec<TagCluster>,
+    /// Frozen query results for `snapshot:` terms, saved in their own file next to the index so
+    /// rebuilds keep them, see [`snapshot`](crate::snapshot)
+    #[serde(skip)]
+    pub snapshots: Snapshots,
+    /// Spelling dictionary of the tag names, built by the first
+    /// [`did_you_mean`](Self::did_you_mean) and dropped when tags are added or renamed
+    #[serde(skip)]
+    spelling: OnceLock<SymSpell>,
+}
+
+/// Byte offsets into the posts and tags files up to which every line has been ingested
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub posts: u64,
+    pub tags: u64,
+}
+
+/// The end of a build of the index, see [`Index::segments`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Segment {
+    /// Counted from 1 in build order
+    pub id: u32,
+    pub built_at: DateTime<Utc>,
+    /// How far the output files had been ingested once the build finished
+    pub watermark: Watermark,
+    /// Posts in the index at that point
+    pub posts: u64,
+}
+
+/// A post whose rating contradicts its tags, see [`Index::audit_ratings`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RatingFinding {
+    pub post_id: u32,
+    pub rating: String,
+    /// Positions of the broken rules in the rule list
+    pub rules: Vec<usize>,
+    /// The tags of the broken rules the post has
+    pub tags: Vec<String>,
+}
+
+/// Posts of an uploader account, see [`Index::uploader_stats`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UploaderStats {
+    pub creator_id: u64,
+    /// The current display name, if known
+    pub name: Option<String>,
+    pub posts: u64,
+    pub first_post: Option<DateTime<Utc>>,
+    pub last_post: Option<DateTime<Utc>>,
+    /// `None` in indexes saved before scores were indexed
+    pub mean_score: Option<f64>,
+}
+
+/// Counts collected while ingesting lines into an index
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct IngestStats {
+    pub lines: u64,
+    pub bytes: u64,
+    /// Lines which couldn't be parsed and were skipped
+    pub failed: u64,
+    /// The first [`MAX_LINE_FAILURES`] of them
+    pub failures: Vec<LineFailure>,
+    /// Posts indexed without some of their tags, because the tags file doesn't have them
+    pub incomplete: u64,
+    /// Tags left out of those posts, they don't find the post in queries
+    pub unknown_tags: u64,
+}
+
+/// A line which couldn't be parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct LineFailure {
+    /// Line number, counted from where the reading started (the watermark of an incremental build)
+    pub line: u64,
+    pub error: String,
+}
+
+/// What went into a build of the index and what was left out, see [`Index::generate`]
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BuildReport {
+    pub tags: IngestStats,
+    pub posts: IngestStats,
+    /// Duration of every phase of the build, in order
+    pub phases: Vec<BuildPhase>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildPhase {
+    pub name: &'static str,
+    pub duration_ms: u128,
+}
+
+impl IngestStats {
+    /// Count the result of parsing line `line`
+    fn record<E: std::fmt::Display>(&mut self, line: u64, result: Result<(), E>) {
+        if let Err(e) = result {
+            self.failed += 1;
+            if self.failures.len() < MAX_LINE_FAILURES {
+                self.failures.push(LineFailure {
+                    line,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Count a post which was indexed without `unknown_tags` of its tags
+    fn record_unknown_tags(&mut self, unknown_tags: u32) {
+        if unknown_tags > 0 {
+            self.incomplete += 1;
+            self.unknown_tags += unknown_tags as u64;
+        }
+    }
+}
+
+impl BuildReport {
+    /// Run `phase` and record how long it took
+    pub fn timed<T>(&mut self, name: &'static str, phase: impl FnOnce() -> T) -> T {
+        // There is no clock on `wasm32-unknown-unknown`
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = name;
+            phase()
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let start = std::time::Instant::now();
+            let result = phase();
+            self.phases.push(BuildPhase {
+                name,
+                duration_ms: start.elapsed().as_millis(),
+            });
+            result
+        }
+    }
+
+    /// Lines of both files which couldn't be parsed
+    pub fn failed(&self) -> u64 {
+        self.tags.failed + self.posts.failed
+    }
 }
 
 impl Index {
-    pub fn generate(post_file: &str, tag_file: &str) -> Result<Self, Box<dyn std::error::Error>> {
+    /// An empty index keeping the optional `post_fields` of every post inserted into it
+    pub fn with_post_fields(post_fields: PostFields) -> Self {
+        Self {
+            post_fields,
+            ..Self::default()
+        }
+    }
+
+    /// Build an index from a posts and a tags file, with a report of the lines it had to skip
+    ///
+    /// Files ending in `.gz` or `.zst` are decompressed while they are read, see
+    /// [`input`](crate::input). Plain files are read whole and their tags parsed in parallel.
+    pub fn generate(
+        post_file: &str,
+        tag_file: &str,
+    ) -> Result<(Self, BuildReport), Box<dyn std::error::Error>> {
+        if Compression::of(post_file) != Compression::None
+            || Compression::of(tag_file) != Compression::None
+        {
+            return Ok(Self::from_readers(
+                input::open(post_file)?,
+                input::open(tag_file)?,
+            )?);
+        }
+        let mut report = BuildReport::default();
+        let (posts, tags) = report.timed("read", || {
+            Ok::<_, std::io::Error>((
+                std::fs::read_to_string(post_file)?,
+                std::fs::read_to_string(tag_file)?,
+            ))
+        })?;
+        let (index, parsed) = Self::from_json_lines(&posts, &tags);
+        report.tags = parsed.tags;
+        report.posts = parsed.posts;
+        report.phases.extend(parsed.phases);
+        Ok((index, report))
+    }
+
+    /// Build the index from posts and tags readers, one line at a time
+    ///
+    /// Unlike [`generate`](Self::generate) the files are never held in memory as a whole, and the
+    /// watermark is set so the index can later be updated with [`ingest_posts`](Self::ingest_posts).
+    pub fn from_readers<P: BufRead, T: BufRead>(
+        posts: P,
+        tags: T,
+    ) -> std::io::Result<(Self, BuildReport)> {
         let mut index = Index::default();
-        let tags = std::fs::read_to_string(tag_file)?;
-        let tags: Vec<Tag> = tags
-            .par_lines()
-            .map(serde_json::from_str)
-            .flatten()
+        let mut report = BuildReport::default();
+        report.tags = report.timed("tags", || index.ingest_tags(tags))?;
+        report.posts = report.timed("posts", || index.ingest_posts(posts))?;
+        Ok((index, report))
+    }
+
+    /// Close the lines ingested since the last segment into a new segment ending at the current
+    /// watermark, returning its id
+    ///
+    /// Nothing is recorded if no line was ingested since, so a build which found no new lines
+    /// doesn't add a boundary. The time is passed in since the index doesn't read the clock on
+    /// every platform.
+    pub fn finish_segment(&mut self, built_at: DateTime<Utc>) -> Option<u32> {
+        if self
+            .segments
+            .last()
+            .map_or(Watermark::default(), |last| last.watermark)
+            == self.watermark
+        {
+            return None;
+        }
+        let id = self.segments.last().map_or(1, |last| last.id + 1);
+        self.segments.push(Segment {
+            id,
+            built_at,
+            watermark: self.watermark,
+            posts: self.post_id_to_post.len() as u64,
+        });
+        Some(id)
+    }
+
+    pub fn segment(&self, id: u32) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.id == id)
+    }
+
+    /// The index as it was when the segment `id` was finished, for queries which have to give
+    /// the same results later on, e.g. to reproduce an experiment or a dataset release
+    ///
+    /// The view is built again from the lines of the posts and tags files up to the watermark of
+    /// the segment, so posts edited or deleted since match like they did back then. The files
+    /// must be the ones the index was built from; a file which was rewritten since (e.g. by
+    /// compaction) no longer ends a line at the watermark and is rejected. Snapshots, file hashes
+    /// and the inventory are taken over from the current index, pools and notes aren't rebuilt,
+    /// so `pool:` and `note:` terms don't match in the view.
+    pub fn at<P: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        id: u32,
+        posts: P,
+        tags: T,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let segment = self
+            .segment(id)
+            .ok_or_else(|| format!("the index has no segment {id}"))?;
+        let mut view = Self::with_post_fields(self.post_fields);
+        view.ingest_tags(input::open(tags)?.take(segment.watermark.tags))?;
+        view.ingest_posts(input::open(posts)?.take(segment.watermark.posts))?;
+        if view.watermark != segment.watermark {
+            return Err(format!(
+                "the output files were rewritten since segment {id} was built, it can't be restored"
+            )
+            .into());
+        }
+        view.update_score_histograms(self.score_histograms.tags.len());
+        view.snapshots = self.snapshots.clone();
+        view.perceptual_hashes = self.perceptual_hashes.clone();
+        view.downloaded = self.downloaded.clone();
+        view.segments = self
+            .segments
+            .iter()
+            .take_while(|segment| segment.id <= id)
+            .cloned()
             .collect();
+        Ok(view)
+    }
+
+    /// Apply the tag lines of `reader`, which must start at the tags watermark
+    ///
+    /// Only complete lines are ingested and counted towards the watermark, so a line that is still
+    /// being written is picked up by the next call. Posts ingested earlier aren't linked to new
+    /// tags, ingest the tags before the posts.
+    #[tracing::instrument(skip_all)]
+    pub fn ingest_tags<R: BufRead>(&mut self, reader: R) -> std::io::Result<IngestStats> {
+        let stats = for_each_complete_line(reader, |line| {
+            self.insert_tag(parse_record(line)?);
+            Ok(())
+        })?;
+        self.watermark.tags += stats.bytes;
+        Ok(stats)
+    }
+
+    /// Apply the post lines of `reader`, which must start at the posts watermark
+    ///
+    /// Posts which are already indexed are replaced and tombstones remove them, like
+    /// [`update_post`](Self::update_post) but without a pass over every tag per post.
+    #[tracing::instrument(skip_all)]
+    pub fn ingest_posts<R: BufRead>(&mut self, reader: R) -> std::io::Result<IngestStats> {
+        let mut unknown = IngestStats::default();
+        let mut batch = Vec::new();
+        let mut stats = for_each_complete_line(reader, |line| {
+            batch.push(parse_record(line)?);
+            if batch.len() == POST_BATCH {
+                self.ingest_post_batch(std::mem::take(&mut batch), &mut unknown);
+            }
+            Ok(())
+        })?;
+        self.ingest_post_batch(batch, &mut unknown);
+        stats.incomplete = unknown.incomplete;
+        stats.unknown_tags = unknown.unknown_tags;
+        self.watermark.posts += stats.bytes;
+        Ok(stats)
+    }
+
+    /// Apply every pool line of `reader`
+    ///
+    /// Pools are edited in place by the site, so the whole file is read every time and a pool
+    /// replaces any earlier one of the same name. Ingest the pools after the posts.
+    #[tracing::instrument(skip_all)]
+    pub fn ingest_pools<R: BufRead>(&mut self, reader: R) -> std::io::Result<IngestStats> {
+        for_each_complete_line(reader, |line| {
+            self.insert_pool(parse_record(line)?);
+            Ok(())
+        })
+    }
+
+    /// Index the words of every note line of `reader`, replacing the notes indexed before
+    ///
+    /// Notes are edited in place by the site, so the whole file is read every time and only the
+    /// latest version of every note counts. Deleted notes and notes of posts which aren't indexed
+    /// are skipped, ingest the notes after the posts.
+    #[tracing::instrument(skip_all)]
+    pub fn ingest_notes<R: BufRead>(&mut self, reader: R) -> std::io::Result<IngestStats> {
+        let mut notes: HashMap<u64, Note> = HashMap::new();
+        let stats = for_each_complete_line(reader, |line| {
+            let note = parse_record::<Note>(line)?;
+            match notes.get(&note.id) {
+                Some(known) if known.version > note.version => {}
+                _ => {
+                    notes.insert(note.id, note);
+                }
+            }
+            Ok(())
+        })?;
 
-        for tag in tags {
+        self.note_to_post_id.clear();
+        for note in notes.into_values() {
+            let post_id = note.post_id as u32;
+            if !note.is_active || !self.post_id_to_post.contains_key(&post_id) {
+                continue;
+            }
+            let tokens = note_tokens(&note.body);
+            let pairs = tokens.windows(2).map(|pair| pair.join(" "));
+            for key in tokens.iter().cloned().chain(pairs) {
+                self.note_to_post_id.entry(key).or_default().insert(post_id);
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Apply every perceptual hash line of `reader`, a later hash of a post replaces an earlier one
+    #[tracing::instrument(skip_all)]
+    pub fn ingest_hashes<R: BufRead>(&mut self, reader: R) -> std::io::Result<IngestStats> {
+        for_each_complete_line(reader, |line| {
+            let hash = parse_record::<PerceptualHash>(line)?;
+            self.perceptual_hashes.insert(hash.post_id as u32, hash);
+            Ok(())
+        })
+    }
+
+    /// Mark the posts whose file is in `inventory` as downloaded, replacing the previous inventory
+    ///
+    /// Returns the number of downloaded posts.
+    #[tracing::instrument(skip_all)]
+    pub fn apply_inventory(&mut self, inventory: &Inventory) -> u64 {
+        self.downloaded = inventory.downloaded(self);
+        self.downloaded.len()
+    }
+
+    /// Build the index from posts and tags stored in object storage, e.g. `s3://bucket/posts.json.gz`
+    #[cfg(feature = "s3")]
+    pub async fn generate_from_object_store(
+        post_url: &str,
+        tag_url: &str,
+    ) -> Result<(Self, BuildReport), Box<dyn std::error::Error>> {
+        use crate::sink::object_store::read_to_string;
+
+        let tags = read_to_string(tag_url).await?;
+        let posts = read_to_string(post_url).await?;
+        Ok(Self::from_json_lines(&posts, &tags))
+    }
+
+    /// Build the index from the contents of the posts and tags files
+    ///
+    /// Later records of a post replace the earlier ones and tombstones remove it, like
+    /// [`ingest_posts`](Self::ingest_posts) does.
+    pub fn from_json_lines(posts: &str, tags: &str) -> (Self, BuildReport) {
+        let mut index = Index::default();
+        let mut report = BuildReport::default();
+        let parsed: Vec<Option<Result<Tag, RecordError>>> = report.timed("parse tags", || {
+            tags.par_lines()
+                .map(|line| (!line.trim().is_empty()).then(|| parse_record(line)))
+                .collect()
+        });
+
+        report.tags = report.timed("tags", || {
+            let mut stats = IngestStats {
+                lines: parsed.len() as u64,
+                bytes: tags.len() as u64,
+                ..IngestStats::default()
+            };
+            for (number, tag) in parsed.into_iter().enumerate() {
+                if let Some(tag) = tag {
+                    stats.record(number as u64 + 1, tag.map(|tag| index.insert_tag(tag)));
+                }
+            }
+            stats
+        });
+
+        report.posts = report.timed("posts", || {
+            let mut stats = IngestStats {
+                bytes: posts.len() as u64,
+                ..IngestStats::default()
+            };
+            let mut batch = Vec::new();
+            for (number, line) in posts.lines().enumerate() {
+                stats.lines += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let post = parse_record(line).map(|post| batch.push(post));
+                stats.record(number as u64 + 1, post);
+                if batch.len() == POST_BATCH {
+                    index.ingest_post_batch(std::mem::take(&mut batch), &mut stats);
+                }
+            }
+            index.ingest_post_batch(batch, &mut stats);
+            stats
+        });
+
+        (index, report)
+    }
+
+    /// Build the index from a database written by [`SqliteSink`](crate::sink::sqlite::SqliteSink)
+    #[cfg(feature = "sqlite")]
+    pub fn generate_from_sqlite<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        use crate::sink::sqlite;
+
+        let mut index = Index::default();
+        let conn = rusqlite::Connection::open(path)?;
+
+        for tag in sqlite::read_tags(&conn)? {
             index.insert_tag(tag);
         }
 
-        let posts = std::fs::read_to_string(post_file)?;
-        posts
-            .lines()
-            .flat_map(serde_json::from_str)
-            .for_each(|post| index.insert_post(post));
+        sqlite::for_each_post(&conn, |post| {
+            index.insert_post(post);
+        })?;
 
         Ok(index)
     }
 
+    /// Compute the score histograms of all posts and of the `tags` most used tags
+    ///
+    /// The histograms aren't kept up to date as posts are inserted, they are computed once the
+    /// posts of a build are ingested. Tags without a precomputed histogram are counted when they
+    /// are queried, see [`score_histogram`](Self::score_histogram).
+    #[tracing::instrument(skip(self))]
+    pub fn update_score_histograms(&mut self, tags: usize) {
+        let histograms = self
+            .most_used_tags(tags)
+            .into_iter()
+            .filter_map(|id| {
+                let post_ids = self.tag_id_to_post_id.get(&id)?;
+                let histogram =
+                    ScoreHistogram::from_bitmaps(&self.score_to_post_id, Some(post_ids));
+                Some((id, histogram))
+            })
+            .collect();
+        self.score_histograms = ScoreHistograms {
+            all: Some(ScoreHistogram::from_bitmaps(&self.score_to_post_id, None)),
+            tags: histograms,
+        };
+    }
+
+    /// Rebuild the bloom filters of the `tags` most used tags, sized for their current posts
+    ///
+    /// Posts inserted later are added to the filters, which raises their false positive rate until
+    /// they are rebuilt, but never makes them miss a post.
+    #[tracing::instrument(skip(self))]
+    pub fn update_bloom_filters(&mut self, tags: usize) {
+        self.tag_id_to_bloom = self
+            .most_used_tags(tags)
+            .into_iter()
+            .filter_map(|id| {
+                let post_ids = self.tag_id_to_post_id.get(&id)?;
+                let mut bloom = BloomFilter::with_capacity(post_ids.len());
+                post_ids.iter().for_each(|post_id| bloom.insert(post_id));
+                Some((id, bloom))
+            })
+            .collect();
+    }
+
+    /// Cluster the most used tags by how often they are used together, see
+    /// [`cluster`](crate::cluster)
+    ///
+    /// Clustering compares every pair of the tags, so it is a job of its own rather than a step of
+    /// every build. Incremental builds keep the clusters, a full build drops them.
+    #[tracing::instrument(skip(self))]
+    pub fn update_tag_clusters(&mut self, options: ClusterOptions) {
+        self.clusters = cluster_tags(self, options);
+    }
+
+    /// The tag clusters of the last [`update_tag_clusters`](Self::update_tag_clusters), largest
+    /// first, empty if they were never computed
+    pub fn tag_clusters(&self) -> &[TagCluster] {
+        &self.clusters
+    }
+
+    /// Ids of the `n` tags with the most posts, most used first
+    fn most_used_tags(&self, n: usize) -> Vec<u32> {
+        let mut most_used: Vec<(u32, u32)> = self
+            .tag_id_freq
+            .iter()
+            .map(|(id, freq)| (*id, *freq))
+            .filter(|(_, freq)| *freq > 0)
+            .collect();
+        most_used.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        most_used.truncate(n);
+        most_used.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// The score histogram of all posts, or of the posts with a tag, precomputed if available
+    ///
+    /// `None` if the tag is unknown.
+    pub fn score_histogram(&self, tag: Option<&str>) -> Option<Cow<'_, ScoreHistogram>> {
+        let Some(tag) = tag else {
+            return Some(match &self.score_histograms.all {
+                Some(histogram) => Cow::Borrowed(histogram),
+                None => Cow::Owned(ScoreHistogram::from_bitmaps(&self.score_to_post_id, None)),
+            });
+        };
+        let tag_id = self.tag_id(tag)?;
+        if let Some(histogram) = self.score_histograms.tags.get(&tag_id) {
+            return Some(Cow::Borrowed(histogram));
+        }
+        let post_ids = self.tag_id_to_post_id.get(&tag_id)?;
+        Some(Cow::Owned(ScoreHistogram::from_bitmaps(
+            &self.score_to_post_id,
+            Some(post_ids),
+        )))
+    }
+
+    /// Save the index to a temporary file which replaces `path` once it is complete
+    #[tracing::instrument(skip_all)]
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
-        let file = std::fs::File::create(path)?;
-        let writer = std::io::BufWriter::new(file);
-        serde_json::to_writer(writer, self)?;
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        let file = std::fs::File::create(&tmp_path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, path)?;
         Ok(())
     }
 
+    /// Load the index saved at `path`, with its snapshots if it has any
+    #[tracing::instrument(skip_all)]
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let file = std::fs::File::open(path)?;
+        let file = std::fs::File::open(&path)?;
         let reader = std::io::BufReader::new(file);
-        let index = serde_json::from_reader(reader)?;
+        let mut index: Self = serde_json::from_reader(reader)?;
+        index.snapshots = Snapshots::load(path)?;
         Ok(index)
     }
 
+    /// Load the index of a `.booru` bundle, see [`bundle`](crate::maintenance::bundle)
+    #[cfg(feature = "scraper")]
+    #[tracing::instrument(skip_all)]
+    pub fn open_bundle<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        use crate::maintenance::bundle::{self, Bundle};
+
+        let bundle = Bundle::open(path)?;
+        Ok(Self::from_bytes(&bundle.read_entry(bundle::INDEX)?)?)
+    }
+
+    /// Parse an index saved by [`save`](Self::save) from its bytes, e.g. a file downloaded by a
+    /// web UI
+    ///
+    /// Unlike [`load`](Self::load) this doesn't touch the file system, so it also works on
+    /// `wasm32-unknown-unknown`, where the queries run without any other platform support.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+
     pub fn insert_tag(&mut self, tag: Tag) {
         self.tag_str_to_id
             .insert(tag.name.to_lowercase(), tag.id as u32);
+        self.tag_id_to_type.insert(tag.id as u32, tag.tag_type);
+        self.spelling.take();
     }
 
-    pub fn insert_post(&mut self, post: Post) {
+    /// Index a post, returning the number of its tags which aren't in the index and were left out
+    pub fn insert_post(&mut self, post: Post) -> u32 {
+        let mut unknown_tags = 0;
         for tag in post.split_tags() {
-            let tag = tag.to_lowercase();
-            let tag_id = match self.tag_str_to_id.get(&tag) {
-                Some(id) => id,
-                None => continue,
+            let Some(tag_id) = self.tag_id(&tag.to_lowercase()) else {
+                unknown_tags += 1;
+                continue;
             };
-            let bitmap = self.tag_id_to_post_id.entry(*tag_id).or_default();
+            let bitmap = self.tag_id_to_post_id.entry(tag_id).or_default();
             if bitmap.insert(post.id as u32) {
-                *self.tag_id_freq.entry(*tag_id).or_default() += 1;
+                *self.tag_id_freq.entry(tag_id).or_default() += 1;
+            }
+            // A filter has to learn every post of its tag, a missed one would drop it from results
+            if let Some(bloom) = self.tag_id_to_bloom.get_mut(&tag_id) {
+                bloom.insert(post.id as u32);
+            }
+        }
+        self.rating_to_post_id
+            .entry(post.rating.as_str().to_string())
+            .or_default()
+            .insert(post.id as u32);
+        for media in post.extension().media() {
+            self.media_to_post_id
+                .entry(media.to_string())
+                .or_default()
+                .insert(post.id as u32);
+        }
+        for domain in post.source_domains() {
+            self.source_to_post_id
+                .entry(domain)
+                .or_default()
+                .insert(post.id as u32);
+        }
+        self.creator_to_post_id
+            .entry(post.creator_id)
+            .or_default()
+            .insert(post.id as u32);
+        if !post.owner.is_empty() {
+            self.creator_names
+                .insert(post.creator_id, post.owner.to_lowercase());
+        }
+        self.score_to_post_id
+            .entry(post.score)
+            .or_default()
+            .insert(post.id as u32);
+        self.post_id_to_post
+            .insert(post.id as u32, PostSimplified::new(post, self.post_fields));
+        unknown_tags
+    }
+
+    /// Insert a pool, its posts must already be indexed to match `pool:` queries
+    pub fn insert_pool(&mut self, pool: Pool) {
+        let order: Vec<u32> = pool.post_ids.iter().map(|id| *id as u32).collect();
+        let post_ids = order
+            .iter()
+            .copied()
+            .filter(|id| self.post_id_to_post.contains_key(id))
+            .collect();
+        let name = pool.query_name();
+        self.pool_to_post_id.insert(name.clone(), post_ids);
+        self.pool_order.insert(name, order);
+    }
+
+    /// Insert a post, first removing an earlier version of it from the tag, rating, media, source
+    /// and score bitmaps
+    ///
+    /// A post with the status `deleted` (a tombstone, e.g. written by `sync`) is only removed.
+    /// Returns the number of tags left out like [`insert_post`](Self::insert_post).
+    pub fn update_post(&mut self, post: Post) -> u32 {
+        if post.status == "deleted" {
+            self.remove_post(post.id as u32);
+            0
+        } else {
+            // The pools keep the post, it is still their member
+            self.unlink_post(post.id as u32);
+            self.insert_post(post)
+        }
+    }
+
+    /// Replace `old`, the indexed version of a post, with `new`
+    ///
+    /// Unlike [`update_post`](Self::update_post), which has to check every tag because the index
+    /// doesn't keep the tags of a post, only the bitmaps of the tags and fields which changed
+    /// between the versions are touched. `old` has to be the version which was indexed, bitmaps of
+    /// tags it is missing keep the post; without it this falls back to `update_post`. A `new`
+    /// with the status `deleted` is only removed. Returns the number of tags left out like
+    /// [`insert_post`](Self::insert_post).
+    pub fn upsert_post(&mut self, old: Option<&Post>, new: Post) -> u32 {
+        let id = new.id as u32;
+        let Some(old) = old.filter(|_| self.post_id_to_post.contains_key(&id)) else {
+            return self.update_post(new);
+        };
+        if new.status == "deleted" {
+            self.unlink_version(old, None);
+            self.remove_linked_post(id);
+            return 0;
+        }
+        self.unlink_version(old, Some(&new));
+        self.insert_post(new)
+    }
+
+    /// Index a batch of post records in order, a later record of a post replacing the earlier ones
+    /// and a tombstone removing it, and count the tags left out in `stats`
+    ///
+    /// The index doesn't keep the tags of a post, so the posts indexed before the batch are
+    /// unlinked from every bitmap in a single pass. A post the batch has several records of is
+    /// replaced with [`upsert_post`](Self::upsert_post), the earlier record being known.
+    fn ingest_post_batch(&mut self, posts: Vec<Post>, stats: &mut IngestStats) {
+        let indexed: RoaringBitmap = posts
+            .iter()
+            .map(|post| post.id as u32)
+            .filter(|id| self.post_id_to_post.contains_key(id))
+            .collect();
+        self.unlink_posts(&indexed);
+
+        let last: HashMap<u32, usize> = posts
+            .iter()
+            .enumerate()
+            .map(|(position, post)| (post.id as u32, position))
+            .collect();
+        // The indexed records of the posts which a later record of the batch replaces
+        let mut replaced: HashMap<u32, Post> = HashMap::new();
+        for (position, post) in posts.into_iter().enumerate() {
+            let id = post.id as u32;
+            let previous = replaced.remove(&id);
+            if last[&id] != position && post.status != "deleted" {
+                replaced.insert(id, post.clone());
+            }
+            let unknown_tags = match previous {
+                Some(previous) => self.upsert_post(Some(&previous), post),
+                None if post.status == "deleted" => {
+                    if self.post_id_to_post.contains_key(&id) {
+                        self.remove_linked_post(id);
+                    }
+                    0
+                }
+                None => self.insert_post(post),
+            };
+            stats.record_unknown_tags(unknown_tags);
+        }
+    }
+
+    /// Remove a post from the index, its pools and its notes, `false` if it wasn't indexed
+    pub fn remove_post(&mut self, id: u32) -> bool {
+        if !self.unlink_post(id) {
+            return false;
+        }
+        self.remove_linked_post(id);
+        true
+    }
+
+    /// Remove a post which is no longer in the tag and field bitmaps from the rest of the index
+    fn remove_linked_post(&mut self, id: u32) {
+        self.post_id_to_post.remove(&id);
+        self.downloaded.remove(id);
+        for bitmap in self
+            .pool_to_post_id
+            .values_mut()
+            .chain(self.note_to_post_id.values_mut())
+        {
+            bitmap.remove(id);
+        }
+    }
+
+    /// Remove the post `old` from the bitmaps of its tags and fields which `new` doesn't share
+    fn unlink_version(&mut self, old: &Post, new: Option<&Post>) {
+        let id = old.id as u32;
+        let kept: HashSet<u32> = new
+            .into_iter()
+            .flat_map(|new| new.split_tags())
+            .filter_map(|tag| self.tag_id(&tag.to_lowercase()))
+            .collect();
+        for tag in old.split_tags() {
+            let Some(tag_id) = self.tag_id(&tag.to_lowercase()) else {
+                continue;
+            };
+            if kept.contains(&tag_id) {
+                continue;
+            }
+            let removed = self
+                .tag_id_to_post_id
+                .get_mut(&tag_id)
+                .is_some_and(|bitmap| bitmap.remove(id));
+            if removed {
+                if let Some(freq) = self.tag_id_freq.get_mut(&tag_id) {
+                    *freq = freq.saturating_sub(1);
+                }
+            }
+        }
+        // The other fields have one value, or a few, re-inserting the new ones is cheap
+        let unlink = |bitmaps: &mut HashMap<String, RoaringBitmap>, keys: Vec<String>| {
+            for key in keys {
+                if let Some(bitmap) = bitmaps.get_mut(&key) {
+                    bitmap.remove(id);
+                }
+            }
+        };
+        unlink(
+            &mut self.rating_to_post_id,
+            vec![old.rating.as_str().to_string()],
+        );
+        unlink(
+            &mut self.media_to_post_id,
+            old.extension()
+                .media()
+                .iter()
+                .map(|media| media.to_string())
+                .collect(),
+        );
+        unlink(&mut self.source_to_post_id, old.source_domains());
+        if let Some(bitmap) = self.creator_to_post_id.get_mut(&old.creator_id) {
+            bitmap.remove(id);
+        }
+        if let Some(bitmap) = self.score_to_post_id.get_mut(&old.score) {
+            bitmap.remove(id);
+        }
+    }
+
+    /// Remove a post from the tag, rating, media, source and score bitmaps, `false` if it wasn't
+    /// indexed
+    ///
+    /// The index doesn't keep the tags of a post, so removing one has to check every tag.
+    fn unlink_post(&mut self, id: u32) -> bool {
+        if !self.post_id_to_post.contains_key(&id) {
+            return false;
+        }
+        for (tag_id, bitmap) in &mut self.tag_id_to_post_id {
+            if bitmap.remove(id) {
+                if let Some(freq) = self.tag_id_freq.get_mut(tag_id) {
+                    *freq = freq.saturating_sub(1);
+                }
+            }
+        }
+        for bitmap in self
+            .rating_to_post_id
+            .values_mut()
+            .chain(self.media_to_post_id.values_mut())
+            .chain(self.source_to_post_id.values_mut())
+            .chain(self.creator_to_post_id.values_mut())
+            .chain(self.score_to_post_id.values_mut())
+        {
+            bitmap.remove(id);
+        }
+        true
+    }
+
+    /// Remove posts from the tag, rating, media, source and score bitmaps, with one pass over the
+    /// bitmaps for all of them
+    fn unlink_posts(&mut self, ids: &RoaringBitmap) {
+        if ids.is_empty() {
+            return;
+        }
+        for (tag_id, bitmap) in &mut self.tag_id_to_post_id {
+            let removed = bitmap.intersection_len(ids);
+            if removed > 0 {
+                *bitmap -= ids;
+                if let Some(freq) = self.tag_id_freq.get_mut(tag_id) {
+                    *freq = freq.saturating_sub(removed as u32);
+                }
             }
         }
-        self.post_id_to_post.insert(post.id as u32, post.into());
+        for bitmap in self
+            .rating_to_post_id
+            .values_mut()
+            .chain(self.media_to_post_id.values_mut())
+            .chain(self.source_to_post_id.values_mut())
+            .chain(self.creator_to_post_id.values_mut())
+            .chain(self.score_to_post_id.values_mut())
+        {
+            *bitmap -= ids;
+        }
+    }
+
+    /// Id of the tag with the lowercase `name`, which may be a former name of the tag
+    pub fn tag_id(&self, name: &str) -> Option<u32> {
+        self.tag_str_to_id
+            .get(name)
+            .or_else(|| self.tag_aliases.get(name))
+            .copied()
+    }
+
+    /// Give the tag `id` a new name, keeping the old one as an alias
+    ///
+    /// Returns the old name, `None` if the tag is unknown or already has this name.
+    pub fn rename_tag(&mut self, id: u32, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        let old = self
+            .tag_str_to_id
+            .iter()
+            .find(|(old, tag_id)| **tag_id == id && **old != name)
+            .map(|(old, _)| old.clone())?;
+        self.tag_str_to_id.remove(&old);
+        self.tag_str_to_id.insert(name.clone(), id);
+        self.tag_aliases.remove(&name);
+        self.tag_aliases.insert(old.clone(), id);
+        self.spelling.take();
+        Some(old)
     }
 
     pub fn get_post_ids_by_tag(&self, tag: &str) -> Option<RoaringBitmap> {
-        let tag_id = self.tag_str_to_id.get(tag)?;
-        let image_ids = self.tag_id_to_post_id.get(tag_id)?.clone();
+        let tag_id = self.tag_id(tag)?;
+        let image_ids = self.tag_id_to_post_id.get(&tag_id)?.clone();
         Some(image_ids)
     }
 
-    pub fn get_images_all_tags_lazy(
+    /// Ids of the posts having all of the given tags
+    pub fn get_post_ids_all_tags(
         &self,
         tags: impl IntoIterator<Item = String>,
-    ) -> Option<impl Iterator<Item = PostSimplified> + '_> {
+    ) -> Option<RoaringBitmap> {
         let mut tag_data: Vec<(u32, u32)> = tags
             .into_iter()
             .filter_map(|tag| {
-                let tag_id = self.tag_str_to_id.get(&tag)?;
-                let frequency = self.tag_id_freq.get(tag_id).copied().unwrap_or(u32::MAX);
-                Some((*tag_id, frequency))
+                let tag_id = self.tag_id(&tag)?;
+                let frequency = self.tag_id_freq.get(&tag_id).copied().unwrap_or(u32::MAX);
+                Some((tag_id, frequency))
             })
             .collect();
 
@@ -104,10 +1034,729 @@ impl Index {
             }
         }
 
+        Some(result)
+    }
+
+    pub fn get_images_all_tags_lazy(
+        &self,
+        tags: impl IntoIterator<Item = String>,
+    ) -> Option<impl Iterator<Item = PostSimplified> + '_> {
+        let result = self.get_post_ids_all_tags(tags)?;
+
         Some(
             result
                 .into_iter() // Iterate over the resulting post IDs
                 .filter_map(move |id| self.post_id_to_post.get(&id).cloned()), // Lazily map IDs to PostSimplified
         )
     }
+
+    /// Ids of every indexed post
+    pub fn all_post_ids(&self) -> RoaringBitmap {
+        self.post_id_to_post.keys().copied().collect()
+    }
+
+    /// Posts matching a single term, `None` if nothing can match it
+    ///
+    /// Only `file:missing`, `top:` and `note:` phrases have to be computed, every other term
+    /// borrows a bitmap of the index.
+    fn term_post_ids(&self, term: &Term) -> Option<Cow<'_, RoaringBitmap>> {
+        let post_ids = match term {
+            Term::Tag(tag) => self.tag_id_to_post_id.get(&self.tag_id(tag)?),
+            Term::TypedTag(tag_type, tag) => {
+                let tag_id = self.tag_id(tag)?;
+                if self.tag_id_to_type.get(&tag_id) != Some(tag_type) {
+                    return None;
+                }
+                self.tag_id_to_post_id.get(&tag_id)
+            }
+            Term::Rating(rating) => self.rating_to_post_id.get(rating),
+            Term::Media(media) => self.media_to_post_id.get(media),
+            Term::Pool(pool) => self.pool_to_post_id.get(pool),
+            Term::Source(domain) => self.source_to_post_id.get(domain),
+            Term::Uploader(uploader) => {
+                let mut post_ids = RoaringBitmap::new();
+                for id in self.creator_ids(uploader) {
+                    if let Some(ids) = self.creator_to_post_id.get(&id) {
+                        post_ids |= ids;
+                    }
+                }
+                return Some(Cow::Owned(post_ids));
+            }
+            Term::File(FileState::Downloaded) => Some(&self.downloaded),
+            Term::Snapshot(name) => self.snapshots.get(name).map(|snapshot| &snapshot.post_ids),
+            Term::File(FileState::Missing) => {
+                return Some(Cow::Owned(self.all_post_ids() - &self.downloaded));
+            }
+            Term::Top(percent, tag) => {
+                let threshold = self
+                    .score_histogram(tag.as_deref())?
+                    .top_threshold(*percent)?;
+                let mut post_ids = RoaringBitmap::new();
+                for ids in self.score_to_post_id.range(threshold..).map(|(_, ids)| ids) {
+                    post_ids |= ids;
+                }
+                if let Some(tag) = tag {
+                    post_ids &= self.tag_id_to_post_id.get(&self.tag_id(tag)?)?;
+                }
+                return Some(Cow::Owned(post_ids));
+            }
+            Term::Note(phrase) => {
+                let words: Vec<&str> = phrase.split(' ').collect();
+                if words.len() == 1 {
+                    return self.note_to_post_id.get(phrase).map(Cow::Borrowed);
+                }
+                // Every pair of adjacent words has to be in the notes of the post
+                let mut post_ids = RoaringBitmap::new();
+                for (i, pair) in words.windows(2).enumerate() {
+                    let pair_ids = self.note_to_post_id.get(&pair.join(" "))?;
+                    match i {
+                        0 => post_ids = pair_ids.clone(),
+                        _ => post_ids &= pair_ids,
+                    }
+                }
+                return Some(Cow::Owned(post_ids));
+            }
+        };
+        post_ids.map(Cow::Borrowed)
+    }
+
+    /// The ids of `post_ids` in result order, starting after the post `after`
+    ///
+    /// If the query requires a pool the posts are in the order of the first such pool, otherwise
+    /// they are ordered by id.
+    pub fn ordered<'a>(
+        &'a self,
+        query: &Query,
+        post_ids: &'a RoaringBitmap,
+        after: Option<u32>,
+    ) -> Box<dyn Iterator<Item = u32> + 'a> {
+        let order = query.include.iter().find_map(|term| match term {
+            Term::Pool(pool) => self.pool_order.get(pool),
+            _ => None,
+        });
+        match (order, after) {
+            (Some(order), after) => {
+                let start = after.map_or(0, |after| {
+                    order
+                        .iter()
+                        .position(|id| *id == after)
+                        .map_or(order.len(), |position| position + 1)
+                });
+                Box::new(
+                    order[start..]
+                        .iter()
+                        .copied()
+                        .filter(|id| post_ids.contains(*id)),
+                )
+            }
+            (None, Some(after)) => Box::new(post_ids.range((Excluded(after), Unbounded))),
+            (None, None) => Box::new(post_ids.iter()),
+        }
+    }
+
+    /// Number of posts matching a single term, used to order the intersections
+    fn term_frequency(&self, term: &Term) -> u64 {
+        self.term_post_ids(term).map_or(0, |ids| ids.len())
+    }
+
+    /// Ids of the posts matching a query
+    ///
+    /// Required terms are intersected starting with the rarest one, so the intermediate results
+    /// stay small. A query with only excluded terms starts from every indexed post.
+    pub fn search(&self, query: &Query) -> RoaringBitmap {
+        self.search_steps(query, |_, _| {})
+    }
+
+    /// Evaluate a query like [`Index::search`], describing every step along with the number of
+    /// posts left after it
+    pub fn explain(&self, query: &Query) -> Vec<(String, u64)> {
+        let mut steps = Vec::new();
+        self.search_steps(query, |step, result| steps.push((step, result.len())));
+        steps
+    }
+
+    fn search_steps(
+        &self,
+        query: &Query,
+        mut step: impl FnMut(String, &RoaringBitmap),
+    ) -> RoaringBitmap {
+        let mut include: Vec<(&Term, u64)> = query
+            .include
+            .iter()
+            .map(|term| (term, self.term_frequency(term)))
+            .collect();
+        include.sort_by_key(|(_, freq)| *freq);
+
+        let mut result: Option<RoaringBitmap> = None;
+        for (term, freq) in include {
+            match &mut result {
+                None => {
+                    let ids = self.term_post_ids(term);
+                    result = Some(ids.map(Cow::into_owned).unwrap_or_default());
+                }
+                Some(result) => match self.filter_by_bloom(result, term, true) {
+                    Some(filtered) => *result = filtered,
+                    None => match self.term_post_ids(term) {
+                        Some(ids) => *result &= ids.as_ref(),
+                        None => result.clear(),
+                    },
+                },
+            }
+            let result = result.as_ref().unwrap();
+            step(format!("and {} ({} posts)", term, freq), result);
+            if result.is_empty() {
+                return RoaringBitmap::new(); // Early exit if intersection becomes empty
+            }
+        }
+
+        if !query.any.is_empty() {
+            let mut any = RoaringBitmap::new();
+            for term in &query.any {
+                if let Some(ids) = self.term_post_ids(term) {
+                    any |= ids.as_ref();
+                }
+            }
+            let names = query.any.iter().map(|term| term.to_string());
+            let description = format!(
+                "and any of {} ({} posts)",
+                names.collect::<Vec<_>>().join(", "),
+                any.len()
+            );
+            match &mut result {
+                Some(result) => *result &= any,
+                None => result = Some(any),
+            }
+            step(description, result.as_ref().unwrap());
+        }
+
+        let mut result = result.unwrap_or_else(|| {
+            let all = self.all_post_ids();
+            step(String::from("all posts"), &all);
+            all
+        });
+
+        for term in &query.exclude {
+            match self.filter_by_bloom(&result, term, false) {
+                Some(filtered) => result = filtered,
+                None => {
+                    if let Some(ids) = self.term_post_ids(term) {
+                        result -= ids.as_ref();
+                    }
+                }
+            }
+            step(
+                format!("not {} ({} posts)", term, self.term_frequency(term)),
+                &result,
+            );
+        }
+
+        result
+    }
+
+    /// The posts of a small `result` which have the tag of `term`, or with `member` false which
+    /// don't, checking one post at a time
+    ///
+    /// The bloom filter of the tag rules out most non-members without touching its bitmap. `None`
+    /// if the tag has no filter or `result` isn't much smaller than its posts, then intersecting
+    /// the bitmaps is faster.
+    fn filter_by_bloom(
+        &self,
+        result: &RoaringBitmap,
+        term: &Term,
+        member: bool,
+    ) -> Option<RoaringBitmap> {
+        let tag_id = match term {
+            Term::Tag(tag) => self.tag_id(tag)?,
+            Term::TypedTag(tag_type, tag) => self
+                .tag_id(tag)
+                .filter(|id| self.tag_id_to_type.get(id) == Some(tag_type))?,
+            _ => return None,
+        };
+        let bloom = self.tag_id_to_bloom.get(&tag_id)?;
+        let post_ids = self.tag_id_to_post_id.get(&tag_id)?;
+        if result.len() > BLOOM_MAX_RESULT || result.len() * BLOOM_MIN_RATIO > post_ids.len() {
+            return None;
+        }
+        let filtered = result
+            .iter()
+            .filter(|id| (bloom.may_contain(*id) && post_ids.contains(*id)) == member)
+            .collect();
+        Some(filtered)
+    }
+
+    /// The most used tags starting with `prefix`, most used first
+    pub fn suggest_tags(&self, prefix: &str, limit: usize) -> Vec<(&str, u32)> {
+        let prefix = prefix.to_lowercase();
+        let mut tags: Vec<(&str, u32)> = self
+            .tag_str_to_id
+            .iter()
+            .filter(|(name, _)| name.starts_with(&prefix))
+            .map(|(name, id)| {
+                let freq = self.tag_id_freq.get(id).copied().unwrap_or_default();
+                (name.as_str(), freq)
+            })
+            .collect();
+        tags.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        tags.truncate(limit);
+        tags
+    }
+
+    /// Tags within two edits of a misspelled `term`, closest first and the most used first among
+    /// those at the same distance
+    ///
+    /// Tags without posts and `term` itself are left out. The dictionary is built from the tag
+    /// names on the first call, see [`symspell`](crate::symspell), so later calls only cost a
+    /// lookup.
+    pub fn did_you_mean(&self, term: &str) -> Vec<Correction> {
+        let term = term.to_lowercase();
+        let spelling = self
+            .spelling
+            .get_or_init(|| SymSpell::new(self.tag_str_to_id.keys().cloned(), 2));
+        let mut corrections: Vec<Correction> = spelling
+            .lookup(&term)
+            .into_iter()
+            .filter(|(name, _)| *name != term)
+            .filter_map(|(name, distance)| {
+                let count = self.tag_id_freq.get(self.tag_str_to_id.get(name)?)?;
+                (*count > 0).then(|| Correction {
+                    name: name.to_string(),
+                    distance,
+                    count: *count,
+                })
+            })
+            .collect();
+        corrections.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then(b.count.cmp(&a.count))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        corrections
+    }
+
+    /// The uploader accounts `uploader` names: the account with that id if it is a number, or
+    /// else every account whose current display name it is
+    ///
+    /// Names are matched against [`creator_names`](Self::creator_names), so an account renamed on
+    /// the site keeps all its posts under its new name once a post with that name is indexed or
+    /// the names are refreshed from the API.
+    pub fn creator_ids(&self, uploader: &str) -> Vec<u64> {
+        if let Ok(id) = uploader.parse() {
+            return vec![id];
+        }
+        let uploader = uploader.to_lowercase();
+        let mut ids: Vec<u64> = self
+            .creator_names
+            .iter()
+            .filter(|(_, name)| **name == uploader)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The `top` uploaders with the most of the posts in `post_ids`, most posts first
+    pub fn uploader_stats(&self, post_ids: &RoaringBitmap, top: usize) -> Vec<UploaderStats> {
+        let mut counts: Vec<(u64, RoaringBitmap)> = self
+            .creator_to_post_id
+            .iter()
+            .map(|(id, posts)| (*id, posts & post_ids))
+            .filter(|(_, posts)| !posts.is_empty())
+            .collect();
+        counts.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+        counts.truncate(top);
+
+        counts
+            .into_iter()
+            .map(|(creator_id, posts)| {
+                let dates = posts
+                    .iter()
+                    .filter_map(|id| self.post_id_to_post.get(&id))
+                    .map(|post| post.created_at);
+                let (first_post, last_post) = dates.fold((None, None), |(first, last), date| {
+                    (
+                        Some(first.map_or(date, |first: DateTime<Utc>| first.min(date))),
+                        Some(last.map_or(date, |last: DateTime<Utc>| last.max(date))),
+                    )
+                });
+                let (mut sum, mut scored) = (0.0, 0);
+                for (score, ids) in &self.score_to_post_id {
+                    let count = ids.intersection_len(&posts);
+                    sum += *score as f64 * count as f64;
+                    scored += count;
+                }
+                UploaderStats {
+                    creator_id,
+                    name: self.creator_names.get(&creator_id).cloned(),
+                    posts: posts.len(),
+                    first_post,
+                    last_post,
+                    mean_score: (scored > 0).then(|| sum / scored as f64),
+                }
+            })
+            .collect()
+    }
+
+    /// Type of a tag, `None` for tags missing from the tags file
+    pub fn tag_type(&self, tag: &str) -> Option<TagType> {
+        self.tag_id_to_type.get(&self.tag_id(tag)?).copied()
+    }
+
+    /// Groups of indexed posts whose perceptual hashes differ in at most `threshold` bits
+    ///
+    /// Posts are grouped transitively and only groups with different md5s are returned, ordered by
+    /// their lowest id. The hashes are split into `threshold + 1` blocks and only posts sharing a
+    /// block are compared, since two hashes within the threshold always share one. That is fast
+    /// for the useful thresholds of up to about 10 bits.
+    pub fn near_duplicates(&self, threshold: u32) -> Vec<Vec<u32>> {
+        let mut hashes: Vec<(u32, PerceptualHash)> = self
+            .perceptual_hashes
+            .iter()
+            .filter(|(id, _)| self.post_id_to_post.contains_key(id))
+            .map(|(id, hash)| (*id, *hash))
+            .collect();
+        hashes.sort_unstable_by_key(|(id, _)| *id);
+
+        let mut parents: Vec<usize> = (0..hashes.len()).collect();
+        let blocks = (threshold as usize + 1).min(64);
+        for block in 0..blocks {
+            let (start, end) = (block * 64 / blocks, (block + 1) * 64 / blocks);
+            let mask = (u64::MAX >> (64 - (end - start))) << start;
+            let mut buckets: HashMap<(HashKind, u64), Vec<usize>> = HashMap::new();
+            for (i, (_, hash)) in hashes.iter().enumerate() {
+                buckets
+                    .entry((hash.kind, hash.hash & mask))
+                    .or_default()
+                    .push(i);
+            }
+            for bucket in buckets.values() {
+                for (position, a) in bucket.iter().enumerate() {
+                    for b in &bucket[position + 1..] {
+                        let distance = hashes[*a].1.distance(&hashes[*b].1);
+                        if distance.is_some_and(|distance| distance <= threshold) {
+                            let (a, b) = (find(&mut parents, *a), find(&mut parents, *b));
+                            parents[a.max(b)] = a.min(b);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+        for (i, (id, _)) in hashes.iter().enumerate() {
+            let root = find(&mut parents, i);
+            groups.entry(root).or_default().push(*id);
+        }
+        groups
+            .into_values()
+            .filter(|group| {
+                let first = &self.post_id_to_post[&group[0]].md5;
+                group[1..]
+                    .iter()
+                    .any(|id| &self.post_id_to_post[id].md5 != first)
+            })
+            .collect()
+    }
+
+    /// Posts with a tag of a rule and a rating the rule doesn't allow, ordered by id
+    ///
+    /// Such a post was usually re-rated on the site after it was scraped, or had its tags edited,
+    /// so it is worth requesting again. Works on the rating and tag bitmaps alone, so indexes
+    /// saved before ratings were indexed have no findings.
+    pub fn audit_ratings(&self, rules: &[RatingRule]) -> Vec<RatingFinding> {
+        let mut findings: BTreeMap<u32, RatingFinding> = BTreeMap::new();
+        for (position, rule) in rules.iter().enumerate() {
+            for (rating, rated) in &self.rating_to_post_id {
+                if rule.allows(&Rating::from(rating.clone())) {
+                    continue;
+                }
+                for tag in &rule.tags {
+                    let Some(tagged) = self
+                        .tag_id(tag)
+                        .and_then(|id| self.tag_id_to_post_id.get(&id))
+                    else {
+                        continue;
+                    };
+                    for post_id in tagged & rated {
+                        let finding = findings.entry(post_id).or_insert_with(|| RatingFinding {
+                            post_id,
+                            rating: rating.clone(),
+                            rules: Vec::new(),
+                            tags: Vec::new(),
+                        });
+                        if finding.rules.last() != Some(&position) {
+                            finding.rules.push(position);
+                        }
+                        if !finding.tags.contains(tag) {
+                            finding.tags.push(tag.clone());
+                        }
+                    }
+                }
+            }
+        }
+        findings.into_values().collect()
+    }
+
+    /// Collect the tag names of every post in `post_ids`
+    ///
+    /// The index only stores tag -> posts, so this intersects every tag bitmap with `post_ids`.
+    /// It is meant for exports, not for the query hot path.
+    pub fn tags_for_posts(&self, post_ids: &RoaringBitmap) -> HashMap<u32, Vec<String>> {
+        let tag_names: HashMap<u32, &str> = self
+            .tag_str_to_id
+            .iter()
+            .map(|(name, id)| (*id, name.as_str()))
+            .collect();
+
+        let mut tags: HashMap<u32, Vec<String>> = HashMap::new();
+        for (tag_id, bitmap) in &self.tag_id_to_post_id {
+            let Some(name) = tag_names.get(tag_id) else {
+                continue;
+            };
+            for post_id in bitmap & post_ids {
+                tags.entry(post_id).or_default().push(name.to_string());
+            }
+        }
+        for tag_list in tags.values_mut() {
+            tag_list.sort_unstable();
+        }
+        tags
+    }
+}
+
+/// Read the full records of the posts in `post_ids` from a posts file, ordered by id
+///
+/// The index only keeps a few fields per post, this joins query results back to the scraped data.
+/// If a post was written more than once the last record wins.
+pub fn read_posts<R: BufRead>(reader: R, post_ids: &RoaringBitmap) -> std::io::Result<Vec<Post>> {
+    let mut posts: BTreeMap<u64, Post> = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        // Only parse the records that are actually needed
+        let Some(id) = record_id(&line) else {
+            continue;
+        };
+        if id > u32::MAX as u64 || !post_ids.contains(id as u32) {
+            continue;
+        }
+        if let Ok(post) = parse_record::<Post>(&line) {
+            posts.insert(id, post);
+        }
+    }
+    Ok(posts.into_values().collect())
+}
+
+/// Like [`read_posts`], but passes the posts to `f` one at a time in the order of their latest
+/// record, so any number of them can be exported
+///
+/// The file is read twice: once to find the offset of the latest record of every post, then once
+/// more seeking to those records. Returns the number of posts passed to `f`.
+pub fn stream_posts<R: BufRead + Seek, E: From<std::io::Error>>(
+    mut reader: R,
+    post_ids: &RoaringBitmap,
+    mut f: impl FnMut(Post) -> Result<(), E>,
+) -> Result<u64, E> {
+    let mut latest: HashMap<u32, u64> = HashMap::new();
+    let mut offset = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        if let Some(id) = record_id(&line).filter(|id| *id <= u32::MAX as u64) {
+            if post_ids.contains(id as u32) {
+                latest.insert(id as u32, offset);
+            }
+        }
+        offset += read as u64;
+    }
+
+    let mut offsets: Vec<u64> = latest.into_values().collect();
+    offsets.sort_unstable();
+    let mut posts = 0;
+    for offset in offsets {
+        reader.seek(SeekFrom::Start(offset))?;
+        line.clear();
+        reader.read_line(&mut line)?;
+        if let Ok(post) = parse_record::<Post>(&line) {
+            f(post)?;
+            posts += 1;
+        }
+    }
+    Ok(posts)
+}
+
+/// Call `f` with every complete (newline terminated) line of `reader` which isn't blank,
+/// counting the lines `f` fails to parse
+fn for_each_complete_line<R: BufRead>(
+    mut reader: R,
+    mut f: impl FnMut(&str) -> Result<(), RecordError>,
+) -> std::io::Result<IngestStats> {
+    let mut stats = IngestStats::default();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            return Ok(stats);
+        }
+        stats.lines += 1;
+        stats.bytes += read as u64;
+        if !line.trim().is_empty() {
+            stats.record(stats.lines, f(&line));
+        }
+    }
+}
+
+/// Lets the scrapers feed a live index directly
+impl Sink<Post> for Index {
+    fn write(&mut self, post: Post) -> Result<(), SinkError> {
+        self.insert_post(post);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+impl Sink<Tag> for Index {
+    fn write(&mut self, tag: Tag) -> Result<(), SinkError> {
+        self.insert_tag(tag);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Root of the set of `i` in a union-find forest, compressing the path to it
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::Fixtures, models::envelope::Envelope};
+
+    fn lines<T: Serialize + crate::models::envelope::Record>(
+        records: impl IntoIterator<Item = T>,
+    ) -> String {
+        records
+            .into_iter()
+            .map(|record| serde_json::to_string(&Envelope::new(record)).unwrap() + "\n")
+            .collect()
+    }
+
+    fn search(index: &Index, query: &str) -> Vec<u32> {
+        index.search(&Query::parse(query).unwrap()).iter().collect()
+    }
+
+    #[test]
+    fn later_records_replace_earlier_ones_in_a_full_build() {
+        let mut fixtures = Fixtures::new(3);
+        fixtures.tags(10);
+        let mut posts = fixtures.posts(5);
+        for post in &mut posts {
+            post.tags = vec![String::from("cat_ears")];
+        }
+        let mut tags = fixtures.generated_tags().to_vec();
+        tags[0].name = String::from("cat_ears");
+        tags[1].name = String::from("long_hair");
+
+        // A sync appends a tombstone of post 2 and a new version of post 3
+        let mut deleted = posts[1].clone();
+        deleted.status = String::from("deleted");
+        deleted.change += 1;
+        let mut edited = posts[2].clone();
+        edited.tags = vec![String::from("long_hair")];
+        edited.change += 1;
+        let posts = lines(posts.into_iter().chain([deleted, edited]));
+
+        let (index, report) = Index::from_json_lines(&posts, &lines(tags));
+        assert_eq!(report.posts.lines, 7);
+        assert_eq!(index.post_id_to_post.len(), 4);
+        assert!(!index.post_id_to_post.contains_key(&2));
+        assert_eq!(search(&index, "cat_ears"), [1, 4, 5]);
+        assert_eq!(search(&index, "long_hair"), [3]);
+        let cat_ears = index.tag_id("cat_ears").unwrap();
+        assert_eq!(index.tag_id_freq[&cat_ears], 3);
+    }
+
+    #[test]
+    fn a_tombstone_of_an_unknown_post_is_ignored() {
+        let mut fixtures = Fixtures::new(4);
+        fixtures.tags(5);
+        let mut post = fixtures.post();
+        post.status = String::from("deleted");
+        let (index, report) =
+            Index::from_json_lines(&lines([post]), &lines(fixtures.generated_tags().to_vec()));
+        assert_eq!(report.posts.failed, 0);
+        assert!(index.post_id_to_post.is_empty());
+    }
+
+    #[test]
+    fn incremental_ingest_matches_a_full_build() {
+        let mut fixtures = Fixtures::new(5);
+        fixtures.tags(20);
+        let tags = lines(fixtures.generated_tags().to_vec());
+        let posts = fixtures.posts(6);
+        let names: Vec<String> = fixtures
+            .generated_tags()
+            .iter()
+            .map(|tag| tag.name.clone())
+            .collect();
+        let version = |post: &Post, tags: &[String], status: &str| {
+            let mut post = post.clone();
+            post.tags = tags.to_vec();
+            post.status = status.to_string();
+            post.change += 1;
+            post
+        };
+
+        // A sync retags post 1 and deletes post 2, which were indexed before, and the same batch
+        // retags post 3 twice and deletes and restores post 4
+        let appended = lines([
+            version(&posts[0], &names[..2], "active"),
+            version(&posts[1], &[], "deleted"),
+            version(&posts[2], &names[2..5], "active"),
+            version(&posts[2], &names[4..6], "active"),
+            version(&posts[3], &[], "deleted"),
+            version(&posts[3], &names[6..7], "active"),
+        ]);
+        let first = lines(posts);
+
+        let (mut index, _) = Index::from_json_lines(&first, &tags);
+        let stats = index.ingest_posts(appended.as_bytes()).unwrap();
+        assert_eq!(stats.lines, 6);
+        let (full, _) = Index::from_json_lines(&(first + &appended), &tags);
+
+        assert_eq!(index.post_id_to_post.len(), 5);
+        assert!(!index.post_id_to_post.contains_key(&2));
+        assert!(!search(&index, &names[2]).contains(&3));
+        assert!(search(&index, &names[5]).contains(&3));
+        assert!(search(&index, &names[6]).contains(&4));
+        for name in &names {
+            assert_eq!(search(&index, name), search(&full, name), "{name}");
+        }
+        let frequencies = |index: &Index| {
+            let mut frequencies: Vec<(u32, u32)> = index
+                .tag_id_freq
+                .iter()
+                .map(|(tag_id, freq)| (*tag_id, *freq))
+                .filter(|(_, freq)| *freq > 0)
+                .collect();
+            frequencies.sort();
+            frequencies
+        };
+        assert_eq!(frequencies(&index), frequencies(&full));
+    }
 }
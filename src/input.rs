@@ -0,0 +1,67 @@
+//! Reading of JSON lines files which may be compressed, see [`open`]
+//!
+//! Long running scrapes leave dumps of tens of gigabytes, which compress very well. Files ending
+//! in `.gz` (with the `gzip` feature) or `.zst` (with the `zstd` feature) are decompressed while
+//! they are read, so they never have to be unpacked on disk.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+/// The compression of a file, by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn of<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Open `path` for reading lines, decompressing it if its extension says it is compressed
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead + Send>> {
+    let path = path.as_ref();
+    decode(File::open(path)?, Compression::of(path))
+}
+
+/// Read lines from `reader`, decompressing it first
+///
+/// Takes the raw reader so callers can count the compressed bytes, e.g. for a progress bar.
+pub fn decode<R: Read + Send + 'static>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<Box<dyn BufRead + Send>> {
+    match compression {
+        Compression::None => Ok(Box::new(BufReader::new(reader))),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(
+            BufReader::new(reader),
+        )))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(BufReader::new(zstd::stream::read::Decoder::new(
+            reader,
+        )?))),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => Err(unsupported("gz", "gzip")),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported("zst", "zstd")),
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(extension: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("reading `.{extension}` files requires the `{feature}` feature"),
+    )
+}
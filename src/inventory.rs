@@ -0,0 +1,148 @@
+//! The files mirrored to a downloads directory, matched to the posts by md5
+//!
+//! `download` stores files as `{md5}.{extension}`, so the md5 in the name of a file is enough to
+//! know which posts it belongs to. [`Inventory::scan`] walks the directory (subdirectories too,
+//! e.g. for `ab/cd/{md5}.jpg` mirrors) and the result is saved next to the output files. The index
+//! keeps the posts whose file is present, see [`Index::apply_inventory`], which is what the
+//! `file:downloaded` and `file:missing` queries use.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+use crate::index::Index;
+
+/// A file of the inventory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InventoryFile {
+    /// Relative to the scanned directory
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// The files of a downloads directory by lowercase hex md5
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    pub scanned_at: DateTime<Utc>,
+    pub dir: PathBuf,
+    pub files: BTreeMap<String, InventoryFile>,
+}
+
+/// Counts of an [`Inventory::scan`]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct ScanStats {
+    pub files: u64,
+    pub bytes: u64,
+    /// Files whose name isn't an md5, e.g. unfinished `.part` downloads
+    pub skipped: u64,
+}
+
+impl Inventory {
+    /// Walk `dir` and record every file named by an md5
+    ///
+    /// If several files have the same md5 (e.g. an original and a sample), the largest is kept.
+    pub fn scan<P: AsRef<Path>>(dir: P) -> std::io::Result<(Self, ScanStats)> {
+        let dir = dir.as_ref();
+        let mut inventory = Inventory {
+            scanned_at: Utc::now(),
+            dir: dir.to_path_buf(),
+            files: BTreeMap::new(),
+        };
+        let mut stats = ScanStats::default();
+
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                    continue;
+                }
+                let path = entry.path();
+                let Some(md5) = file_md5(&path) else {
+                    stats.skipped += 1;
+                    continue;
+                };
+                let bytes = entry.metadata()?.len();
+                stats.files += 1;
+                stats.bytes += bytes;
+
+                let file = InventoryFile {
+                    path: path.strip_prefix(dir).unwrap_or(&path).to_path_buf(),
+                    bytes,
+                };
+                match inventory.files.get(&md5) {
+                    Some(known) if known.bytes >= bytes => {}
+                    _ => {
+                        inventory.files.insert(md5, file);
+                    }
+                }
+            }
+        }
+        Ok((inventory, stats))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    pub fn contains(&self, md5: &[u8; 16]) -> bool {
+        self.files.contains_key(&hex::encode(md5))
+    }
+
+    /// Path of the file with `md5`, including the scanned directory
+    pub fn path(&self, md5: &[u8; 16]) -> Option<PathBuf> {
+        let file = self.files.get(&hex::encode(md5))?;
+        Some(self.dir.join(&file.path))
+    }
+
+    /// Ids of the indexed posts whose file is in the inventory
+    pub fn downloaded(&self, index: &Index) -> RoaringBitmap {
+        index
+            .post_id_to_post
+            .values()
+            .filter(|post| self.contains(&post.md5))
+            .map(|post| post.id)
+            .collect()
+    }
+
+    /// Md5s of the files which don't belong to any indexed post
+    pub fn orphans(&self, index: &Index) -> Vec<&str> {
+        let indexed: HashSet<String> = index
+            .post_id_to_post
+            .values()
+            .map(|post| hex::encode(post.md5))
+            .collect();
+        self.files
+            .keys()
+            .filter(|md5| !indexed.contains(*md5))
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// The md5 a file is named by, `None` for other files
+fn file_md5(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(".part") {
+        return None;
+    }
+    let stem = name.split('.').next()?;
+    (stem.len() == 32 && stem.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| stem.to_ascii_lowercase())
+}
@@ -1,4 +1,70 @@
+//! Scrape a booru, index its posts by tag and query them
+//!
+//! The crate is split by features, so an application only embedding the query engine doesn't pull
+//! in an HTTP client or an async runtime:
+//!
+//! - `index` (default): the bitmap [`index`] with its queries, score [`histogram`]s, stats, file [`inventory`], exporters and
+//!   importers
+//! - `scraper`: the API client, scrapers, downloader, sinks and maintenance jobs
+//! - `serve`: the HTTP search [`server`]
+//! - `cli` (default): the `indexer` binary
+//! - `fixtures`: synthetic posts and tags for tests
+//! - `phash`: perceptual hashes of downloaded images, see [`phash`]
+//! - `sheet`: contact sheets of the previews of a query, see [`sheet`]
+//! - `ffi`: a C API for the query engine, see [`ffi`]
+//! - `python`: a Python module exposing the index, see [`python`]
+//!
+//! [`models`], [`query`] and the [`sink`] trait are always available. With only `index` the crate
+//! builds for `wasm32-unknown-unknown`, see [`Index::from_bytes`](index::Index::from_bytes).
+
 pub mod api;
-pub mod scraper;
+#[cfg(feature = "index")]
+pub mod bloom;
+#[cfg(feature = "index")]
+pub mod cluster;
+#[cfg(feature = "scraper")]
+pub mod config;
+#[cfg(feature = "scraper")]
+pub mod download;
+#[cfg(feature = "index")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
+#[cfg(feature = "index")]
+pub mod histogram;
+#[cfg(feature = "index")]
+pub mod import;
+#[cfg(feature = "index")]
+pub mod index;
+pub mod input;
+#[cfg(feature = "index")]
+pub mod inventory;
+#[cfg(feature = "scraper")]
+pub mod maintenance;
 pub mod models;
-pub mod index;
\ No newline at end of file
+#[cfg(feature = "index")]
+pub mod namespace;
+#[cfg(feature = "phash")]
+pub mod phash;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod query;
+#[cfg(feature = "scraper")]
+pub mod schedule;
+#[cfg(feature = "scraper")]
+pub mod scraper;
+#[cfg(feature = "serve")]
+pub mod server;
+#[cfg(feature = "sheet")]
+pub mod sheet;
+pub mod sink;
+#[cfg(feature = "index")]
+pub mod snapshot;
+#[cfg(feature = "index")]
+pub mod stats;
+#[cfg(all(feature = "index", feature = "scraper"))]
+pub mod stream;
+#[cfg(feature = "index")]
+pub mod symspell;
@@ -1,121 +1,128 @@
-use std::{fs::File, io::BufWriter};
+use std::process::ExitCode;
 
-use indexer::{
-    api::client::ApiClient,
-    index::Index,
-    scraper::{post_scraper::PostScraper, state_manager::StateManager, tag_scraper::TagScraper},
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
+
+use cli::{
+    output::{Status, FAILURE},
+    progress::Progress,
+    telemetry, Cli, Command,
 };
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE, USER_AGENT};
-use tracing::info;
+use indexer::config::{self, Config, LogConfig};
+
+mod cli;
+
+/// The returned guard exports the last spans when dropped
+type BoxedLayer = Box<dyn tracing_subscriber::Layer<tracing_subscriber::Registry> + Send + Sync>;
+
+/// A log output writing to `writer` in `format`
+fn format_layer<W>(format: config::LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    use tracing_subscriber::{fmt, Layer};
+
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        config::LogFormat::Text => layer.boxed(),
+        config::LogFormat::Json => layer
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
 
-fn init_tracing() {
-    use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+fn init_tracing(log: &LogConfig) -> Result<telemetry::Guard, Box<dyn std::error::Error>> {
+    use tracing_appender::rolling::{RollingFileAppender, Rotation};
+    use tracing_subscriber::{prelude::*, EnvFilter, Layer};
+
+    // Logs go to stderr, stdout is reserved for command output
+    let mut layers = vec![format_layer(log.format, std::io::stderr, true)
+        .with_filter(EnvFilter::from_default_env())
+        .boxed()];
+    if let Some(dir) = &log.dir {
+        let rotation = match log.rotation {
+            config::Rotation::Minutely => Rotation::MINUTELY,
+            config::Rotation::Hourly => Rotation::HOURLY,
+            config::Rotation::Daily => Rotation::DAILY,
+            config::Rotation::Never => Rotation::NEVER,
+        };
+        std::fs::create_dir_all(dir)?;
+        let mut appender = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&log.prefix);
+        if let Some(max_files) = log.max_files {
+            appender = appender.max_log_files(max_files);
+        }
+        layers.push(
+            format_layer(log.format, appender.build(dir)?, false)
+                .with_filter(EnvFilter::try_new(&log.level)?)
+                .boxed(),
+        );
+    }
+    let (otlp_layer, guard) = telemetry::layer(log.otlp.as_ref())?;
+    if let Some(otlp_layer) = otlp_layer {
+        layers.push(otlp_layer.boxed());
+    }
 
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
+    tracing_subscriber::registry().with(layers).init();
+    Ok(guard)
 }
 
-/// Create a reqwest client with the necessary headers
-fn create_client() -> reqwest::Client {
-    let mut headers = HeaderMap::default();
-    headers.insert(USER_AGENT, HeaderValue::from_str("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36").unwrap());
-    headers.insert(
-        ACCEPT_LANGUAGE,
-        HeaderValue::from_str("en-US,en;q=0.9").unwrap(),
-    );
-
-    reqwest::Client::builder()
-        .brotli(true)
-        .gzip(true)
-        .deflate(true)
-        .default_headers(headers)
-        .build()
-        .unwrap()
+async fn run(cli: Cli) -> Result<Status, Box<dyn std::error::Error>> {
+    let mut config = Config::load(&cli.config, cli.profile.as_deref())?;
+    if let Some(dir) = cli.log_dir {
+        config.log.dir = Some(dir);
+    }
+    let _telemetry = init_tracing(&config.log)?;
+    let format = cli.format;
+    let progress = Progress::new(!cli.no_progress);
+
+    match cli.command {
+        Command::Scrape(args) => cli::scrape::run(args, config, format, progress).await,
+        Command::Index(command) => cli::index::run(command, config, format, progress).await,
+        Command::Query(args) => cli::query::run(args, config, format),
+        Command::Bench(args) => cli::bench::run(args, config, format),
+        Command::Daemon(args) => cli::daemon::run(args, config).await,
+        Command::Convert(args) => cli::convert::run(args, format),
+        Command::Dataset(args) => cli::dataset::run(args, config, format),
+        Command::Download(args) => cli::download::run(args, config, format, progress).await,
+        Command::Sheet(args) => cli::sheet::run(args, config, format, progress).await,
+        Command::Duplicates(args) => cli::duplicates::run(args, config, format),
+        Command::Inventory(args) => cli::inventory::run(args, config, format),
+        Command::Embed(args) => cli::embed::run(args, config, format),
+        Command::Enrich(args) => cli::enrich::run(args, config, format),
+        Command::Merge(args) => cli::merge::run(args, format),
+        Command::Repl(args) => cli::repl::run(args, config),
+        Command::Repair => cli::repair::run(config, format).await,
+        Command::Serve(args) => cli::serve::run(args, config).await,
+        Command::Stats(args) => cli::stats::run(args, config, format),
+        Command::Sync(args) => cli::sync::run(args, config, format).await,
+        Command::SyncTo(args) => cli::sync_to::run(args, config, format).await,
+        Command::Verify(args) => cli::verify::run(args, config, format),
+        Command::TagDrift(args) => cli::tag_drift::run(args, config, format),
+        Command::Audit(args) => cli::audit::run(args, config, format),
+        Command::Pack(args) => cli::pack::run(args, config, format),
+        Command::Unpack(args) => cli::unpack::run(args, format),
+        Command::Snapshot(command) => cli::snapshot::run(command, config, format),
+        Command::Uploaders(args) => cli::uploaders::run(args, config, format).await,
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_tracing();
-    dotenvy::dotenv().expect("Failed to load .env file");
-
-    let endpoint = dotenvy::var("ENDPOINT").expect("ENDPOINT must be set");
-    let api_key = dotenvy::var("API_KEY").expect("API_KEY must be set");
-    let user_id = dotenvy::var("USER_ID").expect("USER_ID must be set");
-
-    let api_client = ApiClient::builder()
-        .client(create_client())
-        .endpoint(endpoint)
-        .api_key(api_key)
-        .user_id(user_id)
-        .build();
-
-    // Listen for ctrl-c
-    let ctrl_c_task = async {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to listen for ctrl-c");
-    };
-
-    // Scraped tags will be written to this file
-    let tag_output = BufWriter::new(
-        File::options()
-            .append(true)
-            .create(true)
-            .open("tags.json")
-            .expect("Failed to open tags.json"),
-    );
-
-    // Scraped posts will be written to this file
-    let post_output = BufWriter::new(
-        File::options()
-            .append(true)
-            .create(true)
-            .open("posts.json")
-            .expect("Failed to open posts.json"),
-    );
-
-    let state_manager = StateManager::new("state.json").expect("Failed to load state file");
-    let tag_scraper = TagScraper::new(tag_output, state_manager.clone(), api_client.clone());
-    let post_scraper = PostScraper::new(post_output, state_manager.clone(), api_client.clone());
-
-    let tag_scraper_task = async move {
-        tag_scraper.run().await.unwrap();
-    };
-
-    let post_scraper_task = async move {
-        post_scraper.run().await.unwrap();
-    };
-
-    tokio::select! {
-        _ = post_scraper_task => {
-            info!("Finished Scraping Posts");
-            state_manager.save_state("state.json").await?;
-        }
-        _ = tag_scraper_task => {
-            info!("Finished Scraping Tags");
-            state_manager.save_state("state.json").await?;
-        }
-        _ = ctrl_c_task => {
-            info!("Saving State");
-            state_manager.save_state("state.json").await?;
+async fn main() -> ExitCode {
+    // Answers the shell and exits if called for a completion, see `cli::complete`
+    CompleteEnv::with_factory(Cli::command).complete();
+
+    let cli = Cli::parse();
+    let format = cli.format;
+
+    match run(cli).await {
+        Ok(status) => status.into(),
+        Err(e) => {
+            format.print_error(e.as_ref());
+            ExitCode::from(FAILURE)
         }
     }
-
-    Ok(())
-}
-
-/// Example of building an index from the scraped data and querying it
-///
-/// From my benchmarks, most queries take less than 2ms to complete with an index of around 10 million posts
-fn _build_index() {
-    let index = Index::generate("posts.json", "tags.json").expect("Failed to generate index");
-
-    let query = vec![String::from("cat"), String::from("dog")];
-
-    let start = std::time::Instant::now();
-    index.get_images_all_tags_lazy(query).unwrap().count();
-    let duration = start.elapsed();
-    println!("Query took: {:?}", duration);
 }
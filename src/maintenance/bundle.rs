@@ -0,0 +1,344 @@
+//! Single-file `.booru` bundles of a dataset, see [`BundleWriter`] and [`Bundle`]
+//!
+//! A bundle holds the saved index, the tags file, a [`Manifest`] of the included files and
+//! optionally the posts, pools and notes files, so a dataset can be copied to another machine as
+//! one file. The entries are stored one after another, followed by a JSON table of contents and a
+//! trailer pointing at it:
+//!
+//! ```text
+//! "BOORUPK1" | entries | contents (JSON) | length of the contents (u64, little endian) | "BOORUPK1"
+//! ```
+//!
+//! With the contents at the end, entries are compressed while they are written without knowing
+//! their size up front, and a reader seeks straight to the one entry it needs.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::manifest::Manifest;
+
+/// Starts and ends every bundle, the digit is the version of the format
+pub const MAGIC: &[u8; 8] = b"BOORUPK1";
+
+/// Entry names of the files of a dataset
+pub const INDEX: &str = "index.json";
+pub const TAGS: &str = "tags.json";
+pub const MANIFEST: &str = "manifest.json";
+pub const POSTS: &str = "posts.json";
+pub const POOLS: &str = "pools.json";
+pub const NOTES: &str = "notes.json";
+
+#[derive(Debug, Error)]
+pub enum BundleError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid table of contents: {0}")]
+    Contents(#[from] serde_json::Error),
+    #[error("Not a bundle, the file doesn't start and end with `BOORUPK1`")]
+    NotABundle,
+    #[error("The bundle has no entry `{0}`")]
+    MissingEntry(String),
+    #[error("The entry `{0}` doesn't match its checksum")]
+    Checksum(String),
+    #[error("Compressed entries require the `zstd` feature")]
+    CompressionUnavailable,
+}
+
+/// How an entry is stored
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub name: String,
+    /// Position of the stored bytes in the bundle
+    pub offset: u64,
+    /// Number of stored bytes, after compression
+    pub stored_bytes: u64,
+    /// Number of bytes of the file, before compression
+    pub bytes: u64,
+    pub compression: Compression,
+    /// Checksum of the file, before compression
+    pub sha256: String,
+}
+
+/// The table of contents of a bundle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contents {
+    pub created_at: DateTime<Utc>,
+    pub entries: Vec<BundleEntry>,
+}
+
+/// Writes the entries of a bundle, which is only valid once [`finish`](Self::finish)ed
+pub struct BundleWriter {
+    writer: BufWriter<File>,
+    offset: u64,
+    entries: Vec<BundleEntry>,
+}
+
+impl BundleWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, BundleError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(Self {
+            writer,
+            offset: MAGIC.len() as u64,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Add the file at `path` as the entry `name`
+    pub fn add_file<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        path: P,
+        compression: Compression,
+    ) -> Result<&BundleEntry, BundleError> {
+        self.add_reader(name, BufReader::new(File::open(path)?), compression)
+    }
+
+    /// Add everything `reader` returns as the entry `name`
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+        compression: Compression,
+    ) -> Result<&BundleEntry, BundleError> {
+        let mut reader = HashingReader::new(reader);
+        let mut writer = CountingWriter {
+            inner: &mut self.writer,
+            count: 0,
+        };
+        match compression {
+            Compression::None => {
+                std::io::copy(&mut reader, &mut writer)?;
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(&mut writer, 0)?;
+                std::io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?;
+            }
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(BundleError::CompressionUnavailable),
+        }
+
+        let stored_bytes = writer.count;
+        let (bytes, sha256) = reader.finish();
+        self.entries.push(BundleEntry {
+            name: name.to_string(),
+            offset: self.offset,
+            stored_bytes,
+            bytes,
+            compression,
+            sha256,
+        });
+        self.offset += stored_bytes;
+        Ok(self.entries.last().unwrap())
+    }
+
+    /// Write the table of contents and the trailer
+    pub fn finish(mut self) -> Result<Contents, BundleError> {
+        let contents = Contents {
+            created_at: Utc::now(),
+            entries: self.entries,
+        };
+        let json = serde_json::to_vec(&contents)?;
+        self.writer.write_all(&json)?;
+        self.writer.write_all(&(json.len() as u64).to_le_bytes())?;
+        self.writer.write_all(MAGIC)?;
+        self.writer.flush()?;
+        Ok(contents)
+    }
+}
+
+/// An opened bundle, entries are read on demand
+#[derive(Debug)]
+pub struct Bundle {
+    file: File,
+    contents: Contents,
+}
+
+impl Bundle {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BundleError> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)
+            .map_err(|_| BundleError::NotABundle)?;
+        if &magic != MAGIC {
+            return Err(BundleError::NotABundle);
+        }
+
+        let mut trailer = [0u8; 16];
+        file.seek(SeekFrom::End(-16))
+            .and_then(|_| file.read_exact(&mut trailer))
+            .map_err(|_| BundleError::NotABundle)?;
+        if &trailer[8..] != MAGIC {
+            return Err(BundleError::NotABundle);
+        }
+        let len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+        let start = file
+            .seek(SeekFrom::End(-16))?
+            .checked_sub(len)
+            .ok_or(BundleError::NotABundle)?;
+        file.seek(SeekFrom::Start(start))?;
+        let contents = serde_json::from_reader(BufReader::new((&file).take(len)))?;
+        Ok(Self { file, contents })
+    }
+
+    pub fn contents(&self) -> &Contents {
+        &self.contents
+    }
+
+    pub fn entry(&self, name: &str) -> Option<&BundleEntry> {
+        self.contents
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+    }
+
+    /// Write the file of the entry `name` to `writer`, checking it against its checksum
+    ///
+    /// On a mismatch the file has already been written, and should be discarded.
+    pub fn extract<W: Write>(&self, name: &str, writer: W) -> Result<u64, BundleError> {
+        let entry = self
+            .entry(name)
+            .ok_or_else(|| BundleError::MissingEntry(name.to_string()))?;
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let mut stored = BufReader::new(file.take(entry.stored_bytes));
+
+        let mut writer = HashingWriter::new(writer);
+        match entry.compression {
+            Compression::None => {
+                std::io::copy(&mut stored, &mut writer)?;
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut decoder = zstd::stream::read::Decoder::with_buffer(stored)?;
+                std::io::copy(&mut decoder, &mut writer)?;
+            }
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(BundleError::CompressionUnavailable),
+        }
+
+        let (bytes, sha256) = writer.finish()?;
+        if bytes != entry.bytes || sha256 != entry.sha256 {
+            return Err(BundleError::Checksum(name.to_string()));
+        }
+        Ok(bytes)
+    }
+
+    /// The file of the entry `name`, checked against its checksum
+    pub fn read_entry(&self, name: &str) -> Result<Vec<u8>, BundleError> {
+        let capacity = self.entry(name).map_or(0, |entry| entry.bytes as usize);
+        let mut bytes = Vec::with_capacity(capacity);
+        self.extract(name, &mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// A [`Manifest`] of the JSON lines files of a bundle, with the entry names as paths
+pub fn manifest_entry<P: AsRef<Path>>(files: &[(&str, P)]) -> Result<Vec<u8>, BundleError> {
+    let paths: Vec<&Path> = files.iter().map(|(_, path)| path.as_ref()).collect();
+    let mut manifest = Manifest::build(&paths)?;
+    for (chunk, (name, _)) in manifest.chunks.iter_mut().zip(files) {
+        chunk.path = name.into();
+    }
+    Ok(serde_json::to_vec_pretty(&manifest)?)
+}
+
+/// Hashes and counts the bytes read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    fn finish(self) -> (u64, String) {
+        (self.bytes, hex::encode(self.hasher.finalize()))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+/// Hashes and counts the bytes written through it
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    fn finish(mut self) -> std::io::Result<(u64, String)> {
+        self.inner.flush()?;
+        Ok((self.bytes, hex::encode(self.hasher.finalize())))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
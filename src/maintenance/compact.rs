@@ -0,0 +1,94 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::models::{envelope::parse_record, Post};
+
+/// Counts collected while compacting a posts file
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompactStats {
+    pub lines: u64,
+    pub kept: u64,
+    pub duplicates: u64,
+    pub tombstones: u64,
+    pub malformed: u64,
+}
+
+/// Rewrite a posts file keeping only the latest record (highest `change`) per post id
+///
+/// Posts whose latest record has the status `deleted` are dropped entirely. If `sort` is set the
+/// output is ordered by post id, otherwise the original order of the kept lines is preserved.
+/// The output is written to a temporary file which replaces `path` once it is complete.
+pub fn compact_posts<P: AsRef<Path>>(
+    path: P,
+    sort: bool,
+) -> Result<CompactStats, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let mut stats = CompactStats::default();
+
+    // First pass: find the byte offset of the newest record for every post id
+    let mut latest: HashMap<u64, (u64, u64, bool)> = HashMap::new();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut offset = 0u64;
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        stats.lines += 1;
+
+        match parse_record::<Post>(&line) {
+            Ok(post) => {
+                let deleted = post.status == "deleted";
+                match latest.get(&post.id) {
+                    Some((change, _, _)) if *change > post.change => {}
+                    _ => {
+                        latest.insert(post.id, (post.change, offset, deleted));
+                    }
+                }
+            }
+            Err(_) => stats.malformed += 1,
+        }
+        offset += read as u64;
+    }
+
+    let mut kept: Vec<(u64, u64)> = latest
+        .into_iter()
+        .filter_map(|(id, (_, offset, deleted))| {
+            if deleted {
+                stats.tombstones += 1;
+                None
+            } else {
+                Some((id, offset))
+            }
+        })
+        .collect();
+    if sort {
+        kept.sort_unstable_by_key(|(id, _)| *id);
+    } else {
+        kept.sort_unstable_by_key(|(_, offset)| *offset);
+    }
+    stats.kept = kept.len() as u64;
+    stats.duplicates = stats.lines - stats.malformed - stats.kept - stats.tombstones;
+
+    // Second pass: copy the selected lines into the new file
+    let tmp_path = path.with_extension("compact.tmp");
+    let mut output = BufWriter::new(File::create(&tmp_path)?);
+    for (_, offset) in kept {
+        reader.seek(SeekFrom::Start(offset))?;
+        line.clear();
+        reader.read_line(&mut line)?;
+        output.write_all(line.trim_end_matches('\n').as_bytes())?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+    drop(output);
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(stats)
+}
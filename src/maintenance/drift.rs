@@ -0,0 +1,162 @@
+//! Changes of the tag table between two scrapes, see [`tag_drift`]
+//!
+//! Tags are matched by id, so a tag renamed on the site shows up as a rename rather than as a
+//! deleted and a new tag. The report is what a curator needs to keep aliases and blacklists in
+//! line with the site: new and deleted tags, renames, type changes and tags whose post count
+//! moved a lot.
+
+use std::{collections::HashMap, io::BufRead};
+
+use serde::Serialize;
+
+use crate::models::{envelope::parse_record, Tag};
+
+/// When a change of the post count of a tag is reported
+#[derive(Debug, Clone, Copy)]
+pub struct DriftThresholds {
+    /// Smallest absolute change
+    pub min_change: u64,
+    /// Smallest change relative to the old count, e.g. `0.5` for 50%
+    pub min_ratio: f64,
+}
+
+impl Default for DriftThresholds {
+    fn default() -> Self {
+        Self {
+            min_change: 100,
+            min_ratio: 0.5,
+        }
+    }
+}
+
+/// A tag as it appears in the report
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftTag {
+    pub id: u64,
+    pub name: String,
+    pub count: u64,
+    pub tag_type: &'static str,
+}
+
+impl From<&Tag> for DriftTag {
+    fn from(tag: &Tag) -> Self {
+        Self {
+            id: tag.id,
+            name: tag.name.clone(),
+            count: tag.count,
+            tag_type: tag.tag_type.as_str(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rename {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CountChange {
+    pub id: u64,
+    pub name: String,
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TypeChange {
+    pub id: u64,
+    pub name: String,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+/// Result of [`tag_drift`], every list is ordered by tag id except `counts`, which has the
+/// largest changes first
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TagDrift {
+    /// Tags in the old table
+    pub old: u64,
+    /// Tags in the new table
+    pub new: u64,
+    pub added: Vec<DriftTag>,
+    pub deleted: Vec<DriftTag>,
+    pub renamed: Vec<Rename>,
+    pub retyped: Vec<TypeChange>,
+    pub counts: Vec<CountChange>,
+}
+
+/// The latest record of every tag in a tags file, by id
+pub fn read_tags<R: BufRead>(reader: R) -> std::io::Result<HashMap<u64, Tag>> {
+    let mut tags = HashMap::new();
+    for line in reader.lines() {
+        if let Ok(tag) = parse_record::<Tag>(&line?) {
+            tags.insert(tag.id, tag);
+        }
+    }
+    Ok(tags)
+}
+
+/// Compare two tag tables, e.g. the tags files of two scrape runs
+pub fn tag_drift(
+    old: &HashMap<u64, Tag>,
+    new: &HashMap<u64, Tag>,
+    thresholds: DriftThresholds,
+) -> TagDrift {
+    let mut drift = TagDrift {
+        old: old.len() as u64,
+        new: new.len() as u64,
+        ..Default::default()
+    };
+
+    for (id, tag) in new {
+        let Some(before) = old.get(id) else {
+            drift.added.push(DriftTag::from(tag));
+            continue;
+        };
+        if before.name != tag.name {
+            drift.renamed.push(Rename {
+                id: *id,
+                from: before.name.clone(),
+                to: tag.name.clone(),
+            });
+        }
+        if before.tag_type != tag.tag_type {
+            drift.retyped.push(TypeChange {
+                id: *id,
+                name: tag.name.clone(),
+                from: before.tag_type.as_str(),
+                to: tag.tag_type.as_str(),
+            });
+        }
+        let change = before.count.abs_diff(tag.count);
+        if change >= thresholds.min_change
+            && change as f64 >= before.count as f64 * thresholds.min_ratio
+        {
+            drift.counts.push(CountChange {
+                id: *id,
+                name: tag.name.clone(),
+                from: before.count,
+                to: tag.count,
+            });
+        }
+    }
+    drift.deleted = old
+        .iter()
+        .filter(|(id, _)| !new.contains_key(id))
+        .map(|(_, tag)| DriftTag::from(tag))
+        .collect();
+
+    drift.added.sort_unstable_by_key(|tag| tag.id);
+    drift.deleted.sort_unstable_by_key(|tag| tag.id);
+    drift.renamed.sort_unstable_by_key(|rename| rename.id);
+    drift.retyped.sort_unstable_by_key(|change| change.id);
+    drift.counts.sort_unstable_by_key(|change| {
+        (
+            std::cmp::Reverse(change.from.abs_diff(change.to)),
+            change.id,
+        )
+    });
+    drift
+}
@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{
+    models::{
+        envelope::{parse_record, Envelope},
+        Post, Tag, TagType,
+    },
+    sink::{Sink, SinkError},
+};
+
+/// Counts collected while resolving the tags of a posts file
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct EnrichStats {
+    pub posts: u64,
+    /// Tags which aren't in the tags file
+    pub unresolved: u64,
+    pub malformed: u64,
+}
+
+/// Read the type of every tag in a tags file, keyed by the lowercase name
+pub fn load_tag_types<P: AsRef<Path>>(path: P) -> std::io::Result<HashMap<String, TagType>> {
+    let mut types = HashMap::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(tag) = parse_record::<Tag>(&line?) {
+            types.insert(tag.name.to_lowercase(), tag.tag_type);
+        }
+    }
+    Ok(types)
+}
+
+/// Rewrite a posts file with the types of their tags, see [`Post::resolve_tags`]
+///
+/// Every post is written in an envelope, malformed lines are dropped. The output is written to a
+/// temporary file which replaces `out` once it is complete, `out` may be `posts`.
+pub fn enrich_posts<P: AsRef<Path>, Q: AsRef<Path>>(
+    posts: P,
+    tag_types: &HashMap<String, TagType>,
+    out: Q,
+) -> Result<EnrichStats, Box<dyn std::error::Error>> {
+    let out = out.as_ref();
+    let mut stats = EnrichStats::default();
+
+    let tmp_path = out.with_extension("enrich.tmp");
+    let mut output = BufWriter::new(File::create(&tmp_path)?);
+    for line in BufReader::new(File::open(posts)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(mut post) = parse_record::<Post>(&line) else {
+            stats.malformed += 1;
+            continue;
+        };
+        stats.unresolved += post.resolve_tags(|tag| lookup(tag_types, tag)) as u64;
+        stats.posts += 1;
+        serde_json::to_writer(&mut output, &Envelope::new(post))?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+    drop(output);
+
+    std::fs::rename(&tmp_path, out)?;
+    Ok(stats)
+}
+
+/// Resolves the tags of every post before passing it on, for typed tags at scrape time
+pub struct EnrichSink<S> {
+    inner: S,
+    tag_types: HashMap<String, TagType>,
+}
+
+impl<S> EnrichSink<S> {
+    pub fn new(inner: S, tag_types: HashMap<String, TagType>) -> Self {
+        Self { inner, tag_types }
+    }
+}
+
+impl<S: Sink<Post>> Sink<Post> for EnrichSink<S> {
+    fn write(&mut self, mut post: Post) -> Result<(), SinkError> {
+        post.resolve_tags(|tag| lookup(&self.tag_types, tag));
+        self.inner.write(post)
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.inner.flush()
+    }
+}
+
+fn lookup(tag_types: &HashMap<String, TagType>, tag: &str) -> Option<TagType> {
+    tag_types.get(&tag.to_lowercase()).copied()
+}
@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::envelope::record_id;
+
+/// Description of a single output file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub lines: u64,
+    pub min_id: Option<u64>,
+    pub max_id: Option<u64>,
+    pub sha256: String,
+}
+
+/// A list of produced output files with their checksums, used to sync and verify mirrors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created_at: DateTime<Utc>,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+impl ChunkEntry {
+    /// Read a JSON lines file and describe it
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut hasher = Sha256::new();
+        let mut entry = ChunkEntry {
+            path: path.to_path_buf(),
+            bytes: 0,
+            lines: 0,
+            min_id: None,
+            max_id: None,
+            sha256: String::new(),
+        };
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&line);
+            entry.bytes += read as u64;
+            entry.lines += 1;
+
+            if let Some(id) = std::str::from_utf8(&line).ok().and_then(record_id) {
+                entry.min_id = Some(entry.min_id.map_or(id, |min| min.min(id)));
+                entry.max_id = Some(entry.max_id.map_or(id, |max| max.max(id)));
+            }
+        }
+
+        entry.sha256 = hex::encode(hasher.finalize());
+        Ok(entry)
+    }
+
+    /// Check that the file still matches the recorded size and checksum
+    pub fn verify(&self) -> Result<bool, std::io::Error> {
+        let mut file = File::open(&self.path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 1 << 16];
+        let mut bytes = 0u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            bytes += read as u64;
+        }
+        Ok(bytes == self.bytes && hex::encode(hasher.finalize()) == self.sha256)
+    }
+}
+
+impl Manifest {
+    pub fn build<P: AsRef<Path>>(paths: &[P]) -> Result<Self, std::io::Error> {
+        let chunks = paths
+            .iter()
+            .map(ChunkEntry::from_file)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            created_at: Utc::now(),
+            chunks,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Return the chunks which are missing or don't match their checksum
+    pub fn verify(&self) -> Vec<&ChunkEntry> {
+        self.chunks
+            .iter()
+            .filter(|chunk| !chunk.verify().unwrap_or(false))
+            .collect()
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    models::{
+        envelope::{parse_record, Record},
+        Post, Tag,
+    },
+    scraper::state_manager::ScrapeState,
+};
+
+/// Counts collected while merging output files
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct MergeStats {
+    pub lines: u64,
+    pub kept: u64,
+    pub duplicates: u64,
+    pub malformed: u64,
+}
+
+/// Merge posts files, keeping the latest record (highest `change`) per post id
+///
+/// Unlike [`compact_posts`](super::compact::compact_posts) deleted posts are kept, so a deletion
+/// seen by one machine wins over an older copy of the post from another.
+pub fn merge_posts<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    out: Q,
+) -> Result<MergeStats, Box<dyn std::error::Error>> {
+    merge_file::<Post, _, _>(inputs, out, |post| (post.id, post.change))
+}
+
+/// Merge tags files by tag id, a record in a later file replaces the one in an earlier file
+pub fn merge_tags<P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    out: Q,
+) -> Result<MergeStats, Box<dyn std::error::Error>> {
+    merge_file::<Tag, _, _>(inputs, out, |tag| (tag.id, 0))
+}
+
+/// Merge JSON lines files into one file sorted by id
+///
+/// `key` returns the id and version of a record, for every id only the line with the highest
+/// version is kept (the later one on ties). The lines are copied as they are, so bare records stay
+/// bare and enveloped ones keep their envelope. The output is written to a temporary file which
+/// replaces `out` once it is complete, `out` may be one of the inputs.
+pub fn merge_file<T: Record + DeserializeOwned, P: AsRef<Path>, Q: AsRef<Path>>(
+    inputs: &[P],
+    out: Q,
+    key: impl Fn(&T) -> (u64, u64),
+) -> Result<MergeStats, Box<dyn std::error::Error>> {
+    let out = out.as_ref();
+    let mut stats = MergeStats::default();
+
+    // First pass: find the input and byte offset of the newest record for every id
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut latest: HashMap<u64, (u64, usize, u64)> = HashMap::new();
+    let mut line = String::new();
+    for (input, path) in inputs.iter().enumerate() {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut offset = 0u64;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+
+            if !line.trim().is_empty() {
+                stats.lines += 1;
+                match parse_record::<T>(&line) {
+                    Ok(record) => {
+                        let (id, version) = key(&record);
+                        match latest.get(&id) {
+                            Some((latest_version, _, _)) if *latest_version > version => {}
+                            _ => {
+                                latest.insert(id, (version, input, offset));
+                            }
+                        }
+                    }
+                    Err(_) => stats.malformed += 1,
+                }
+            }
+            offset += read as u64;
+        }
+        readers.push(reader);
+    }
+
+    let mut kept: Vec<(u64, usize, u64)> = latest
+        .into_iter()
+        .map(|(id, (_, input, offset))| (id, input, offset))
+        .collect();
+    kept.sort_unstable_by_key(|(id, _, _)| *id);
+    stats.kept = kept.len() as u64;
+    stats.duplicates = stats.lines - stats.malformed - stats.kept;
+
+    // Second pass: copy the selected lines into the new file
+    let tmp_path = out.with_extension("merge.tmp");
+    let mut output = BufWriter::new(File::create(&tmp_path)?);
+    for (_, input, offset) in kept {
+        let reader = &mut readers[input];
+        reader.seek(SeekFrom::Start(offset))?;
+        line.clear();
+        reader.read_line(&mut line)?;
+        output.write_all(line.trim_end_matches('\n').as_bytes())?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+    drop(output);
+
+    std::fs::rename(&tmp_path, out)?;
+    Ok(stats)
+}
+
+/// Combine the state files of several scrapes
+///
+/// The completed post ranges are the union of those of every state, with the post watermark at the
+/// end of their first range. The tag watermark is the highest of all states, and the errors of
+/// every state are kept unless another state completed their range, so `repair` can retry them
+/// against the merged output.
+pub fn merge_states<P: AsRef<Path>>(
+    states: &[P],
+) -> Result<ScrapeState, Box<dyn std::error::Error>> {
+    let mut merged = ScrapeState {
+        last_post_id: 0,
+        last_tag_id: 0,
+        errors: Vec::new(),
+        completed_posts: Default::default(),
+        held_back_posts: Default::default(),
+        jobs: Default::default(),
+        tasks: Default::default(),
+        last_post_page_at: None,
+        last_tag_page_at: None,
+        cooldown_until: None,
+    };
+    for path in states {
+        let mut state: ScrapeState = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        state.upgrade_completed_posts();
+        merged.completed_posts.extend(&state.completed_posts);
+        merged.last_tag_id = merged.last_tag_id.max(state.last_tag_id);
+        merged.errors.extend(state.errors);
+        merged.last_post_page_at = merged.last_post_page_at.max(state.last_post_page_at);
+        merged.last_tag_page_at = merged.last_tag_page_at.max(state.last_tag_page_at);
+        merged.cooldown_until = merged.cooldown_until.max(state.cooldown_until);
+    }
+    merged.update_post_watermark();
+    Ok(merged)
+}
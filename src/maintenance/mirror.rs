@@ -0,0 +1,422 @@
+//! Delta sync of a dataset to another copy of it, see [`plan`] and [`Remote`]
+//!
+//! The remote copy keeps a [`Manifest`] of its files next to them, with the file names as paths.
+//! Comparing it with the local files decides what is sent: files with the same checksum are
+//! skipped, and since the output files are only appended to between compactions, a file whose
+//! first bytes still match the remote checksum only gets its new tail appended. Everything else,
+//! e.g. a compacted posts file or the saved index, is sent whole. The manifest is written last, so
+//! it only describes files which were transferred completely.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use super::manifest::{ChunkEntry, Manifest};
+
+/// Name of the manifest in the remote copy
+pub const MANIFEST: &str = "manifest.json";
+
+#[derive(Debug, Error)]
+pub enum MirrorError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Invalid remote manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("`{command}` failed: {stderr}")]
+    Ssh { command: String, stderr: String },
+    #[error("HTTP Error: `{0}`")]
+    Http(#[from] reqwest::Error),
+    #[cfg(feature = "s3")]
+    #[error(transparent)]
+    ObjectStore(#[from] crate::sink::SinkError),
+    #[error("`{0}` targets require the `s3` feature")]
+    ObjectStoreUnavailable(String),
+}
+
+/// How a file is brought up to date in the remote copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum Action {
+    /// The remote file has the same checksum
+    Unchanged,
+    /// The remote file is a prefix of the local one, the bytes from `offset` on are appended
+    Append { offset: u64 },
+    /// The file is missing or differs, it is sent whole
+    Replace,
+}
+
+/// A local file of the dataset and what has to be sent of it
+#[derive(Debug, Clone, Serialize)]
+pub struct Transfer {
+    /// Name of the file in the remote copy
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub action: Action,
+    /// Bytes to send
+    pub bytes: u64,
+    /// The local file, written to the remote manifest once it was sent
+    #[serde(skip)]
+    pub entry: ChunkEntry,
+}
+
+/// Compare the local `files` (remote name and local path) with the `remote` manifest
+///
+/// Appending is only planned if the remote supports it, see [`Remote::can_append`].
+pub fn plan<P: AsRef<Path>>(
+    files: &[(&str, P)],
+    remote: Option<&Manifest>,
+    can_append: bool,
+) -> io::Result<Vec<Transfer>> {
+    files
+        .iter()
+        .map(|(name, path)| {
+            let entry = ChunkEntry::from_file(path)?;
+            let remote = remote.and_then(|manifest| {
+                manifest
+                    .chunks
+                    .iter()
+                    .find(|chunk| chunk.path == Path::new(name))
+            });
+            let action = match remote {
+                Some(remote) if remote.bytes == entry.bytes && remote.sha256 == entry.sha256 => {
+                    Action::Unchanged
+                }
+                Some(remote)
+                    if can_append
+                        && remote.bytes < entry.bytes
+                        && prefix_sha256(path.as_ref(), remote.bytes)? == remote.sha256 =>
+                {
+                    Action::Append {
+                        offset: remote.bytes,
+                    }
+                }
+                _ => Action::Replace,
+            };
+            Ok(Transfer {
+                name: name.to_string(),
+                path: path.as_ref().to_path_buf(),
+                bytes: match action {
+                    Action::Unchanged => 0,
+                    Action::Append { offset } => entry.bytes - offset,
+                    Action::Replace => entry.bytes,
+                },
+                action,
+                entry,
+            })
+        })
+        .collect()
+}
+
+/// The manifest describing the remote copy once `transfers` were sent
+///
+/// Files of the `remote` manifest which weren't part of the transfers, e.g. an index left out of
+/// this sync, are kept.
+pub fn remote_manifest(remote: Option<&Manifest>, transfers: &[Transfer]) -> Manifest {
+    let kept = remote.into_iter().flat_map(|manifest| {
+        manifest.chunks.iter().filter(|chunk| {
+            !transfers
+                .iter()
+                .any(|transfer| chunk.path == Path::new(&transfer.name))
+        })
+    });
+    let chunks = kept
+        .cloned()
+        .chain(transfers.iter().map(|transfer| ChunkEntry {
+            path: PathBuf::from(&transfer.name),
+            ..transfer.entry.clone()
+        }))
+        .collect();
+    Manifest {
+        created_at: chrono::Utc::now(),
+        chunks,
+    }
+}
+
+/// Checksum of the first `bytes` bytes of a file
+fn prefix_sha256(path: &Path, bytes: u64) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?).take(bytes);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The other copy of a dataset
+pub enum Remote {
+    /// A directory, e.g. on a mounted network share
+    Dir(PathBuf),
+    /// A directory on another machine, `ssh://[user@]host[:port]/path`, through the `ssh` binary
+    Ssh {
+        host: String,
+        port: Option<u16>,
+        dir: String,
+    },
+    /// A url such as `s3://bucket/prefix`, credentials are taken from the `AWS_*` variables
+    #[cfg(feature = "s3")]
+    ObjectStore {
+        store: std::sync::Arc<dyn object_store::ObjectStore>,
+        prefix: object_store::path::Path,
+    },
+    /// A server accepting `PUT` requests below a url, e.g. WebDAV
+    Http {
+        client: reqwest::Client,
+        url: reqwest::Url,
+    },
+}
+
+impl Remote {
+    pub fn parse(target: &str) -> Result<Self, MirrorError> {
+        if let Some(rest) = target.strip_prefix("ssh://") {
+            let (authority, dir) = rest.split_once('/').unwrap_or((rest, ""));
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    Some(port.parse().map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "invalid ssh port")
+                    })?),
+                ),
+                None => (authority, None),
+            };
+            return Ok(Remote::Ssh {
+                host: host.to_string(),
+                port,
+                dir: format!("/{dir}"),
+            });
+        }
+        if target.starts_with("http://") || target.starts_with("https://") {
+            let mut url = reqwest::Url::parse(target)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            if !url.path().ends_with('/') {
+                url.set_path(&format!("{}/", url.path()));
+            }
+            return Ok(Remote::Http {
+                client: reqwest::Client::new(),
+                url,
+            });
+        }
+        #[cfg(feature = "s3")]
+        if target.contains("://") {
+            let (store, prefix) = crate::sink::object_store::store_from_url(target)?;
+            return Ok(Remote::ObjectStore { store, prefix });
+        }
+        #[cfg(not(feature = "s3"))]
+        if let Some((scheme, _)) = target.split_once("://") {
+            return Err(MirrorError::ObjectStoreUnavailable(scheme.to_string()));
+        }
+        Ok(Remote::Dir(PathBuf::from(target)))
+    }
+
+    /// Whether files can be appended to, object stores and HTTP servers get whole files
+    pub fn can_append(&self) -> bool {
+        matches!(self, Remote::Dir(_) | Remote::Ssh { .. })
+    }
+
+    /// The manifest of the remote copy, `None` if there is none yet
+    pub async fn manifest(&self) -> Result<Option<Manifest>, MirrorError> {
+        let bytes = match self {
+            Remote::Dir(dir) => match std::fs::read(dir.join(MANIFEST)) {
+                Ok(bytes) => bytes,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            },
+            Remote::Ssh { dir, .. } => {
+                let path = quote(&format!("{dir}/{MANIFEST}"));
+                self.ssh(
+                    &format!("if [ -f {path} ]; then cat {path}; fi"),
+                    None::<&[u8]>,
+                )
+                .await?
+            }
+            #[cfg(feature = "s3")]
+            Remote::ObjectStore { store, prefix } => match store.get(&prefix.child(MANIFEST)).await
+            {
+                Ok(result) => result
+                    .bytes()
+                    .await
+                    .map_err(crate::sink::SinkError::from)?
+                    .to_vec(),
+                Err(object_store::Error::NotFound { .. }) => return Ok(None),
+                Err(e) => return Err(crate::sink::SinkError::from(e).into()),
+            },
+            Remote::Http { client, url } => {
+                let response = client.get(join(url, MANIFEST)).send().await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                response.error_for_status()?.bytes().await?.to_vec()
+            }
+        };
+        match bytes.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(serde_json::from_slice(&bytes)?)),
+        }
+    }
+
+    /// Bring the remote file of a transfer up to date
+    ///
+    /// Appending first cuts the remote file back to the planned offset, so a transfer which was
+    /// interrupted can simply be run again. Replaced files are written next to the target and
+    /// renamed where the remote allows it.
+    pub async fn send(&self, transfer: &Transfer) -> Result<(), MirrorError> {
+        let offset = match transfer.action {
+            Action::Unchanged => return Ok(()),
+            Action::Append { offset } => Some(offset),
+            Action::Replace => None,
+        };
+        match self {
+            Remote::Dir(dir) => {
+                std::fs::create_dir_all(dir)?;
+                let mut local = File::open(&transfer.path)?;
+                let target = dir.join(&transfer.name);
+                match offset {
+                    Some(offset) => {
+                        let mut file = OpenOptions::new().write(true).open(&target)?;
+                        file.set_len(offset)?;
+                        file.seek(SeekFrom::End(0))?;
+                        local.seek(SeekFrom::Start(offset))?;
+                        io::copy(&mut local, &mut file)?;
+                        file.sync_all()?;
+                    }
+                    None => {
+                        let tmp_path = target.with_extension("tmp");
+                        let mut file = File::create(&tmp_path)?;
+                        io::copy(&mut local, &mut file)?;
+                        file.sync_all()?;
+                        std::fs::rename(&tmp_path, &target)?;
+                    }
+                }
+            }
+            Remote::Ssh { dir, .. } => {
+                let target = quote(&format!("{dir}/{}", transfer.name));
+                let tmp_path = quote(&format!("{dir}/{}.tmp", transfer.name));
+                let command = match offset {
+                    Some(offset) => format!("truncate -s {offset} {target} && cat >> {target}"),
+                    None => format!(
+                        "mkdir -p {} && cat > {tmp_path} && mv {tmp_path} {target}",
+                        quote(dir)
+                    ),
+                };
+                let mut local = tokio::fs::File::open(&transfer.path).await?;
+                tokio::io::AsyncSeekExt::seek(&mut local, SeekFrom::Start(offset.unwrap_or(0)))
+                    .await?;
+                self.ssh(&command, Some(local)).await?;
+            }
+            #[cfg(feature = "s3")]
+            Remote::ObjectStore { store, prefix } => {
+                crate::sink::object_store::upload_file(
+                    store.as_ref(),
+                    &prefix.child(transfer.name.as_str()),
+                    &transfer.path,
+                )
+                .await?;
+            }
+            Remote::Http { client, url } => {
+                // Streamed from the file, with its length as servers such as WebDAV ones refuse
+                // chunked uploads
+                let file = tokio::fs::File::open(&transfer.path).await?;
+                let len = file.metadata().await?.len();
+                client
+                    .put(join(url, &transfer.name))
+                    .header(reqwest::header::CONTENT_LENGTH, len)
+                    .body(reqwest::Body::from(file))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the manifest of the remote copy, after every file was sent
+    pub async fn save_manifest(&self, manifest: &Manifest) -> Result<(), MirrorError> {
+        let json = serde_json::to_vec_pretty(manifest)?;
+        match self {
+            Remote::Dir(dir) => {
+                std::fs::create_dir_all(dir)?;
+                std::fs::write(dir.join(MANIFEST), json)?;
+            }
+            Remote::Ssh { dir, .. } => {
+                let command = format!(
+                    "mkdir -p {} && cat > {}",
+                    quote(dir),
+                    quote(&format!("{dir}/{MANIFEST}"))
+                );
+                self.ssh(&command, Some(json.as_slice())).await?;
+            }
+            #[cfg(feature = "s3")]
+            Remote::ObjectStore { store, prefix } => {
+                store
+                    .put(&prefix.child(MANIFEST), json.into())
+                    .await
+                    .map_err(crate::sink::SinkError::from)?;
+            }
+            Remote::Http { client, url } => {
+                client
+                    .put(join(url, MANIFEST))
+                    .body(json)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a shell command on the ssh host, feeding it `stdin`, and return its output
+    async fn ssh<R: tokio::io::AsyncRead + Unpin>(
+        &self,
+        command: &str,
+        stdin: Option<R>,
+    ) -> Result<Vec<u8>, MirrorError> {
+        use std::process::Stdio;
+        use tokio::io::AsyncWriteExt;
+
+        let Remote::Ssh { host, port, .. } = self else {
+            unreachable!("only called for ssh remotes");
+        };
+        let mut ssh = tokio::process::Command::new("ssh");
+        if let Some(port) = port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+        // A host starting with `-` must not be read as an option
+        let mut child = ssh
+            .arg("--")
+            .arg(host)
+            .arg(command)
+            .stdin(match stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let (Some(mut input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            tokio::io::copy(&mut input, &mut pipe).await?;
+            pipe.shutdown().await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(MirrorError::Ssh {
+                command: command.to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The url of a file below the base url, which ends with a slash
+fn join(url: &reqwest::Url, name: &str) -> reqwest::Url {
+    url.join(name).unwrap_or_else(|_| url.clone())
+}
+
+/// Quote a path for the remote shell
+fn quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', r"'\''"))
+}
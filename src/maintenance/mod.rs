@@ -0,0 +1,14 @@
+//! Maintenance jobs operating on the scraped output files
+
+#[cfg(feature = "index")]
+pub mod bundle;
+pub mod compact;
+pub mod drift;
+pub mod enrich;
+pub mod manifest;
+pub mod merge;
+pub mod mirror;
+#[cfg(feature = "index")]
+pub mod rename;
+pub mod sync;
+pub mod verify;
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
+use governor::{Quota, RateLimiter};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    api::{client::ApiClient, models::ApiError},
+    index::Index,
+    models::Tag,
+};
+
+/// A tag whose name changed on the site
+#[derive(Debug, Clone, Serialize)]
+pub struct TagRename {
+    pub id: u64,
+    pub from: String,
+    pub to: String,
+}
+
+/// Result of [`reconcile_tag_names`]
+#[derive(Debug, Default, Serialize)]
+pub struct RenameReport {
+    /// Tags fetched from the site
+    pub checked: u64,
+    pub renames: Vec<TagRename>,
+    /// The renamed tags as fetched, to be appended to the tags file so a rebuilt index has the
+    /// new names as well
+    #[serde(skip)]
+    pub tags: Vec<Tag>,
+}
+
+/// Page through every tag of the site and rename the tags of `index` whose name changed
+///
+/// The old names stay in the index as aliases, so posts scraped before the rename are still
+/// found by them. Tags the index doesn't know are ignored, `scrape` picks them up.
+pub async fn reconcile_tag_names(
+    client: &ApiClient,
+    index: &mut Index,
+    requests_per_second: NonZeroU32,
+) -> Result<RenameReport, ApiError> {
+    let names: HashMap<u32, &str> = index
+        .tag_str_to_id
+        .iter()
+        .map(|(name, id)| (*id, name.as_str()))
+        .collect();
+    let limiter = RateLimiter::direct(Quota::per_second(requests_per_second));
+    let mut report = RenameReport::default();
+
+    let mut after_id = 0;
+    for page in 1.. {
+        limiter.until_ready().await;
+        let response = client.query_tags_backoff(after_id).await?;
+        let Some(highest_id) = response.tags.iter().map(|tag| tag.id).max() else {
+            break;
+        };
+        for tag in response.tags {
+            report.checked += 1;
+            let tag = Tag::from(tag);
+            if let Some(old) = names.get(&(tag.id as u32)) {
+                if *old != tag.name.to_lowercase() {
+                    report.renames.push(TagRename {
+                        id: tag.id,
+                        from: old.to_string(),
+                        to: tag.name.clone(),
+                    });
+                    report.tags.push(tag);
+                }
+            }
+        }
+        if page % 100 == 0 {
+            info!("Checked {} tags", report.checked);
+        }
+        after_id = highest_id;
+    }
+
+    for rename in &report.renames {
+        info!(
+            "Tag {} was renamed from {} to {}",
+            rename.id, rename.from, rename.to
+        );
+        index.rename_tag(rename.id as u32, &rename.to);
+    }
+    Ok(report)
+}
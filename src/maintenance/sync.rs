@@ -0,0 +1,242 @@
+//! Reconcile the local posts with the site, see [`sync_posts`]
+//!
+//! The scrapers only move forward, so posts edited or deleted on the site after they were scraped
+//! stay outdated. A sync requests the id ranges of the local posts again and reports every
+//! difference as a [`SyncChange`]: posts which are new or have a higher `change` are written
+//! again, and posts which disappeared from the site get a tombstone, a record with the status
+//! `deleted` which `compact` drops and the index removes.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::BufRead,
+    num::NonZeroU32,
+    ops::Range,
+};
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use governor::{state::StreamRateLimitExt, Quota, RateLimiter};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    api::{client::ApiClient, models::SchemaDrift},
+    models::{envelope::parse_record, Post},
+    scraper::state_manager::{StateManager, Task},
+};
+
+/// Width of the id ranges requested from the site, one page each
+const RANGE: u64 = 100;
+
+/// What the local store knows about a post, from its latest record
+#[derive(Debug, Clone, Copy)]
+pub struct LocalPost {
+    pub change: u64,
+    pub created_at: DateTime<Utc>,
+    pub deleted: bool,
+}
+
+impl From<&Post> for LocalPost {
+    fn from(post: &Post) -> Self {
+        Self {
+            change: post.change,
+            created_at: post.created_at,
+            deleted: post.status == "deleted",
+        }
+    }
+}
+
+/// A difference between the local posts and the site
+#[derive(Debug, Clone)]
+pub enum SyncChange {
+    /// A post the local store doesn't have, e.g. because its page failed while scraping
+    Added(Post),
+    /// A post with a higher `change` than the local one, or which was restored on the site
+    Updated(Post),
+    /// The id of a local post which is gone from the site
+    Deleted(u64),
+}
+
+/// Result of [`sync_posts`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    /// When the sync started, the local posts agree with the site as of this time
+    pub as_of: DateTime<Utc>,
+    /// Id ranges requested from the site
+    pub ranges: u64,
+    /// Posts returned by the site
+    pub checked: u64,
+    pub added: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    /// Ranges whose request failed, their posts were left as they are
+    pub failed: u64,
+}
+
+/// The latest record of every post in a posts file, by id
+pub fn local_posts<R: BufRead>(reader: R) -> std::io::Result<HashMap<u64, LocalPost>> {
+    let mut posts: HashMap<u64, LocalPost> = HashMap::new();
+    for line in reader.lines() {
+        let Ok(post) = parse_record::<Post>(&line?) else {
+            continue;
+        };
+        if posts
+            .get(&post.id)
+            .is_none_or(|local| local.change <= post.change)
+        {
+            posts.insert(post.id, LocalPost::from(&post));
+        }
+    }
+    Ok(posts)
+}
+
+/// The latest records of the posts in `ids` from a posts file, to turn into tombstones
+pub fn latest_records<R: BufRead>(
+    reader: R,
+    ids: &HashSet<u64>,
+) -> std::io::Result<HashMap<u64, Post>> {
+    let mut posts: HashMap<u64, Post> = HashMap::new();
+    for line in reader.lines() {
+        let Ok(post) = parse_record::<Post>(&line?) else {
+            continue;
+        };
+        if ids.contains(&post.id)
+            && posts
+                .get(&post.id)
+                .is_none_or(|latest| latest.change <= post.change)
+        {
+            posts.insert(post.id, post);
+        }
+    }
+    Ok(posts)
+}
+
+/// Mark the latest record of a deleted post as deleted as of `as_of`
+///
+/// The `change` is raised above the one of the record, so the tombstone wins in `compact` and
+/// `merge`.
+pub fn tombstone(mut post: Post, as_of: DateTime<Utc>) -> Post {
+    post.status = String::from("deleted");
+    post.change = (post.change + 1).max(as_of.timestamp().max(0) as u64);
+    post
+}
+
+/// Request the id ranges of the `local` posts created since `since` (all of them if it is
+/// `None`) and pass every difference to `on_change`
+///
+/// Only the ranges containing local posts are requested, posts newer than the newest local one
+/// are left to `scrape`. The progress through the ranges is recorded as the [`Task::Updates`] of
+/// `state_manager` if given.
+pub async fn sync_posts(
+    client: &ApiClient,
+    local: &HashMap<u64, LocalPost>,
+    since: Option<DateTime<Utc>>,
+    requests_per_second: NonZeroU32,
+    parallel_requests: usize,
+    state_manager: Option<&StateManager>,
+    mut on_change: impl FnMut(SyncChange),
+) -> SyncReport {
+    let mut report = SyncReport {
+        as_of: Utc::now(),
+        ranges: 0,
+        checked: 0,
+        added: 0,
+        updated: 0,
+        deleted: 0,
+        failed: 0,
+    };
+
+    let starts: BTreeSet<u64> = local
+        .iter()
+        .filter(|(_, post)| since.is_none_or(|since| post.created_at >= since))
+        .map(|(id, _)| id / RANGE * RANGE)
+        .collect();
+    if let Some(state_manager) = state_manager {
+        state_manager
+            .start_task(Task::Updates, Some(starts.len() as u64))
+            .await;
+    }
+    let mut drift = SchemaDrift::default();
+    let limiter = RateLimiter::direct(Quota::per_second(requests_per_second));
+    let mut responses = futures::stream::iter(starts.into_iter().map(|start| start..start + RANGE))
+        .map(|range| async {
+            if let Some(state_manager) = state_manager {
+                state_manager.start_unit(Task::Updates).await;
+            }
+            let response = client.query_posts_backoff(range.clone()).await;
+            (range, response)
+        })
+        .buffered(parallel_requests.max(1))
+        .ratelimit_stream(&limiter);
+
+    while let Some((range, response)) = responses.next().await {
+        report.ranges += 1;
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                error!(
+                    range = ?range,
+                    error_kind = e.kind(),
+                    error = %e,
+                    "Failed to sync posts"
+                );
+                report.failed += 1;
+                if let Some(state_manager) = state_manager {
+                    state_manager.fail_unit(Task::Updates).await;
+                }
+                continue;
+            }
+        };
+
+        let changes_before = report.added + report.updated + report.deleted;
+        let mut seen = HashSet::new();
+        for post in response.posts {
+            drift.record(&post);
+            let post = Post::from(post);
+            report.checked += 1;
+            seen.insert(post.id);
+            match local.get(&post.id) {
+                None => {
+                    report.added += 1;
+                    on_change(SyncChange::Added(post));
+                }
+                Some(known) if known.change < post.change || known.deleted => {
+                    report.updated += 1;
+                    on_change(SyncChange::Updated(post));
+                }
+                Some(_) => {}
+            }
+        }
+        for id in range_ids(&range, local) {
+            if !seen.contains(&id) {
+                report.deleted += 1;
+                on_change(SyncChange::Deleted(id));
+            }
+        }
+
+        if let Some(state_manager) = state_manager {
+            let changed = report.added + report.updated + report.deleted - changes_before;
+            state_manager.complete_unit(Task::Updates, changed).await;
+        }
+        if report.ranges.is_multiple_of(100) {
+            info!("Synced {} ranges", report.ranges);
+        }
+    }
+    if let Some(state_manager) = state_manager {
+        state_manager.finish_task(Task::Updates).await;
+    }
+    if !drift.is_empty() {
+        warn!("The posts differed from the expected fields, {drift}");
+    }
+    report
+}
+
+/// The ids in `range` of the local posts which aren't deleted yet
+fn range_ids<'a>(
+    range: &'a Range<u64>,
+    local: &'a HashMap<u64, LocalPost>,
+) -> impl Iterator<Item = u64> + 'a {
+    range
+        .clone()
+        .filter(|id| local.get(id).is_some_and(|post| !post.deleted))
+}
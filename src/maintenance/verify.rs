@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    input,
+    models::{
+        envelope::{parse_record, Record},
+        Post, Tag,
+    },
+    scraper::state_manager::ScrapeState,
+};
+
+/// Something wrong with the scraped output files
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Problem {
+    /// A line which could not be parsed as a record
+    Malformed {
+        file: PathBuf,
+        line: u64,
+        error: String,
+    },
+    /// A record whose id was already seen earlier in the file
+    Duplicate {
+        file: PathBuf,
+        line: u64,
+        id: u64,
+        first_line: u64,
+    },
+    /// No records with ids between `after` and `before` (both exclusive)
+    Gap {
+        file: PathBuf,
+        after: u64,
+        before: u64,
+    },
+    /// The highest id the state file says was scraped doesn't match the highest id in the output
+    /// file
+    Watermark {
+        kind: &'static str,
+        state: u64,
+        max_id: Option<u64>,
+    },
+    /// The state file exists but could not be read
+    State { error: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Malformed { file, line, error } => {
+                write!(f, "{}:{}: malformed record: {}", file.display(), line, error)
+            }
+            Problem::Duplicate {
+                file,
+                line,
+                id,
+                first_line,
+            } => write!(
+                f,
+                "{}:{}: duplicate id {} (first seen on line {})",
+                file.display(),
+                line,
+                id,
+                first_line
+            ),
+            Problem::Gap {
+                file,
+                after,
+                before,
+            } => write!(
+                f,
+                "{}: {} missing ids between {} and {}",
+                file.display(),
+                before - after - 1,
+                after,
+                before
+            ),
+            Problem::Watermark {
+                kind,
+                state,
+                max_id: Some(max_id),
+            } if state > max_id => write!(
+                f,
+                "state: {}s were scraped up to id {} but the highest {} id is {}, records were lost",
+                kind, state, kind, max_id
+            ),
+            Problem::Watermark {
+                kind,
+                state,
+                max_id: Some(max_id),
+            } => write!(
+                f,
+                "state: {}s were scraped up to id {} but the highest {} id is {}, records will be scraped again",
+                kind, state, kind, max_id
+            ),
+            Problem::Watermark { kind, state, .. } => write!(
+                f,
+                "state: {}s were scraped up to id {} but there are no {} records",
+                kind, state, kind
+            ),
+            Problem::State { error } => write!(f, "state: {}", error),
+        }
+    }
+}
+
+/// Result of checking one output file
+#[derive(Debug, Default, Clone)]
+pub struct FileReport {
+    pub records: u64,
+    pub max_id: Option<u64>,
+    pub problems: Vec<Problem>,
+}
+
+/// Check every line of a posts or tags file
+///
+/// Reports malformed lines, ids seen more than once and gaps of at least `min_gap` ids. Gaps are
+/// normal for deleted posts, so `min_gap` should be set well above the usual gap size. Duplicates
+/// are left behind by re-scrapes and can be removed with
+/// [`compact_posts`](super::compact::compact_posts). Compressed files are decompressed while they
+/// are read, see [`input`].
+pub fn verify_file<T: Record + DeserializeOwned, P: AsRef<Path>>(
+    path: P,
+    id: impl Fn(&T) -> u64,
+    min_gap: u64,
+) -> std::io::Result<FileReport> {
+    let path = path.as_ref();
+    let reader = input::open(path)?;
+    let mut report = FileReport::default();
+    let mut first_lines: HashMap<u64, u64> = HashMap::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = number as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = match parse_record::<T>(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                report.problems.push(Problem::Malformed {
+                    file: path.to_path_buf(),
+                    line: number,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let record_id = id(&record);
+        report.records += 1;
+        report.max_id = report.max_id.max(Some(record_id));
+        if let Some(first_line) = first_lines.insert(record_id, number) {
+            // Keep pointing at the first occurrence
+            first_lines.insert(record_id, first_line);
+            report.problems.push(Problem::Duplicate {
+                file: path.to_path_buf(),
+                line: number,
+                id: record_id,
+                first_line,
+            });
+        }
+    }
+
+    let mut ids: Vec<u64> = first_lines.into_keys().collect();
+    ids.sort_unstable();
+    for window in ids.windows(2) {
+        if window[1] - window[0] > min_gap {
+            report.problems.push(Problem::Gap {
+                file: path.to_path_buf(),
+                after: window[0],
+                before: window[1],
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Result of checking the posts, tags and state files together
+#[derive(Debug, Default, Clone)]
+pub struct VerifyReport {
+    pub posts: FileReport,
+    pub tags: FileReport,
+    pub state: Vec<Problem>,
+}
+
+impl VerifyReport {
+    pub fn problems(&self) -> impl Iterator<Item = &Problem> {
+        self.posts
+            .problems
+            .iter()
+            .chain(&self.tags.problems)
+            .chain(&self.state)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems().next().is_none()
+    }
+}
+
+/// Check the output files of a scrape and whether the state file agrees with them
+///
+/// A missing state file is not a problem, it just means no scrape has been saved yet.
+pub fn verify<P: AsRef<Path>>(
+    posts: P,
+    tags: P,
+    state: P,
+    min_gap: u64,
+) -> std::io::Result<VerifyReport> {
+    let mut report = VerifyReport {
+        posts: verify_file::<Post, _>(posts, |post| post.id, min_gap)?,
+        tags: verify_file::<Tag, _>(tags, |tag| tag.id, min_gap)?,
+        state: Vec::new(),
+    };
+
+    let mut state: ScrapeState = match File::open(state) {
+        Ok(file) => match serde_json::from_reader(file) {
+            Ok(state) => state,
+            Err(e) => {
+                report.state.push(Problem::State {
+                    error: e.to_string(),
+                });
+                return Ok(report);
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(report),
+        Err(e) => return Err(e),
+    };
+
+    // Post pages complete out of order, the newest scraped post is the end of the last range
+    state.upgrade_completed_posts();
+    for (kind, watermark, max_id) in [
+        (
+            "post",
+            state.completed_posts.end().saturating_sub(1),
+            report.posts.max_id,
+        ),
+        ("tag", state.last_tag_id, report.tags.max_id),
+    ] {
+        if max_id.unwrap_or(0) != watermark {
+            report.state.push(Problem::Watermark {
+                kind,
+                state: watermark,
+                max_id,
+            });
+        }
+    }
+
+    Ok(report)
+}
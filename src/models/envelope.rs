@@ -0,0 +1,116 @@
+//! Versioned envelopes for output records
+//!
+//! Records written with an envelope look like `{"v":2,"kind":"post","data":{...}}`. Version 1
+//! files contain the bare records, which are still accepted when reading.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Comment, Note, PerceptualHash, Pool, Post, Tag};
+
+/// The version of the record format written by this crate
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A record type that can be wrapped in an envelope
+pub trait Record {
+    const KIND: &'static str;
+}
+
+impl Record for Post {
+    const KIND: &'static str = "post";
+}
+
+impl Record for Tag {
+    const KIND: &'static str = "tag";
+}
+
+impl Record for Comment {
+    const KIND: &'static str = "comment";
+}
+
+impl Record for Note {
+    const KIND: &'static str = "note";
+}
+
+impl Record for Pool {
+    const KIND: &'static str = "pool";
+}
+
+impl Record for PerceptualHash {
+    const KIND: &'static str = "phash";
+}
+
+#[derive(Debug, Serialize)]
+pub struct Envelope<'a, T> {
+    pub v: u32,
+    pub kind: &'a str,
+    pub data: T,
+}
+
+impl<T: Record> Envelope<'static, T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            v: SCHEMA_VERSION,
+            kind: T::KIND,
+            data,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnedEnvelope<T> {
+    v: u32,
+    kind: String,
+    data: T,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    #[error("Serde Error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+    #[error("Unsupported record version {0} (newest supported is {SCHEMA_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("Expected a `{expected}` record, got `{got}`")]
+    WrongKind { expected: &'static str, got: String },
+}
+
+/// Parse a line written by any version of the output format
+pub fn parse_record<T: Record + DeserializeOwned>(line: &str) -> Result<T, RecordError> {
+    if !line.trim_start().starts_with("{\"v\":") {
+        return Ok(serde_json::from_str(line)?);
+    }
+
+    let envelope: OwnedEnvelope<T> = serde_json::from_str(line)?;
+    if envelope.v > SCHEMA_VERSION {
+        return Err(RecordError::UnsupportedVersion(envelope.v));
+    }
+    if envelope.kind != T::KIND {
+        return Err(RecordError::WrongKind {
+            expected: T::KIND,
+            got: envelope.kind,
+        });
+    }
+    Ok(envelope.data)
+}
+
+#[derive(Deserialize)]
+struct IdOnly {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct IdEnvelope {
+    data: IdOnly,
+}
+
+/// Extract just the `id` of a record of any kind and version, without parsing the whole record
+pub fn record_id(line: &str) -> Option<u64> {
+    if line.trim_start().starts_with("{\"v\":") {
+        serde_json::from_str::<IdEnvelope>(line)
+            .ok()
+            .map(|envelope| envelope.data.id)
+    } else {
+        serde_json::from_str::<IdOnly>(line)
+            .ok()
+            .map(|record| record.id)
+    }
+}
@@ -1,9 +1,12 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, str::FromStr};
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-use crate::api::models::{ApiPost, ApiTag};
+use crate::api::models::{ApiComment, ApiNote, ApiPool, ApiPost, ApiTag};
+
+pub mod envelope;
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
 pub enum Rating {
@@ -11,7 +14,8 @@ pub enum Rating {
     Sensitive,    // sensitive
     Questionable, // questionable
     Explicit,     // explicit
-                  // Other(String), // just in case
+    /// A rating added or renamed by the site after this was written, kept as it was sent
+    Other(String),
 }
 
 impl From<String> for Rating {
@@ -21,11 +25,73 @@ impl From<String> for Rating {
             "sensitive" => Rating::Sensitive,
             "questionable" => Rating::Questionable,
             "explicit" => Rating::Explicit,
-            _ => panic!("invalid value for rating"),
+            _ => Rating::Other(value),
+        }
+    }
+}
+
+impl Rating {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Rating::Safe => "safe",
+            Rating::Sensitive => "sensitive",
+            Rating::Questionable => "questionable",
+            Rating::Explicit => "explicit",
+            Rating::Other(rating) => rating,
+        }
+    }
+
+    /// How explicit the rating is, from 0 for safe to 3 for explicit, `None` for other ratings
+    pub fn level(&self) -> Option<u8> {
+        match self {
+            Rating::Safe => Some(0),
+            Rating::Sensitive => Some(1),
+            Rating::Questionable => Some(2),
+            Rating::Explicit => Some(3),
+            Rating::Other(_) => None,
         }
     }
 }
 
+/// Tags which imply a range of ratings, e.g. `nude` implies at least `questionable`
+///
+/// A post with any of the tags and a rating outside `min..=max` is inconsistent, see
+/// `Index::audit_ratings`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RatingRule {
+    pub tags: Vec<String>,
+    #[serde(default, deserialize_with = "known_rating")]
+    pub min: Option<Rating>,
+    #[serde(default, deserialize_with = "known_rating")]
+    pub max: Option<Rating>,
+}
+
+impl RatingRule {
+    /// Whether a post with one of the tags may have `rating`, ratings of unknown level always may
+    pub fn allows(&self, rating: &Rating) -> bool {
+        let Some(level) = rating.level() else {
+            return true;
+        };
+        let min = self.min.as_ref().and_then(Rating::level).unwrap_or(0);
+        let max = self.max.as_ref().and_then(Rating::level).unwrap_or(u8::MAX);
+        (min..=max).contains(&level)
+    }
+}
+
+fn known_rating<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Rating>, D::Error> {
+    use serde::de::Error;
+
+    match Option::<String>::deserialize(deserializer)?.map(Rating::from) {
+        Some(Rating::Other(rating)) => Err(D::Error::custom(format!(
+            "unknown rating `{rating}`, expected safe, sensitive, questionable or explicit"
+        ))),
+        rating => Ok(rating),
+    }
+}
+
 #[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Varient {
     pub url: String,
@@ -61,6 +127,25 @@ pub struct Post {
     pub status: String,
     pub post_locked: bool,
     pub has_children: bool,
+    /// `tags` with their types, empty unless the tags were resolved, see [`Post::resolve_tags`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub typed_tags: Vec<TypedTag>,
+    /// The `created_at` the API sent if it matched none of the date formats of the client, in
+    /// which case `created_at` is the unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_created_at: Option<String>,
+    /// Fields the API sent which [`ApiPost`] doesn't know, kept as they were sent
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Map<String, Value>,
+}
+
+/// A tag of a post along with its type
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TypedTag {
+    pub name: String,
+    /// Unknown if the tag wasn't scraped yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_type: Option<TagType>,
 }
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
@@ -69,6 +154,10 @@ pub enum Extension {
     Jpg,
     Jpeg,
     Gif,
+    Apng,
+    Swf,
+    Webm,
+    Mp4,
     Mov,
     Other(String),
 }
@@ -80,6 +169,10 @@ impl From<String> for Extension {
             "jpg" => Self::Jpg,
             "jpeg" => Self::Jpeg,
             "gif" => Self::Gif,
+            "apng" => Self::Apng,
+            "swf" => Self::Swf,
+            "webm" => Self::Webm,
+            "mp4" => Self::Mp4,
             "mov" => Self::Mov,
             _ => Self::Other(value),
         }
@@ -93,10 +186,205 @@ impl Extension {
             Extension::Jpg => "jpg",
             Extension::Jpeg => "jpeg",
             Extension::Gif => "gif",
+            Extension::Apng => "apng",
+            Extension::Swf => "swf",
+            Extension::Webm => "webm",
+            Extension::Mp4 => "mp4",
             Extension::Mov => "mov",
             Extension::Other(v) => v.as_str(),
         }
     }
+
+    /// Compares the name, so an `Other` read from an index saved before the variant existed counts
+    pub fn is_video(&self) -> bool {
+        matches!(self.as_str(), "webm" | "mp4" | "mov")
+    }
+
+    /// Videos, flash and formats which may be animated (every gif counts, even a still one)
+    pub fn is_animated(&self) -> bool {
+        self.is_video() || matches!(self.as_str(), "gif" | "apng" | "swf")
+    }
+
+    /// The `media:` query values matching files with this extension
+    pub fn media(&self) -> &'static [&'static str] {
+        if self.is_video() {
+            &["animated", "video"]
+        } else if self.is_animated() {
+            &["animated"]
+        } else {
+            &["image"]
+        }
+    }
+}
+
+/// Which file of a post to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Original,
+    /// The resized sample, or the original for posts without one
+    Sample,
+    Preview,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Variant::Original),
+            "sample" => Ok(Variant::Sample),
+            "preview" => Ok(Variant::Preview),
+            _ => Err(format!(
+                "unknown variant `{s}`, expected original, sample or preview"
+            )),
+        }
+    }
+}
+
+impl Variant {
+    /// The url of the file as it was scraped
+    pub fn url<'a>(&self, post: &'a Post) -> &'a str {
+        match self {
+            Variant::Original => &post.original.url,
+            Variant::Sample => post
+                .sample
+                .as_ref()
+                .map_or(&post.original.url, |sample| &sample.url),
+            Variant::Preview => &post.preview.url,
+        }
+    }
+}
+
+/// Where a site stores the files of its posts
+///
+/// `{md5}`, `{dir}`, `{id}` and `{ext}` (the extension of the original) are replaced. `{dir}` is
+/// the `directory` of a [`Post`], for a [`PostSimplified`] it is derived from the md5 as `ab/cd`
+/// like the site does for its newer posts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrlTemplates {
+    pub original: String,
+    /// Posts without a sample don't have a file here, the sample url of a [`PostSimplified`] may
+    /// not exist
+    pub sample: String,
+    pub preview: String,
+}
+
+impl Default for UrlTemplates {
+    fn default() -> Self {
+        Self {
+            original: String::from("https://img3.gelbooru.com/images/{dir}/{md5}.{ext}"),
+            sample: String::from("https://img3.gelbooru.com/samples/{dir}/sample_{md5}.jpg"),
+            preview: String::from("https://img3.gelbooru.com/thumbnails/{dir}/thumbnail_{md5}.jpg"),
+        }
+    }
+}
+
+impl UrlTemplates {
+    pub fn template(&self, variant: Variant) -> &str {
+        match variant {
+            Variant::Original => &self.original,
+            Variant::Sample => &self.sample,
+            Variant::Preview => &self.preview,
+        }
+    }
+
+    /// Fill in a template with the values of one post
+    pub fn fill(template: &str, md5: &str, dir: &str, id: u64, extension: &str) -> String {
+        template
+            .replace("{md5}", md5)
+            .replace("{dir}", dir)
+            .replace("{id}", &id.to_string())
+            .replace("{ext}", extension)
+    }
+}
+
+/// The `ab/cd` directory of a file named by its md5
+fn md5_dir(md5: &str) -> String {
+    format!("{}/{}", &md5[..2], &md5[2..4])
+}
+
+/// An optional field of a [`Post`] which [`PostSimplified`] can keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostField {
+    Score,
+    Rating,
+    /// Width and height of the original
+    Dimensions,
+    ParentId,
+}
+
+impl FromStr for PostField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "score" => Ok(PostField::Score),
+            "rating" => Ok(PostField::Rating),
+            "dimensions" => Ok(PostField::Dimensions),
+            "parent_id" => Ok(PostField::ParentId),
+            _ => Err(format!(
+                "unknown field `{s}`, expected score, rating, dimensions or parent_id"
+            )),
+        }
+    }
+}
+
+/// The optional fields kept by [`PostSimplified`], none by default to keep the index small
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostFields {
+    pub score: bool,
+    pub rating: bool,
+    pub dimensions: bool,
+    pub parent_id: bool,
+}
+
+impl PostFields {
+    pub fn all() -> Self {
+        Self {
+            score: true,
+            rating: true,
+            dimensions: true,
+            parent_id: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl FromIterator<PostField> for PostFields {
+    fn from_iter<I: IntoIterator<Item = PostField>>(fields: I) -> Self {
+        let mut kept = Self::default();
+        for field in fields {
+            match field {
+                PostField::Score => kept.score = true,
+                PostField::Rating => kept.rating = true,
+                PostField::Dimensions => kept.dimensions = true,
+                PostField::ParentId => kept.parent_id = true,
+            }
+        }
+        kept
+    }
+}
+
+/// The optional fields of a [`PostSimplified`], only those selected by [`PostFields`] are set
+#[derive(Debug, Default, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PostDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<Rating>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<u64>,
 }
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
@@ -105,26 +393,150 @@ pub struct PostSimplified {
     pub extension: Extension,
     pub id: u32,
     pub created_at: DateTime<Utc>,
+    /// Boxed, so posts without any of the optional fields only pay for a pointer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Box<PostDetails>>,
 }
 
-impl From<Post> for PostSimplified {
-    fn from(value: Post) -> Self {
+impl PostSimplified {
+    /// Simplify `post`, keeping the optional `fields`
+    pub fn new(post: Post, fields: PostFields) -> Self {
+        let details = (!fields.is_empty()).then(|| {
+            Box::new(PostDetails {
+                score: fields.score.then_some(post.score),
+                rating: fields.rating.then(|| post.rating.clone()),
+                width: fields.dimensions.then_some(post.original.width),
+                height: fields.dimensions.then_some(post.original.height),
+                parent_id: fields.parent_id.then_some(post.parent_id).flatten(),
+            })
+        });
+
         let mut hash = [0u8; 16];
-        let extension = Extension::from(value.image.rsplit_once('.').unwrap().1.to_string());
-        hex::decode_to_slice(value.md5, &mut hash).unwrap();
+        let extension = post.extension();
+        hex::decode_to_slice(post.md5, &mut hash).unwrap();
         Self {
             md5: hash,
             extension,
-            id: value.id as u32,
-            created_at: value.created_at,
+            id: post.id as u32,
+            created_at: post.created_at,
+            details,
         }
     }
 }
 
+impl PostSimplified {
+    /// Reconstruct the url of a file of the post, see [`UrlTemplates`]
+    pub fn file_url(&self, variant: Variant, urls: &UrlTemplates) -> String {
+        let md5 = hex::encode(self.md5);
+        UrlTemplates::fill(
+            urls.template(variant),
+            &md5,
+            &md5_dir(&md5),
+            self.id as u64,
+            self.extension.as_str(),
+        )
+    }
+}
+
+impl From<Post> for PostSimplified {
+    fn from(value: Post) -> Self {
+        Self::new(value, PostFields::default())
+    }
+}
+
 impl Post {
     pub fn split_tags(&self) -> impl Iterator<Item = &str> {
         self.tags.iter().map(|tag| tag.as_str()) // wrong change tags back to string
     }
+
+    /// Reconstruct the url of a file of the post, e.g. when the scraped one has expired
+    ///
+    /// A post without a sample gets the url of its original for [`Variant::Sample`], like
+    /// [`Variant::url`].
+    pub fn file_url(&self, variant: Variant, urls: &UrlTemplates) -> String {
+        let variant = match variant {
+            Variant::Sample if self.sample.is_none() => Variant::Original,
+            variant => variant,
+        };
+        let md5 = self.md5.to_lowercase();
+        let dir = match self.directory.as_str() {
+            "" => md5_dir(&md5),
+            directory => directory.to_string(),
+        };
+        UrlTemplates::fill(
+            urls.template(variant),
+            &md5,
+            &dir,
+            self.id,
+            self.extension().as_str(),
+        )
+    }
+
+    /// Fill `typed_tags` with the type of every tag, as looked up by `tag_type`
+    ///
+    /// Returns the number of tags whose type is unknown.
+    pub fn resolve_tags(&mut self, tag_type: impl Fn(&str) -> Option<TagType>) -> usize {
+        self.typed_tags = self
+            .tags
+            .iter()
+            .map(|name| TypedTag {
+                name: name.clone(),
+                tag_type: tag_type(name),
+            })
+            .collect();
+        self.typed_tags
+            .iter()
+            .filter(|tag| tag.tag_type.is_none())
+            .count()
+    }
+
+    /// Extension of the original file
+    pub fn extension(&self) -> Extension {
+        let extension = self
+            .image
+            .rsplit_once('.')
+            .map_or("", |(_, extension)| extension);
+        Extension::from(extension.to_string())
+    }
+
+    /// The normalized domains of the urls in `source`, see [`source_domain`]
+    ///
+    /// A source may list several urls separated by spaces, each domain is returned once.
+    pub fn source_domains(&self) -> Vec<String> {
+        let mut domains = Vec::new();
+        for domain in self
+            .source
+            .iter()
+            .flat_map(|source| source.split_whitespace())
+            .filter_map(source_domain)
+        {
+            if !domains.contains(&domain) {
+                domains.push(domain);
+            }
+        }
+        domains
+    }
+}
+
+/// The domain of a source url, lowercased and without a `www.` or `m.` prefix, the port or the
+/// credentials
+///
+/// The scheme is optional, so `pixiv.net` and `https://www.pixiv.net/artworks/1` both give
+/// `pixiv.net`. `None` for values without a dot in their host, e.g. plain text sources.
+pub fn source_domain(url: &str) -> Option<String> {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = url.split(['/', '?', '#']).next()?;
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split(':').next()?.trim_end_matches('.').to_lowercase();
+    let host = ["www.", "m."]
+        .iter()
+        .find_map(|prefix| host.strip_prefix(prefix))
+        .unwrap_or(&host);
+    let valid = host.contains('.')
+        && host
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '.' || c == '-');
+    valid.then(|| host.to_string())
 }
 
 impl From<ApiPost> for Post {
@@ -136,7 +548,8 @@ impl From<ApiPost> for Post {
 
         Post {
             id: value.id,
-            created_at: value.created_at,
+            created_at: value.created_at.or_epoch(),
+            raw_created_at: value.created_at.unparsed().map(str::to_string),
             score: value.score,
             md5: value.md5,
             directory: value.directory,
@@ -169,11 +582,13 @@ impl From<ApiPost> for Post {
             status: value.status,
             post_locked: value.post_locked,
             has_children: value.has_children,
+            typed_tags: Vec::new(),
+            extra: value.extra.into_iter().collect(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Hash, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TagType {
     Artist,
     Character,
@@ -196,6 +611,32 @@ impl From<u32> for TagType {
     }
 }
 
+impl From<TagType> for u32 {
+    fn from(value: TagType) -> Self {
+        match value {
+            TagType::Descriptive => 0,
+            TagType::Artist => 1,
+            TagType::Copyright => 3,
+            TagType::Character => 4,
+            TagType::Metadata => 5,
+            TagType::Other(v) => v,
+        }
+    }
+}
+
+impl TagType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagType::Artist => "artist",
+            TagType::Character => "character",
+            TagType::Copyright => "copyright",
+            TagType::Metadata => "metadata",
+            TagType::Descriptive => "descriptive",
+            TagType::Other(_) => "other",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct Tag {
     pub id: u64,
@@ -216,3 +657,189 @@ impl From<ApiTag> for Tag {
         }
     }
 }
+
+/// A comment on a post
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Comment {
+    pub id: u64,
+    pub post_id: u64,
+    pub created_at: DateTime<Utc>,
+    /// Name of the commenter
+    pub creator: String,
+    pub creator_id: u64,
+    pub body: String,
+}
+
+impl From<ApiComment> for Comment {
+    fn from(value: ApiComment) -> Self {
+        Comment {
+            id: value.id,
+            post_id: value.post_id,
+            created_at: value.created_at,
+            creator: value.creator,
+            creator_id: value.creator_id,
+            body: value.body,
+        }
+    }
+}
+
+/// A box over part of a post with some text, usually a translation
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Note {
+    pub id: u64,
+    pub post_id: u64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub creator_id: u64,
+    /// Position and size of the box in pixels of the original
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub body: String,
+    /// Deleted notes are kept by the site, but not shown
+    pub is_active: bool,
+    /// Incremented on every edit, like `change` on posts
+    pub version: u32,
+}
+
+impl From<ApiNote> for Note {
+    fn from(value: ApiNote) -> Self {
+        Note {
+            id: value.id,
+            post_id: value.post_id,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            creator_id: value.creator_id,
+            x: value.x,
+            y: value.y,
+            width: value.width,
+            height: value.height,
+            body: value.body,
+            is_active: value.is_active,
+            version: value.version,
+        }
+    }
+}
+
+/// The lowercase words of a note body, as indexed for `note:` queries
+///
+/// Words are runs of letters and digits, except that every CJK character is a word of its own
+/// since those languages don't separate their words. Markup like `<br>` and entities like `&amp;`
+/// are skipped.
+pub fn note_tokens(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let markup = match c {
+            '<' => rest.find('>'),
+            '&' => rest.find(';').filter(|end| {
+                let name = &rest[1..*end];
+                !name.is_empty()
+                    && name.len() <= 10
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#')
+            }),
+            _ => None,
+        };
+        rest = match markup {
+            Some(end) => &rest[end + 1..],
+            None => &rest[c.len_utf8()..],
+        };
+
+        if markup.is_none() && c.is_alphanumeric() && !is_cjk(c) {
+            word.extend(c.to_lowercase());
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+        if markup.is_none() && is_cjk(c) {
+            tokens.push(c.to_string());
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Kana, CJK ideographs and hangul
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{ff66}'..='\u{ff9f}')
+}
+
+/// A named, ordered group of posts, e.g. the pages of a comic
+#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Pool {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    /// In pool order, which is not the order of the ids
+    pub post_ids: Vec<u64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Pool {
+    /// The name as used in `pool:` queries, lowercase with underscores instead of spaces
+    pub fn query_name(&self) -> String {
+        self.name.trim().to_lowercase().replace(' ', "_")
+    }
+}
+
+impl From<ApiPool> for Pool {
+    fn from(value: ApiPool) -> Self {
+        Pool {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            post_ids: value.post_ids,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+/// Algorithm of a [`PerceptualHash`], hashes of different kinds can't be compared
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKind {
+    /// Compares the low frequencies of a DCT, robust against resizing and recompression
+    #[default]
+    Phash,
+    /// Compares the brightness of neighbouring pixels, faster but less robust
+    Dhash,
+}
+
+impl FromStr for HashKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "phash" => Ok(HashKind::Phash),
+            "dhash" => Ok(HashKind::Dhash),
+            _ => Err(format!("unknown hash `{s}`, expected phash or dhash")),
+        }
+    }
+}
+
+/// A 64 bit hash of the image of a downloaded file, which stays (nearly) the same when the image
+/// is resized or recompressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PerceptualHash {
+    pub post_id: u64,
+    pub kind: HashKind,
+    pub hash: u64,
+}
+
+impl PerceptualHash {
+    /// Number of differing bits, `None` for hashes of different kinds
+    pub fn distance(&self, other: &PerceptualHash) -> Option<u32> {
+        (self.kind == other.kind).then(|| (self.hash ^ other.hash).count_ones())
+    }
+}
@@ -0,0 +1,122 @@
+//! Several indexes behind one query front-end, see [`Namespaces`]
+//!
+//! One server can host the posts of several sites or curation projects. Every namespace has an
+//! index of its own, so tag dictionaries, postings and stats never mix, and a query picks its
+//! namespace with a prefix, e.g. `art:cat -dog`. Queries without a prefix go to the default index,
+//! which can also be named explicitly as `default:`.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use thiserror::Error;
+
+use crate::{index::Index, query::TERM_KEYS};
+
+/// Name of the index queried without a prefix
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NamespaceError {
+    #[error("Invalid namespace `{0}`, names consist of lowercase letters, digits, `-` and `_`")]
+    InvalidName(String),
+    #[error("The namespace `{0}` would be taken for a query term of the same name")]
+    ReservedName(String),
+    #[error("Unknown namespace `{0}`")]
+    Unknown(String),
+}
+
+/// The default index and the named ones next to it
+#[derive(Debug, Clone)]
+pub struct Namespaces {
+    default: Arc<Index>,
+    named: BTreeMap<String, Arc<Index>>,
+}
+
+impl Namespaces {
+    pub fn new(default: impl Into<Arc<Index>>) -> Self {
+        Self {
+            default: default.into(),
+            named: BTreeMap::new(),
+        }
+    }
+
+    /// Add the namespace `name`, replacing an earlier one of the same name
+    pub fn with_namespace(
+        mut self,
+        name: &str,
+        index: impl Into<Arc<Index>>,
+    ) -> Result<Self, NamespaceError> {
+        validate_name(name)?;
+        self.named.insert(name.to_string(), index.into());
+        Ok(self)
+    }
+
+    pub fn default_index(&self) -> &Arc<Index> {
+        &self.default
+    }
+
+    /// The index of the namespace `name`, [`DEFAULT_NAMESPACE`] is the default index
+    pub fn get(&self, name: &str) -> Result<&Arc<Index>, NamespaceError> {
+        if name == DEFAULT_NAMESPACE {
+            return Ok(&self.default);
+        }
+        self.named
+            .get(name)
+            .ok_or_else(|| NamespaceError::Unknown(name.to_string()))
+    }
+
+    /// Every namespace with its index, the default one first
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<Index>)> {
+        std::iter::once((DEFAULT_NAMESPACE, &self.default)).chain(
+            self.named
+                .iter()
+                .map(|(name, index)| (name.as_str(), index)),
+        )
+    }
+
+    /// Split the namespace prefix off `query`
+    ///
+    /// The first term is only taken for a namespace if a namespace of that name exists, so tags
+    /// containing a colon (`re:zero`) keep working as long as no namespace is called like their
+    /// prefix. Returns the namespace, its index and the rest of the query.
+    pub fn resolve<'q>(&self, query: &'q str) -> (&str, &Arc<Index>, &'q str) {
+        let query = query.trim_start();
+        if let Some((prefix, rest)) = query.split_once(':') {
+            if !prefix.contains(char::is_whitespace) {
+                let prefix = prefix.to_lowercase();
+                if prefix == DEFAULT_NAMESPACE {
+                    return (DEFAULT_NAMESPACE, &self.default, rest);
+                }
+                if let Some((name, index)) = self.named.get_key_value(&prefix) {
+                    return (name, index, rest);
+                }
+            }
+        }
+        (DEFAULT_NAMESPACE, &self.default, query)
+    }
+}
+
+/// Check that `name` can be used as a namespace
+///
+/// The names of query terms (`rating`, `pool`, ...) are reserved, a query like `pool:name` would
+/// otherwise be ambiguous.
+pub fn validate_name(name: &str) -> Result<(), NamespaceError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        return Err(NamespaceError::InvalidName(name.to_string()));
+    }
+    if name == DEFAULT_NAMESPACE || TERM_KEYS.contains(&name) {
+        return Err(NamespaceError::ReservedName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// `query` with the prefix of `namespace`, which the default namespace doesn't need
+pub fn qualified(namespace: &str, query: &str) -> String {
+    match namespace {
+        DEFAULT_NAMESPACE => query.to_string(),
+        namespace => format!("{namespace}:{query}"),
+    }
+}
@@ -0,0 +1,106 @@
+//! Perceptual hashes of downloaded images, see [`PerceptualHash`]
+//!
+//! Files that aren't images (e.g. videos) can't be hashed, of an animated gif only the first frame
+//! is hashed. Near duplicates are found with [`Index::near_duplicates`](crate::index::Index::near_duplicates).
+
+use std::path::Path;
+
+use image::{imageops::FilterType, DynamicImage, GrayImage, ImageError};
+
+use crate::models::{HashKind, PerceptualHash};
+
+/// Side of the image the DCT of a pHash is computed on
+const DCT_SIZE: usize = 32;
+
+/// Hash the image in `bytes`
+pub fn hash_image(bytes: &[u8], kind: HashKind) -> Result<u64, ImageError> {
+    let image = image::load_from_memory(bytes)?;
+    Ok(hash(&image, kind))
+}
+
+/// Hash the image stored at `path`, the format is guessed from the contents
+pub fn hash_file<P: AsRef<Path>>(path: P, kind: HashKind) -> Result<u64, ImageError> {
+    let image = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?;
+    Ok(hash(&image, kind))
+}
+
+/// The [`PerceptualHash`] record of the file of a post
+pub fn hash_post_file<P: AsRef<Path>>(
+    post_id: u64,
+    path: P,
+    kind: HashKind,
+) -> Result<PerceptualHash, ImageError> {
+    Ok(PerceptualHash {
+        post_id,
+        kind,
+        hash: hash_file(path, kind)?,
+    })
+}
+
+pub fn hash(image: &DynamicImage, kind: HashKind) -> u64 {
+    match kind {
+        HashKind::Phash => phash(image),
+        HashKind::Dhash => dhash(image),
+    }
+}
+
+/// One bit per coefficient of the 8x8 lowest frequencies of the DCT, set if it is above their
+/// median
+fn phash(image: &DynamicImage) -> u64 {
+    let pixels = gray(image, DCT_SIZE as u32, DCT_SIZE as u32);
+    let pixels: Vec<f64> = pixels.pixels().map(|pixel| pixel.0[0] as f64).collect();
+
+    // The DCT is separable, rows first and then the columns of the result. Only the first 8
+    // frequencies of each are needed.
+    let cosines: Vec<f64> = (0..8)
+        .flat_map(|frequency| {
+            (0..DCT_SIZE).map(move |x| {
+                (std::f64::consts::PI / DCT_SIZE as f64 * (x as f64 + 0.5) * frequency as f64).cos()
+            })
+        })
+        .collect();
+    let mut rows = [[0.0; 8]; DCT_SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..DCT_SIZE)
+                .map(|x| pixels[y * DCT_SIZE + x] * cosines[u * DCT_SIZE + x])
+                .sum();
+        }
+    }
+    let mut coefficients = [0.0; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            coefficients[v * 8 + u] = (0..DCT_SIZE)
+                .map(|y| rows[y][u] * cosines[v * DCT_SIZE + y])
+                .sum();
+        }
+    }
+
+    // The DC coefficient is the average brightness and would skew the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_unstable_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|coefficient| *coefficient > median))
+}
+
+/// One bit per pair of horizontally neighbouring pixels of a 9x8 image, set if the left one is
+/// brighter
+fn dhash(image: &DynamicImage) -> u64 {
+    let pixels = gray(image, 9, 8);
+    bits((0..8).flat_map(|y| {
+        let pixels = &pixels;
+        (0..8).map(move |x| pixels.get_pixel(x, y).0[0] > pixels.get_pixel(x + 1, y).0[0])
+    }))
+}
+
+fn gray(image: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+}
+
+fn bits(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
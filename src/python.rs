@@ -0,0 +1,174 @@
+//! Python bindings for the index, built with maturin (`maturin develop --release`)
+//!
+//! ```python
+//! import indexer
+//!
+//! index = indexer.Index.load("index.json", mmap=True)
+//! index.count("cat -dog")
+//! posts = index.query("cat top:1%", limit=100)
+//! ```
+//!
+//! Loading and querying release the GIL, so other Python threads keep running while a large index
+//! is parsed. Posts are returned as dicts with the fields of the JSON results of `query`.
+
+use std::path::{Path, PathBuf};
+
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+
+use crate::{
+    index::Index,
+    models::PostSimplified,
+    query::{Query, QueryError},
+    stats::DatasetStats,
+};
+
+impl From<QueryError> for PyErr {
+    fn from(error: QueryError) -> Self {
+        PyValueError::new_err(error.to_string())
+    }
+}
+
+/// A loaded index, see the `Index` of the crate
+#[pyclass(name = "Index", module = "indexer", frozen)]
+pub struct PyIndex {
+    index: Index,
+}
+
+#[pymethods]
+impl PyIndex {
+    /// Load an index saved by `indexer index build`
+    ///
+    /// With `mmap` the file is mapped into memory and parsed in place instead of being read
+    /// through a buffer, which parses faster. The parsed index is held in memory either way.
+    #[staticmethod]
+    #[pyo3(signature = (path, mmap = false))]
+    fn load(py: Python<'_>, path: PathBuf, mmap: bool) -> PyResult<Self> {
+        let index = py
+            .detach(|| match mmap {
+                true => load_mmap(&path),
+                false => Index::load(&path).map_err(|e| e.to_string()),
+            })
+            .map_err(PyIOError::new_err)?;
+        Ok(Self { index })
+    }
+
+    /// The posts matching `query` in result order, at most `limit` and starting after the post
+    /// `after`
+    #[pyo3(signature = (query, limit = None, after = None))]
+    fn query<'py>(
+        &self,
+        py: Python<'py>,
+        query: &str,
+        limit: Option<usize>,
+        after: Option<u32>,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let query = Query::parse(query)?;
+        let ids: Vec<u32> = py.detach(|| {
+            let post_ids = self.index.search(&query);
+            self.index
+                .ordered(&query, &post_ids, after)
+                .take(limit.unwrap_or(usize::MAX))
+                .collect()
+        });
+        ids.into_iter()
+            .filter_map(|id| self.index.post_id_to_post.get(&id))
+            .map(|post| post_dict(py, post))
+            .collect()
+    }
+
+    /// The ids of the posts matching `query`, in ascending order
+    fn ids(&self, py: Python<'_>, query: &str) -> PyResult<Vec<u32>> {
+        let query = Query::parse(query)?;
+        Ok(py.detach(|| self.index.search(&query).into_iter().collect()))
+    }
+
+    /// Number of posts matching `query`
+    fn count(&self, py: Python<'_>, query: &str) -> PyResult<u64> {
+        let query = Query::parse(query)?;
+        Ok(py.detach(|| self.index.search(&query).len()))
+    }
+
+    /// The most used tags starting with `prefix` with their post count, most used first
+    #[pyo3(signature = (prefix, limit = 10))]
+    fn suggest(&self, prefix: &str, limit: usize) -> Vec<(String, u32)> {
+        self.index
+            .suggest_tags(prefix, limit)
+            .into_iter()
+            .map(|(tag, count)| (tag.to_string(), count))
+            .collect()
+    }
+
+    /// The stats served by `GET /stats`, as a dict
+    #[pyo3(signature = (top_tags = 20))]
+    fn stats<'py>(&self, py: Python<'py>, top_tags: usize) -> PyResult<Bound<'py, PyAny>> {
+        let stats = py.detach(|| DatasetStats::from_index(&self.index, top_tags));
+        let json =
+            serde_json::to_string(&stats).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        py.import("json")?.call_method1("loads", (json,))
+    }
+
+    /// The post with the id, `None` if it isn't indexed
+    fn post<'py>(&self, py: Python<'py>, id: u32) -> PyResult<Option<Bound<'py, PyDict>>> {
+        self.index
+            .post_id_to_post
+            .get(&id)
+            .map(|post| post_dict(py, post))
+            .transpose()
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.post_id_to_post.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<Index with {} posts and {} tags>",
+            self.index.post_id_to_post.len(),
+            self.index.tag_str_to_id.len()
+        )
+    }
+}
+
+fn load_mmap(path: &Path) -> Result<Index, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    // The index files are replaced by a rename when they are saved, never written in place
+    let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| e.to_string())?;
+    Index::from_bytes(&map).map_err(|e| e.to_string())
+}
+
+/// A post as a dict, the optional fields are only set if the index keeps them
+fn post_dict<'py>(py: Python<'py>, post: &PostSimplified) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("id", post.id)?;
+    dict.set_item("md5", hex::encode(post.md5))?;
+    dict.set_item("extension", post.extension.as_str())?;
+    dict.set_item("created_at", post.created_at)?;
+    if let Some(details) = &post.details {
+        if let Some(score) = details.score {
+            dict.set_item("score", score)?;
+        }
+        if let Some(rating) = &details.rating {
+            dict.set_item("rating", rating.as_str())?;
+        }
+        if let Some(width) = details.width {
+            dict.set_item("width", width)?;
+        }
+        if let Some(height) = details.height {
+            dict.set_item("height", height)?;
+        }
+        if let Some(parent_id) = details.parent_id {
+            dict.set_item("parent_id", parent_id)?;
+        }
+    }
+    Ok(dict)
+}
+
+#[pymodule]
+fn indexer(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyIndex>()?;
+    Ok(())
+}
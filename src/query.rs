@@ -0,0 +1,475 @@
+//! Parsing of search queries
+//!
+//! A query is a whitespace separated list of terms:
+//!
+//! - `tag` only matches posts having the tag
+//! - `-tag` excludes posts having the tag
+//! - `~tag_a ~tag_b` matches posts having at least one of the tags
+//! - `rating:safe` matches posts with the rating (`safe`/`general`, `sensitive`, `questionable`,
+//!   `explicit` or their first letter), any other value matches a rating the site added later
+//! - `media:video` matches posts by file type: `image`, `animated` (gif, apng, flash and videos) or
+//!   `video`
+//! - `source:pixiv.net` matches posts with a source on the domain, see
+//!   [`source_domain`](crate::models::source_domain) for how domains are normalized
+//! - `file:downloaded` matches posts whose file is in the inventory of the downloads directory,
+//!   `file:missing` those whose file isn't
+//! - `note:"good morning"` matches posts whose notes contain the words in this order, see
+//!   [`note_tokens`] for what a word is. The quotes are only needed for several words.
+//! - `top:1%` matches the posts whose score is among the highest 1% of all posts, `top:1%:tag`
+//!   those among the highest 1% of the posts with the tag. Posts tied with the lowest score of the
+//!   top percent are included, see [`ScoreHistogram`](crate::histogram::ScoreHistogram).
+//! - `pool:name` matches the posts of a pool, and orders the results like the pool if it is
+//!   required
+//! - `uploader:name` matches the posts of the uploader accounts currently using the name,
+//!   `uploader:123` those of the account with the id, which survives renames
+//! - `snapshot:name` matches the posts frozen in a snapshot of an earlier result, see
+//!   [`snapshot`](crate::snapshot)
+//! - `artist:name` matches the tag only if it has the given type (`artist`, `character`,
+//!   `copyright`, `metadata` or `general`)
+//!
+//! Every term can be combined with the `-` and `~` operators. Tags containing a colon with any
+//! other prefix (e.g. `re:zero`) are plain tags. Tags are matched case-insensitively.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use thiserror::Error;
+
+use crate::models::{note_tokens, source_domain, Post, Rating, TagType};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("The query is empty")]
+    Empty,
+    #[error("Invalid term `{0}`")]
+    InvalidTerm(String),
+    #[error("Missing closing quote in `{0}`")]
+    UnclosedQuote(String),
+}
+
+/// The prefixes of the terms which aren't plain tags, e.g. `rating` of `rating:safe`
+pub const TERM_KEYS: &[&str] = &[
+    "rating",
+    "media",
+    "pool",
+    "file",
+    "note",
+    "top",
+    "snapshot",
+    "uploader",
+    "user",
+    "source",
+    "artist",
+    "character",
+    "copyright",
+    "metadata",
+    "meta",
+    "general",
+];
+
+/// A single condition of a query
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    Tag(String),
+    /// A tag which must also have the given type
+    TypedTag(TagType, String),
+    Rating(String),
+    /// `image`, `animated` or `video`
+    Media(String),
+    /// Name of a pool, see [`Pool::query_name`](crate::models::Pool::query_name)
+    Pool(String),
+    /// Normalized domain of a source url
+    Source(String),
+    File(FileState),
+    /// The words of a phrase in notes, see [`note_tokens`], separated by single spaces
+    Note(String),
+    /// The posts with a score in the top percent (1 to 100) of all posts, or of the posts with
+    /// the tag
+    Top(u8, Option<String>),
+    /// Name of a snapshot saved with the index
+    Snapshot(String),
+    /// Account id or current display name of an uploader
+    Uploader(String),
+}
+
+/// Whether the file of a post was downloaded, according to the inventory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileState {
+    Downloaded,
+    Missing,
+}
+
+impl FileState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            FileState::Downloaded => "downloaded",
+            FileState::Missing => "missing",
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Tag(tag) => write!(f, "{}", tag),
+            Term::TypedTag(tag_type, tag) => write!(f, "{}:{}", type_prefix(tag_type), tag),
+            Term::Rating(rating) => write!(f, "rating:{}", rating),
+            Term::Media(media) => write!(f, "media:{}", media),
+            Term::Pool(pool) => write!(f, "pool:{}", pool),
+            Term::Source(domain) => write!(f, "source:{}", domain),
+            Term::File(state) => write!(f, "file:{}", state.as_str()),
+            Term::Note(phrase) if phrase.contains(' ') => write!(f, "note:\"{}\"", phrase),
+            Term::Note(phrase) => write!(f, "note:{}", phrase),
+            Term::Top(percent, Some(tag)) => write!(f, "top:{}%:{}", percent, tag),
+            Term::Top(percent, None) => write!(f, "top:{}%", percent),
+            Term::Snapshot(name) => write!(f, "snapshot:{}", name),
+            Term::Uploader(uploader) => write!(f, "uploader:{}", uploader),
+        }
+    }
+}
+
+impl Term {
+    fn parse(term: &str) -> Result<Self, QueryError> {
+        let term = term.to_lowercase();
+        if term.is_empty() {
+            return Err(QueryError::InvalidTerm(term));
+        }
+
+        let Some((key, value)) = term.split_once(':') else {
+            return Ok(Term::Tag(term));
+        };
+        let tag_type = match key {
+            "rating" => {
+                let rating = match value {
+                    "" => return Err(QueryError::InvalidTerm(term)),
+                    "s" | "safe" | "g" | "general" => Rating::Safe,
+                    "sensitive" => Rating::Sensitive,
+                    "q" | "questionable" => Rating::Questionable,
+                    "e" | "explicit" => Rating::Explicit,
+                    other => Rating::Other(other.to_string()),
+                };
+                return Ok(Term::Rating(rating.as_str().to_string()));
+            }
+            "media" => {
+                let media = match value {
+                    "image" | "static" => "image",
+                    "animated" | "animation" => "animated",
+                    "video" => "video",
+                    _ => return Err(QueryError::InvalidTerm(term)),
+                };
+                return Ok(Term::Media(media.to_string()));
+            }
+            "pool" => {
+                if value.is_empty() {
+                    return Err(QueryError::InvalidTerm(term));
+                }
+                return Ok(Term::Pool(value.to_string()));
+            }
+            "file" => {
+                let state = match value {
+                    "downloaded" | "present" => FileState::Downloaded,
+                    "missing" => FileState::Missing,
+                    _ => return Err(QueryError::InvalidTerm(term)),
+                };
+                return Ok(Term::File(state));
+            }
+            "note" => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                let tokens = note_tokens(value);
+                if tokens.is_empty() {
+                    return Err(QueryError::InvalidTerm(term));
+                }
+                return Ok(Term::Note(tokens.join(" ")));
+            }
+            "top" => {
+                let (percent, tag) = match value.split_once(':') {
+                    Some((_, "")) => return Err(QueryError::InvalidTerm(term)),
+                    Some((percent, tag)) => (percent, Some(tag.to_string())),
+                    None => (value, None),
+                };
+                let percent = percent.strip_suffix('%').unwrap_or(percent);
+                return match percent.parse() {
+                    Ok(percent @ 1..=100) => Ok(Term::Top(percent, tag)),
+                    _ => Err(QueryError::InvalidTerm(term)),
+                };
+            }
+            "snapshot" => {
+                if value.is_empty() {
+                    return Err(QueryError::InvalidTerm(term));
+                }
+                return Ok(Term::Snapshot(value.to_string()));
+            }
+            "uploader" | "user" => {
+                if value.is_empty() {
+                    return Err(QueryError::InvalidTerm(term));
+                }
+                return Ok(Term::Uploader(value.to_string()));
+            }
+            "source" => {
+                let Some(domain) = source_domain(value) else {
+                    return Err(QueryError::InvalidTerm(term));
+                };
+                return Ok(Term::Source(domain));
+            }
+            "artist" => TagType::Artist,
+            "character" => TagType::Character,
+            "copyright" => TagType::Copyright,
+            "metadata" | "meta" => TagType::Metadata,
+            "general" => TagType::Descriptive,
+            _ => return Ok(Term::Tag(term)),
+        };
+        if value.is_empty() {
+            return Err(QueryError::InvalidTerm(term));
+        }
+        Ok(Term::TypedTag(tag_type, value.to_string()))
+    }
+}
+
+fn type_prefix(tag_type: &TagType) -> &str {
+    match tag_type {
+        TagType::Descriptive => "general",
+        tag_type => tag_type.as_str(),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Every one of these must match
+    pub include: Vec<Term>,
+    /// At least one of these must match, ignored if empty
+    pub any: Vec<Term>,
+    /// None of these may match
+    pub exclude: Vec<Term>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut parsed = Query::default();
+        for word in split_words(query)? {
+            let word = word.as_str();
+            if let Some(term) = word.strip_prefix('-') {
+                parsed.exclude.push(Term::parse(term)?);
+            } else if let Some(term) = word.strip_prefix('~') {
+                parsed.any.push(Term::parse(term)?);
+            } else {
+                parsed.include.push(Term::parse(word)?);
+            }
+        }
+
+        if parsed.is_empty() {
+            return Err(QueryError::Empty);
+        }
+        Ok(parsed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.any.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether a single post matches, without an index
+    ///
+    /// `tag_type` looks up the type of a tag for typed terms, e.g. in the tags of an index. Posts
+    /// don't know their pools, files, notes, snapshots or the scores of the other posts, so `pool:`,
+    /// `file:`, `note:`, `snapshot:` and `top:` terms never match.
+    pub fn matches(&self, post: &Post, tag_type: impl Fn(&str) -> Option<TagType>) -> bool {
+        let tags: HashSet<String> = post.split_tags().map(str::to_lowercase).collect();
+        let media = post.extension().media();
+        let matches = |term: &Term| match term {
+            Term::Tag(tag) => tags.contains(tag),
+            Term::TypedTag(expected, tag) => {
+                tags.contains(tag) && tag_type(tag).as_ref() == Some(expected)
+            }
+            Term::Rating(rating) => post.rating.as_str() == rating,
+            Term::Media(value) => media.contains(&value.as_str()),
+            Term::Pool(_) | Term::File(_) | Term::Note(_) | Term::Top(..) | Term::Snapshot(_) => {
+                false
+            }
+            Term::Source(domain) => post.source_domains().contains(domain),
+            Term::Uploader(uploader) => match uploader.parse::<u64>() {
+                Ok(id) => post.creator_id == id,
+                Err(_) => post.owner.eq_ignore_ascii_case(uploader),
+            },
+        };
+
+        self.include.iter().all(matches)
+            && (self.any.is_empty() || self.any.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Split a query at whitespace, except within a quoted value like `note:"good morning"`
+///
+/// A quote only starts a value right after a colon, so tags containing quotes still work.
+fn split_words(query: &str) -> Result<Vec<String>, QueryError> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' if quoted => quoted = false,
+            '"' if word.ends_with(':') => quoted = true,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        word.push(c);
+    }
+    if quoted {
+        return Err(QueryError::UnclosedQuote(word));
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::parse(s)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let terms = self
+            .include
+            .iter()
+            .map(|term| term.to_string())
+            .chain(self.any.iter().map(|term| format!("~{}", term)))
+            .chain(self.exclude.iter().map(|term| format!("-{}", term)))
+            .collect::<Vec<_>>();
+        write!(f, "{}", terms.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> Term {
+        Term::Tag(name.to_string())
+    }
+
+    #[test]
+    fn include_any_and_exclude() {
+        let query = Query::parse("Cat ~dog ~bird -fish").unwrap();
+        assert_eq!(query.include, [tag("cat")]);
+        assert_eq!(query.any, [tag("dog"), tag("bird")]);
+        assert_eq!(query.exclude, [tag("fish")]);
+    }
+
+    #[test]
+    fn empty_queries() {
+        assert_eq!(Query::parse(""), Err(QueryError::Empty));
+        assert_eq!(Query::parse("   "), Err(QueryError::Empty));
+        assert_eq!(
+            Query::parse("cat -"),
+            Err(QueryError::InvalidTerm(String::new()))
+        );
+    }
+
+    #[test]
+    fn ratings() {
+        for (value, rating) in [
+            ("s", "safe"),
+            ("general", "safe"),
+            ("sensitive", "sensitive"),
+            ("q", "questionable"),
+            ("E", "explicit"),
+            ("new", "new"),
+        ] {
+            let query = Query::parse(&format!("rating:{value}")).unwrap();
+            assert_eq!(query.include, [Term::Rating(rating.to_string())]);
+        }
+        assert!(Query::parse("rating:").is_err());
+    }
+
+    #[test]
+    fn keyed_terms() {
+        let query = Query::parse(
+            "media:animation file:present pool:best top:5%:cat top:1 uploader:123 \
+             artist:someone source:https://www.pixiv.net/artworks/1",
+        )
+        .unwrap();
+        assert_eq!(
+            query.include,
+            [
+                Term::Media(String::from("animated")),
+                Term::File(FileState::Downloaded),
+                Term::Pool(String::from("best")),
+                Term::Top(5, Some(String::from("cat"))),
+                Term::Top(1, None),
+                Term::Uploader(String::from("123")),
+                Term::TypedTag(TagType::Artist, String::from("someone")),
+                Term::Source(String::from("pixiv.net")),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_keyed_terms() {
+        for query in [
+            "media:audio",
+            "file:deleted",
+            "top:0%",
+            "top:101%",
+            "top:5%:",
+            "pool:",
+            "artist:",
+            "note:\"<br>\"",
+        ] {
+            assert!(
+                matches!(Query::parse(query), Err(QueryError::InvalidTerm(_))),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_prefixes_are_plain_tags() {
+        assert_eq!(Query::parse("re:zero").unwrap().include, [tag("re:zero")]);
+    }
+
+    #[test]
+    fn quoted_note_phrases() {
+        let query = Query::parse("note:\"Good  morning!\" -note:hello cat").unwrap();
+        assert_eq!(
+            query.include,
+            [Term::Note(String::from("good morning")), tag("cat")]
+        );
+        assert_eq!(query.exclude, [Term::Note(String::from("hello"))]);
+        assert_eq!(
+            Query::parse("note:\"good morning"),
+            Err(QueryError::UnclosedQuote(String::from(
+                "note:\"good morning"
+            )))
+        );
+    }
+
+    #[test]
+    fn split_words_only_quotes_after_a_colon() {
+        assert_eq!(
+            split_words("  note:\"a b\"  c ").unwrap(),
+            ["note:\"a b\"", "c"]
+        );
+        // A quote inside a tag doesn't start a value
+        assert_eq!(split_words("a\"b c\"").unwrap(), ["a\"b", "c\""]);
+        assert!(split_words("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn display_parses_back() {
+        let text = "cat general:dog ~rating:safe ~note:\"good morning\" -top:5%:cat -pool:best";
+        let query = Query::parse(text).unwrap();
+        assert_eq!(query.to_string(), text);
+        assert_eq!(Query::parse(&query.to_string()).unwrap(), query);
+    }
+}
@@ -0,0 +1,432 @@
+//! Run jobs on cron schedules, see [`Scheduler`]
+//!
+//! Expressions have the five usual fields, `minute hour day-of-month month day-of-week`, each
+//! either `*`, a value, a range `a-b`, a list `a,b` or a step `*/n` / `a-b/n`. Days of the week
+//! count from Sunday as 0 (7 is Sunday too). If both day fields are restricted, a day matching
+//! either of them runs the job, like cron does. `@hourly`, `@daily`, `@weekly` and `@monthly` are
+//! shorthands. Times are in UTC.
+
+use std::{
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Timelike, Utc};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+use crate::scraper::state_manager::StateManager;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CronError {
+    #[error("Expected 5 fields in `{0}`")]
+    FieldCount(String),
+    #[error("Invalid {field} `{value}`")]
+    InvalidField { field: &'static str, value: String },
+}
+
+/// A parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether the day of the month and the day of the week are `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expression = s.trim();
+        let expanded = match expression {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(CronError::FieldCount(expression.to_string()));
+        };
+
+        // Sunday may be given as 7, it is folded onto 0
+        let weekdays = parse_field(weekday, "day of the week", 0, 7)?;
+        let weekdays = (weekdays & 0x7f) | (weekdays >> 7);
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_field(minute, "minute", 0, 59)?,
+            hours: parse_field(hour, "hour", 0, 23)? as u32,
+            days: parse_field(day, "day of the month", 1, 31)? as u32,
+            months: parse_field(month, "month", 1, 12)? as u16,
+            weekdays: weekdays as u8,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = CronError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+impl Cron {
+    /// The first time after `after` at which the expression matches, at the start of a minute
+    ///
+    /// `None` if it never matches within the next five years, e.g. for `0 0 31 2 *`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date_naive();
+        for _ in 0..366 * 5 {
+            if self.matches_day(date) {
+                let first = match date == start.date_naive() {
+                    true => start.hour() * 60 + start.minute(),
+                    false => 0,
+                };
+                for minute_of_day in first..24 * 60 {
+                    let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                    if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                        let time = NaiveTime::from_hms_opt(hour, minute, 0)?;
+                        return Some(date.and_time(time).and_utc());
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_day(&self, date: chrono::NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+/// One bit per allowed value of a field
+fn parse_field(field: &str, name: &'static str, min: u32, max: u32) -> Result<u64, CronError> {
+    let invalid = || CronError::InvalidField {
+        field: name,
+        value: field.to_string(),
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                // `5/15` runs from 5 to the end of the field
+                None if part.contains('/') => (range.parse().map_err(|_| invalid())?, max),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// The result of a job run, a short summary of what it did
+pub type JobResult = Result<String, Box<dyn std::error::Error + Send + Sync>>;
+
+struct Job {
+    name: String,
+    cron: Cron,
+    run: Box<dyn Fn() -> BoxFuture<'static, JobResult> + Send + Sync>,
+}
+
+/// Jobs run on their cron schedules, with their status kept in the scrape state
+///
+/// A job doesn't start while its previous run is still going, the missed run is counted as
+/// skipped. Jobs which must not run at the same time as each other have to share a lock.
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    state_manager: StateManager,
+    state_path: String,
+}
+
+impl Scheduler {
+    /// The job statuses are saved to `state_path` after every run
+    pub fn new(state_manager: StateManager, state_path: impl Into<String>) -> Self {
+        Self {
+            jobs: Vec::new(),
+            state_manager,
+            state_path: state_path.into(),
+        }
+    }
+
+    pub fn with_job<F, Fut>(mut self, name: impl Into<String>, cron: Cron, run: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = JobResult> + Send + 'static,
+    {
+        self.jobs.push(Job {
+            name: name.into(),
+            cron,
+            run: Box::new(move || Box::pin(run())),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Run the jobs until the returned future is dropped
+    pub async fn run(self) {
+        let state = Arc::new((self.state_manager, self.state_path));
+        let loops = self.jobs.into_iter().map(|job| {
+            let state = state.clone();
+            async move { job_loop(Arc::new(job), state).await }
+        });
+        futures::future::join_all(loops).await;
+    }
+}
+
+async fn job_loop(job: Arc<Job>, state: Arc<(StateManager, String)>) {
+    let running = Arc::new(AtomicBool::new(false));
+    info!("Scheduled {} at `{}`", job.name, job.cron);
+    loop {
+        let state_manager = &state.0;
+        let Some(next) = job.cron.next_after(Utc::now()) else {
+            warn!(
+                "`{}` of {} never matches, not running it",
+                job.cron, job.name
+            );
+            return;
+        };
+        state_manager
+            .update_job(&job.name, |status| status.next_run = Some(next))
+            .await;
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        if running.swap(true, Ordering::AcqRel) {
+            warn!("Skipping {}, its previous run is still going", job.name);
+            state_manager
+                .update_job(&job.name, |status| status.skipped += 1)
+                .await;
+            continue;
+        }
+        state_manager
+            .update_job(&job.name, |status| status.last_started = Some(Utc::now()))
+            .await;
+
+        // The run is spawned, so the loop stays on schedule and notices overlaps
+        let (job, state, running) = (job.clone(), state.clone(), running.clone());
+        tokio::spawn(async move {
+            let (state_manager, state_path) = &*state;
+            let start = Instant::now();
+            let result = (job.run)().await;
+            running.store(false, Ordering::Release);
+            match &result {
+                Ok(outcome) => info!(
+                    job = job.name,
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    outcome,
+                    "Finished a job"
+                ),
+                Err(e) => error!(
+                    job = job.name,
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    error_kind = "job",
+                    error = %e,
+                    "A job failed"
+                ),
+            }
+            state_manager
+                .update_job(&job.name, |status| {
+                    status.last_finished = Some(Utc::now());
+                    status.runs += 1;
+                    match result {
+                        Ok(outcome) => {
+                            status.last_outcome = Some(outcome);
+                            status.last_error = None;
+                        }
+                        Err(e) => {
+                            status.failures += 1;
+                            status.last_error = Some(e.to_string());
+                        }
+                    }
+                })
+                .await;
+            if let Err(e) = state_manager.save_state(state_path).await {
+                error!("Failed to save the state after {}: {}", job.name, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+        expression.parse::<Cron>().unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn shorthands() {
+        assert_eq!(
+            next("@hourly", "2025-01-04T17:30:00Z"),
+            Some(at("2025-01-04T18:00:00Z"))
+        );
+        assert_eq!(
+            next("@daily", "2025-01-04T17:30:00Z"),
+            Some(at("2025-01-05T00:00:00Z"))
+        );
+        // 2025-01-04 is a Saturday
+        assert_eq!(
+            next("@weekly", "2025-01-04T17:30:00Z"),
+            Some(at("2025-01-05T00:00:00Z"))
+        );
+        assert_eq!(
+            next("@monthly", "2025-01-04T17:30:00Z"),
+            Some(at("2025-02-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_is_after_the_current_minute() {
+        assert_eq!(
+            next("30 17 * * *", "2025-01-04T17:30:00Z"),
+            Some(at("2025-01-05T17:30:00Z"))
+        );
+        assert_eq!(
+            next("* * * * *", "2025-01-04T17:30:59Z"),
+            Some(at("2025-01-04T17:31:00Z"))
+        );
+    }
+
+    #[test]
+    fn lists_ranges_and_steps() {
+        let cron: Cron = "*/20 9-10,14 * * *".parse().unwrap();
+        let mut time = at("2025-01-04T08:00:00Z");
+        let mut runs = Vec::new();
+        for _ in 0..9 {
+            time = cron.next_after(time).unwrap();
+            runs.push(time.format("%H:%M").to_string());
+        }
+        assert_eq!(
+            runs,
+            ["09:00", "09:20", "09:40", "10:00", "10:20", "10:40", "14:00", "14:20", "14:40"]
+        );
+
+        // A step from a value runs to the end of the field
+        assert_eq!(
+            next("5/20 * * * *", "2025-01-04T17:30:00Z"),
+            Some(at("2025-01-04T17:45:00Z"))
+        );
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        for expression in ["0 0 * * 0", "0 0 * * 7"] {
+            assert_eq!(
+                next(expression, "2025-01-01T00:00:00Z"),
+                Some(at("2025-01-05T00:00:00Z"))
+            );
+        }
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 13th or a Friday, 2025-01-01 is a Wednesday
+        assert_eq!(
+            next("0 0 13 * 5", "2025-01-01T00:00:00Z"),
+            Some(at("2025-01-03T00:00:00Z"))
+        );
+        // With one restricted field only that one counts
+        assert_eq!(
+            next("0 0 13 * *", "2025-01-01T00:00:00Z"),
+            Some(at("2025-01-13T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn impossible_dates_never_match() {
+        assert_eq!(next("0 0 31 2 *", "2025-01-01T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert_eq!(
+            "0 0 * *".parse::<Cron>(),
+            Err(CronError::FieldCount(String::from("0 0 * *")))
+        );
+        for (expression, field) in [
+            ("60 * * * *", "minute"),
+            ("* 24 * * *", "hour"),
+            ("* * 0 * *", "day of the month"),
+            ("* * * 13 *", "month"),
+            ("* * * * 8", "day of the week"),
+            ("*/0 * * * *", "minute"),
+            ("5-1 * * * *", "minute"),
+            ("a * * * *", "minute"),
+        ] {
+            assert!(
+                matches!(
+                    expression.parse::<Cron>(),
+                    Err(CronError::InvalidField { field: f, .. }) if f == field
+                ),
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn displays_the_expression() {
+        assert_eq!("@daily".parse::<Cron>().unwrap().to_string(), "@daily");
+    }
+}
@@ -0,0 +1,79 @@
+//! Posts dropped while scraping, so they never reach the output files
+
+use std::{collections::HashSet, sync::Mutex};
+
+use serde::Serialize;
+
+use crate::{
+    config::BlacklistConfig,
+    models::{Post, Rating},
+};
+
+/// Number of posts dropped by each rule of a [`Blacklist`], a post only counts for the first rule
+/// it matches
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct BlacklistStats {
+    pub tags: u64,
+    pub ratings: u64,
+    pub uploaders: u64,
+}
+
+impl BlacklistStats {
+    pub fn total(&self) -> u64 {
+        self.tags + self.ratings + self.uploaders
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Blacklist {
+    tags: HashSet<String>,
+    ratings: HashSet<String>,
+    /// Names or ids of the uploaders
+    uploaders: HashSet<String>,
+    stats: Mutex<BlacklistStats>,
+}
+
+impl Blacklist {
+    /// Everything is compared case-insensitively, `general` is the same rating as `safe`
+    pub fn new(config: &BlacklistConfig) -> Self {
+        let lowercase = |values: &[String]| values.iter().map(|v| v.to_lowercase()).collect();
+        Self {
+            tags: lowercase(&config.tags),
+            ratings: config
+                .ratings
+                .iter()
+                .map(|rating| Rating::from(rating.to_lowercase()).as_str().to_string())
+                .collect(),
+            uploaders: lowercase(&config.uploaders),
+            stats: Mutex::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.ratings.is_empty() && self.uploaders.is_empty()
+    }
+
+    /// Whether `post` is blacklisted, counting it in the stats if it is
+    pub fn blocks(&self, post: &Post) -> bool {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        if post
+            .split_tags()
+            .any(|tag| self.tags.contains(&tag.to_lowercase()))
+        {
+            stats.tags += 1;
+        } else if self.ratings.contains(post.rating.as_str()) {
+            stats.ratings += 1;
+        } else if self.uploaders.contains(&post.owner.to_lowercase())
+            || self.uploaders.contains(&post.creator_id.to_string())
+        {
+            stats.uploaders += 1;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn stats(&self) -> BlacklistStats {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -1,3 +1,9 @@
+pub mod blacklist;
+pub mod plan;
 pub mod post_scraper;
+pub mod processor;
+pub mod ranges;
+pub mod repair;
+pub mod seen;
+pub mod state_manager;
 pub mod tag_scraper;
-pub mod state_manager;
\ No newline at end of file
@@ -0,0 +1,88 @@
+//! The post pages of a scrape, planned from what the site reports before it starts
+
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use serde::Serialize;
+
+use super::ranges::IdRanges;
+use crate::api::{client::ApiClient, models::ApiError};
+
+/// Posts per page request
+pub const PAGE_SIZE: u64 = 100;
+
+/// The id ranges from the state up to the newest post, with a count of the finished pages
+///
+/// A [`PostScraper`](super::post_scraper::PostScraper) with a plan stops after its last page
+/// instead of polling for new posts forever, posts uploaded in the meantime are left to the next
+/// run. The counter is shared by the clones, so a progress bar can follow the scraper.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScrapePlan {
+    /// First post id of the first page
+    pub start_id: u64,
+    pub max_post_id: u64,
+    pub total_posts: u64,
+    pub pages: u64,
+    /// Ranges scraped by earlier runs, their pages are left out
+    #[serde(skip)]
+    completed: IdRanges,
+    #[serde(skip)]
+    done: Arc<AtomicU64>,
+}
+
+impl ScrapePlan {
+    /// Plan the pages from `start_id` on with one request for the newest post
+    pub async fn fetch(client: &ApiClient, start_id: u64) -> Result<Self, ApiError> {
+        let latest = client.latest_posts().await?;
+        let max_post_id = latest.posts.iter().map(|post| post.id).max().unwrap_or(0);
+        Ok(Self::new(start_id, max_post_id, latest.attributes.count))
+    }
+
+    pub fn new(start_id: u64, max_post_id: u64, total_posts: u64) -> Self {
+        let pages = (max_post_id + 1)
+            .saturating_sub(start_id)
+            .div_ceil(PAGE_SIZE);
+        Self {
+            start_id,
+            max_post_id,
+            total_posts,
+            pages,
+            completed: IdRanges::default(),
+            done: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Skip the post ids which earlier runs already scraped, e.g. past a page which failed
+    pub fn with_completed(mut self, completed: IdRanges) -> Self {
+        self.completed = completed;
+        self.pages = self.ranges().count() as u64;
+        self
+    }
+
+    /// The id ranges of the pages, in order
+    ///
+    /// The pages run up to the newest post and around the completed ranges, so the last page of
+    /// a gap may be shorter than [`PAGE_SIZE`].
+    pub fn ranges(&self) -> impl Iterator<Item = Range<u64>> + Send + 'static {
+        self.completed
+            .pages(self.start_id..self.max_post_id + 1, PAGE_SIZE)
+    }
+
+    /// Pages which got a response so far, including failed ones
+    pub fn pages_done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.pages_done() >= self.pages
+    }
+
+    pub(crate) fn record_page(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -1,104 +1,281 @@
-use super::state_manager::StateManager;
+use super::{
+    blacklist::Blacklist,
+    plan::{ScrapePlan, PAGE_SIZE},
+    processor::{prepare_post, Pipeline},
+    ranges::IdRanges,
+    seen::SeenPosts,
+    state_manager::StateManager,
+};
 use crate::{
     api::{
         client::ApiClient,
-        models::{ApiError, ApiPostResponse},
+        models::{ApiError, ApiPostResponse, SchemaDrift},
     },
     models::Post,
-    scraper::state_manager::ScrapeError,
+    scraper::state_manager::{ScrapeError, Task},
+    sink::writer::SinkHandle,
+};
+use futures::{Stream, StreamExt};
+use governor::{state::StreamRateLimitExt, DefaultDirectRateLimiter, Quota, RateLimiter};
+use std::{
+    num::NonZeroU32,
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
-use futures::StreamExt;
-use governor::{state::StreamRateLimitExt, Quota, RateLimiter};
-use std::{io::Write, num::NonZeroU32, sync::Arc};
-use tokio::sync::Mutex;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
-pub struct PostScraper<W: Write> {
+pub struct PostScraper {
     state_manager: StateManager,
     client: ApiClient,
-    output: Arc<Mutex<W>>,
+    output: SinkHandle<Post>,
     parallel_requests: usize,
-    requests_per_second: u32,
+    requests_per_second: NonZeroU32,
+    blacklist: Option<Arc<Blacklist>>,
+    pipeline: Option<Arc<Pipeline>>,
+    plan: Option<ScrapePlan>,
+    seen: Option<Arc<SeenPosts>>,
+    /// How the posts of the current run differed from [`ApiPost`](crate::api::models::ApiPost)
+    drift: Mutex<SchemaDrift>,
 }
 
-impl<W: Write> PostScraper<W> {
-    pub fn new(output: W, state_manager: StateManager, client: ApiClient) -> Self {
+impl PostScraper {
+    pub fn new(output: SinkHandle<Post>, state_manager: StateManager, client: ApiClient) -> Self {
         Self {
             state_manager,
             client,
-            output: Arc::new(Mutex::new(output)),
+            output,
             parallel_requests: 2,
-            requests_per_second: 8,
+            requests_per_second: NonZeroU32::new(8).unwrap(),
+            blacklist: None,
+            pipeline: None,
+            plan: None,
+            seen: None,
+            drift: Mutex::default(),
         }
     }
 
+    pub fn with_requests_per_second(mut self, requests_per_second: NonZeroU32) -> Self {
+        self.requests_per_second = requests_per_second;
+        self
+    }
+
+    /// Number of pages requested concurrently
+    pub fn with_parallel_requests(mut self, parallel_requests: usize) -> Self {
+        self.parallel_requests = parallel_requests.max(1);
+        self
+    }
+
+    /// Drop the posts matching `blacklist` instead of writing them, an empty one is ignored
+    pub fn with_blacklist(mut self, blacklist: Arc<Blacklist>) -> Self {
+        self.blacklist = (!blacklist.is_empty()).then_some(blacklist);
+        self
+    }
+
+    /// Run every post through `pipeline` before writing it, after the blacklist. An empty one is
+    /// ignored.
+    pub fn with_pipeline(mut self, pipeline: Arc<Pipeline>) -> Self {
+        self.pipeline = (!pipeline.is_empty()).then_some(pipeline);
+        self
+    }
+
+    /// Only scrape the pages of `plan`, which ends [`run`](Self::run) after its last page
+    ///
+    /// The plan starts at its own id, which should be the one after the state's.
+    pub fn with_plan(mut self, plan: ScrapePlan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    /// Don't write the posts which are already in the output, e.g. those of a page which a scrape
+    /// that crashed wrote in part
+    pub fn with_seen(mut self, seen: Arc<SeenPosts>) -> Self {
+        self.seen = (!seen.is_empty()).then_some(seen);
+        self
+    }
+
+    /// Scrape the posts after the state, forever unless there is a plan
+    ///
+    /// Ranges which an earlier run already completed are skipped.
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
         let starting_id = self.state_manager.last_post_id().await + 1;
-        let ranges = (starting_id..).step_by(100).map(|start| start..start + 100);
-        let limiter = RateLimiter::direct(Quota::per_second(
-            NonZeroU32::new(self.requests_per_second).unwrap(),
-        ));
-        let posts = futures::stream::iter(ranges)
-            .map(|id_range| async {
-                (
-                    id_range.clone(),
-                    self.client.query_posts_backoff(id_range).await,
-                )
-            })
-            .buffered(self.parallel_requests)
-            .ratelimit_stream(&limiter);
+        let completed = self.state_manager.completed_posts().await;
+        let limiter = RateLimiter::direct(Quota::per_second(self.requests_per_second));
+        self.state_manager
+            .start_task(Task::Posts, self.plan.as_ref().map(|plan| plan.pages))
+            .await;
+        self.pages(starting_id, &completed, &limiter)
+            .for_each(|(id_range, post, elapsed)| self.process_response(id_range, post, elapsed))
+            .await;
+        self.state_manager.finish_task(Task::Posts).await;
+        self.report_drift();
+
+        if let Some(plan) = &self.plan {
+            info!(
+                pages = plan.pages,
+                max_post_id = plan.max_post_id,
+                "Finished the planned pages"
+            );
+        }
+        Ok(())
+    }
 
-        posts
-            .for_each(|(id_range, post)| async {
-                self.process_response(id_range, post).await;
+    /// Scrape the posts after the state up to the first empty page, instead of forever
+    ///
+    /// Used by scheduled scrapes, which have to finish before the next one starts. A range of
+    /// 100 deleted posts after the newest known post also ends the run, the next run continues
+    /// after it. Empty pages in the gaps between completed ranges don't.
+    pub async fn catch_up(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let starting_id = self.state_manager.last_post_id().await + 1;
+        let completed = self.state_manager.completed_posts().await;
+        let limiter = RateLimiter::direct(Quota::per_second(self.requests_per_second));
+        self.state_manager
+            .start_task(Task::Posts, self.plan.as_ref().map(|plan| plan.pages))
+            .await;
+        self.pages(starting_id, &completed, &limiter)
+            .take_while(|(id_range, post, _)| {
+                let empty = matches!(post, Ok(response) if response.attributes.count == 0);
+                futures::future::ready(!empty || id_range.start < completed.end())
             })
+            .for_each(|(id_range, post, elapsed)| self.process_response(id_range, post, elapsed))
             .await;
+        self.state_manager.finish_task(Task::Posts).await;
+        self.report_drift();
 
         Ok(())
     }
 
+    /// The responses for the id ranges from `starting_id` on which aren't `completed`, in order,
+    /// with how long each took
+    fn pages<'a>(
+        &'a self,
+        starting_id: u64,
+        completed: &IdRanges,
+        limiter: &'a DefaultDirectRateLimiter,
+    ) -> impl Stream<Item = (Range<u64>, Result<ApiPostResponse, ApiError>, Duration)> + 'a {
+        let ranges: Box<dyn Iterator<Item = Range<u64>> + Send> = match &self.plan {
+            Some(plan) => Box::new(plan.ranges()),
+            None => Box::new(completed.pages(starting_id..u64::MAX, PAGE_SIZE)),
+        };
+        futures::stream::iter(ranges)
+            .map(|id_range| async {
+                self.state_manager.start_unit(Task::Posts).await;
+                let start = Instant::now();
+                let response = self.client.query_posts_backoff(id_range.clone()).await;
+                (id_range, response, start.elapsed())
+            })
+            .buffered(self.parallel_requests)
+            .ratelimit_stream(limiter)
+    }
+
     pub async fn process_response(
         &self,
-        id_range: std::ops::Range<u64>,
+        id_range: Range<u64>,
         result: Result<ApiPostResponse, ApiError>,
+        elapsed: Duration,
     ) {
+        if let Some(plan) = &self.plan {
+            plan.record_page();
+        }
         match result {
             Ok(result) => {
-                if result.attributes.count == 0 {
-                    return;
-                }
-
-                let highest_id = result
+                self.state_manager.record_post_page().await;
+                // Only the ids after the newest post on the site are held back, the plan knows it
+                let newest_id = result
                     .posts
                     .iter()
-                    .max_by_key(|post| post.id)
                     .map(|post| post.id)
-                    .unwrap_or(0);
+                    .max()
+                    .max(self.plan.as_ref().map(|plan| plan.max_post_id));
+                {
+                    let mut drift = self.drift.lock().unwrap_or_else(|e| e.into_inner());
+                    for post in &result.posts {
+                        drift.record(post);
+                    }
+                }
+                if result.attributes.count == 0 {
+                    self.state_manager
+                        .complete_post_range(id_range, newest_id)
+                        .await;
+                    self.state_manager.complete_unit(Task::Posts, 0).await;
+                    return;
+                }
 
-                self.state_manager.update_last_post_id(highest_id).await;
-                let output_lock = &mut *self.output.lock().await;
-                result.posts.into_iter().rev().for_each(|post| {
-                    self.process_post(output_lock, post.into());
-                });
+                let mut written = 0;
+                for post in result.posts.into_iter().rev() {
+                    if self
+                        .seen
+                        .as_ref()
+                        .is_some_and(|seen| seen.contains(post.id))
+                    {
+                        debug!(post_id = post.id, "Skipped a post which is already written");
+                        continue;
+                    }
+                    let post: Post = post.into();
+                    if let Some(raw) = &post.raw_created_at {
+                        warn!(
+                            post_id = post.id,
+                            created_at = raw,
+                            "No date format matches created_at, saved the post with the unix epoch"
+                        );
+                    }
+                    let Some(post) =
+                        prepare_post(self.blacklist.as_deref(), self.pipeline.as_deref(), post)
+                            .await
+                    else {
+                        continue;
+                    };
+                    self.process_post(post).await;
+                    written += 1;
+                }
                 info!(
-                    "Downloaded {:?}. Got: {} Posts",
-                    id_range, result.attributes.count
+                    range = ?id_range,
+                    posts = result.attributes.count,
+                    duration_ms = elapsed.as_millis() as u64,
+                    "Downloaded posts"
                 );
+                self.state_manager
+                    .complete_post_range(id_range, newest_id)
+                    .await;
+                self.state_manager.complete_unit(Task::Posts, written).await;
             }
             Err(e) => {
                 self.state_manager
                     .append_error(ScrapeError::Post(id_range.clone()))
                     .await;
+                self.state_manager.fail_unit(Task::Posts).await;
                 error!(
-                    "Got error while scraping posts: {} in id range: {:?}",
-                    e, id_range
+                    range = ?id_range,
+                    duration_ms = elapsed.as_millis() as u64,
+                    error_kind = e.kind(),
+                    error = %e,
+                    "Failed to scrape posts"
                 );
             }
         }
     }
 
-    pub fn process_post(&self, output: &mut W, post: Post) {
-        serde_json::to_writer(&mut *output, &post).expect("Failed to write to output");
-        output.write_all(b"\n").expect("Failed to write to output");
+    /// Log the fields the site added or dropped during the run, once per run rather than per post
+    ///
+    /// Also called when the scraper is dropped, a run which is cancelled (by ctrl-c, or when the
+    /// other scraper of `scrape` finishes first) doesn't return.
+    fn report_drift(&self) {
+        let drift = std::mem::take(&mut *self.drift.lock().unwrap_or_else(|e| e.into_inner()));
+        if !drift.is_empty() {
+            warn!("The posts differed from the expected fields, {drift}");
+        }
+    }
+
+    pub async fn process_post(&self, post: Post) {
+        self.output
+            .write(post)
+            .await
+            .expect("Failed to write to output");
+    }
+}
+
+impl Drop for PostScraper {
+    fn drop(&mut self) {
+        self.report_drift();
     }
 }
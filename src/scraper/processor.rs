@@ -0,0 +1,221 @@
+//! Steps run on every scraped post before it is written, see [`PostProcessor`]
+//!
+//! A [`Pipeline`] chains processors, either built from `[[scraper.processors]]` in the config or
+//! assembled in code with custom processors, and is passed to
+//! [`PostScraper::with_pipeline`](super::post_scraper::PostScraper::with_pipeline).
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use super::blacklist::Blacklist;
+use crate::{config::ProcessorConfig, models::Post};
+
+#[derive(Debug, Error)]
+pub enum ProcessorError {
+    #[error("Reqwest Error: `{0}`")]
+    Reqwest(#[from] reqwest::Error),
+    #[error("{0}")]
+    Other(String),
+}
+
+/// What happens to a post after a processor ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Pass the post on to the next processor
+    Keep,
+    /// Don't write the post
+    Drop,
+}
+
+/// A step of a [`Pipeline`], e.g. normalizing the tags or asking an external tagger
+///
+/// A processor changes the post in place. The future is boxed so processors of different types
+/// can be chained.
+pub trait PostProcessor: Send + Sync {
+    /// Used in logs
+    fn name(&self) -> &str;
+
+    fn process<'a>(&'a self, post: &'a mut Post) -> BoxFuture<'a, Result<Verdict, ProcessorError>>;
+}
+
+/// Counts collected by a [`Pipeline`]
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct PipelineStats {
+    pub processed: u64,
+    pub dropped: u64,
+    /// Processor runs which failed, the post was passed on unchanged by them
+    pub failed: u64,
+}
+
+/// Processors run on every post one after another, in the order they were added
+#[derive(Default)]
+pub struct Pipeline {
+    processors: Vec<Arc<dyn PostProcessor>>,
+    stats: Mutex<PipelineStats>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The processors of `[[scraper.processors]]`, `client` is used by the ones making requests
+    pub fn from_config(config: &[ProcessorConfig], client: reqwest::Client) -> Self {
+        config
+            .iter()
+            .fold(Self::new(), |pipeline, processor| match processor {
+                ProcessorConfig::NormalizeTags => pipeline.with(NormalizeTags),
+                ProcessorConfig::HttpTagger { url, timeout_secs } => pipeline.with(HttpTagger {
+                    client: client.clone(),
+                    url: url.clone(),
+                    timeout: Duration::from_secs(*timeout_secs),
+                }),
+            })
+    }
+
+    /// Append a processor to the chain
+    pub fn with(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.processors.push(Arc::new(processor));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processors.is_empty()
+    }
+
+    /// Run every processor on `post`, `None` if one of them dropped it
+    ///
+    /// A failing processor is logged and the post is passed on to the next one, keeping the
+    /// changes made before the error.
+    pub async fn run(&self, mut post: Post) -> Option<Post> {
+        let mut verdict = Verdict::Keep;
+        let mut failed = 0;
+        for processor in &self.processors {
+            match processor.process(&mut post).await {
+                Ok(Verdict::Keep) => {}
+                Ok(Verdict::Drop) => {
+                    verdict = Verdict::Drop;
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "Processor {} failed on post {}: {}",
+                        processor.name(),
+                        post.id,
+                        e
+                    );
+                    failed += 1;
+                }
+            }
+        }
+
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.processed += 1;
+        stats.failed += failed;
+        match verdict {
+            Verdict::Keep => Some(post),
+            Verdict::Drop => {
+                stats.dropped += 1;
+                None
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// `post` as it is written by the scrapers, `None` if `blacklist` blocks it or `pipeline` drops it
+///
+/// Every path which writes posts fetched from the site (scrape, repair, sync) goes through this,
+/// so blacklisted posts never reach the output.
+pub async fn prepare_post(
+    blacklist: Option<&Blacklist>,
+    pipeline: Option<&Pipeline>,
+    post: Post,
+) -> Option<Post> {
+    if blacklist.is_some_and(|blacklist| blacklist.blocks(&post)) {
+        return None;
+    }
+    match pipeline {
+        Some(pipeline) => pipeline.run(post).await,
+        None => Some(post),
+    }
+}
+
+/// Lowercases the tags, replaces spaces with underscores and removes duplicates, keeping the
+/// order
+pub struct NormalizeTags;
+
+impl PostProcessor for NormalizeTags {
+    fn name(&self) -> &str {
+        "normalize_tags"
+    }
+
+    fn process<'a>(&'a self, post: &'a mut Post) -> BoxFuture<'a, Result<Verdict, ProcessorError>> {
+        let mut tags: Vec<String> = Vec::with_capacity(post.tags.len());
+        for tag in &post.tags {
+            let tag = tag.trim().to_lowercase().replace(' ', "_");
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        post.tags = tags;
+        Box::pin(futures::future::ready(Ok(Verdict::Keep)))
+    }
+}
+
+/// Posts every post as JSON to an external tagger, e.g. an ML model behind an HTTP endpoint
+///
+/// The tagger answers with `{"tags": ["a", "b"], "drop": false}`, both fields are optional. The
+/// tags missing from the post are appended, and `drop` discards the post.
+pub struct HttpTagger {
+    pub client: reqwest::Client,
+    pub url: String,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TaggerResponse {
+    tags: Vec<String>,
+    drop: bool,
+}
+
+impl PostProcessor for HttpTagger {
+    fn name(&self) -> &str {
+        "http_tagger"
+    }
+
+    fn process<'a>(&'a self, post: &'a mut Post) -> BoxFuture<'a, Result<Verdict, ProcessorError>> {
+        Box::pin(async move {
+            let response: TaggerResponse = self
+                .client
+                .post(&self.url)
+                .timeout(self.timeout)
+                .json(&*post)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if response.drop {
+                return Ok(Verdict::Drop);
+            }
+            for tag in response.tags {
+                if !post.tags.contains(&tag) {
+                    post.tags.push(tag);
+                }
+            }
+            Ok(Verdict::Keep)
+        })
+    }
+}
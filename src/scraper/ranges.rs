@@ -0,0 +1,204 @@
+//! Sets of post id ranges, see [`IdRanges`]
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Disjoint, sorted ranges of ids, e.g. the post ids whose pages were scraped
+///
+/// Adjacent and overlapping ranges are merged as they are inserted, so a scrape which finished
+/// without errors is a single range however its pages arrived.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IdRanges(Vec<Range<u64>>);
+
+impl IdRanges {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<u64>> {
+        self.0.iter()
+    }
+
+    /// Add `range`, merging it with the ranges it overlaps or touches
+    pub fn insert(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        let first = self.0.partition_point(|known| known.end < range.start);
+        let last = self.0.partition_point(|known| known.start <= range.end);
+        let merged = match self.0[first..last] {
+            [] => range,
+            ref touched => {
+                touched[0].start.min(range.start)..touched[touched.len() - 1].end.max(range.end)
+            }
+        };
+        self.0.splice(first..last, [merged]);
+    }
+
+    /// Add every range of `other`
+    pub fn extend(&mut self, other: &IdRanges) {
+        for range in other.iter() {
+            self.insert(range.clone());
+        }
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        let position = self.0.partition_point(|range| range.end <= id);
+        self.0.get(position).is_some_and(|range| range.start <= id)
+    }
+
+    /// Whether every id of `range` is in the set
+    pub fn covers(&self, range: &Range<u64>) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        let position = self.0.partition_point(|known| known.end <= range.start);
+        self.0
+            .get(position)
+            .is_some_and(|known| known.start <= range.start && range.end <= known.end)
+    }
+
+    /// The first id from `start` on which isn't in the set
+    pub fn first_missing(&self, start: u64) -> u64 {
+        let position = self.0.partition_point(|range| range.end <= start);
+        match self.0.get(position) {
+            Some(range) if range.start <= start => range.end,
+            _ => start,
+        }
+    }
+
+    /// One past the highest id in the set, 0 if it is empty
+    pub fn end(&self) -> u64 {
+        self.0.last().map_or(0, |range| range.end)
+    }
+
+    /// The parts of `within` which aren't in the set, in order
+    pub fn gaps(&self, within: Range<u64>) -> Vec<Range<u64>> {
+        let mut gaps = Vec::new();
+        let mut next = within.start;
+        for range in &self.0 {
+            if range.start >= within.end {
+                break;
+            }
+            if range.start > next {
+                gaps.push(next..range.start);
+            }
+            next = next.max(range.end);
+        }
+        if next < within.end {
+            gaps.push(next..within.end);
+        }
+        gaps
+    }
+
+    /// The gaps of `within` cut into pages of at most `size` ids, no page crosses a range of the
+    /// set
+    pub fn pages(
+        &self,
+        within: Range<u64>,
+        size: u64,
+    ) -> impl Iterator<Item = Range<u64>> + Send + 'static {
+        self.gaps(within).into_iter().flat_map(move |gap| {
+            (gap.start..gap.end)
+                .step_by(size as usize)
+                .map(move |start| start..start.saturating_add(size).min(gap.end))
+        })
+    }
+}
+
+#[cfg(test)]
+// Expected ranges are lists, even of a single range
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    fn ranges(list: &[Range<u64>]) -> IdRanges {
+        let mut ranges = IdRanges::default();
+        for range in list {
+            ranges.insert(range.clone());
+        }
+        ranges
+    }
+
+    fn list(ranges: &IdRanges) -> Vec<Range<u64>> {
+        ranges.iter().cloned().collect()
+    }
+
+    #[test]
+    fn insert_merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            list(&ranges(&[20..30, 1..10, 40..50])),
+            [1..10, 20..30, 40..50]
+        );
+        // Adjacent
+        assert_eq!(list(&ranges(&[1..10, 10..20])), [1..20]);
+        // Overlapping
+        assert_eq!(list(&ranges(&[1..10, 5..15])), [1..15]);
+        // Bridging several
+        assert_eq!(list(&ranges(&[1..10, 20..30, 40..50, 5..45])), [1..50]);
+        // Inside an existing one
+        assert_eq!(list(&ranges(&[1..50, 10..20])), [1..50]);
+        // Empty ranges are ignored
+        assert!(ranges(&[5..5]).is_empty());
+    }
+
+    #[test]
+    fn pages_arriving_out_of_order_end_as_one_range() {
+        let pages = [201..301, 1..101, 301..401, 101..201];
+        assert_eq!(list(&ranges(&pages)), [1..401]);
+    }
+
+    #[test]
+    fn contains_and_covers() {
+        let ranges = ranges(&[1..10, 20..30]);
+        assert!(ranges.contains(1));
+        assert!(ranges.contains(9));
+        assert!(!ranges.contains(10));
+        assert!(!ranges.contains(0));
+        assert!(ranges.contains(25));
+
+        assert!(ranges.covers(&(2..8)));
+        assert!(ranges.covers(&(20..30)));
+        assert!(!ranges.covers(&(5..25)));
+        assert!(!ranges.covers(&(8..12)));
+        assert!(ranges.covers(&(15..15)));
+    }
+
+    #[test]
+    fn first_missing_and_end() {
+        let ranges = ranges(&[1..10, 20..30]);
+        assert_eq!(ranges.first_missing(1), 10);
+        assert_eq!(ranges.first_missing(5), 10);
+        assert_eq!(ranges.first_missing(10), 10);
+        assert_eq!(ranges.first_missing(15), 15);
+        assert_eq!(ranges.first_missing(20), 30);
+        assert_eq!(ranges.end(), 30);
+        assert_eq!(IdRanges::default().end(), 0);
+    }
+
+    #[test]
+    fn gaps() {
+        let ranges = ranges(&[10..20, 30..40]);
+        assert_eq!(ranges.gaps(0..50), [0..10, 20..30, 40..50]);
+        assert_eq!(ranges.gaps(15..35), [20..30]);
+        assert_eq!(ranges.gaps(12..18), []);
+        assert_eq!(IdRanges::default().gaps(1..5), [1..5]);
+    }
+
+    #[test]
+    fn pages_dont_cross_completed_ranges() {
+        let ranges = ranges(&[1..101, 151..201]);
+        let pages: Vec<_> = ranges.pages(1..301, 40).collect();
+        assert_eq!(pages, [101..141, 141..151, 201..241, 241..281, 281..301]);
+    }
+
+    #[test]
+    fn serializes_as_a_list() {
+        let ranges = ranges(&[1..10]);
+        let json = serde_json::to_string(&ranges).unwrap();
+        assert_eq!(json, r#"[{"start":1,"end":10}]"#);
+        assert_eq!(serde_json::from_str::<IdRanges>(&json).unwrap(), ranges);
+    }
+}
@@ -0,0 +1,165 @@
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    api::{client::ApiClient, models::ApiError},
+    models::{Post, Tag},
+    sink::writer::SinkHandle,
+};
+
+use std::sync::Arc;
+
+use super::{
+    blacklist::Blacklist,
+    processor::{prepare_post, Pipeline},
+    ranges::IdRanges,
+    state_manager::{ScrapeError, StateManager},
+};
+
+/// Counts collected while retrying the recorded errors
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RepairStats {
+    pub resolved: u64,
+    pub failed: u64,
+    pub posts: u64,
+    pub tags: u64,
+}
+
+/// Retries the post ranges and tag pages which failed during earlier scrapes
+///
+/// Recovered records are written to the outputs like during a normal scrape, posts go through the
+/// same blacklist and pipeline. A repaired post range
+/// is marked as completed, which moves the post watermark past it if it was the first gap, and
+/// only errors which still fail are kept in the state. The parts of a post range which a later
+/// scrape already completed aren't requested again.
+pub struct Repairer {
+    state_manager: StateManager,
+    client: ApiClient,
+    posts: SinkHandle<Post>,
+    tags: SinkHandle<Tag>,
+    blacklist: Option<Arc<Blacklist>>,
+    pipeline: Option<Arc<Pipeline>>,
+}
+
+impl Repairer {
+    pub fn new(
+        posts: SinkHandle<Post>,
+        tags: SinkHandle<Tag>,
+        state_manager: StateManager,
+        client: ApiClient,
+    ) -> Self {
+        Self {
+            state_manager,
+            client,
+            posts,
+            tags,
+            blacklist: None,
+            pipeline: None,
+        }
+    }
+
+    /// Drop the posts matching `blacklist` instead of writing them, an empty one is ignored
+    pub fn with_blacklist(mut self, blacklist: Arc<Blacklist>) -> Self {
+        self.blacklist = (!blacklist.is_empty()).then_some(blacklist);
+        self
+    }
+
+    /// Run every post through `pipeline` before writing it, after the blacklist. An empty one is
+    /// ignored.
+    pub fn with_pipeline(mut self, pipeline: Arc<Pipeline>) -> Self {
+        self.pipeline = (!pipeline.is_empty()).then_some(pipeline);
+        self
+    }
+
+    pub async fn run(&self) -> RepairStats {
+        let mut stats = RepairStats::default();
+        let completed = self.state_manager.completed_posts().await;
+
+        for scrape_error in self.state_manager.take_errors().await {
+            if let ScrapeError::Post(id_range) = &scrape_error {
+                if completed.covers(id_range) {
+                    info!(range = ?id_range, "Skipped a failed page which was scraped since");
+                    stats.resolved += 1;
+                    continue;
+                }
+            }
+            let recovered = match &scrape_error {
+                ScrapeError::Post(id_range) => self.repair_post_gaps(&completed, id_range).await,
+                ScrapeError::Tag(after_id) => self.repair_tags(*after_id).await,
+            };
+
+            let (range, after_id) = match &scrape_error {
+                ScrapeError::Post(id_range) => (Some(id_range.clone()), None),
+                ScrapeError::Tag(after_id) => (None, Some(*after_id)),
+            };
+            match recovered {
+                Ok((posts, tags)) => {
+                    info!(range = ?range, after_id, posts, tags, "Repaired a failed page");
+                    stats.resolved += 1;
+                    stats.posts += posts;
+                    stats.tags += tags;
+                }
+                Err(e) => {
+                    // Errors which aren't the API's come from writing the records
+                    let error_kind = e.downcast_ref::<ApiError>().map_or("write", ApiError::kind);
+                    error!(
+                        range = ?range,
+                        after_id,
+                        error_kind,
+                        error = %e,
+                        "Failed to repair a failed page"
+                    );
+                    stats.failed += 1;
+                    self.state_manager.append_error(scrape_error).await;
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Repair the parts of `id_range` which aren't `completed`
+    async fn repair_post_gaps(
+        &self,
+        completed: &IdRanges,
+        id_range: &std::ops::Range<u64>,
+    ) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let mut posts = 0;
+        for gap in completed.gaps(id_range.clone()) {
+            posts += self.repair_posts(gap).await?.0;
+        }
+        Ok((posts, 0))
+    }
+
+    async fn repair_posts(
+        &self,
+        id_range: std::ops::Range<u64>,
+    ) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let response = self.client.query_posts_backoff(id_range.clone()).await?;
+        let highest_id = response.posts.iter().map(|post| post.id).max();
+        let mut count = 0;
+        for post in response.posts.into_iter().rev() {
+            let post = post.into();
+            let Some(post) =
+                prepare_post(self.blacklist.as_deref(), self.pipeline.as_deref(), post).await
+            else {
+                continue;
+            };
+            self.posts.write(post).await?;
+            count += 1;
+        }
+        self.state_manager
+            .complete_post_range(id_range, highest_id)
+            .await;
+        Ok((count, 0))
+    }
+
+    async fn repair_tags(&self, after_id: u64) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let response = self.client.query_tags_backoff(after_id).await?;
+        let count = response.tags.len() as u64;
+        for tag in response.tags.into_iter().rev() {
+            self.tags.write(tag.into()).await?;
+        }
+        Ok((0, count))
+    }
+}
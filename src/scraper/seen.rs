@@ -0,0 +1,253 @@
+//! The posts already in the posts file, see [`SeenPosts`]
+//!
+//! The state is saved when a scrape ends, so a scrape which crashed leaves posts in the output
+//! which the state doesn't know about, and the next run would request their pages again. The ids
+//! in the posts file tell which pages were written before the crash: pages are processed in
+//! order, so a page with posts in the output and later posts after it was finished.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use super::ranges::IdRanges;
+use crate::models::envelope::record_id;
+
+/// Bytes at the end of the posts file checked for posts after the watermark before reading it all
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// The ids of the posts in the posts file above a watermark, as a bitset
+#[derive(Debug, Clone, Default)]
+pub struct SeenPosts {
+    /// Id of the first bit
+    base: u64,
+    words: Vec<u64>,
+    len: u64,
+}
+
+impl SeenPosts {
+    /// An empty set for the ids from `base` on
+    pub fn new(base: u64) -> Self {
+        Self {
+            base,
+            words: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// The posts of the file at `path` with an id above `after`, `None` if the file has none
+    ///
+    /// Only the end of the file is read unless it has such posts, so the check is cheap after a
+    /// scrape which saved its state. A missing file has no posts.
+    pub fn load<P: AsRef<Path>>(path: P, after: u64) -> io::Result<Option<Self>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))?;
+        let mut tail = Vec::new();
+        BufReader::new(&mut file).read_to_end(&mut tail)?;
+        // The first line of the tail is cut off unless the tail is the whole file, possibly in the
+        // middle of a character
+        let skip = usize::from(len > TAIL_BYTES);
+        if !tail
+            .split(|byte| *byte == b'\n')
+            .skip(skip)
+            .filter_map(|line| record_id(&String::from_utf8_lossy(line)))
+            .any(|id| id > after)
+        {
+            return Ok(None);
+        }
+
+        // A crash can leave a torn last line, which may end inside a character, lines which
+        // don't parse are skipped
+        file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(file);
+        let mut seen = Self::new(after + 1);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if let Some(id) = record_id(&String::from_utf8_lossy(&line)) {
+                seen.insert(id);
+            }
+            line.clear();
+        }
+        Ok(Some(seen))
+    }
+
+    /// Add a post, ids below the base are ignored
+    pub fn insert(&mut self, id: u64) -> bool {
+        let Some(bit) = id.checked_sub(self.base) else {
+            return false;
+        };
+        let (word, mask) = ((bit / 64) as usize, 1u64 << (bit % 64));
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let new = self.words[word] & mask == 0;
+        self.words[word] |= mask;
+        self.len += u64::from(new);
+        new
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        let Some(bit) = id.checked_sub(self.base) else {
+            return false;
+        };
+        self.words
+            .get((bit / 64) as usize)
+            .is_some_and(|word| word & (1u64 << (bit % 64)) != 0)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The highest id in the set
+    pub fn max(&self) -> Option<u64> {
+        let (word, bits) = self
+            .words
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, bits)| **bits != 0)?;
+        Some(self.base + word as u64 * 64 + 63 - bits.leading_zeros() as u64)
+    }
+
+    /// Whether any id of `range` is in the set
+    pub fn any_in(&self, range: &Range<u64>) -> bool {
+        range.clone().any(|id| self.contains(id))
+    }
+
+    /// The pages of `pages` which were finished before the scrape that wrote the posts stopped:
+    /// those with posts in the set and more posts after them
+    ///
+    /// The pages have to be cut like the stopped scrape cut them. Pages without any post are left
+    /// out, they may have failed, and so is the page of the newest post, which may have been
+    /// written only in part.
+    pub fn finished_pages(&self, pages: impl IntoIterator<Item = Range<u64>>) -> IdRanges {
+        let mut finished = IdRanges::default();
+        let Some(max) = self.max() else {
+            return finished;
+        };
+        for page in pages {
+            if page.end > max {
+                break;
+            }
+            if self.any_in(&page) {
+                finished.insert(page);
+            }
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+// Expected ranges are lists, even of a single range
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::{fixtures::Fixtures, models::envelope::Envelope};
+
+    /// A posts file in the temporary directory, removed when dropped
+    struct PostsFile(PathBuf);
+
+    impl PostsFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("indexer-{name}-{}.json", std::process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for PostsFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Post lines with titles of multibyte characters, ids ascending like the pages of a scrape
+    fn posts(count: usize) -> String {
+        let mut fixtures = Fixtures::new(7);
+        fixtures.tags(20);
+        let mut lines = String::new();
+        for mut post in fixtures.posts(count) {
+            post.title = Some("猫耳の女の子".repeat(20));
+            lines.push_str(&serde_json::to_string(&Envelope::new(post)).unwrap());
+            lines.push('\n');
+        }
+        lines
+    }
+
+    #[test]
+    fn load_with_the_tail_starting_inside_a_character() {
+        let mut contents = posts(300);
+        assert!(contents.len() as u64 > TAIL_BYTES);
+        // Shift the start of the tail until it falls inside a character
+        while contents.is_char_boundary(contents.len() - TAIL_BYTES as usize) {
+            contents.push('\n');
+        }
+        let file = PostsFile::new("seen-utf8", &contents);
+
+        let seen = SeenPosts::load(&file.0, 200).unwrap().unwrap();
+        assert_eq!(seen.len(), 100);
+        assert!(seen.contains(201));
+        assert!(seen.contains(300));
+        assert!(!seen.contains(200));
+        assert_eq!(seen.max(), Some(300));
+    }
+
+    #[test]
+    fn load_with_a_torn_last_line() {
+        let mut contents = posts(300).into_bytes();
+        // The last post was cut off inside a character of its title
+        let last = contents[..contents.len() - 1]
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .unwrap();
+        let title = last
+            + contents[last..]
+                .windows(3)
+                .position(|bytes| bytes == "猫".as_bytes())
+                .unwrap();
+        contents.truncate(title + 1);
+        let file = PostsFile::new("seen-torn", "");
+        fs::write(&file.0, &contents).unwrap();
+
+        let seen = SeenPosts::load(&file.0, 200).unwrap().unwrap();
+        assert_eq!(seen.len(), 99);
+        assert!(seen.contains(299));
+        assert!(!seen.contains(300));
+    }
+
+    #[test]
+    fn load_without_posts_after_the_watermark() {
+        let file = PostsFile::new("seen-none", &posts(300));
+        assert!(SeenPosts::load(&file.0, 300).unwrap().is_none());
+        assert!(SeenPosts::load(file.0.with_extension("missing"), 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn finished_pages_leave_out_the_last_page() {
+        let mut seen = SeenPosts::new(101);
+        for id in (101..=250).filter(|id| !(151..201).contains(id)) {
+            seen.insert(id);
+        }
+        let pages = [101..151, 151..201, 201..251, 251..301];
+        let finished: Vec<_> = seen.finished_pages(pages).iter().cloned().collect();
+        // The empty page may have failed, the page of the newest post may be incomplete
+        assert_eq!(finished, [101..151]);
+    }
+}
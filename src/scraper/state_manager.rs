@@ -1,8 +1,19 @@
-use std::{ops::Range, path::Path, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, ErrorKind, Write},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use tracing::error;
+use tracing::{error, info};
+
+use super::{plan::PAGE_SIZE, ranges::IdRanges, seen::SeenPosts};
+use crate::api::throttle::Throttle;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ScrapeError {
@@ -10,40 +21,269 @@ pub enum ScrapeError {
     Tag(u64),
 }
 
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScrapeState {
+    /// Every post up to this id was scraped, the end of the first range of
+    /// [`completed_posts`](Self::completed_posts)
     pub last_post_id: u64,
     pub last_tag_id: u64,
-    pub errors: Vec<ScrapeError>
+    pub errors: Vec<ScrapeError>,
+    /// The post id ranges whose pages were scraped, up to the newest post seen at the time
+    ///
+    /// Pages arrive out of order and some fail, so the scraped ids can have gaps. A resumed scrape
+    /// only requests the gaps, and a failed page keeps the watermark below it until it succeeds.
+    #[serde(default, skip_serializing_if = "IdRanges::is_empty")]
+    pub completed_posts: IdRanges,
+    /// Ids of scraped pages above the newest post known at the time, which posts uploaded later
+    /// could still take. They are completed once a page shows a newer post, and a new process
+    /// requests them again.
+    #[serde(skip)]
+    pub held_back_posts: IdRanges,
+    /// Status of the scheduled jobs of the daemon by name, see [`crate::schedule`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub jobs: BTreeMap<String, JobStatus>,
+    /// Progress of every kind of work, see [`StateManager::progress`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tasks: BTreeMap<Task, TaskProgress>,
+    /// When the last post page was received, including empty ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_post_page_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_tag_page_at: Option<DateTime<Utc>>,
+    /// No request is sent before this time, after the API throttled the scraper
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooldown_until: Option<DateTime<Utc>>,
+}
+
+impl ScrapeState {
+    /// Mark the posts up to the watermark as completed in a state written before
+    /// [`completed_posts`](Self::completed_posts) was tracked
+    ///
+    /// That watermark moved past pages which failed, so the ranges of the recorded errors are left
+    /// out, otherwise they would count as scraped and their repairs would be dropped.
+    pub fn upgrade_completed_posts(&mut self) {
+        if self.completed_posts.is_empty() && self.last_post_id > 0 {
+            let mut failed = IdRanges::default();
+            for error in &self.errors {
+                if let ScrapeError::Post(range) = error {
+                    failed.insert(range.clone());
+                }
+            }
+            for gap in failed.gaps(FIRST_POST_ID..self.last_post_id + 1) {
+                self.completed_posts.insert(gap);
+            }
+        }
+    }
+
+    /// Move the watermark to the end of the completed posts from the first one on, and drop the
+    /// errors of the post ranges which are complete
+    pub fn update_post_watermark(&mut self) {
+        self.last_post_id = self.completed_posts.first_missing(FIRST_POST_ID) - 1;
+        let completed = &self.completed_posts;
+        self.errors
+            .retain(|error| !matches!(error, ScrapeError::Post(range) if completed.covers(range)));
+    }
+}
+
+/// What is known about the runs of a scheduled job
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct JobStatus {
+    pub next_run: Option<DateTime<Utc>>,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    /// Summary of the last successful run
+    pub last_outcome: Option<String>,
+    /// Error of the last run, `None` if it succeeded
+    pub last_error: Option<String>,
+    pub runs: u64,
+    pub failures: u64,
+    /// Runs which were due while the previous one was still running
+    pub skipped: u64,
+}
+
+/// A kind of work whose progress is kept in the state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    /// The post sweep of `scrape` and of the `posts` job of the daemon
+    Posts,
+    /// The tag sweep, and refreshes of every tag
+    Tags,
+    /// Syncs requesting the scraped posts again, see [`sync_posts`](crate::maintenance::sync::sync_posts)
+    Updates,
+    /// Files of a [`Downloader`](crate::download::Downloader) which was given the state
+    Downloads,
+}
+
+/// Progress of a [`Task`] in its current or last run, and over all runs
+///
+/// A unit is what the task requests at once: a page of posts or tags, an id range of a sync or a
+/// file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaskProgress {
+    /// Units of the run, if known when it started, e.g. the pages of a scrape plan
+    pub total: Option<u64>,
+    pub started: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Records the run wrote: posts, tags or files
+    pub items: u64,
+    pub run_started_at: Option<DateTime<Utc>>,
+    /// `None` while the run is going on
+    pub run_finished_at: Option<DateTime<Utc>>,
+    /// When the last unit completed or failed
+    pub last_unit_at: Option<DateTime<Utc>>,
+    pub runs: u64,
+    /// Units completed by all runs
+    pub completed_total: u64,
+    /// Records written by all runs
+    pub items_total: u64,
+}
+
+impl TaskProgress {
+    pub fn is_running(&self) -> bool {
+        self.run_started_at.is_some() && self.run_finished_at.is_none()
+    }
+
+    /// Share of the units of the run which completed or failed, if its total is known
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total.filter(|total| *total > 0)?;
+        Some(((self.completed + self.failed) as f64 / total as f64).min(1.0))
+    }
+
+    fn start_run(&mut self, total: Option<u64>) {
+        *self = Self {
+            total,
+            run_started_at: Some(Utc::now()),
+            runs: self.runs + 1,
+            completed_total: self.completed_total,
+            items_total: self.items_total,
+            ..Self::default()
+        };
+    }
+
+    fn complete_unit(&mut self, items: u64) {
+        self.completed += 1;
+        self.completed_total += 1;
+        self.items += items;
+        self.items_total += items;
+        self.last_unit_at = Some(Utc::now());
+    }
+
+    fn fail_unit(&mut self) {
+        self.failed += 1;
+        self.last_unit_at = Some(Utc::now());
+    }
+
+    /// End a run which is going on at its last unit
+    fn stop(&mut self) {
+        if self.is_running() {
+            self.run_finished_at = self.last_unit_at.or(self.run_started_at);
+        }
+    }
 }
 
+/// Post ids start at 1, the watermark is 0 before the first page
+pub const FIRST_POST_ID: u64 = 1;
+
 /// Manages the state of the scraper across multiple threads
 #[derive(Debug, Clone)]
 pub struct StateManager {
-    state: Arc<Mutex<ScrapeState>>
+    state: Arc<Mutex<ScrapeState>>,
+    throttle: Throttle,
 }
 
 impl StateManager {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, serde_json::Error> {
-        let state = match std::fs::File::open(path) {
+        let mut state: ScrapeState = match std::fs::File::open(path) {
             Ok(state_file) => serde_json::from_reader(state_file)?,
             Err(e) => {
-                error!("Unable to open state file: {:?}", e);
+                match e.kind() {
+                    ErrorKind::NotFound => info!("No state file yet, starting from the beginning"),
+                    _ => error!("Unable to open state file: {:?}", e),
+                }
                 ScrapeState {
                     last_post_id: 0,
                     last_tag_id: 0,
                     errors: Vec::new(),
+                    completed_posts: IdRanges::default(),
+                    held_back_posts: IdRanges::default(),
+                    jobs: BTreeMap::new(),
+                    tasks: BTreeMap::new(),
+                    last_post_page_at: None,
+                    last_tag_page_at: None,
+                    cooldown_until: None,
                 }
             }
         };
+        state.upgrade_completed_posts();
+        // Runs of a process which stopped without finishing them
+        for progress in state.tasks.values_mut() {
+            progress.stop();
+        }
 
+        let throttle = Throttle::new(state.cooldown_until);
         let state = Arc::new(Mutex::new(state));
-        Ok(Self { state })
+        Ok(Self { state, throttle })
     }
 
-    pub async fn update_last_post_id(&self, last_post_id: u64) {
-        self.state.lock().await.last_post_id = last_post_id;
+    /// The cool-down of the API, saved with the state
+    ///
+    /// Pass it to the client with [`ApiClient::with_throttle`](crate::api::client::ApiClient::with_throttle).
+    pub fn throttle(&self) -> Throttle {
+        self.throttle.clone()
+    }
+
+    /// Record that the page of `range` was scraped, `newest_id` being the newest post known on the
+    /// site, e.g. the max id of the scrape plan or the newest post of the page
+    ///
+    /// Only ids above the newest post seen so far are held back, posts uploaded later can still
+    /// take them. A page whose posts are newer completes the ids held back below them, so sparse
+    /// pages in the middle of a scrape don't leave gaps. Moves the watermark to the end of the
+    /// completed ranges from the first post on, and drops the recorded errors of post ranges which
+    /// are now complete.
+    pub async fn complete_post_range(&self, range: Range<u64>, newest_id: Option<u64>) {
+        let mut state = self.state.lock().await;
+        let newest = newest_id
+            .unwrap_or(0)
+            .max(state.completed_posts.end().saturating_sub(1));
+        let frontier = range.end.min(newest + 1).max(range.start);
+        state.completed_posts.insert(range.start..frontier);
+        state.held_back_posts.insert(frontier..range.end);
+        for held_back in std::mem::take(&mut state.held_back_posts).iter() {
+            state
+                .completed_posts
+                .insert(held_back.start..held_back.end.min(newest + 1));
+            state
+                .held_back_posts
+                .insert(held_back.start.max(newest + 1)..held_back.end);
+        }
+        state.update_post_watermark();
+    }
+
+    /// Mark the pages after the watermark as completed which a scrape that stopped without saving
+    /// the state finished, judging by the posts it wrote, see [`SeenPosts::finished_pages`]
+    ///
+    /// Returns the number of recovered pages.
+    pub async fn recover_post_pages(&self, seen: &SeenPosts) -> u64 {
+        let mut state = self.state.lock().await;
+        let Some(max) = seen.max() else {
+            return 0;
+        };
+        let pages = state
+            .completed_posts
+            .pages(state.last_post_id + 1..max + 1, PAGE_SIZE);
+        let finished = seen.finished_pages(pages);
+        let recovered = finished.iter().count() as u64;
+        state.completed_posts.extend(&finished);
+        state.update_post_watermark();
+        recovered
+    }
+
+    /// The post id ranges scraped so far, see [`ScrapeState::completed_posts`]
+    pub async fn completed_posts(&self) -> IdRanges {
+        self.state.lock().await.completed_posts.clone()
     }
 
     pub async fn update_last_tag_id(&self, last_tag_id: u64) {
@@ -58,18 +298,194 @@ impl StateManager {
         self.state.lock().await.last_tag_id
     }
 
+    /// Note that a post page was received, see [`ScrapeState::last_post_page_at`]
+    pub async fn record_post_page(&self) {
+        self.state.lock().await.last_post_page_at = Some(Utc::now());
+    }
+
+    pub async fn record_tag_page(&self) {
+        self.state.lock().await.last_tag_page_at = Some(Utc::now());
+    }
+
     pub async fn append_error(&self, error: ScrapeError) {
         self.state.lock().await.errors.push(error);
     }
 
+    /// Remove and return every recorded error, e.g. to retry them
+    pub async fn take_errors(&self) -> Vec<ScrapeError> {
+        std::mem::take(&mut self.state.lock().await.errors)
+    }
+
+    /// Change the status of the scheduled job `name`, creating it if needed
+    pub async fn update_job(&self, name: &str, update: impl FnOnce(&mut JobStatus)) {
+        let mut state = self.state.lock().await;
+        update(state.jobs.entry(name.to_string()).or_default());
+    }
+
+    /// Start a run of `task`, expecting `total` units if known
+    pub async fn start_task(&self, task: Task, total: Option<u64>) {
+        let mut state = self.state.lock().await;
+        state.tasks.entry(task).or_default().start_run(total);
+    }
+
+    /// Note that a unit of `task` was requested
+    pub async fn start_unit(&self, task: Task) {
+        self.state
+            .lock()
+            .await
+            .tasks
+            .entry(task)
+            .or_default()
+            .started += 1;
+    }
+
+    /// Note that a unit of `task` completed, writing `items` records
+    pub async fn complete_unit(&self, task: Task, items: u64) {
+        let mut state = self.state.lock().await;
+        state.tasks.entry(task).or_default().complete_unit(items);
+    }
+
+    pub async fn fail_unit(&self, task: Task) {
+        self.state
+            .lock()
+            .await
+            .tasks
+            .entry(task)
+            .or_default()
+            .fail_unit();
+    }
+
+    pub async fn finish_task(&self, task: Task) {
+        let mut state = self.state.lock().await;
+        state.tasks.entry(task).or_default().run_finished_at = Some(Utc::now());
+    }
+
+    /// End the runs which are going on, e.g. because the scrape was cancelled
+    pub async fn stop_tasks(&self) {
+        for progress in self.state.lock().await.tasks.values_mut() {
+            progress.stop();
+        }
+    }
+
+    /// The progress of every task which ran, for dashboards and `/health`
+    pub async fn progress(&self) -> BTreeMap<Task, TaskProgress> {
+        self.state.lock().await.tasks.clone()
+    }
+
     pub fn get_state(&self) -> Arc<Mutex<ScrapeState>> {
         self.state.clone()
     }
 
+    /// Write the state to `file_path`, through a temporary file renamed over it so a crash or
+    /// another process reading the state never sees a partial file
     pub async fn save_state(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let state = self.state.lock().await;
-        let file = std::fs::File::create(file_path)?;
-        serde_json::to_writer(file, &*state)?;
+        let mut state = self.state.lock().await;
+        state.cooldown_until = self.throttle.until();
+        let temp_path = format!("{file_path}.{}.tmp", std::process::id());
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer(&mut file, &*state)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        std::fs::rename(&temp_path, file_path)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+// Expected ranges are lists, even of a single range
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    /// A state written before the completed ranges were tracked, its watermark moved past the
+    /// failed pages
+    const LEGACY_STATE: &str = r#"{
+        "last_post_id": 400,
+        "last_tag_id": 5,
+        "errors": [{"Post": {"start": 101, "end": 201}}, {"Tag": 3}, {"Post": {"start": 301, "end": 351}}]
+    }"#;
+
+    #[test]
+    fn upgrading_a_legacy_state_keeps_the_failed_ranges() {
+        let mut state: ScrapeState = serde_json::from_str(LEGACY_STATE).unwrap();
+        state.upgrade_completed_posts();
+        assert_eq!(
+            state.completed_posts.iter().cloned().collect::<Vec<_>>(),
+            [1..101, 201..301, 351..401]
+        );
+
+        // The failed ranges stay errors for `repair`, the watermark stops below the first one
+        state.update_post_watermark();
+        assert_eq!(state.last_post_id, 100);
+        assert_eq!(state.errors.len(), 3);
+    }
+
+    #[test]
+    fn upgrading_only_applies_to_legacy_states() {
+        let mut state: ScrapeState = serde_json::from_str(LEGACY_STATE).unwrap();
+        state.completed_posts.insert(1..51);
+        state.upgrade_completed_posts();
+        assert_eq!(
+            state.completed_posts.iter().cloned().collect::<Vec<_>>(),
+            [1..51]
+        );
+
+        let mut state: ScrapeState =
+            serde_json::from_str(r#"{"last_post_id": 0, "last_tag_id": 0, "errors": []}"#).unwrap();
+        state.upgrade_completed_posts();
+        assert!(state.completed_posts.is_empty());
+    }
+
+    #[test]
+    fn repairing_a_range_moves_the_watermark() {
+        let mut state: ScrapeState = serde_json::from_str(LEGACY_STATE).unwrap();
+        state.upgrade_completed_posts();
+        state.completed_posts.insert(101..201);
+        state.update_post_watermark();
+        assert_eq!(state.last_post_id, 300);
+        assert_eq!(state.errors.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_legacy_state_file_keeps_its_repairs() {
+        let path = std::env::temp_dir().join(format!("indexer-state-{}.json", std::process::id()));
+        std::fs::write(&path, LEGACY_STATE).unwrap();
+        let state_manager = StateManager::new(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(state_manager.take_errors().await.len(), 3);
+        let completed = state_manager.completed_posts().await;
+        assert!(!completed.covers(&(101..201)));
+        assert!(!completed.covers(&(301..351)));
+        assert!(completed.covers(&(201..301)));
+    }
+
+    #[tokio::test]
+    async fn a_sparse_page_before_newer_posts_is_completed() {
+        let path = std::env::temp_dir().join(format!("indexer-sparse-{}.json", std::process::id()));
+        let state_manager = StateManager::new(&path).unwrap();
+        let completed = || async {
+            let completed = state_manager.completed_posts().await;
+            completed.iter().cloned().collect::<Vec<_>>()
+        };
+
+        state_manager.complete_post_range(1..101, Some(100)).await;
+        // The newest posts of the page were deleted
+        state_manager.complete_post_range(101..201, Some(150)).await;
+        assert_eq!(completed().await, [1..151]);
+
+        state_manager.complete_post_range(201..301, Some(250)).await;
+        assert_eq!(completed().await, [1..251]);
+        assert_eq!(state_manager.last_post_id().await, 250);
+
+        // A page from the plan, whose newest post on the site is further on
+        state_manager
+            .complete_post_range(301..401, Some(1000))
+            .await;
+        assert_eq!(completed().await, [1..401]);
+
+        // Past the newest post on the site
+        state_manager.complete_post_range(1001..1101, None).await;
+        assert_eq!(completed().await, [1..401]);
+    }
+}
@@ -1,47 +1,54 @@
-use std::{io::Write, num::NonZeroU32, sync::Arc};
+use std::{num::NonZeroU32, time::Instant};
 
 use futures::StreamExt;
 use governor::{Quota, RateLimiter};
-use tokio::sync::Mutex;
 use tracing::{error, info};
 
-use crate::{api::client::ApiClient, models::Tag, scraper::state_manager::ScrapeError};
+use crate::{
+    api::client::ApiClient, models::Tag, scraper::state_manager::ScrapeError,
+    sink::writer::SinkHandle,
+};
 
-use super::state_manager::StateManager;
+use super::state_manager::{StateManager, Task};
 
-
-
-pub struct TagScraper<W: Write> {
+pub struct TagScraper {
     state_manager: StateManager,
     client: ApiClient,
-    output: Arc<Mutex<W>>,
+    output: SinkHandle<Tag>,
     requests_per_second: NonZeroU32,
 }
 
-impl<W: Write> TagScraper<W> {
-    pub fn new(output: W, state_manager: StateManager, client: ApiClient) -> Self {
+impl TagScraper {
+    pub fn new(output: SinkHandle<Tag>, state_manager: StateManager, client: ApiClient) -> Self {
         Self {
             state_manager,
             client,
-            output: Arc::new(Mutex::new(output)),
+            output,
             requests_per_second: NonZeroU32::new(8).unwrap(),
         }
     }
 
+    pub fn with_requests_per_second(mut self, requests_per_second: NonZeroU32) -> Self {
+        self.requests_per_second = requests_per_second;
+        self
+    }
+
     pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let limiter = &RateLimiter::direct(Quota::per_second(
-            self.requests_per_second,
-        ));
-        
+        let limiter = &RateLimiter::direct(Quota::per_second(self.requests_per_second));
+
         let after_id = self.state_manager.last_tag_id().await;
+        self.state_manager.start_task(Task::Tags, None).await;
         let tags = futures::stream::unfold(after_id, |after_id| async move {
-            
             // Wait until the rate limiter is ready
             limiter.until_ready().await;
 
+            self.state_manager.start_unit(Task::Tags).await;
+            let start = Instant::now();
             let response = self.client.query_tags_backoff(after_id).await;
+            let duration_ms = start.elapsed().as_millis() as u64;
             match response {
                 Ok(response) => {
+                    self.state_manager.record_tag_page().await;
                     let tag_count = response.tags.len();
                     let highest_id = response
                         .tags
@@ -50,23 +57,29 @@ impl<W: Write> TagScraper<W> {
                         .map(|tag| tag.id)
                         .unwrap_or(0);
                     self.state_manager.update_last_tag_id(highest_id).await;
-                    let output_lock = &mut *self.output.lock().await;
-                    response.tags.into_iter().rev().for_each(|tag| {
-                        self.process_tag(output_lock, tag.into());
-                    });
+                    for tag in response.tags.into_iter().rev() {
+                        self.process_tag(tag.into()).await;
+                    }
+                    self.state_manager
+                        .complete_unit(Task::Tags, tag_count as u64)
+                        .await;
 
-                    info!("Downloaded after_id={}, Got {} Tags", after_id, tag_count);
+                    info!(after_id, tags = tag_count, duration_ms, "Downloaded tags");
 
                     Some(((), highest_id))
                 }
                 Err(e) => {
                     error!(
-                        "Got error while scraping tags: {} at after_id={}",
-                        e, after_id
+                        after_id,
+                        duration_ms,
+                        error_kind = e.kind(),
+                        error = %e,
+                        "Failed to scrape tags"
                     );
                     self.state_manager
                         .append_error(ScrapeError::Tag(after_id))
                         .await;
+                    self.state_manager.fail_unit(Task::Tags).await;
                     None
                 }
             }
@@ -74,13 +87,71 @@ impl<W: Write> TagScraper<W> {
 
         // Consuming the stream to completion
         tags.count().await;
+        self.state_manager.finish_task(Task::Tags).await;
 
         Ok(())
     }
 
-    pub fn process_tag(&self, output: &mut W, tag: Tag) {
-        serde_json::to_writer(&mut *output, &tag).expect("Failed to write to output");
-        output.write_all(b"\n").expect("Failed to write to output");
+    /// Scrape every tag again from the first one up to the first empty page, to pick up changed
+    /// counts and types
+    ///
+    /// The later records replace the earlier ones when the index is built. Returns the number of
+    /// tags written.
+    pub async fn refresh(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let limiter = RateLimiter::direct(Quota::per_second(self.requests_per_second));
+        let mut after_id = 0;
+        let mut written = 0;
+        self.state_manager.start_task(Task::Tags, None).await;
+        loop {
+            limiter.until_ready().await;
+            self.state_manager.start_unit(Task::Tags).await;
+            let response = match self.client.query_tags_backoff(after_id).await {
+                Ok(response) => {
+                    self.state_manager.record_tag_page().await;
+                    response
+                }
+                Err(e) => {
+                    error!(
+                        after_id,
+                        error_kind = e.kind(),
+                        error = %e,
+                        "Failed to refresh tags"
+                    );
+                    self.state_manager
+                        .append_error(ScrapeError::Tag(after_id))
+                        .await;
+                    self.state_manager.fail_unit(Task::Tags).await;
+                    self.state_manager.finish_task(Task::Tags).await;
+                    return Err(e.into());
+                }
+            };
+            let Some(highest_id) = response.tags.iter().map(|tag| tag.id).max() else {
+                self.state_manager.complete_unit(Task::Tags, 0).await;
+                break;
+            };
+            let tag_count = response.tags.len() as u64;
+            written += tag_count;
+            for tag in response.tags.into_iter().rev() {
+                self.process_tag(tag.into()).await;
+            }
+            self.state_manager
+                .complete_unit(Task::Tags, tag_count)
+                .await;
+            after_id = highest_id;
+        }
+
+        if after_id > self.state_manager.last_tag_id().await {
+            self.state_manager.update_last_tag_id(after_id).await;
+        }
+        self.state_manager.finish_task(Task::Tags).await;
+        info!("Refreshed {} tags", written);
+        Ok(written)
     }
 
-}
\ No newline at end of file
+    pub async fn process_tag(&self, tag: Tag) {
+        self.output
+            .write(tag)
+            .await
+            .expect("Failed to write to output");
+    }
+}
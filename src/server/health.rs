@@ -0,0 +1,242 @@
+//! The status of a scraper running in the server process, served at `/health`
+//!
+//! The response reports the cursors and failed ranges of the scrape state, when the last page was
+//! received, how many records wait for the writers, the progress of every task (post and tag
+//! sweeps, syncs, downloads) and the scheduled jobs. If a post sweep is running and no page arrived
+//! for longer than the stall threshold the status is `stalled` and the response is a 503, so a
+//! plain HTTP check is enough for monitoring to notice. A daemon waiting for the next run of its
+//! `posts` job is idle, not stalled.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::{
+    scraper::state_manager::{
+        JobStatus, ScrapeError, ScrapeState, StateManager, Task, TaskProgress,
+    },
+    sink::writer::QueueDepth,
+};
+
+/// What `/health` reports on
+#[derive(Debug, Clone)]
+pub struct Health {
+    state_manager: StateManager,
+    started: DateTime<Utc>,
+    /// `None` if the scraper isn't expected to request pages, e.g. without a `posts` job
+    stall_after: Option<Duration>,
+    queues: Arc<Mutex<BTreeMap<String, QueueDepth>>>,
+}
+
+impl Health {
+    pub fn new(state_manager: StateManager) -> Self {
+        Self {
+            state_manager,
+            started: Utc::now(),
+            stall_after: None,
+            queues: Default::default(),
+        }
+    }
+
+    /// Report the scrape as stalled if a running post sweep received no page for `stall_after`
+    pub fn with_stall_after(mut self, stall_after: std::time::Duration) -> Self {
+        self.stall_after = Duration::from_std(stall_after).ok();
+        self
+    }
+
+    /// Report the depth of a writer queue, replacing an earlier queue with the same name
+    pub fn track_queue(&self, name: impl Into<String>, depth: QueueDepth) {
+        self.queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(name.into(), depth);
+    }
+
+    /// Whether the scraper makes progress, for a watchdog: it isn't stalled, or it waits until the
+    /// API lets it send requests again
+    pub async fn is_live(&self) -> bool {
+        let report = self.report().await;
+        report.status == HealthStatus::Ok || report.cooldown_until.is_some()
+    }
+
+    pub async fn report(&self) -> HealthResponse {
+        let state = self.state_manager.get_state().lock().await.clone();
+        let now = Utc::now();
+
+        // Before the first page the scrape counts from the start of the process
+        let since_post_page = now
+            - state
+                .last_post_page_at
+                .unwrap_or(self.started)
+                .max(self.started);
+        let stalled = self
+            .stall_after
+            .is_some_and(|stall_after| stalled(&state, now, stall_after));
+
+        let (mut post_errors, mut tag_errors) = (0, 0);
+        for error in &state.errors {
+            match error {
+                ScrapeError::Post(_) => post_errors += 1,
+                ScrapeError::Tag(_) => tag_errors += 1,
+            }
+        }
+
+        // Queues of writers which have stopped are left out
+        let queues = self
+            .queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .filter_map(|(name, depth)| Some((name.clone(), depth.get()?)))
+            .collect();
+
+        HealthResponse {
+            status: match stalled {
+                true => HealthStatus::Stalled,
+                false => HealthStatus::Ok,
+            },
+            started: self.started,
+            last_post_id: state.last_post_id,
+            last_tag_id: state.last_tag_id,
+            post_errors,
+            tag_errors,
+            last_post_page_at: state.last_post_page_at,
+            last_tag_page_at: state.last_tag_page_at,
+            cooldown_until: self.state_manager.throttle().until(),
+            seconds_since_post_page: since_post_page.num_seconds(),
+            stall_after_seconds: self
+                .stall_after
+                .map(|stall_after| stall_after.num_seconds()),
+            queues,
+            tasks: state.tasks,
+            jobs: state.jobs,
+        }
+    }
+}
+
+/// Whether the running post sweep received no page for `stall_after`, counted from its start if
+/// the last page is older
+fn stalled(state: &ScrapeState, now: DateTime<Utc>, stall_after: Duration) -> bool {
+    let Some(run_started_at) = state
+        .tasks
+        .get(&Task::Posts)
+        .filter(|progress| progress.is_running())
+        .and_then(|progress| progress.run_started_at)
+    else {
+        return false;
+    };
+    let last_activity = state
+        .last_post_page_at
+        .map_or(run_started_at, |at| at.max(run_started_at));
+    now - last_activity > stall_after
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Stalled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub status: HealthStatus,
+    pub started: DateTime<Utc>,
+    pub last_post_id: u64,
+    pub last_tag_id: u64,
+    /// Post ranges waiting to be retried
+    pub post_errors: usize,
+    pub tag_errors: usize,
+    pub last_post_page_at: Option<DateTime<Utc>>,
+    pub last_tag_page_at: Option<DateTime<Utc>>,
+    /// Set while the scraper waits after the API throttled it
+    pub cooldown_until: Option<DateTime<Utc>>,
+    /// Since the last post page, or since the start if there was none yet
+    pub seconds_since_post_page: i64,
+    pub stall_after_seconds: Option<i64>,
+    /// Records waiting for each writer
+    pub queues: BTreeMap<String, usize>,
+    /// Progress of the current or last run of every task
+    pub tasks: BTreeMap<Task, TaskProgress>,
+    pub jobs: BTreeMap<String, JobStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(last_post_page_at: Option<DateTime<Utc>>) -> ScrapeState {
+        let mut state: ScrapeState =
+            serde_json::from_str(r#"{"last_post_id": 0, "last_tag_id": 0, "errors": []}"#).unwrap();
+        state.last_post_page_at = last_post_page_at;
+        state
+    }
+
+    fn run(state: &mut ScrapeState, started_at: DateTime<Utc>, finished_at: Option<DateTime<Utc>>) {
+        let progress = state.tasks.entry(Task::Posts).or_default();
+        progress.run_started_at = Some(started_at);
+        progress.run_finished_at = finished_at;
+    }
+
+    #[test]
+    fn idle_daemon_between_runs() {
+        let now = Utc::now();
+        let stall_after = Duration::minutes(15);
+
+        // The last run of a daily job finished hours ago
+        let mut state = state(Some(now - Duration::hours(20)));
+        run(
+            &mut state,
+            now - Duration::hours(21),
+            Some(now - Duration::hours(20)),
+        );
+        assert!(!stalled(&state, now, stall_after));
+
+        // No run since the start of the daemon
+        assert!(!stalled(&self::state(None), now, stall_after));
+    }
+
+    #[test]
+    fn running_sweep_without_pages() {
+        let now = Utc::now();
+        let stall_after = Duration::minutes(15);
+
+        let mut state = state(Some(now - Duration::hours(20)));
+        run(&mut state, now - Duration::hours(1), None);
+        assert!(stalled(&state, now, stall_after));
+
+        // The pages of the last run don't count against a run which just started
+        run(&mut state, now - Duration::minutes(5), None);
+        assert!(!stalled(&state, now, stall_after));
+
+        state.last_post_page_at = Some(now - Duration::minutes(1));
+        run(&mut state, now - Duration::hours(1), None);
+        assert!(!stalled(&state, now, stall_after));
+    }
+
+    #[tokio::test]
+    async fn a_scraper_waiting_for_the_api_is_live() {
+        let path = std::env::temp_dir().join(format!("indexer-health-{}.json", std::process::id()));
+        let state_manager = StateManager::new(&path).unwrap();
+        let health = Health::new(state_manager.clone())
+            .with_stall_after(std::time::Duration::from_secs(900));
+        assert!(health.is_live().await);
+
+        let now = Utc::now();
+        run(
+            &mut *state_manager.get_state().lock().await,
+            now - Duration::hours(1),
+            None,
+        );
+        assert!(!health.is_live().await);
+
+        state_manager
+            .throttle()
+            .hold_until(now + Duration::minutes(10));
+        assert!(health.is_live().await);
+    }
+}
@@ -0,0 +1,982 @@
+//! HTTP search server backed by an in-memory index
+//!
+//! Every request parses its own query, so the server holds no per-client state:
+//!
+//! - `GET /search?q=cat -dog&limit=20&cursor=...` returns the matching posts in ascending id
+//!   order, and an opaque cursor for the next page
+//! - `GET /export?q=cat -dog` streams every matching post as JSON lines, in batches computed as
+//!   the client reads them, see [`QueryStream`]
+//! - `GET /post/{id}` returns a single indexed post
+//! - `GET /tags/suggest?prefix=ca&limit=10` returns the most used tags starting with the prefix,
+//!   or the corrections of a misspelled prefix if no tag starts with it
+//! - `GET /stats` returns an overview of the indexed dataset
+//! - `GET /thumb/{id}` returns the preview of a post through a disk cache, see [`thumbnail`]
+//! - `POST /admin/reload` loads the index file again
+//! - `GET /feed?q=cat -dog` is a WebSocket streaming newly scraped posts matching the query, if the
+//!   scraper runs in the same process (see [`Feed`])
+//! - `GET /health` reports the progress of that scraper, see [`health`]
+//!
+//! The OpenAPI document of these endpoints is served at `/openapi.json`, with a Swagger UI at
+//! `/docs`.
+//!
+//! If API keys are configured every request but `/health` needs an `Authorization: Bearer <key>`
+//! header, and each key is rate limited on its own.
+//!
+//! Indexes of other sites or projects can be served next to the main one as namespaces, see
+//! [`namespace`](crate::namespace). `/search`, `/export` and `/tags/suggest` pick the namespace
+//! with a prefix of the query (`q=art:cat -dog`, `prefix=art:ca`), `/post/{id}` and `/stats` with
+//! a `namespace` parameter. Thumbnails and the feed belong to the main index.
+//!
+//! A reload builds the new index next to the old one and swaps it in once it is complete, requests
+//! which already started keep using the index they started with.
+
+pub mod health;
+pub mod thumbnail;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query as QueryParams, Request, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota, RateLimiter,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info, warn};
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    IntoParams, Modify, OpenApi, ToSchema,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use self::{
+    health::{Health, HealthStatus},
+    thumbnail::ThumbnailCache,
+};
+use crate::{
+    index::Index,
+    models::{Post, PostSimplified},
+    namespace::{qualified, NamespaceError, Namespaces, DEFAULT_NAMESPACE},
+    query::{Query, QueryError},
+    sink::{Sink, SinkError},
+    stats::DatasetStats,
+    stream::QueryStream,
+};
+
+/// Number of results per page if the request doesn't ask for a limit
+pub const DEFAULT_LIMIT: usize = 20;
+/// Upper bound for the `limit` of a request
+pub const MAX_LIMIT: usize = 1_000;
+
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Invalid query: {0}")]
+    Query(#[from] QueryError),
+    #[error("Post `{0}` not found")]
+    PostNotFound(u32),
+    #[error("Invalid cursor")]
+    InvalidCursor,
+    #[error("The cursor belongs to a different query")]
+    CursorMismatch,
+    #[error("Failed to reload the index: {0}")]
+    Reload(String),
+    #[error("Missing or unknown API key")]
+    Unauthorized,
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+    #[error("The live feed is only available while the scraper runs in the server process")]
+    FeedUnavailable,
+    #[error("The health check is only available while the scraper runs in the server process")]
+    HealthUnavailable,
+    #[error("Thumbnails are not configured, see `[server.thumbnails]`")]
+    ThumbnailsUnavailable,
+    #[error("No preview of post `{0}` is available")]
+    ThumbnailNotFound(u32),
+    #[error("Failed to fetch the preview: {0}")]
+    Thumbnail(String),
+    #[error(transparent)]
+    Namespace(#[from] NamespaceError),
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ServerError::Query(_) | ServerError::InvalidCursor | ServerError::CursorMismatch => {
+                StatusCode::BAD_REQUEST
+            }
+            ServerError::PostNotFound(_)
+            | ServerError::FeedUnavailable
+            | ServerError::HealthUnavailable
+            | ServerError::ThumbnailsUnavailable
+            | ServerError::ThumbnailNotFound(_)
+            | ServerError::Namespace(_) => StatusCode::NOT_FOUND,
+            ServerError::Thumbnail(_) => StatusCode::BAD_GATEWAY,
+            ServerError::Reload(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ServerError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ServerError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        };
+        let body = ErrorResponse {
+            error: self.to_string(),
+        };
+        let mut response = (status, Json(body)).into_response();
+        match self {
+            ServerError::Unauthorized => {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+            }
+            ServerError::RateLimited(seconds) => {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, seconds.into());
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Bearer token authentication with a rate limit per key
+#[derive(Debug, Clone)]
+pub struct Auth {
+    keys: Arc<HashSet<String>>,
+    limiter: Arc<DefaultKeyedRateLimiter<String>>,
+}
+
+impl Auth {
+    pub fn new(
+        keys: impl IntoIterator<Item = String>,
+        requests_per_second: NonZeroU32,
+        burst: NonZeroU32,
+    ) -> Self {
+        let quota = Quota::per_second(requests_per_second).allow_burst(burst);
+        Self {
+            keys: Arc::new(keys.into_iter().collect()),
+            limiter: Arc::new(RateLimiter::keyed(quota)),
+        }
+    }
+
+    /// Check the key of a request and count it against the key's limit
+    pub fn check(&self, authorization: Option<&str>) -> Result<(), ServerError> {
+        let key = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|key| self.keys.contains(*key))
+            .ok_or(ServerError::Unauthorized)?;
+
+        self.limiter
+            .check_key(&key.to_string())
+            .map_err(|not_until| {
+                let wait = not_until.wait_time_from(DefaultClock::default().now());
+                ServerError::RateLimited(wait.as_secs_f64().ceil() as u64)
+            })
+    }
+}
+
+async fn authorize(
+    State(auth): State<Auth>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    auth.check(authorization)?;
+    Ok(next.run(request).await)
+}
+
+/// The indexes of every namespace together with what is derived from them
+#[derive(Debug)]
+struct Snapshot {
+    /// Shared with the [`QueryStream`]s of running exports
+    namespaces: Namespaces,
+    /// By namespace
+    stats: HashMap<String, DatasetStats>,
+}
+
+impl Snapshot {
+    fn new(namespaces: Namespaces) -> Self {
+        let stats = namespaces
+            .iter()
+            .map(|(name, index)| (name.to_string(), DatasetStats::from_index(index, 20)))
+            .collect();
+        Self { namespaces, stats }
+    }
+
+    /// The index of the main namespace
+    fn index(&self) -> &Arc<Index> {
+        self.namespaces.default_index()
+    }
+}
+
+/// Newly scraped posts, published to the WebSocket clients of `/feed`
+///
+/// The feed is a [`Sink`], so it can be tee'd next to the posts file of a scraper running in the
+/// same process. Every client has a buffer of `capacity` posts, a client falling further behind
+/// misses the oldest ones. Writing never blocks, and posts are dropped if nobody listens.
+#[derive(Debug, Clone)]
+pub struct Feed {
+    sender: broadcast::Sender<Arc<Post>>,
+}
+
+impl Feed {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Post>> {
+        self.sender.subscribe()
+    }
+}
+
+impl Sink<Post> for Feed {
+    fn write(&mut self, record: Post) -> Result<(), SinkError> {
+        // Fails only if there are no subscribers
+        let _ = self.sender.send(Arc::new(record));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+/// Shared by every request
+#[derive(Debug, Clone)]
+pub struct AppState {
+    snapshot: Arc<RwLock<Arc<Snapshot>>>,
+    /// Where the index is reloaded from
+    path: Option<PathBuf>,
+    /// Where the indexes of the other namespaces are reloaded from
+    namespace_paths: BTreeMap<String, PathBuf>,
+    feed: Option<Feed>,
+    thumbnails: Option<Arc<ThumbnailCache>>,
+    health: Option<Health>,
+}
+
+impl AppState {
+    pub fn new(index: Index) -> Self {
+        Self {
+            snapshot: Arc::new(RwLock::new(Arc::new(Snapshot::new(Namespaces::new(index))))),
+            path: None,
+            namespace_paths: BTreeMap::new(),
+            feed: None,
+            thumbnails: None,
+            health: None,
+        }
+    }
+
+    /// Serve `/feed` from the posts published to `feed`
+    pub fn with_feed(mut self, feed: Feed) -> Self {
+        self.feed = Some(feed);
+        self
+    }
+
+    /// Serve `/health` from the state of a scraper running in the same process
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// The health of the scraper in the process, if there is one
+    pub fn health(&self) -> Option<&Health> {
+        self.health.as_ref()
+    }
+
+    /// Serve `/thumb/{id}` through `thumbnails`
+    pub fn with_thumbnails(mut self, thumbnails: ThumbnailCache) -> Self {
+        self.thumbnails = Some(Arc::new(thumbnails));
+        self
+    }
+
+    /// Load the index at `path`, which is also used for reloads
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.into();
+        let mut state = Self::new(Index::load(&path)?);
+        state.path = Some(path);
+        Ok(state)
+    }
+
+    /// Also serve the index files of `namespaces` by namespace name, they are reloaded together
+    /// with the main index
+    pub fn with_namespaces(
+        mut self,
+        namespaces: &HashMap<String, PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut loaded = self.snapshot().namespaces.clone();
+        for (name, path) in namespaces {
+            loaded = loaded.with_namespace(name, Index::load(path)?)?;
+            self.namespace_paths.insert(name.clone(), path.clone());
+        }
+        self.snapshot = Arc::new(RwLock::new(Arc::new(Snapshot::new(loaded))));
+        Ok(self)
+    }
+
+    /// The current index, which stays usable even if it is replaced in the meantime
+    fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Load the index files of all namespaces again and swap them in once they are ready
+    pub async fn reload(&self) -> Result<ReloadResponse, ServerError> {
+        let path = self
+            .path
+            .clone()
+            .ok_or_else(|| ServerError::Reload(String::from("the index has no file")))?;
+        let namespace_paths = self.namespace_paths.clone();
+
+        let start = Instant::now();
+        let snapshot = tokio::task::spawn_blocking(move || {
+            let index = Index::load(&path).map_err(|e| e.to_string())?;
+            let mut namespaces = Namespaces::new(index);
+            for (name, path) in &namespace_paths {
+                let index = Index::load(path).map_err(|e| format!("namespace `{name}`: {e}"))?;
+                namespaces = namespaces
+                    .with_namespace(name, index)
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(Snapshot::new(namespaces))
+        })
+        .await
+        .map_err(|e| ServerError::Reload(e.to_string()))?
+        .map_err(ServerError::Reload)?;
+
+        let response = ReloadResponse {
+            posts: snapshot.index().post_id_to_post.len(),
+            tags: snapshot.index().tag_str_to_id.len(),
+            namespaces: snapshot.namespaces.iter().count() - 1,
+            duration_ms: start.elapsed().as_millis(),
+        };
+        *self
+            .snapshot
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(snapshot);
+        info!(
+            "Reloaded the index with {} posts in {}ms",
+            response.posts, response.duration_ms
+        );
+        Ok(response)
+    }
+
+    /// Reload the indexes whenever one of their files is modified, checking every `interval`
+    ///
+    /// [`Index::save`] replaces the file only once it is complete, so a changed modification time
+    /// always means a readable index.
+    pub fn watch(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let Some(path) = state.path.clone() else {
+                return;
+            };
+            let paths: Vec<PathBuf> = std::iter::once(path)
+                .chain(state.namespace_paths.values().cloned())
+                .collect();
+            let modified = || -> Vec<Option<SystemTime>> {
+                paths
+                    .iter()
+                    .map(|path| {
+                        std::fs::metadata(path)
+                            .and_then(|meta| meta.modified())
+                            .ok()
+                    })
+                    .collect()
+            };
+
+            let mut last_modified = modified();
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let current = modified();
+                if current.iter().any(Option::is_none) || current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                if let Err(e) = state.reload().await {
+                    error!("{}", e);
+                }
+            }
+        })
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "indexer", description = "Search posts by tags"),
+    paths(search, export, get_post, get_thumbnail, suggest_tags, stats, reload),
+    modifiers(&BearerAuth),
+    security(("bearer" = []))
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token scheme, which is only enforced if API keys are configured
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// The routes of the server, every one of them behind `auth` if given
+///
+/// The OpenAPI document, the Swagger UI and the health check are always public.
+pub fn router(state: AppState, auth: Option<Auth>) -> Router {
+    let public = Router::new()
+        .route("/health", get(health))
+        .with_state(state.clone());
+    let router = Router::new()
+        .route("/search", get(search))
+        .route("/export", get(export))
+        .route("/post/{id}", get(get_post))
+        .route("/thumb/{id}", get(get_thumbnail))
+        .route("/tags/suggest", get(suggest_tags))
+        .route("/stats", get(stats))
+        .route("/admin/reload", post(reload))
+        .route("/feed", get(feed))
+        .with_state(state);
+
+    let router = match auth {
+        Some(auth) => router.layer(middleware::from_fn_with_state(auth, authorize)),
+        None => router,
+    };
+    router
+        .merge(public)
+        .merge(SwaggerUi::new("/docs").url("/openapi.json", ApiDoc::openapi()))
+}
+
+/// An indexed post as returned by the server
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PostResponse {
+    pub id: u32,
+    pub md5: String,
+    pub extension: String,
+    pub created_at: DateTime<Utc>,
+    /// Only set if the index keeps the field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<u64>,
+}
+
+impl From<&PostSimplified> for PostResponse {
+    fn from(post: &PostSimplified) -> Self {
+        let details = post.details.as_deref().cloned().unwrap_or_default();
+        Self {
+            id: post.id,
+            md5: hex::encode(post.md5),
+            extension: post.extension.as_str().to_string(),
+            created_at: post.created_at,
+            score: details.score,
+            rating: details.rating.map(|rating| rating.as_str().to_string()),
+            width: details.width,
+            height: details.height,
+            parent_id: details.parent_id,
+        }
+    }
+}
+
+/// Position in the results of a query, handed to clients as an opaque string
+///
+/// A cursor holds the last returned post id, so the next page starts right after it without
+/// skipping over the earlier results, and stays correct when posts are added to the index in the
+/// meantime. It also holds a hash of the query and its namespace, so it can't be used with a
+/// different one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub last_id: u32,
+    pub query_hash: u64,
+}
+
+impl Cursor {
+    pub fn new(namespace: &str, query: &Query, last_id: u32) -> Self {
+        Self {
+            last_id,
+            query_hash: query_hash(namespace, query),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = [0u8; 12];
+        bytes[..4].copy_from_slice(&self.last_id.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.query_hash.to_be_bytes());
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ServerError> {
+        let bytes: [u8; 12] = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(ServerError::InvalidCursor)?;
+        Ok(Self {
+            last_id: u32::from_be_bytes(bytes[..4].try_into().unwrap()),
+            query_hash: u64::from_be_bytes(bytes[4..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Hash of the normalized query, stable across processes and releases
+///
+/// Queries of the main namespace hash like they did before there were namespaces, so their
+/// cursors stay valid.
+fn query_hash(namespace: &str, query: &Query) -> u64 {
+    let digest = Sha256::digest(qualified(namespace, &query.to_string()).as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SearchParams {
+    /// The query, e.g. `cat -dog ~red ~blue rating:safe`, or `art:cat` in the namespace `art`
+    pub q: String,
+    /// Results per page, at most 1000
+    pub limit: Option<usize>,
+    /// The `next_cursor` of the previous page
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResponse {
+    /// The normalized query, with the prefix of its namespace
+    pub query: String,
+    /// Number of matching posts across all pages
+    pub count: u64,
+    pub results: Vec<PostResponse>,
+    /// Pass as `cursor` to get the next page, `null` on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(SearchParams),
+    responses(
+        (status = 200, body = SearchResponse),
+        (status = 400, description = "Invalid query or cursor", body = ErrorResponse),
+    )
+)]
+#[tracing::instrument(skip_all, fields(q = %params.q, results))]
+async fn search(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<SearchParams>,
+) -> Result<Json<SearchResponse>, ServerError> {
+    let snapshot = state.snapshot();
+    let (namespace, index, q) = snapshot.namespaces.resolve(&params.q);
+    let query = Query::parse(q)?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let post_ids = index.search(&query);
+    let count = post_ids.len();
+    tracing::Span::current().record("results", count);
+    let after = match &params.cursor {
+        Some(cursor) => {
+            let cursor = Cursor::decode(cursor)?;
+            if cursor.query_hash != query_hash(namespace, &query) {
+                return Err(ServerError::CursorMismatch);
+            }
+            Some(cursor.last_id)
+        }
+        None => None,
+    };
+
+    let mut ordered = index.ordered(&query, &post_ids, after).peekable();
+    let results: Vec<PostResponse> = ordered
+        .by_ref()
+        .take(limit)
+        .filter_map(|id| index.post_id_to_post.get(&id))
+        .map(PostResponse::from)
+        .collect();
+    let next_cursor = match results.last() {
+        Some(last) if ordered.peek().is_some() => {
+            Some(Cursor::new(namespace, &query, last.id).encode())
+        }
+        _ => None,
+    };
+
+    Ok(Json(SearchResponse {
+        query: qualified(namespace, &query.to_string()),
+        count,
+        results,
+        next_cursor,
+    }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ExportParams {
+    /// The query, e.g. `cat -dog rating:safe`, or `art:cat` in the namespace `art`
+    pub q: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct NamespaceParams {
+    /// The namespace of the post, the main index if missing
+    pub namespace: Option<String>,
+}
+
+impl NamespaceParams {
+    /// The index of the namespace
+    fn index<'a>(&self, snapshot: &'a Snapshot) -> Result<&'a Arc<Index>, ServerError> {
+        let name = self.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+        Ok(snapshot.namespaces.get(name)?)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/export",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "Every matching post as a `PostResponse` per line", content_type = "application/x-ndjson"),
+        (status = 400, description = "Invalid query", body = ErrorResponse),
+    )
+)]
+#[tracing::instrument(skip_all, fields(q = %params.q, results))]
+async fn export(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<ExportParams>,
+) -> Result<Response, ServerError> {
+    let snapshot = state.snapshot();
+    let (_, index, q) = snapshot.namespaces.resolve(&params.q);
+    let query = Query::parse(q)?;
+    let stream = QueryStream::search(index.clone(), query);
+    tracing::Span::current().record("results", stream.len());
+
+    // The body pulls a batch whenever the connection has room for more, a slow client slows down
+    // the export instead of having the results pile up in memory
+    let body = stream.map(|batch| {
+        let mut lines = Vec::new();
+        for post in batch.posts() {
+            serde_json::to_writer(&mut lines, &PostResponse::from(post))?;
+            lines.push(b'\n');
+        }
+        Ok::<_, serde_json::Error>(Bytes::from(lines))
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/post/{id}",
+    params(("id" = u32, Path, description = "Post id"), NamespaceParams),
+    responses(
+        (status = 200, body = PostResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn get_post(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    QueryParams(params): QueryParams<NamespaceParams>,
+) -> Result<Json<PostResponse>, ServerError> {
+    params
+        .index(&state.snapshot())?
+        .post_id_to_post
+        .get(&id)
+        .map(|post| Json(PostResponse::from(post)))
+        .ok_or(ServerError::PostNotFound(id))
+}
+
+/// The preview image of a post
+///
+/// Previews are identified by the md5 of the post, so they can be cached for long and are
+/// revalidated with `If-None-Match`.
+#[utoipa::path(
+    get,
+    path = "/thumb/{id}",
+    params(("id" = u32, Path, description = "Post id")),
+    responses(
+        (status = 200, description = "The preview", content_type = "image/jpeg"),
+        (status = 304, description = "The cached preview is still valid"),
+        (status = 404, body = ErrorResponse),
+        (status = 502, description = "The site failed to return the preview", body = ErrorResponse),
+    )
+)]
+async fn get_thumbnail(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+    headers: HeaderMap,
+) -> Result<Response, ServerError> {
+    let thumbnails = state
+        .thumbnails
+        .clone()
+        .ok_or(ServerError::ThumbnailsUnavailable)?;
+    let snapshot = state.snapshot();
+    let post = snapshot
+        .index()
+        .post_id_to_post
+        .get(&id)
+        .ok_or(ServerError::PostNotFound(id))?;
+
+    let etag = format!("\"{}\"", hex::encode(post.md5));
+    let cache_control = format!("public, max-age={}, immutable", thumbnails.max_age);
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes())
+    {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response());
+    }
+
+    let thumbnail = thumbnails.get(post).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, thumbnail.content_type().to_string()),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        thumbnail.bytes,
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SuggestParams {
+    /// Start of a tag name, `art:ca` suggests the tags of the namespace `art`
+    #[serde(default)]
+    pub prefix: String,
+    /// Number of tags, at most 1000
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TagSuggestion {
+    pub name: String,
+    pub count: u32,
+    /// Edit distance to the prefix if no tag starts with it, see [`Index::did_you_mean`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u32>,
+}
+
+/// The most used tags starting with the prefix, most used first
+///
+/// If no tag starts with the prefix it is taken as a misspelled tag, and the tags closest to it
+/// are returned instead, closest first.
+#[utoipa::path(
+    get,
+    path = "/tags/suggest",
+    params(SuggestParams),
+    responses((status = 200, body = Vec<TagSuggestion>))
+)]
+#[tracing::instrument(skip_all, fields(prefix = %params.prefix))]
+async fn suggest_tags(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<SuggestParams>,
+) -> Json<Vec<TagSuggestion>> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let snapshot = state.snapshot();
+    let (_, index, prefix) = snapshot.namespaces.resolve(&params.prefix);
+    let prefix = prefix.to_lowercase();
+    let mut suggestions: Vec<TagSuggestion> = index
+        .suggest_tags(&prefix, limit)
+        .into_iter()
+        .map(|(name, count)| TagSuggestion {
+            name: name.to_string(),
+            count,
+            distance: None,
+        })
+        .collect();
+    if suggestions.is_empty() && !prefix.is_empty() {
+        suggestions = index
+            .did_you_mean(&prefix)
+            .into_iter()
+            .take(limit)
+            .map(|correction| TagSuggestion {
+                name: correction.name,
+                count: correction.count,
+                distance: Some(correction.distance),
+            })
+            .collect();
+    }
+    Json(suggestions)
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    params(NamespaceParams),
+    responses(
+        (status = 200, body = DatasetStats),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn stats(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<NamespaceParams>,
+) -> Result<Json<DatasetStats>, ServerError> {
+    let name = params.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE);
+    state
+        .snapshot()
+        .stats
+        .get(name)
+        .map(|stats| Json(stats.clone()))
+        .ok_or_else(|| NamespaceError::Unknown(name.to_string()).into())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReloadResponse {
+    /// Of the main index
+    pub posts: usize,
+    pub tags: usize,
+    /// Other namespaces reloaded with it
+    pub namespaces: usize,
+    pub duration_ms: u128,
+}
+
+/// Load the index files again and swap them in
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    responses(
+        (status = 200, body = ReloadResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+async fn reload(State(state): State<AppState>) -> Result<Json<ReloadResponse>, ServerError> {
+    state.reload().await.map(Json)
+}
+
+/// The health of the scraper, a 503 if it has stalled
+async fn health(State(state): State<AppState>) -> Result<Response, ServerError> {
+    let health = state
+        .health
+        .as_ref()
+        .ok_or(ServerError::HealthUnavailable)?;
+    let report = health.report().await;
+    let status = match report.status {
+        HealthStatus::Ok => StatusCode::OK,
+        HealthStatus::Stalled => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    Ok((status, Json(report)).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedParams {
+    /// Only posts matching this query are sent, every post if missing
+    pub q: Option<String>,
+}
+
+/// Stream newly scraped posts as JSON text messages
+///
+/// The subscription can be changed by sending a new query as a text message, an empty one
+/// subscribes to every post. An invalid query is answered with an [`ErrorResponse`] and the
+/// previous subscription stays in place.
+async fn feed(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<FeedParams>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ServerError> {
+    let feed = state.feed.clone().ok_or(ServerError::FeedUnavailable)?;
+    let query = params
+        .q
+        .as_deref()
+        .filter(|q| !q.trim().is_empty())
+        .map(Query::parse)
+        .transpose()?;
+    let posts = feed.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| stream_feed(socket, state, posts, query)))
+}
+
+async fn stream_feed(
+    mut socket: WebSocket,
+    state: AppState,
+    mut posts: broadcast::Receiver<Arc<Post>>,
+    mut query: Option<Query>,
+) {
+    loop {
+        tokio::select! {
+            post = posts.recv() => {
+                let post = match post {
+                    Ok(post) => post,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("A feed client fell behind and missed {} posts", missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if let Some(query) = &query {
+                    let snapshot = state.snapshot();
+                    if !query.matches(&post, |tag| snapshot.index().tag_type(tag)) {
+                        continue;
+                    }
+                }
+                let json = serde_json::to_string(&*post).expect("Failed to serialize a post");
+                if socket.send(Message::text(json)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break,
+                };
+                if text.trim().is_empty() {
+                    query = None;
+                    continue;
+                }
+                match Query::parse(&text) {
+                    Ok(parsed) => query = Some(parsed),
+                    Err(e) => {
+                        let error = ErrorResponse {
+                            error: ServerError::from(e).to_string(),
+                        };
+                        let json = serde_json::to_string(&error).expect("Failed to serialize an error");
+                        if socket.send(Message::text(json)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    debug!("A feed client disconnected");
+}
@@ -0,0 +1,207 @@
+//! Proxy and disk cache for the previews of posts
+//!
+//! A downloaded file of the post is served if there is one, otherwise the preview is fetched from
+//! the site once and kept in the cache directory. Files are named by md5 like the downloads, so a
+//! cached preview never goes stale and only has to be removed to keep the cache within its size.
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use super::ServerError;
+use crate::{
+    config::ThumbnailConfig,
+    models::{PostSimplified, UrlTemplates, Variant},
+};
+
+/// A preview ready to be sent
+#[derive(Debug)]
+pub struct Thumbnail {
+    pub bytes: Vec<u8>,
+    pub extension: String,
+}
+
+impl Thumbnail {
+    pub fn content_type(&self) -> &'static str {
+        match self.extension.as_str() {
+            "jpg" | "jpeg" => "image/jpeg",
+            "png" => "image/png",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ThumbnailCache {
+    client: reqwest::Client,
+    urls: UrlTemplates,
+    downloads: Option<PathBuf>,
+    dir: PathBuf,
+    max_bytes: u64,
+    /// How long clients may cache a preview, in seconds
+    pub max_age: u64,
+    /// Bytes in `dir`, the lock is also held while evicting
+    size: Mutex<u64>,
+}
+
+impl ThumbnailCache {
+    /// Open the cache, creating its directory if needed
+    ///
+    /// Previews are fetched from `config.url` if it is set, otherwise from `urls.preview`.
+    pub fn new(
+        config: &ThumbnailConfig,
+        urls: &UrlTemplates,
+        client: reqwest::Client,
+    ) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&config.cache_dir)?;
+        let size = cached_files(&config.cache_dir)?
+            .iter()
+            .map(|(_, len, _)| len)
+            .sum();
+        Ok(Self {
+            client,
+            urls: UrlTemplates {
+                preview: config.url.clone().unwrap_or_else(|| urls.preview.clone()),
+                ..urls.clone()
+            },
+            downloads: config.downloads.clone(),
+            dir: config.cache_dir.clone(),
+            max_bytes: config.max_cache_bytes,
+            max_age: config.max_age,
+            size: Mutex::new(size),
+        })
+    }
+
+    /// Upstream URL of the preview of `post`
+    pub fn url(&self, post: &PostSimplified) -> String {
+        post.file_url(Variant::Preview, &self.urls)
+    }
+
+    /// The preview of `post`, from the downloads, the cache or the site in this order
+    pub async fn get(&self, post: &PostSimplified) -> Result<Thumbnail, ServerError> {
+        let md5 = hex::encode(post.md5);
+        if let Some(downloads) = &self.downloads {
+            // Previews and samples are stored as jpg, originals with their own extension
+            for extension in ["jpg", post.extension.as_str()] {
+                if let Ok(bytes) =
+                    tokio::fs::read(downloads.join(format!("{md5}.{extension}"))).await
+                {
+                    return Ok(Thumbnail {
+                        bytes,
+                        extension: extension.to_string(),
+                    });
+                }
+            }
+        }
+
+        let url = self.url(post);
+        let extension = url
+            .rsplit_once('.')
+            .map(|(_, extension)| extension)
+            .filter(|extension| !extension.contains('/'))
+            .unwrap_or("jpg")
+            .to_string();
+        let path = self.dir.join(format!("{md5}.{extension}"));
+        if let Ok(bytes) = tokio::fs::read(&path).await {
+            // The modification time orders the files for eviction
+            let _ = tokio::task::spawn_blocking(move || touch(&path)).await;
+            return Ok(Thumbnail { bytes, extension });
+        }
+
+        let bytes = self.fetch(post.id, &url).await?;
+        self.store(&path, &bytes).await?;
+        Ok(Thumbnail { bytes, extension })
+    }
+
+    /// Fetch a preview, a 404 from the site is passed on since the template may not fit the post
+    async fn fetch(&self, post_id: u32, url: &str) -> Result<Vec<u8>, ServerError> {
+        debug!("Fetching {}", url);
+        let fetch = async {
+            self.client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await
+        };
+        fetch
+            .await
+            .map(|bytes| bytes.to_vec())
+            .map_err(|e: reqwest::Error| match e.status() {
+                Some(reqwest::StatusCode::NOT_FOUND) => ServerError::ThumbnailNotFound(post_id),
+                _ => ServerError::Thumbnail(e.to_string()),
+            })
+    }
+
+    /// Write a preview to the cache, then remove the oldest ones if it grew too large
+    async fn store(&self, path: &Path, bytes: &[u8]) -> Result<(), ServerError> {
+        let io_error = |e: std::io::Error| ServerError::Thumbnail(e.to_string());
+        let mut part = path.as_os_str().to_owned();
+        part.push(".part");
+        tokio::fs::write(&part, bytes).await.map_err(io_error)?;
+        tokio::fs::rename(&part, path).await.map_err(io_error)?;
+
+        let mut size = self.size.lock().await;
+        *size += bytes.len() as u64;
+        if *size > self.max_bytes {
+            let dir = self.dir.clone();
+            let max_bytes = self.max_bytes;
+            *size = tokio::task::spawn_blocking(move || evict(&dir, max_bytes))
+                .await
+                .map_err(|e| ServerError::Thumbnail(e.to_string()))?
+                .map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Path, size and modification time of every cached preview, skipping the ones being written
+fn cached_files(dir: &Path) -> std::io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file()
+            && entry
+                .path()
+                .extension()
+                .is_none_or(|extension| extension != "part")
+        {
+            files.push((entry.path(), meta.len(), meta.modified()?));
+        }
+    }
+    Ok(files)
+}
+
+/// Remove the least recently used previews until at most `max_bytes` are left, returns the size
+/// of the remaining ones
+fn evict(dir: &Path, max_bytes: u64) -> std::io::Result<u64> {
+    let mut files = cached_files(dir)?;
+    files.sort_unstable_by_key(|(_, _, modified)| *modified);
+    let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+    let mut removed = 0;
+    for (path, len, _) in files {
+        if size <= max_bytes {
+            break;
+        }
+        std::fs::remove_file(path)?;
+        size -= len;
+        removed += 1;
+    }
+    info!("Removed {} previews from the thumbnail cache", removed);
+    Ok(size)
+}
+
+fn touch(path: &Path) -> std::io::Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
@@ -0,0 +1,81 @@
+//! Contact sheets of post previews, see [`compose`]
+//!
+//! A sheet shows a random sample of the posts of a tag or query as a grid of thumbnails, which is
+//! the quickest way to see what a tag actually contains. The previews themselves are downloaded
+//! by the caller, e.g. with the `Downloader` of the `scraper` feature.
+
+use std::path::PathBuf;
+
+use image::{imageops::FilterType, ImageError, ImageReader, Rgb, RgbImage};
+use roaring::RoaringBitmap;
+
+use crate::export::dataset::unit;
+
+/// Background of the sheet and of the margins of the cells
+const BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+/// Space between the cells, in pixels
+const GAP: u32 = 4;
+
+/// Up to `count` of the posts in `post_ids`, picked by a hash of their id and the seed
+///
+/// The same seed always picks the same posts, and a post picked from a smaller result is picked
+/// again once the result grows, unless it is pushed out by one with a lower hash.
+pub fn sample(post_ids: &RoaringBitmap, count: usize, seed: u64) -> Vec<u32> {
+    let mut ids: Vec<(f64, u32)> = post_ids.iter().map(|id| (unit(seed, id), id)).collect();
+    if ids.len() > count {
+        ids.select_nth_unstable_by(count, |a, b| a.0.total_cmp(&b.0));
+        ids.truncate(count);
+    }
+    ids.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+    ids.into_iter().map(|(_, id)| id).collect()
+}
+
+/// A composed sheet
+pub struct Sheet {
+    pub image: RgbImage,
+    /// Posts whose preview was placed, in grid order
+    pub placed: Vec<u32>,
+    /// Posts whose preview couldn't be decoded
+    pub skipped: Vec<u32>,
+}
+
+/// Place the previews at `files` (post id and path) in a grid of `columns` square cells of `cell`
+/// pixels, each scaled to fit its cell
+pub fn compose(files: &[(u32, PathBuf)], columns: u32, cell: u32) -> Sheet {
+    let columns = columns.max(1);
+    let mut thumbnails = Vec::new();
+    let mut skipped = Vec::new();
+    for (post_id, path) in files {
+        let image = ImageReader::open(path)
+            .and_then(|reader| reader.with_guessed_format())
+            .map_err(ImageError::from)
+            .and_then(|reader| reader.decode());
+        match image {
+            Ok(image) => {
+                thumbnails.push((*post_id, image.resize(cell, cell, FilterType::Triangle)))
+            }
+            Err(_) => skipped.push(*post_id),
+        }
+    }
+
+    let rows = (thumbnails.len() as u32).div_ceil(columns).max(1);
+    let columns_used = columns.min(thumbnails.len().max(1) as u32);
+    let mut sheet = RgbImage::from_pixel(
+        columns_used * (cell + GAP) + GAP,
+        rows * (cell + GAP) + GAP,
+        BACKGROUND,
+    );
+    for (position, (_, thumbnail)) in thumbnails.iter().enumerate() {
+        let (column, row) = (position as u32 % columns, position as u32 / columns);
+        // Centered in its cell
+        let x = GAP + column * (cell + GAP) + (cell - thumbnail.width()) / 2;
+        let y = GAP + row * (cell + GAP) + (cell - thumbnail.height()) / 2;
+        image::imageops::overlay(&mut sheet, &thumbnail.to_rgb8(), x as i64, y as i64);
+    }
+
+    Sheet {
+        image: sheet,
+        placed: thumbnails.into_iter().map(|(id, _)| id).collect(),
+        skipped,
+    }
+}
@@ -0,0 +1,235 @@
+//! Conversion of scraped records into Arrow record batches
+
+use std::sync::{Arc, LazyLock};
+
+use arrow_array::{
+    builder::{ListBuilder, StringBuilder},
+    ArrayRef, BooleanArray, FixedSizeBinaryArray, Int32Array, RecordBatch, StringArray,
+    TimestampMillisecondArray, UInt32Array, UInt64Array,
+};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+
+use crate::models::{Post, PostSimplified, Tag};
+
+/// A record type with a fixed Arrow schema
+pub trait ArrowRecord: Sized {
+    fn schema() -> SchemaRef;
+
+    fn to_record_batch(records: &[Self]) -> Result<RecordBatch, ArrowError>;
+}
+
+static POST_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new(
+            "created_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("score", DataType::Int32, false),
+        Field::new("md5", DataType::Utf8, false),
+        Field::new("directory", DataType::Utf8, false),
+        Field::new("image", DataType::Utf8, false),
+        Field::new("rating", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, true),
+        Field::new("change", DataType::UInt64, false),
+        Field::new("owner", DataType::Utf8, false),
+        Field::new("creator_id", DataType::UInt64, false),
+        Field::new("parent_id", DataType::UInt64, true),
+        Field::new("sample_url", DataType::Utf8, true),
+        Field::new("sample_width", DataType::UInt32, true),
+        Field::new("sample_height", DataType::UInt32, true),
+        Field::new("preview_url", DataType::Utf8, false),
+        Field::new("preview_width", DataType::UInt32, false),
+        Field::new("preview_height", DataType::UInt32, false),
+        Field::new("file_url", DataType::Utf8, false),
+        Field::new("width", DataType::UInt32, false),
+        Field::new("height", DataType::UInt32, false),
+        Field::new(
+            "tags",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+        Field::new("title", DataType::Utf8, true),
+        Field::new("has_notes", DataType::Boolean, false),
+        Field::new("has_comments", DataType::Boolean, false),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("post_locked", DataType::Boolean, false),
+        Field::new("has_children", DataType::Boolean, false),
+    ]))
+});
+
+static TAG_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("count", DataType::UInt64, false),
+        Field::new("tag_type", DataType::Utf8, false),
+        Field::new("ambiguous", DataType::Boolean, false),
+    ]))
+});
+
+static POST_SIMPLIFIED_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::UInt32, false),
+        Field::new("md5", DataType::FixedSizeBinary(16), false),
+        Field::new("extension", DataType::Utf8, false),
+        Field::new(
+            "created_at",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+    ]))
+});
+
+impl ArrowRecord for Post {
+    fn schema() -> SchemaRef {
+        POST_SCHEMA.clone()
+    }
+
+    fn to_record_batch(posts: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let mut tags = ListBuilder::new(StringBuilder::new());
+        for post in posts {
+            for tag in &post.tags {
+                tags.values().append_value(tag);
+            }
+            tags.append(true);
+        }
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from_iter_values(posts.iter().map(|p| p.id))),
+            Arc::new(
+                TimestampMillisecondArray::from_iter_values(
+                    posts.iter().map(|p| p.created_at.timestamp_millis()),
+                )
+                .with_timezone("UTC"),
+            ),
+            Arc::new(Int32Array::from_iter_values(posts.iter().map(|p| p.score))),
+            Arc::new(StringArray::from_iter_values(posts.iter().map(|p| &p.md5))),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.directory),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.image),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| p.rating.as_str()),
+            )),
+            Arc::new(StringArray::from_iter(
+                posts.iter().map(|p| p.source.as_deref()),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                posts.iter().map(|p| p.change),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.owner),
+            )),
+            Arc::new(UInt64Array::from_iter_values(
+                posts.iter().map(|p| p.creator_id),
+            )),
+            Arc::new(UInt64Array::from_iter(posts.iter().map(|p| p.parent_id))),
+            Arc::new(StringArray::from_iter(
+                posts
+                    .iter()
+                    .map(|p| p.sample.as_ref().map(|s| s.url.as_str())),
+            )),
+            Arc::new(UInt32Array::from_iter(
+                posts.iter().map(|p| p.sample.as_ref().map(|s| s.width)),
+            )),
+            Arc::new(UInt32Array::from_iter(
+                posts.iter().map(|p| p.sample.as_ref().map(|s| s.height)),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.preview.url),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                posts.iter().map(|p| p.preview.width),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                posts.iter().map(|p| p.preview.height),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.original.url),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                posts.iter().map(|p| p.original.width),
+            )),
+            Arc::new(UInt32Array::from_iter_values(
+                posts.iter().map(|p| p.original.height),
+            )),
+            Arc::new(tags.finish()),
+            Arc::new(StringArray::from_iter(
+                posts.iter().map(|p| p.title.as_deref()),
+            )),
+            Arc::new(BooleanArray::from_iter(
+                posts.iter().map(|p| Some(p.has_notes)),
+            )),
+            Arc::new(BooleanArray::from_iter(
+                posts.iter().map(|p| Some(p.has_comments)),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| &p.status),
+            )),
+            Arc::new(BooleanArray::from_iter(
+                posts.iter().map(|p| Some(p.post_locked)),
+            )),
+            Arc::new(BooleanArray::from_iter(
+                posts.iter().map(|p| Some(p.has_children)),
+            )),
+        ];
+
+        RecordBatch::try_new(Self::schema(), columns)
+    }
+}
+
+impl ArrowRecord for Tag {
+    fn schema() -> SchemaRef {
+        TAG_SCHEMA.clone()
+    }
+
+    fn to_record_batch(tags: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from_iter_values(tags.iter().map(|t| t.id))),
+            Arc::new(StringArray::from_iter_values(tags.iter().map(|t| &t.name))),
+            Arc::new(UInt64Array::from_iter_values(tags.iter().map(|t| t.count))),
+            Arc::new(StringArray::from_iter_values(
+                tags.iter().map(|t| t.tag_type.as_str()),
+            )),
+            Arc::new(BooleanArray::from_iter(
+                tags.iter().map(|t| Some(t.ambiguous)),
+            )),
+        ];
+
+        RecordBatch::try_new(Self::schema(), columns)
+    }
+}
+
+impl ArrowRecord for PostSimplified {
+    fn schema() -> SchemaRef {
+        POST_SIMPLIFIED_SCHEMA.clone()
+    }
+
+    fn to_record_batch(posts: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let md5 = if posts.is_empty() {
+            FixedSizeBinaryArray::new_null(16, 0)
+        } else {
+            FixedSizeBinaryArray::try_from_iter(posts.iter().map(|p| p.md5))?
+        };
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt32Array::from_iter_values(posts.iter().map(|p| p.id))),
+            Arc::new(md5),
+            Arc::new(StringArray::from_iter_values(
+                posts.iter().map(|p| p.extension.as_str()),
+            )),
+            Arc::new(
+                TimestampMillisecondArray::from_iter_values(
+                    posts.iter().map(|p| p.created_at.timestamp_millis()),
+                )
+                .with_timezone("UTC"),
+            ),
+        ];
+
+        RecordBatch::try_new(Self::schema(), columns)
+    }
+}
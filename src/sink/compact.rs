@@ -0,0 +1,127 @@
+//! Compact binary output formats
+//!
+//! Every record is written as a little-endian `u32` byte length followed by the encoded record, so
+//! files can be read back record by record with [`LengthPrefixedReader`].
+
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Sink, SinkError};
+
+/// A binary encoding for length-prefixed records
+pub trait Encoding {
+    fn encode<T: Serialize>(record: &T, buffer: &mut Vec<u8>) -> Result<(), SinkError>;
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SinkError>;
+}
+
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Encoding for MessagePack {
+    fn encode<T: Serialize>(record: &T, buffer: &mut Vec<u8>) -> Result<(), SinkError> {
+        rmp_serde::encode::write_named(buffer, record).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SinkError> {
+        Ok(rmp_serde::from_slice(bytes).map_err(io::Error::other)?)
+    }
+}
+
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Encoding for Cbor {
+    fn encode<T: Serialize>(record: &T, buffer: &mut Vec<u8>) -> Result<(), SinkError> {
+        ciborium::into_writer(record, buffer).map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, SinkError> {
+        Ok(ciborium::from_reader(bytes).map_err(io::Error::other)?)
+    }
+}
+
+/// Writes every record as a length-prefixed frame in the encoding `E`
+pub struct LengthPrefixedSink<E: Encoding, W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    encoding: PhantomData<E>,
+}
+
+impl<E: Encoding, W: Write> LengthPrefixedSink<E, W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::new(),
+            encoding: PhantomData,
+        }
+    }
+}
+
+impl<T: Serialize, E: Encoding, W: Write> Sink<T> for LengthPrefixedSink<E, W> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.buffer.clear();
+        E::encode(&record, &mut self.buffer)?;
+        let length = u32::try_from(self.buffer.len()).map_err(io::Error::other)?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "msgpack")]
+pub type MessagePackSink<W> = LengthPrefixedSink<MessagePack, W>;
+
+#[cfg(feature = "cbor")]
+pub type CborSink<W> = LengthPrefixedSink<Cbor, W>;
+
+/// Reads records written by a [`LengthPrefixedSink`]
+pub struct LengthPrefixedReader<E: Encoding, T, R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    marker: PhantomData<(E, T)>,
+}
+
+impl<E: Encoding, T: DeserializeOwned, R: Read> LengthPrefixedReader<E, T, R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    fn read_frame(&mut self) -> Result<Option<T>, SinkError> {
+        let mut length = [0u8; 4];
+        match self.reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        self.buffer.resize(u32::from_le_bytes(length) as usize, 0);
+        self.reader.read_exact(&mut self.buffer)?;
+        E::decode(&self.buffer).map(Some)
+    }
+}
+
+impl<E: Encoding, T: DeserializeOwned, R: Read> Iterator for LengthPrefixedReader<E, T, R> {
+    type Item = Result<T, SinkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
@@ -0,0 +1,239 @@
+use std::io::Write;
+
+use crate::models::{Post, Tag};
+
+use super::{Sink, SinkError};
+
+/// A column that can be included in the posts CSV
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostColumn {
+    Id,
+    CreatedAt,
+    Score,
+    Md5,
+    Directory,
+    Image,
+    Rating,
+    Source,
+    Change,
+    Owner,
+    CreatorId,
+    ParentId,
+    FileUrl,
+    Width,
+    Height,
+    PreviewUrl,
+    SampleUrl,
+    Title,
+    Status,
+    Tags,
+}
+
+impl PostColumn {
+    /// Every column, in the order used when no subset is configured
+    pub const ALL: &'static [PostColumn] = &[
+        PostColumn::Id,
+        PostColumn::CreatedAt,
+        PostColumn::Score,
+        PostColumn::Md5,
+        PostColumn::Directory,
+        PostColumn::Image,
+        PostColumn::Rating,
+        PostColumn::Source,
+        PostColumn::Change,
+        PostColumn::Owner,
+        PostColumn::CreatorId,
+        PostColumn::ParentId,
+        PostColumn::FileUrl,
+        PostColumn::Width,
+        PostColumn::Height,
+        PostColumn::PreviewUrl,
+        PostColumn::SampleUrl,
+        PostColumn::Title,
+        PostColumn::Status,
+        PostColumn::Tags,
+    ];
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            PostColumn::Id => "id",
+            PostColumn::CreatedAt => "created_at",
+            PostColumn::Score => "score",
+            PostColumn::Md5 => "md5",
+            PostColumn::Directory => "directory",
+            PostColumn::Image => "image",
+            PostColumn::Rating => "rating",
+            PostColumn::Source => "source",
+            PostColumn::Change => "change",
+            PostColumn::Owner => "owner",
+            PostColumn::CreatorId => "creator_id",
+            PostColumn::ParentId => "parent_id",
+            PostColumn::FileUrl => "file_url",
+            PostColumn::Width => "width",
+            PostColumn::Height => "height",
+            PostColumn::PreviewUrl => "preview_url",
+            PostColumn::SampleUrl => "sample_url",
+            PostColumn::Title => "title",
+            PostColumn::Status => "status",
+            PostColumn::Tags => "tags",
+        }
+    }
+
+    fn value(&self, post: &Post, tag_delimiter: &str) -> String {
+        match self {
+            PostColumn::Id => post.id.to_string(),
+            PostColumn::CreatedAt => post.created_at.to_rfc3339(),
+            PostColumn::Score => post.score.to_string(),
+            PostColumn::Md5 => post.md5.clone(),
+            PostColumn::Directory => post.directory.clone(),
+            PostColumn::Image => post.image.clone(),
+            PostColumn::Rating => post.rating.as_str().to_string(),
+            PostColumn::Source => post.source.clone().unwrap_or_default(),
+            PostColumn::Change => post.change.to_string(),
+            PostColumn::Owner => post.owner.clone(),
+            PostColumn::CreatorId => post.creator_id.to_string(),
+            PostColumn::ParentId => post.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+            PostColumn::FileUrl => post.original.url.clone(),
+            PostColumn::Width => post.original.width.to_string(),
+            PostColumn::Height => post.original.height.to_string(),
+            PostColumn::PreviewUrl => post.preview.url.clone(),
+            PostColumn::SampleUrl => post
+                .sample
+                .as_ref()
+                .map(|sample| sample.url.clone())
+                .unwrap_or_default(),
+            PostColumn::Title => post.title.clone().unwrap_or_default(),
+            PostColumn::Status => post.status.clone(),
+            PostColumn::Tags => post.tags.join(tag_delimiter),
+        }
+    }
+}
+
+/// How the tags of a post are written
+pub enum TagsMode<W: Write> {
+    /// Join the tags into a single `tags` column with the given delimiter
+    Joined(String),
+    /// Write one `post_id,tag` row per tag into a second CSV file
+    Exploded(Box<csv::Writer<W>>),
+}
+
+/// Writes posts as CSV rows with a configurable set of columns
+pub struct CsvPostSink<W: Write> {
+    writer: csv::Writer<W>,
+    columns: Vec<PostColumn>,
+    tags: TagsMode<W>,
+    header_written: bool,
+}
+
+impl<W: Write> CsvPostSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            columns: PostColumn::ALL.to_vec(),
+            tags: TagsMode::Joined(String::from(" ")),
+            header_written: false,
+        }
+    }
+
+    /// Only write the given columns, in the given order
+    pub fn with_columns(mut self, columns: impl Into<Vec<PostColumn>>) -> Self {
+        self.columns = columns.into();
+        self
+    }
+
+    /// Join the tags column with `delimiter` instead of a space
+    pub fn with_tag_delimiter(mut self, delimiter: impl Into<String>) -> Self {
+        self.tags = TagsMode::Joined(delimiter.into());
+        self
+    }
+
+    /// Write tags into a separate `post_id,tag` file instead of a column
+    pub fn with_exploded_tags(mut self, tag_writer: W) -> Self {
+        self.columns.retain(|column| *column != PostColumn::Tags);
+        self.tags = TagsMode::Exploded(Box::new(csv::Writer::from_writer(tag_writer)));
+        self
+    }
+
+    fn write_header(&mut self) -> Result<(), SinkError> {
+        self.writer
+            .write_record(self.columns.iter().map(|column| column.as_str()))?;
+        if let TagsMode::Exploded(writer) = &mut self.tags {
+            writer.write_record(["post_id", "tag"])?;
+        }
+        self.header_written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink<Post> for CsvPostSink<W> {
+    fn write(&mut self, post: Post) -> Result<(), SinkError> {
+        if !self.header_written {
+            self.write_header()?;
+        }
+
+        let delimiter = match &self.tags {
+            TagsMode::Joined(delimiter) => delimiter.as_str(),
+            TagsMode::Exploded(_) => " ",
+        };
+        self.writer.write_record(
+            self.columns
+                .iter()
+                .map(|column| column.value(&post, delimiter)),
+        )?;
+
+        if let TagsMode::Exploded(writer) = &mut self.tags {
+            let id = post.id.to_string();
+            for tag in &post.tags {
+                writer.write_record([id.as_str(), tag.as_str()])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        if let TagsMode::Exploded(writer) = &mut self.tags {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes tags as CSV rows
+pub struct CsvTagSink<W: Write> {
+    writer: csv::Writer<W>,
+    header_written: bool,
+}
+
+impl<W: Write> CsvTagSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            header_written: false,
+        }
+    }
+}
+
+impl<W: Write> Sink<Tag> for CsvTagSink<W> {
+    fn write(&mut self, tag: Tag) -> Result<(), SinkError> {
+        if !self.header_written {
+            self.writer
+                .write_record(["id", "name", "count", "tag_type", "ambiguous"])?;
+            self.header_written = true;
+        }
+
+        self.writer.write_record([
+            tag.id.to_string(),
+            tag.name,
+            tag.count.to_string(),
+            tag.tag_type.as_str().to_string(),
+            tag.ambiguous.to_string(),
+        ])?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! DuckDB integration through the `duckdb` command line tool
+//!
+//! Records are staged as Parquet files and loaded with `read_parquet`, so no native DuckDB library
+//! has to be linked into the crate. The `duckdb` binary must be on the `PATH` (or configured with
+//! [`DuckDbSink::with_cli`]).
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{arrow::ArrowRecord, parquet::write_parquet, Sink, SinkError};
+
+/// Appends records to a table inside a DuckDB database file
+pub struct DuckDbSink<T: ArrowRecord> {
+    database: PathBuf,
+    table: String,
+    cli: PathBuf,
+    buffer: Vec<T>,
+    batch_size: usize,
+}
+
+impl<T: ArrowRecord> DuckDbSink<T> {
+    pub fn new<P: AsRef<Path>>(database: P, table: impl Into<String>) -> Self {
+        Self {
+            database: database.as_ref().to_path_buf(),
+            table: table.into(),
+            cli: PathBuf::from("duckdb"),
+            buffer: Vec::new(),
+            batch_size: 50_000,
+        }
+    }
+
+    pub fn with_cli<P: AsRef<Path>>(mut self, cli: P) -> Self {
+        self.cli = cli.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn append_buffer(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let staging = self.database.with_extension("staging.parquet");
+        write_parquet(&self.buffer, &staging)?;
+
+        let table = quote_identifier(&self.table);
+        let source = format!("read_parquet({})", quote_literal(&staging));
+        let result = run_sql(
+            &self.cli,
+            &self.database,
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {table} AS SELECT * FROM {source} LIMIT 0;
+                 INSERT INTO {table} SELECT * FROM {source};"
+            ),
+        );
+        std::fs::remove_file(&staging)?;
+        result?;
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<T: ArrowRecord> Sink<T> for DuckDbSink<T> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.batch_size {
+            self.append_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.append_buffer()
+    }
+}
+
+impl<T: ArrowRecord> Drop for DuckDbSink<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.append_buffer() {
+            tracing::error!("Failed to append to duckdb table {}: {}", self.table, e);
+        }
+    }
+}
+
+/// Store a set of records (e.g. index query results) as a table in a DuckDB database,
+/// replacing the table if it already exists
+pub fn export_relation<T: ArrowRecord, P: AsRef<Path>>(
+    database: P,
+    table: &str,
+    records: &[T],
+) -> Result<(), SinkError> {
+    let database = database.as_ref();
+    let staging = database.with_extension("export.parquet");
+    write_parquet(records, &staging)?;
+
+    let result = run_sql(
+        Path::new("duckdb"),
+        database,
+        &format!(
+            "CREATE OR REPLACE TABLE {} AS SELECT * FROM read_parquet({});",
+            quote_identifier(table),
+            quote_literal(&staging)
+        ),
+    );
+    std::fs::remove_file(&staging)?;
+    result
+}
+
+fn run_sql(cli: &Path, database: &Path, sql: &str) -> Result<(), SinkError> {
+    let output = Command::new(cli)
+        .arg(database)
+        .arg("-c")
+        .arg(sql)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "duckdb exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn quote_identifier(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn quote_literal(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "''"))
+}
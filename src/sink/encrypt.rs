@@ -0,0 +1,157 @@
+//! At-rest encryption of output files with AES-256-GCM
+//!
+//! Data is split into chunks of up to [`CHUNK_SIZE`] bytes. Each chunk is stored as a 12 byte
+//! random nonce, a little-endian `u32` ciphertext length and the ciphertext (including the tag),
+//! so appending to an existing file from a later run keeps it readable.
+
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+
+/// Amount of plaintext encrypted as one chunk
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Environment variable holding the hex encoded 32 byte key
+pub const KEY_ENV: &str = "INDEXER_ENCRYPTION_KEY";
+
+/// Load a key from [`KEY_ENV`] or, if that is unset, from a file containing the hex encoded key
+pub fn load_key(keyfile: Option<&Path>) -> io::Result<Key<Aes256Gcm>> {
+    let encoded = match (std::env::var(KEY_ENV), keyfile) {
+        (Ok(key), _) => key,
+        (Err(_), Some(path)) => std::fs::read_to_string(path)?,
+        (Err(_), None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{KEY_ENV} is not set and no keyfile was given"),
+            ))
+        }
+    };
+
+    let mut key = [0u8; 32];
+    hex::decode_to_slice(encoded.trim(), &mut key).map_err(io::Error::other)?;
+    Ok(key.into())
+}
+
+/// Encrypts everything written to it before passing it on to `inner`
+///
+/// The last partial chunk is written on [`Write::flush`] and when the writer is dropped.
+pub struct EncryptedWriter<W: Write> {
+    inner: W,
+    cipher: Aes256Gcm,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    pub fn new(inner: W, key: &Key<Aes256Gcm>) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(key),
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, self.buffer.as_slice())
+            .map_err(|_| io::Error::other("encryption failed"))?;
+
+        self.inner.write_all(&nonce)?;
+        self.inner
+            .write_all(&(ciphertext.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&ciphertext)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            tracing::error!("Failed to write final encrypted chunk: {}", e);
+        }
+    }
+}
+
+/// Decrypts a stream written by [`EncryptedWriter`]
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: Aes256Gcm,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, key: &Key<Aes256Gcm>) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(key),
+            plaintext: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Read and decrypt the next chunk, returning `false` at the end of the stream
+    fn read_chunk(&mut self) -> io::Result<bool> {
+        let mut nonce = [0u8; 12];
+        match self.inner.read_exact(&mut nonce) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        let mut length = [0u8; 4];
+        self.inner.read_exact(&mut length)?;
+        let mut ciphertext = vec![0u8; u32::from_le_bytes(length) as usize];
+        self.inner.read_exact(&mut ciphertext)?;
+
+        self.plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "decryption failed"))?;
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if !self.read_chunk()? {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Write;
+
+use arrow_ipc::writer::StreamWriter;
+use tracing::error;
+
+use super::{arrow::ArrowRecord, Sink, SinkError};
+
+/// Writes records as an Arrow IPC stream, one record batch per `batch_size` records
+///
+/// The stream can be read directly by polars, datafusion or pyarrow without any parsing.
+pub struct IpcStreamSink<T: ArrowRecord, W: Write> {
+    writer: Option<StreamWriter<W>>,
+    buffer: Vec<T>,
+    batch_size: usize,
+}
+
+impl<T: ArrowRecord, W: Write> IpcStreamSink<T, W> {
+    pub fn new(writer: W) -> Result<Self, SinkError> {
+        let writer = StreamWriter::try_new(writer, &T::schema())?;
+
+        Ok(Self {
+            writer: Some(writer),
+            buffer: Vec::new(),
+            batch_size: 10_000,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn write_buffer(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(writer) = &mut self.writer {
+            let batch = T::to_record_batch(&self.buffer)?;
+            writer.write(&batch)?;
+            writer.flush()?;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Write the remaining records and the end-of-stream marker
+    pub fn close(&mut self) -> Result<(), SinkError> {
+        self.write_buffer()?;
+        if let Some(mut writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ArrowRecord, W: Write> Sink<T> for IpcStreamSink<T, W> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.batch_size {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.write_buffer()
+    }
+}
+
+impl<T: ArrowRecord, W: Write> Drop for IpcStreamSink<T, W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("Failed to finish arrow stream: {}", e);
+        }
+    }
+}
+
+/// Write a complete set of records (e.g. index query results) as a single Arrow IPC stream
+pub fn write_ipc_stream<T: ArrowRecord, W: Write>(
+    records: &[T],
+    writer: W,
+) -> Result<(), SinkError> {
+    let mut writer = StreamWriter::try_new(writer, &T::schema())?;
+    writer.write(&T::to_record_batch(records)?)?;
+    writer.finish()?;
+    Ok(())
+}
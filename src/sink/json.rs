@@ -0,0 +1,49 @@
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::models::envelope::{Envelope, Record};
+
+use super::{Sink, SinkError};
+
+/// Writes every record as a single line of JSON
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+    envelope: bool,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            envelope: false,
+        }
+    }
+
+    /// Wrap every record in a versioned [`Envelope`]
+    pub fn with_envelope(mut self) -> Self {
+        self.envelope = true;
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<T: Serialize + Record, W: Write> Sink<T> for JsonLinesSink<W> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        if self.envelope {
+            serde_json::to_writer(&mut self.writer, &Envelope::new(record))?;
+        } else {
+            serde_json::to_writer(&mut self.writer, &record)?;
+        }
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
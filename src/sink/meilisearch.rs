@@ -0,0 +1,108 @@
+//! Export of posts into a Meilisearch index for typo-tolerant full-text search
+
+use serde::Serialize;
+use tokio::runtime::Handle;
+use typed_builder::TypedBuilder;
+
+use crate::models::Post;
+
+use super::{Sink, SinkError};
+
+/// The subset of a post sent to Meilisearch
+#[derive(Debug, Serialize)]
+pub struct SearchDocument<'a> {
+    pub id: u64,
+    pub tags: &'a [String],
+    pub rating: &'a str,
+    pub score: i32,
+    pub title: Option<&'a str>,
+}
+
+impl<'a> From<&'a Post> for SearchDocument<'a> {
+    fn from(post: &'a Post) -> Self {
+        Self {
+            id: post.id,
+            tags: &post.tags,
+            rating: post.rating.as_str(),
+            score: post.score,
+            title: post.title.as_deref(),
+        }
+    }
+}
+
+/// Pushes posts into a Meilisearch index in batches
+#[derive(TypedBuilder)]
+pub struct MeilisearchSink {
+    #[builder(default)]
+    client: reqwest::Client,
+
+    /// Base url of the Meilisearch instance, e.g. `http://localhost:7700`
+    #[builder(setter(into))]
+    url: String,
+
+    /// Uid of the index the documents are added to
+    #[builder(setter(into))]
+    index: String,
+
+    #[builder(default, setter(into, strip_option))]
+    api_key: Option<String>,
+
+    #[builder(default = 1_000)]
+    batch_size: usize,
+
+    #[builder(default, setter(skip))]
+    buffer: Vec<Post>,
+}
+
+impl MeilisearchSink {
+    /// Add or replace a batch of documents
+    pub async fn push(&self, posts: &[Post]) -> Result<(), SinkError> {
+        if posts.is_empty() {
+            return Ok(());
+        }
+
+        let documents: Vec<SearchDocument> = posts.iter().map(SearchDocument::from).collect();
+        let mut req = self
+            .client
+            .post(format!(
+                "{}/indexes/{}/documents?primaryKey=id",
+                self.url.trim_end_matches('/'),
+                self.index
+            ))
+            .json(&documents);
+        if let Some(api_key) = &self.api_key {
+            req = req.bearer_auth(api_key);
+        }
+
+        backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
+            let response = req
+                .try_clone()
+                .expect("request body is not a stream")
+                .send()
+                .await
+                .map_err(SinkError::from)?;
+            response.error_for_status().map_err(SinkError::from)?;
+            Ok(())
+        })
+        .await
+    }
+
+    fn push_buffer(&mut self) -> Result<(), SinkError> {
+        let posts = std::mem::take(&mut self.buffer);
+        tokio::task::block_in_place(|| Handle::current().block_on(self.push(&posts)))
+    }
+}
+
+impl Sink<Post> for MeilisearchSink {
+    fn write(&mut self, post: Post) -> Result<(), SinkError> {
+        self.buffer.push(post);
+        if self.buffer.len() >= self.batch_size {
+            self.push_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.push_buffer()
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::{Arc, Mutex};
+
+use thiserror::Error;
+
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+pub mod compact;
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "duckdb")]
+pub mod duckdb;
+#[cfg(feature = "encryption")]
+pub mod encrypt;
+#[cfg(feature = "arrow")]
+pub mod ipc;
+pub mod json;
+#[cfg(feature = "meilisearch")]
+pub mod meilisearch;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "s3")]
+pub mod object_store;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod partition;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod tee;
+#[cfg(feature = "scraper")]
+pub mod writer;
+
+/// A destination for scraped records
+///
+/// The scrapers hand every record they produce to a sink, which decides how and where it is stored.
+pub trait Sink<T> {
+    /// Write a single record to the sink
+    fn write(&mut self, record: T) -> Result<(), SinkError>;
+
+    /// Flush any buffered records to the underlying storage
+    fn flush(&mut self) -> Result<(), SinkError>;
+}
+
+impl<T, S: Sink<T> + ?Sized> Sink<T> for Box<S> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        (**self).write(record)
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        (**self).flush()
+    }
+}
+
+/// Allows a sink to be shared, e.g. a live index that is also queried while scraping
+impl<T, S: Sink<T>> Sink<T> for Arc<Mutex<S>> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .write(record)
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .flush()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SinkError {
+    #[error("IO Error: `{0}`")]
+    Io(#[from] std::io::Error),
+    #[error("Serde Error: `{0}`")]
+    Serde(#[from] serde_json::Error),
+    #[cfg(feature = "scraper")]
+    #[error("Reqwest Error: `{0}`")]
+    Reqwest(#[from] reqwest::Error),
+    #[cfg(feature = "csv")]
+    #[error("CSV Error: `{0}`")]
+    Csv(#[from] ::csv::Error),
+    #[cfg(feature = "arrow")]
+    #[error("Arrow Error: `{0}`")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[cfg(feature = "parquet")]
+    #[error("Parquet Error: `{0}`")]
+    Parquet(#[from] ::parquet::errors::ParquetError),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite Error: `{0}`")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "s3")]
+    #[error("Object Store Error: `{0}`")]
+    ObjectStore(#[from] ::object_store::Error),
+    #[cfg(feature = "postgres")]
+    #[error("Postgres Error: `{0}`")]
+    Postgres(#[from] tokio_postgres::Error),
+}
@@ -0,0 +1,54 @@
+use std::io;
+
+use serde::Serialize;
+use tokio::runtime::Handle;
+
+use super::{Sink, SinkError};
+
+/// Publishes every record as a JSON message on a NATS subject
+///
+/// Downstream consumers (indexers, notification bots, ML pipelines) can subscribe to the subject
+/// to follow the live scrape.
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsSink {
+    pub async fn connect(server: &str, subject: impl Into<String>) -> Result<Self, SinkError> {
+        let client = async_nats::connect(server)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(Self::new(client, subject))
+    }
+
+    pub fn new(client: async_nats::Client, subject: impl Into<String>) -> Self {
+        Self {
+            client,
+            subject: subject.into(),
+        }
+    }
+
+    /// Publish a single record
+    pub async fn publish<T: Serialize>(&self, record: &T) -> Result<(), SinkError> {
+        let payload = serde_json::to_vec(record)?;
+        self.client
+            .publish(self.subject.clone(), payload.into())
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+impl<T: Serialize> Sink<T> for NatsSink {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.publish(&record)))
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.client.flush()))
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Upload of rotated output chunks to S3-compatible object storage
+
+use std::{
+    io::{self, Read, Write},
+    sync::Arc,
+};
+
+use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use object_store::{path::Path, ObjectStore, WriteMultipart};
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tracing::info;
+use url::Url;
+
+use super::{Sink, SinkError};
+
+/// Size of the parts used for multipart uploads
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Create an object store from a url such as `s3://bucket/prefix`
+///
+/// Credentials and region are taken from the usual `AWS_*` environment variables.
+pub fn store_from_url(url: &str) -> Result<(Arc<dyn ObjectStore>, Path), SinkError> {
+    let url = Url::parse(url).map_err(io::Error::other)?;
+    let options = std::env::vars().map(|(key, value)| (key.to_lowercase(), value));
+    let (store, path) = object_store::parse_url_opts(&url, options)?;
+    Ok((Arc::from(store), path))
+}
+
+/// Writes records as gzip compressed JSON lines and uploads a chunk every `chunk_records` records
+///
+/// Chunks are named `{prefix}/{name}-{timestamp}-{sequence}.jsonl.gz` and uploaded with a multipart
+/// upload. A failed upload is retried with a backoff strategy before the error is returned.
+pub struct ObjectStoreSink {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    name: String,
+    encoder: GzEncoder<Vec<u8>>,
+    records: usize,
+    chunk_records: usize,
+    sequence: u64,
+}
+
+impl ObjectStoreSink {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Path, name: impl Into<String>) -> Self {
+        Self {
+            store,
+            prefix,
+            name: name.into(),
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            records: 0,
+            chunk_records: 100_000,
+            sequence: 0,
+        }
+    }
+
+    /// Create a sink uploading below a url such as `s3://bucket/prefix`
+    pub fn from_url(url: &str, name: impl Into<String>) -> Result<Self, SinkError> {
+        let (store, prefix) = store_from_url(url)?;
+        Ok(Self::new(store, prefix, name))
+    }
+
+    pub fn with_chunk_records(mut self, chunk_records: usize) -> Self {
+        self.chunk_records = chunk_records.max(1);
+        self
+    }
+
+    /// Finish the current chunk and upload it
+    pub async fn rotate(&mut self) -> Result<(), SinkError> {
+        if self.records == 0 {
+            return Ok(());
+        }
+
+        let encoder = std::mem::replace(
+            &mut self.encoder,
+            GzEncoder::new(Vec::new(), Compression::default()),
+        );
+        let chunk = encoder.finish()?;
+        let location = self.prefix.child(format!(
+            "{}-{}-{:06}.jsonl.gz",
+            self.name,
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            self.sequence
+        ));
+
+        backoff::future::retry(backoff::ExponentialBackoff::default(), || async {
+            Ok(upload(self.store.as_ref(), &location, &chunk).await?)
+        })
+        .await?;
+
+        info!("Uploaded {} records to {}", self.records, location);
+        self.records = 0;
+        self.sequence += 1;
+        Ok(())
+    }
+
+    fn rotate_blocking(&mut self) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.rotate()))
+    }
+}
+
+async fn upload(store: &dyn ObjectStore, location: &Path, chunk: &[u8]) -> Result<(), SinkError> {
+    let upload = store.put_multipart(location).await?;
+    let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+    writer.write(chunk);
+    writer.finish().await?;
+    Ok(())
+}
+
+/// Upload the file at `path` to `location`, reading it a part at a time
+pub async fn upload_file(
+    store: &dyn ObjectStore,
+    location: &Path,
+    path: &std::path::Path,
+) -> Result<(), SinkError> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let upload = store.put_multipart(location).await?;
+    let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+    let mut buffer = vec![0u8; PART_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        writer.write(&buffer[..read]);
+    }
+    writer.finish().await?;
+    Ok(())
+}
+
+impl<T: Serialize> Sink<T> for ObjectStoreSink {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        serde_json::to_writer(&mut self.encoder, &record)?;
+        self.encoder.write_all(b"\n")?;
+        self.records += 1;
+
+        if self.records >= self.chunk_records {
+            self.rotate_blocking()?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the current chunk, even if it is not full yet
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.rotate_blocking()
+    }
+}
+
+/// Download an object and return its contents as text, decompressing `.gz` objects
+pub async fn read_to_string(url: &str) -> Result<String, SinkError> {
+    let (store, path) = store_from_url(url)?;
+    let bytes = store.get(&path).await?.bytes().await?;
+
+    let mut contents = String::new();
+    if path.as_ref().ends_with(".gz") {
+        GzDecoder::new(&bytes[..]).read_to_string(&mut contents)?;
+    } else {
+        contents = String::from_utf8(bytes.to_vec()).map_err(io::Error::other)?;
+    }
+    Ok(contents)
+}
@@ -0,0 +1,98 @@
+use std::{fs::File, path::Path};
+
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use tracing::error;
+
+use super::{arrow::ArrowRecord, Sink, SinkError};
+
+/// Writes records into a Parquet file, one row group per `batch_size` records
+///
+/// The file footer is only written once the sink is closed (or dropped), so a file from an
+/// interrupted run without a clean shutdown will not be readable.
+pub struct ParquetSink<T: ArrowRecord> {
+    writer: Option<ArrowWriter<File>>,
+    buffer: Vec<T>,
+    batch_size: usize,
+}
+
+impl<T: ArrowRecord> ParquetSink<T> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, SinkError> {
+        let file = File::create(path)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(file, T::schema(), Some(properties))?;
+
+        Ok(Self {
+            writer: Some(writer),
+            buffer: Vec::new(),
+            batch_size: 10_000,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn write_buffer(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(writer) = &mut self.writer {
+            let batch = T::to_record_batch(&self.buffer)?;
+            writer.write(&batch)?;
+            writer.flush()?;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Write the remaining records and the file footer
+    pub fn close(&mut self) -> Result<(), SinkError> {
+        self.write_buffer()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ArrowRecord> Sink<T> for ParquetSink<T> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.buffer.push(record);
+        if self.buffer.len() >= self.batch_size {
+            self.write_buffer()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.write_buffer()
+    }
+}
+
+impl<T: ArrowRecord> Drop for ParquetSink<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("Failed to close parquet file: {}", e);
+        }
+    }
+}
+
+/// Write a complete set of records (e.g. index query results) into a single Parquet file
+pub fn write_parquet<T: ArrowRecord, P: AsRef<Path>>(
+    records: &[T],
+    path: P,
+) -> Result<(), SinkError> {
+    let file = File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, T::schema(), None)?;
+    writer.write(&T::to_record_batch(records)?)?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use crate::models::{Post, Tag};
+
+use super::{json::JsonLinesSink, Sink, SinkError};
+
+type KeyFn<T> = Box<dyn Fn(&T) -> String + Send>;
+type Factory<S> = Box<dyn FnMut(&str) -> Result<S, SinkError> + Send>;
+
+/// Routes records into separate sinks based on a partition key
+///
+/// Sinks are created lazily by the factory the first time a key is seen. If a set of keys is
+/// configured with [`PartitionedSink::only`], records for every other key are dropped.
+pub struct PartitionedSink<T, S: Sink<T>> {
+    key: KeyFn<T>,
+    factory: Factory<S>,
+    partitions: HashMap<String, S>,
+    only: Option<HashSet<String>>,
+}
+
+impl<T, S: Sink<T>> PartitionedSink<T, S> {
+    pub fn new(
+        key: impl Fn(&T) -> String + Send + 'static,
+        factory: impl FnMut(&str) -> Result<S, SinkError> + Send + 'static,
+    ) -> Self {
+        Self {
+            key: Box::new(key),
+            factory: Box::new(factory),
+            partitions: HashMap::new(),
+            only: None,
+        }
+    }
+
+    /// Only keep records for the given partitions
+    pub fn only<I: IntoIterator<Item = impl Into<String>>>(mut self, keys: I) -> Self {
+        self.only = Some(keys.into_iter().map(Into::into).collect());
+        self
+    }
+}
+
+impl<T, S: Sink<T>> Sink<T> for PartitionedSink<T, S> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        let key = (self.key)(&record);
+        if let Some(only) = &self.only {
+            if !only.contains(&key) {
+                return Ok(());
+            }
+        }
+
+        let sink = match self.partitions.get_mut(&key) {
+            Some(sink) => sink,
+            None => {
+                let sink = (self.factory)(&key)?;
+                self.partitions.entry(key).or_insert(sink)
+            }
+        };
+        sink.write(record)
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        for sink in self.partitions.values_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub type JsonLinesFileSink = JsonLinesSink<BufWriter<File>>;
+
+/// Write posts into `{dir}/{rating}/{file_name}` as JSON lines
+pub fn posts_by_rating<P: AsRef<Path>>(
+    dir: P,
+    file_name: &str,
+) -> PartitionedSink<Post, JsonLinesFileSink> {
+    let dir = dir.as_ref().to_path_buf();
+    let file_name = file_name.to_string();
+    PartitionedSink::new(
+        |post: &Post| post.rating.as_str().to_string(),
+        move |key| open_partition(dir.join(key), &file_name),
+    )
+}
+
+/// Write tags into `{dir}/{tag_type}/{file_name}` as JSON lines
+pub fn tags_by_type<P: AsRef<Path>>(
+    dir: P,
+    file_name: &str,
+) -> PartitionedSink<Tag, JsonLinesFileSink> {
+    let dir = dir.as_ref().to_path_buf();
+    let file_name = file_name.to_string();
+    PartitionedSink::new(
+        |tag: &Tag| tag.tag_type.as_str().to_string(),
+        move |key| open_partition(dir.join(key), &file_name),
+    )
+}
+
+/// The `year=YYYY/month=MM` partition of a post, based on its `created_at`
+pub fn date_partition(post: &Post) -> String {
+    post.created_at.format("year=%Y/month=%m").to_string()
+}
+
+/// Write posts into `{dir}/year=YYYY/month=MM/{file_name}` as JSON lines
+pub fn posts_by_date<P: AsRef<Path>>(
+    dir: P,
+    file_name: &str,
+) -> PartitionedSink<Post, JsonLinesFileSink> {
+    let dir = dir.as_ref().to_path_buf();
+    let file_name = file_name.to_string();
+    PartitionedSink::new(date_partition, move |key| {
+        open_partition(dir.join(key), &file_name)
+    })
+}
+
+#[cfg(feature = "zstd")]
+pub type ZstdJsonLinesSink =
+    JsonLinesSink<zstd::stream::write::AutoFinishEncoder<'static, BufWriter<File>>>;
+
+/// Write posts into `{dir}/year=YYYY/month=MM/posts.jsonl.zst` as zstd compressed JSON lines
+///
+/// Every run appends a new zstd frame to the existing files, which decoders read as one stream.
+#[cfg(feature = "zstd")]
+pub fn posts_by_date_zstd<P: AsRef<Path>>(dir: P) -> PartitionedSink<Post, ZstdJsonLinesSink> {
+    let dir = dir.as_ref().to_path_buf();
+    PartitionedSink::new(date_partition, move |key| {
+        let dir = dir.join(key);
+        std::fs::create_dir_all(&dir)?;
+        let file = File::options()
+            .append(true)
+            .create(true)
+            .open(dir.join("posts.jsonl.zst"))?;
+        let encoder = zstd::stream::write::Encoder::new(BufWriter::new(file), 0)?;
+        Ok(JsonLinesSink::new(encoder.auto_finish()))
+    })
+}
+
+fn open_partition(dir: PathBuf, file_name: &str) -> Result<JsonLinesFileSink, SinkError> {
+    std::fs::create_dir_all(&dir)?;
+    let file = File::options()
+        .append(true)
+        .create(true)
+        .open(dir.join(file_name))?;
+    Ok(JsonLinesSink::new(BufWriter::new(file)))
+}
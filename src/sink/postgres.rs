@@ -0,0 +1,303 @@
+use std::pin::pin;
+
+use tokio::runtime::Handle;
+use tokio_postgres::{
+    binary_copy::BinaryCopyInWriter,
+    types::{ToSql, Type},
+    Client, NoTls,
+};
+use tracing::error;
+
+use crate::models::{Post, Tag};
+
+use super::{Sink, SinkError};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS posts (
+    id BIGINT PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL,
+    score INTEGER NOT NULL,
+    md5 TEXT NOT NULL,
+    directory TEXT NOT NULL,
+    image TEXT NOT NULL,
+    rating TEXT NOT NULL,
+    source TEXT,
+    change BIGINT NOT NULL,
+    owner TEXT NOT NULL,
+    creator_id BIGINT NOT NULL,
+    parent_id BIGINT,
+    sample_url TEXT,
+    sample_width INTEGER,
+    sample_height INTEGER,
+    preview_url TEXT NOT NULL,
+    preview_width INTEGER NOT NULL,
+    preview_height INTEGER NOT NULL,
+    file_url TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    tags TEXT[] NOT NULL,
+    title TEXT,
+    has_notes BOOLEAN NOT NULL,
+    has_comments BOOLEAN NOT NULL,
+    status TEXT NOT NULL,
+    post_locked BOOLEAN NOT NULL,
+    has_children BOOLEAN NOT NULL
+);
+CREATE INDEX IF NOT EXISTS posts_tags ON posts USING GIN (tags);
+CREATE INDEX IF NOT EXISTS posts_md5 ON posts (md5);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id BIGINT PRIMARY KEY,
+    name TEXT NOT NULL,
+    count BIGINT NOT NULL,
+    tag_type INTEGER NOT NULL,
+    ambiguous BOOLEAN NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tags_name ON tags (name);
+";
+
+const POST_COLUMNS: &str = "id, created_at, score, md5, directory, image, rating, source, change, \
+    owner, creator_id, parent_id, sample_url, sample_width, sample_height, preview_url, \
+    preview_width, preview_height, file_url, width, height, tags, title, has_notes, has_comments, \
+    status, post_locked, has_children";
+
+const POST_TYPES: &[Type] = &[
+    Type::INT8,
+    Type::TIMESTAMPTZ,
+    Type::INT4,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::TEXT,
+    Type::INT8,
+    Type::TEXT,
+    Type::INT8,
+    Type::INT8,
+    Type::TEXT,
+    Type::INT4,
+    Type::INT4,
+    Type::TEXT,
+    Type::INT4,
+    Type::INT4,
+    Type::TEXT,
+    Type::INT4,
+    Type::INT4,
+    Type::TEXT_ARRAY,
+    Type::TEXT,
+    Type::BOOL,
+    Type::BOOL,
+    Type::TEXT,
+    Type::BOOL,
+    Type::BOOL,
+];
+
+const TAG_COLUMNS: &str = "id, name, count, tag_type, ambiguous";
+
+/// The tag columns and `seq`, the position of the tag in its batch
+const TAG_TYPES: &[Type] = &[
+    Type::INT8,
+    Type::TEXT,
+    Type::INT8,
+    Type::INT4,
+    Type::BOOL,
+    Type::INT8,
+];
+
+/// Stores posts and tags in a PostgreSQL database
+///
+/// Records are buffered and written in batches with `COPY` into a temporary staging table, then
+/// upserted into the real tables. An existing post is only replaced if the incoming record has a
+/// newer `change` value. A batch can hold a record more than once (sync and repair write posts
+/// again), only its newest post and its last tag are upserted.
+pub struct PostgresSink {
+    client: Client,
+    posts: Vec<Post>,
+    tags: Vec<Tag>,
+    batch_size: usize,
+}
+
+impl PostgresSink {
+    /// Connect to the database and create the tables if they don't exist yet
+    pub async fn connect(config: &str) -> Result<Self, SinkError> {
+        let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("Postgres connection error: {}", e);
+            }
+        });
+
+        client.batch_execute(SCHEMA).await?;
+
+        Ok(Self {
+            client,
+            posts: Vec::new(),
+            tags: Vec::new(),
+            batch_size: 5_000,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Copy a batch of posts into the database
+    pub async fn write_posts(&mut self, posts: &[Post]) -> Result<(), SinkError> {
+        if posts.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.client.transaction().await?;
+        tx.batch_execute(
+            "CREATE TEMP TABLE posts_staging (LIKE posts INCLUDING DEFAULTS) ON COMMIT DROP",
+        )
+        .await?;
+
+        let sink = tx
+            .copy_in(&format!(
+                "COPY posts_staging ({POST_COLUMNS}) FROM STDIN BINARY"
+            ))
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, POST_TYPES));
+        for post in posts {
+            let sample = post.sample.as_ref();
+            let row: [&(dyn ToSql + Sync); 28] = [
+                &(post.id as i64),
+                &post.created_at,
+                &post.score,
+                &post.md5,
+                &post.directory,
+                &post.image,
+                &post.rating.as_str(),
+                &post.source,
+                &(post.change as i64),
+                &post.owner,
+                &(post.creator_id as i64),
+                &post.parent_id.map(|id| id as i64),
+                &sample.map(|s| s.url.as_str()),
+                &sample.map(|s| s.width as i32),
+                &sample.map(|s| s.height as i32),
+                &post.preview.url,
+                &(post.preview.width as i32),
+                &(post.preview.height as i32),
+                &post.original.url,
+                &(post.original.width as i32),
+                &(post.original.height as i32),
+                &post.tags,
+                &post.title,
+                &post.has_notes,
+                &post.has_comments,
+                &post.status,
+                &post.post_locked,
+                &post.has_children,
+            ];
+            writer.as_mut().write(&row).await?;
+        }
+        writer.finish().await?;
+
+        let updates = POST_COLUMNS
+            .split(", ")
+            .filter(|column| *column != "id")
+            .map(|column| format!("{column} = EXCLUDED.{column}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        tx.batch_execute(&format!(
+            "INSERT INTO posts ({POST_COLUMNS})
+             SELECT DISTINCT ON (id) {POST_COLUMNS} FROM posts_staging ORDER BY id, change DESC
+             ON CONFLICT (id) DO UPDATE SET {updates} WHERE posts.change < EXCLUDED.change"
+        ))
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Copy a batch of tags into the database
+    pub async fn write_tags(&mut self, tags: &[Tag]) -> Result<(), SinkError> {
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.client.transaction().await?;
+        tx.batch_execute(
+            "CREATE TEMP TABLE tags_staging (LIKE tags INCLUDING DEFAULTS, seq BIGINT NOT NULL)
+             ON COMMIT DROP",
+        )
+        .await?;
+
+        let sink = tx
+            .copy_in(&format!(
+                "COPY tags_staging ({TAG_COLUMNS}, seq) FROM STDIN BINARY"
+            ))
+            .await?;
+        let mut writer = pin!(BinaryCopyInWriter::new(sink, TAG_TYPES));
+        for (seq, tag) in tags.iter().enumerate() {
+            let row: [&(dyn ToSql + Sync); 6] = [
+                &(tag.id as i64),
+                &tag.name,
+                &(tag.count as i64),
+                &(u32::from(tag.tag_type) as i32),
+                &tag.ambiguous,
+                &(seq as i64),
+            ];
+            writer.as_mut().write(&row).await?;
+        }
+        writer.finish().await?;
+
+        tx.batch_execute(&format!(
+            "INSERT INTO tags ({TAG_COLUMNS})
+             SELECT DISTINCT ON (id) {TAG_COLUMNS} FROM tags_staging ORDER BY id, seq DESC
+             ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, count = EXCLUDED.count,
+             tag_type = EXCLUDED.tag_type, ambiguous = EXCLUDED.ambiguous"
+        ))
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Write every buffered record
+    pub async fn flush_async(&mut self) -> Result<(), SinkError> {
+        let posts = std::mem::take(&mut self.posts);
+        self.write_posts(&posts).await?;
+        let tags = std::mem::take(&mut self.tags);
+        self.write_tags(&tags).await?;
+        Ok(())
+    }
+
+    /// Drive the async flush from the synchronous [`Sink`] interface
+    ///
+    /// This requires the multi-threaded tokio runtime.
+    fn flush_blocking(&mut self) -> Result<(), SinkError> {
+        tokio::task::block_in_place(|| Handle::current().block_on(self.flush_async()))
+    }
+}
+
+impl Sink<Post> for PostgresSink {
+    fn write(&mut self, post: Post) -> Result<(), SinkError> {
+        self.posts.push(post);
+        if self.posts.len() >= self.batch_size {
+            self.flush_blocking()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.flush_blocking()
+    }
+}
+
+impl Sink<Tag> for PostgresSink {
+    fn write(&mut self, tag: Tag) -> Result<(), SinkError> {
+        self.tags.push(tag);
+        if self.tags.len() >= self.batch_size {
+            self.flush_blocking()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.flush_blocking()
+    }
+}
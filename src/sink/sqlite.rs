@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+
+use crate::models::{Post, Rating, Tag, TagType, Varient};
+
+use super::{Sink, SinkError};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS posts (
+    id INTEGER PRIMARY KEY,
+    created_at TEXT NOT NULL,
+    score INTEGER NOT NULL,
+    md5 TEXT NOT NULL,
+    directory TEXT NOT NULL,
+    image TEXT NOT NULL,
+    rating TEXT NOT NULL,
+    source TEXT,
+    change INTEGER NOT NULL,
+    owner TEXT NOT NULL,
+    creator_id INTEGER NOT NULL,
+    parent_id INTEGER,
+    sample_url TEXT,
+    sample_width INTEGER,
+    sample_height INTEGER,
+    preview_url TEXT NOT NULL,
+    preview_width INTEGER NOT NULL,
+    preview_height INTEGER NOT NULL,
+    file_url TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    title TEXT,
+    has_notes INTEGER NOT NULL,
+    has_comments INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    post_locked INTEGER NOT NULL,
+    has_children INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS posts_md5 ON posts (md5);
+CREATE INDEX IF NOT EXISTS posts_created_at ON posts (created_at);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    tag_type INTEGER NOT NULL,
+    ambiguous INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tags_name ON tags (name);
+
+CREATE TABLE IF NOT EXISTS post_tags (
+    post_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (post_id, tag)
+) WITHOUT ROWID;
+CREATE INDEX IF NOT EXISTS post_tags_tag ON post_tags (tag);
+";
+
+/// Stores posts, tags and the post-tag relation in a SQLite database
+///
+/// Records are written inside a transaction which is committed every `batch_size` records and on
+/// [`Sink::flush`]. Posts which are written again replace the previous row.
+pub struct SqliteSink {
+    conn: Connection,
+    pending: usize,
+    batch_size: usize,
+}
+
+impl SqliteSink {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SinkError> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Self {
+            conn,
+            pending: 0,
+            batch_size: 1_000,
+        })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn begin(&mut self) -> Result<(), SinkError> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN")?;
+        }
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn commit_if_full(&mut self) -> Result<(), SinkError> {
+        if self.pending >= self.batch_size {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), SinkError> {
+        if self.pending > 0 {
+            self.conn.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Sink<Post> for SqliteSink {
+    fn write(&mut self, post: Post) -> Result<(), SinkError> {
+        self.begin()?;
+
+        let sample = post.sample.as_ref();
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO posts VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14,
+                    ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27
+                )",
+            )?
+            .execute(params![
+                post.id,
+                post.created_at.to_rfc3339(),
+                post.score,
+                post.md5,
+                post.directory,
+                post.image,
+                post.rating.as_str(),
+                post.source,
+                post.change,
+                post.owner,
+                post.creator_id,
+                post.parent_id,
+                sample.map(|s| &s.url),
+                sample.map(|s| s.width),
+                sample.map(|s| s.height),
+                post.preview.url,
+                post.preview.width,
+                post.preview.height,
+                post.original.url,
+                post.original.width,
+                post.original.height,
+                post.title,
+                post.has_notes,
+                post.has_comments,
+                post.status,
+                post.post_locked,
+                post.has_children,
+            ])?;
+
+        self.conn
+            .prepare_cached("DELETE FROM post_tags WHERE post_id = ?1")?
+            .execute([post.id])?;
+        let mut insert_tag = self
+            .conn
+            .prepare_cached("INSERT OR IGNORE INTO post_tags (post_id, tag) VALUES (?1, ?2)")?;
+        for tag in &post.tags {
+            insert_tag.execute(params![post.id, tag])?;
+        }
+        drop(insert_tag);
+
+        self.commit_if_full()
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.commit()
+    }
+}
+
+impl Sink<Tag> for SqliteSink {
+    fn write(&mut self, tag: Tag) -> Result<(), SinkError> {
+        self.begin()?;
+
+        self.conn
+            .prepare_cached("INSERT OR REPLACE INTO tags VALUES (?1, ?2, ?3, ?4, ?5)")?
+            .execute(params![
+                tag.id,
+                tag.name,
+                tag.count,
+                u32::from(tag.tag_type),
+                tag.ambiguous,
+            ])?;
+
+        self.commit_if_full()
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.commit()
+    }
+}
+
+impl Drop for SqliteSink {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            tracing::error!("Failed to commit sqlite transaction: {}", e);
+        }
+    }
+}
+
+/// Read every tag stored in the database
+pub fn read_tags(conn: &Connection) -> Result<Vec<Tag>, SinkError> {
+    let mut stmt = conn.prepare("SELECT id, name, count, tag_type, ambiguous FROM tags")?;
+    let tags = stmt
+        .query_map([], |row| {
+            Ok(Tag {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                count: row.get(2)?,
+                tag_type: TagType::from(row.get::<_, u32>(3)?),
+                ambiguous: row.get(4)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(tags)
+}
+
+/// Stream every post stored in the database (with its tags) into `f`, ordered by id
+pub fn for_each_post(conn: &Connection, mut f: impl FnMut(Post)) -> Result<(), SinkError> {
+    let mut stmt = conn.prepare(
+        "SELECT p.*, (SELECT group_concat(tag, ' ') FROM post_tags t WHERE t.post_id = p.id)
+         FROM posts p ORDER BY p.id",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        f(post_from_row(row)?);
+    }
+    Ok(())
+}
+
+fn post_from_row(row: &Row) -> rusqlite::Result<Post> {
+    let created_at: String = row.get(1)?;
+    let created_at = DateTime::parse_from_rfc3339(&created_at)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+        })?;
+
+    let sample = match (row.get(12)?, row.get(13)?, row.get(14)?) {
+        (Some(url), Some(width), Some(height)) => Some(Varient { url, width, height }),
+        _ => None,
+    };
+
+    let tags: Option<String> = row.get(27)?;
+
+    Ok(Post {
+        id: row.get(0)?,
+        created_at,
+        score: row.get(2)?,
+        md5: row.get(3)?,
+        directory: row.get(4)?,
+        image: row.get(5)?,
+        rating: Rating::from(row.get::<_, String>(6)?),
+        source: row.get(7)?,
+        change: row.get(8)?,
+        owner: row.get(9)?,
+        creator_id: row.get(10)?,
+        parent_id: row.get(11)?,
+        sample,
+        preview: Varient {
+            url: row.get(15)?,
+            width: row.get(16)?,
+            height: row.get(17)?,
+        },
+        original: Varient {
+            url: row.get(18)?,
+            width: row.get(19)?,
+            height: row.get(20)?,
+        },
+        tags: tags
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|tag| tag.to_string())
+            .collect(),
+        title: row.get(21)?,
+        has_notes: row.get(22)?,
+        has_comments: row.get(23)?,
+        status: row.get(24)?,
+        post_locked: row.get(25)?,
+        has_children: row.get(26)?,
+        typed_tags: Vec::new(),
+        raw_created_at: None,
+        extra: serde_json::Map::new(),
+    })
+}
@@ -0,0 +1,105 @@
+use tracing::{error, warn};
+
+use super::{Sink, SinkError};
+
+/// What a [`TeeSink`] does when one of its sinks fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Return the error to the caller
+    Fail,
+    /// Log the error and keep writing to the sink
+    Log,
+    /// Log the error and stop writing to the sink
+    Disable,
+}
+
+struct Branch<T> {
+    name: String,
+    sink: Box<dyn Sink<T> + Send>,
+    policy: ErrorPolicy,
+    disabled: bool,
+}
+
+/// Writes every record to multiple sinks, e.g. an NDJSON file, a live index and a message queue
+///
+/// Each sink has its own [`ErrorPolicy`], so a failing optional destination does not stop the
+/// scrape. Records are written to the sinks in the order they were added.
+pub struct TeeSink<T> {
+    branches: Vec<Branch<T>>,
+}
+
+impl<T> Default for TeeSink<T> {
+    fn default() -> Self {
+        Self {
+            branches: Vec::new(),
+        }
+    }
+}
+
+impl<T> TeeSink<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sink with the given name and error policy
+    pub fn with(
+        mut self,
+        name: impl Into<String>,
+        sink: impl Sink<T> + Send + 'static,
+        policy: ErrorPolicy,
+    ) -> Self {
+        self.push(name, Box::new(sink), policy);
+        self
+    }
+
+    pub fn push(
+        &mut self,
+        name: impl Into<String>,
+        sink: Box<dyn Sink<T> + Send>,
+        policy: ErrorPolicy,
+    ) {
+        self.branches.push(Branch {
+            name: name.into(),
+            sink,
+            policy,
+            disabled: false,
+        });
+    }
+
+    /// Names of the sinks that were disabled after an error
+    pub fn disabled(&self) -> impl Iterator<Item = &str> {
+        self.branches
+            .iter()
+            .filter(|branch| branch.disabled)
+            .map(|branch| branch.name.as_str())
+    }
+
+    fn each(
+        &mut self,
+        mut f: impl FnMut(&mut dyn Sink<T>) -> Result<(), SinkError>,
+    ) -> Result<(), SinkError> {
+        for branch in self.branches.iter_mut().filter(|branch| !branch.disabled) {
+            if let Err(e) = f(branch.sink.as_mut()) {
+                match branch.policy {
+                    ErrorPolicy::Fail => return Err(e),
+                    ErrorPolicy::Log => error!("Sink {} failed: {}", branch.name, e),
+                    ErrorPolicy::Disable => {
+                        warn!("Sink {} failed and was disabled: {}", branch.name, e);
+                        branch.disabled = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone> Sink<T> for TeeSink<T> {
+    fn write(&mut self, record: T) -> Result<(), SinkError> {
+        self.each(|sink| sink.write(record.clone()))
+    }
+
+    fn flush(&mut self) -> Result<(), SinkError> {
+        self.each(|sink| sink.flush())
+    }
+}
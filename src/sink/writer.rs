@@ -0,0 +1,87 @@
+//! A dedicated writer task that owns a sink
+//!
+//! Producers send records over a bounded channel instead of locking the sink from async code, so
+//! a slow disk never blocks the runtime and a full channel applies backpressure to the scrapers.
+
+use std::sync::Arc;
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use super::{Sink, SinkError};
+
+/// The sending side of a writer task
+#[derive(Debug)]
+pub struct SinkHandle<T> {
+    sender: mpsc::Sender<T>,
+}
+
+impl<T> Clone for SinkHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> SinkHandle<T> {
+    /// Queue a record, waiting while the channel is full
+    pub async fn write(&self, record: T) -> Result<(), SinkError> {
+        self.sender
+            .send(record)
+            .await
+            .map_err(|_| SinkError::Io(std::io::Error::other("the writer task has stopped")))
+    }
+
+    /// Watch the number of records waiting for the writer, e.g. for a health check
+    pub fn depth(&self) -> QueueDepth
+    where
+        T: Send + 'static,
+    {
+        // A weak sender doesn't keep the writer running once the handles are dropped
+        let sender = self.sender.downgrade();
+        QueueDepth(Arc::new(move || {
+            let sender = sender.upgrade()?;
+            Some(sender.max_capacity() - sender.capacity())
+        }))
+    }
+}
+
+/// The number of records queued for a writer task, see [`SinkHandle::depth`]
+#[derive(Clone)]
+pub struct QueueDepth(Arc<dyn Fn() -> Option<usize> + Send + Sync>);
+
+impl QueueDepth {
+    /// `None` once the writer has stopped
+    pub fn get(&self) -> Option<usize> {
+        (self.0)()
+    }
+}
+
+impl std::fmt::Debug for QueueDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("QueueDepth").field(&self.get()).finish()
+    }
+}
+
+/// Move `sink` onto a blocking writer thread fed by a channel holding up to `capacity` records
+///
+/// The writer flushes the sink once every handle has been dropped. Await the returned
+/// [`JoinHandle`] to make sure everything has been written.
+pub fn spawn_writer<T, S>(
+    mut sink: S,
+    capacity: usize,
+) -> (SinkHandle<T>, JoinHandle<Result<(), SinkError>>)
+where
+    T: Send + 'static,
+    S: Sink<T> + Send + 'static,
+{
+    let (sender, mut receiver) = mpsc::channel(capacity.max(1));
+    let task = tokio::task::spawn_blocking(move || {
+        while let Some(record) = receiver.blocking_recv() {
+            sink.write(record)?;
+        }
+        sink.flush()
+    });
+
+    (SinkHandle { sender }, task)
+}
@@ -0,0 +1,81 @@
+//! Named, frozen query results, see [`Snapshots`]
+//!
+//! A snapshot keeps the post ids a query matched when it was taken. The `snapshot:name` query
+//! term matches exactly these posts, so an export, a download or a dataset split taken later
+//! still sees the same post set after the index was rebuilt with new posts or retagged ones.
+//! Snapshots are saved next to the index in `{index}.snapshots.json`, a rebuild of the index
+//! doesn't touch them.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+/// The result of a query at the time it was frozen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The query as normalized by the parser
+    pub query: String,
+    pub created_at: DateTime<Utc>,
+    pub post_ids: RoaringBitmap,
+}
+
+/// The snapshots of an index by name
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Snapshots(pub BTreeMap<String, Snapshot>);
+
+impl Snapshots {
+    /// Path of the snapshots of the index at `index`
+    pub fn path_for<P: AsRef<Path>>(index: P) -> PathBuf {
+        let mut path = index.as_ref().as_os_str().to_owned();
+        path.push(".snapshots.json");
+        PathBuf::from(path)
+    }
+
+    /// Load the snapshots of the index at `index`, none if it has no snapshots file
+    pub fn load<P: AsRef<Path>>(index: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let file = match File::open(Self::path_for(index)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Save the snapshots of the index at `index`, through a temporary file like
+    /// [`Index::save`](crate::index::Index::save)
+    pub fn save<P: AsRef<Path>>(&self, index: P) -> Result<(), Box<dyn std::error::Error>> {
+        let path = Self::path_for(index);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        drop(file);
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Snapshot> {
+        self.0.get(name)
+    }
+
+    /// Add a snapshot, returning the one it replaced
+    pub fn insert(&mut self, name: String, snapshot: Snapshot) -> Option<Snapshot> {
+        self.0.insert(name, snapshot)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Snapshot> {
+        self.0.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Snapshot)> {
+        self.0.iter()
+    }
+}
@@ -0,0 +1,416 @@
+//! Overview of a scraped dataset, computed from an index or streamed from the output files
+//!
+//! [`DatasetStats`] is the cheap summary served by `GET /stats`, [`DatasetReport`] the detailed
+//! breakdown over time, artists and tags, which can also be rendered as a standalone HTML page.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write,
+    io::BufRead,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    index::Index,
+    models::{envelope::parse_record, Post, Tag, TagType},
+};
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "serve", derive(utoipa::ToSchema))]
+pub struct DatasetStats {
+    pub posts: u64,
+    pub tags: u64,
+    pub min_post_id: Option<u64>,
+    pub max_post_id: Option<u64>,
+    /// Number of posts per rating, only known when streamed from the posts file
+    pub ratings: BTreeMap<String, u64>,
+    /// The most used tags with their post count, most used first
+    pub top_tags: Vec<(String, u64)>,
+    /// Number of posts per `YYYY-MM`
+    pub posts_per_month: BTreeMap<String, u64>,
+    /// The source domains with the most posts, most posts first
+    pub top_sources: Vec<(String, u64)>,
+}
+
+impl DatasetStats {
+    /// Collect the stats available in an index
+    pub fn from_index(index: &Index, top_tags: usize) -> Self {
+        let mut stats = DatasetStats {
+            tags: index.tag_str_to_id.len() as u64,
+            ..Default::default()
+        };
+        for post in index.post_id_to_post.values() {
+            stats.add_post(post.id as u64, &post.created_at);
+        }
+
+        let mut tag_counts: Vec<(String, u64)> = index
+            .tag_str_to_id
+            .iter()
+            .filter_map(|(name, id)| Some((name.clone(), *index.tag_id_freq.get(id)? as u64)))
+            .collect();
+        stats.top_tags = top_n(&mut tag_counts, top_tags);
+
+        let mut source_counts: Vec<(String, u64)> = index
+            .source_to_post_id
+            .iter()
+            .map(|(domain, post_ids)| (domain.clone(), post_ids.len()))
+            .collect();
+        stats.top_sources = top_n(&mut source_counts, top_tags);
+        stats
+    }
+
+    /// Stream the posts and tags files, without loading either into memory
+    ///
+    /// Lines which can't be parsed are skipped, like [`Index::generate`] does.
+    pub fn from_json_lines<P: BufRead, T: BufRead>(
+        posts: P,
+        tags: T,
+        top_tags: usize,
+    ) -> std::io::Result<Self> {
+        let mut stats = DatasetStats::default();
+
+        for line in tags.lines() {
+            if parse_record::<Tag>(&line?).is_ok() {
+                stats.tags += 1;
+            }
+        }
+
+        let mut tag_counts: HashMap<String, u64> = HashMap::new();
+        let mut source_counts: HashMap<String, u64> = HashMap::new();
+        for line in posts.lines() {
+            let Ok(post) = parse_record::<Post>(&line?) else {
+                continue;
+            };
+            stats.add_post(post.id, &post.created_at);
+            *stats
+                .ratings
+                .entry(post.rating.as_str().to_string())
+                .or_default() += 1;
+            for domain in post.source_domains() {
+                *source_counts.entry(domain).or_default() += 1;
+            }
+            for tag in post.tags {
+                *tag_counts.entry(tag.to_lowercase()).or_default() += 1;
+            }
+        }
+
+        stats.top_tags = top_n(&mut tag_counts.into_iter().collect(), top_tags);
+        stats.top_sources = top_n(&mut source_counts.into_iter().collect(), top_tags);
+        Ok(stats)
+    }
+
+    fn add_post(&mut self, id: u64, created_at: &DateTime<Utc>) {
+        self.posts += 1;
+        self.min_post_id = Some(self.min_post_id.map_or(id, |min| min.min(id)));
+        self.max_post_id = Some(self.max_post_id.map_or(id, |max| max.max(id)));
+        *self
+            .posts_per_month
+            .entry(created_at.format("%Y-%m").to_string())
+            .or_default() += 1;
+    }
+
+    /// Fraction of the ids between the lowest and highest post id which are present
+    ///
+    /// Deleted posts leave gaps, so this is expected to stay below 1 even for a complete scrape.
+    pub fn id_coverage(&self) -> Option<f64> {
+        let (min, max) = (self.min_post_id?, self.max_post_id?);
+        Some(self.posts as f64 / (max - min + 1) as f64)
+    }
+}
+
+fn top_n(counts: &mut Vec<(String, u64)>, n: usize) -> Vec<(String, u64)> {
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(n);
+    std::mem::take(counts)
+}
+
+/// Average score of the posts with a tag
+#[derive(Debug, Clone, Serialize)]
+pub struct TagScore {
+    pub tag: String,
+    /// Posts with the tag whose score is known
+    pub posts: u64,
+    pub average_score: f64,
+}
+
+/// Detailed breakdown of a dataset, see [`DatasetReport::to_html`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DatasetReport {
+    pub posts: u64,
+    /// Number of posts per rating, per `YYYY-MM`
+    pub ratings_per_month: BTreeMap<String, BTreeMap<String, u64>>,
+    /// The artists with the most posts, most posts first
+    pub top_artists: Vec<(String, u64)>,
+    /// Average score of the most used tags, most used first
+    ///
+    /// Empty for an index built without `--fields score`.
+    pub tag_scores: Vec<TagScore>,
+    /// Number of posts uploaded per `YYYY-MM-DD`
+    pub posts_per_day: BTreeMap<String, u64>,
+}
+
+impl DatasetReport {
+    /// Compute the report from an index
+    ///
+    /// Scores are only known if the index keeps them, see
+    /// [`PostFields`](crate::models::PostFields).
+    pub fn from_index(index: &Index, top: usize) -> Self {
+        let mut report = DatasetReport::default();
+        for post in index.post_id_to_post.values() {
+            report.add_post(&post.created_at);
+        }
+        for (rating, post_ids) in &index.rating_to_post_id {
+            for id in post_ids {
+                let Some(post) = index.post_id_to_post.get(&id) else {
+                    continue;
+                };
+                *report
+                    .ratings_per_month
+                    .entry(post.created_at.format("%Y-%m").to_string())
+                    .or_default()
+                    .entry(rating.clone())
+                    .or_default() += 1;
+            }
+        }
+
+        let mut artists: Vec<(String, u64)> = index
+            .tag_str_to_id
+            .iter()
+            .filter(|(_, id)| index.tag_id_to_type.get(id) == Some(&TagType::Artist))
+            .filter_map(|(name, id)| Some((name.clone(), *index.tag_id_freq.get(id)? as u64)))
+            .collect();
+        report.top_artists = top_n(&mut artists, top);
+
+        if index.post_fields.score {
+            let mut tags: Vec<(String, u64)> = index
+                .tag_str_to_id
+                .iter()
+                .filter_map(|(name, id)| Some((name.clone(), *index.tag_id_freq.get(id)? as u64)))
+                .collect();
+            report.tag_scores = top_n(&mut tags, top)
+                .into_iter()
+                .filter_map(|(tag, _)| {
+                    let post_ids = &index.tag_id_to_post_id[&index.tag_str_to_id[&tag]];
+                    let scores = post_ids
+                        .iter()
+                        .filter_map(|id| index.post_id_to_post.get(&id)?.details.as_ref()?.score);
+                    tag_score(tag, scores)
+                })
+                .collect();
+        }
+        report
+    }
+
+    /// Stream the posts and tags files, the tags file is only read for the artist names
+    ///
+    /// Lines which can't be parsed are skipped, like [`Index::generate`] does.
+    pub fn from_json_lines<P: BufRead, T: BufRead>(
+        posts: P,
+        tags: T,
+        top: usize,
+    ) -> std::io::Result<Self> {
+        let mut artists = HashSet::new();
+        for line in tags.lines() {
+            if let Ok(tag) = parse_record::<Tag>(&line?) {
+                if tag.tag_type == TagType::Artist {
+                    artists.insert(tag.name.to_lowercase());
+                }
+            }
+        }
+
+        let mut report = DatasetReport::default();
+        // Post count and score sum per tag
+        let mut tags: HashMap<String, (u64, i64)> = HashMap::new();
+        for line in posts.lines() {
+            let Ok(post) = parse_record::<Post>(&line?) else {
+                continue;
+            };
+            report.add_post(&post.created_at);
+            *report
+                .ratings_per_month
+                .entry(post.created_at.format("%Y-%m").to_string())
+                .or_default()
+                .entry(post.rating.as_str().to_string())
+                .or_default() += 1;
+            for tag in post.tags {
+                let (count, sum) = tags.entry(tag.to_lowercase()).or_default();
+                *count += 1;
+                *sum += post.score as i64;
+            }
+        }
+
+        let mut artist_counts: Vec<(String, u64)> = tags
+            .iter()
+            .filter(|(name, _)| artists.contains(*name))
+            .map(|(name, (count, _))| (name.clone(), *count))
+            .collect();
+        report.top_artists = top_n(&mut artist_counts, top);
+
+        let mut tag_counts: Vec<(String, u64)> = tags
+            .iter()
+            .map(|(name, (count, _))| (name.clone(), *count))
+            .collect();
+        report.tag_scores = top_n(&mut tag_counts, top)
+            .into_iter()
+            .map(|(tag, count)| TagScore {
+                average_score: tags[&tag].1 as f64 / count as f64,
+                tag,
+                posts: count,
+            })
+            .collect();
+        Ok(report)
+    }
+
+    fn add_post(&mut self, created_at: &DateTime<Utc>) {
+        self.posts += 1;
+        *self
+            .posts_per_day
+            .entry(created_at.format("%Y-%m-%d").to_string())
+            .or_default() += 1;
+    }
+
+    /// Render the report as a standalone HTML page, without scripts or external resources
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Dataset report</title>\n<style>\n\
+             body { font-family: sans-serif; margin: 2em; }\n\
+             table { border-collapse: collapse; margin-bottom: 2em; }\n\
+             th, td { padding: 2px 10px; text-align: right; border-bottom: 1px solid #ddd; }\n\
+             th:first-child, td:first-child { text-align: left; }\n\
+             svg rect { fill: #4a7ab5; }\n\
+             </style>\n</head>\n<body>\n",
+        );
+        let _ = writeln!(html, "<h1>Dataset report</h1>\n<p>{} posts</p>", self.posts);
+
+        html.push_str("<h2>Uploads per day</h2>\n");
+        html.push_str(&self.uploads_svg());
+
+        let ratings: Vec<&String> = self
+            .ratings_per_month
+            .values()
+            .flat_map(|ratings| ratings.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        html.push_str("<h2>Ratings per month</h2>\n<table>\n<tr><th>month</th>");
+        for rating in &ratings {
+            let _ = write!(html, "<th>{}</th>", escape(rating));
+        }
+        html.push_str("</tr>\n");
+        for (month, counts) in &self.ratings_per_month {
+            let _ = write!(html, "<tr><td>{}</td>", month);
+            for rating in &ratings {
+                let _ = write!(html, "<td>{}</td>", counts.get(*rating).unwrap_or(&0));
+            }
+            html.push_str("</tr>\n");
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Top artists</h2>\n<table>\n<tr><th>artist</th><th>posts</th></tr>\n");
+        for (artist, count) in &self.top_artists {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(artist),
+                count
+            );
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Average score by tag</h2>\n");
+        if self.tag_scores.is_empty() {
+            html.push_str("<p>No scores known.</p>\n");
+        } else {
+            html.push_str("<table>\n<tr><th>tag</th><th>posts</th><th>average score</th></tr>\n");
+            for score in &self.tag_scores {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{:.2}</td></tr>",
+                    escape(&score.tag),
+                    score.posts,
+                    score.average_score
+                );
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// A bar per day from the first to the last upload, days without uploads left empty
+    fn uploads_svg(&self) -> String {
+        let (Some(first), Some(last)) = (
+            self.posts_per_day.keys().next(),
+            self.posts_per_day.keys().next_back(),
+        ) else {
+            return String::from("<p>No posts.</p>\n");
+        };
+        let parse = |day: &str| chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").ok();
+        let (Some(first_day), Some(last_day)) = (parse(first), parse(last)) else {
+            return String::new();
+        };
+        let days = (last_day - first_day).num_days() as u64 + 1;
+        let max = self
+            .posts_per_day
+            .values()
+            .copied()
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        const HEIGHT: u64 = 200;
+        const BAR: u64 = 3;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg width=\"{}\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">",
+            days * BAR,
+            HEIGHT
+        );
+        for (day, count) in &self.posts_per_day {
+            let Some(date) = parse(day) else {
+                continue;
+            };
+            let x = (date - first_day).num_days() as u64 * BAR;
+            let height = (count * HEIGHT).div_ceil(max);
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"><title>{}: {}</title></rect>",
+                x,
+                HEIGHT - height,
+                BAR - 1,
+                height,
+                day,
+                count
+            );
+        }
+        svg.push_str("</svg>\n");
+        let _ = writeln!(
+            svg,
+            "<p>{} to {}, at most {} posts a day</p>",
+            first, last, max
+        );
+        svg
+    }
+}
+
+fn tag_score(tag: String, scores: impl Iterator<Item = i32>) -> Option<TagScore> {
+    let (posts, sum) = scores.fold((0u64, 0i64), |(posts, sum), score| {
+        (posts + 1, sum + score as i64)
+    });
+    (posts > 0).then(|| TagScore {
+        tag,
+        posts,
+        average_score: sum as f64 / posts as f64,
+    })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
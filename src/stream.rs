@@ -0,0 +1,125 @@
+//! Query results in bounded batches, see [`QueryStream`]
+//!
+//! Exporting millions of results at once means holding every record in memory before the first
+//! one is written. A [`QueryStream`] instead hands out the results a batch at a time and only
+//! computes the next batch when the consumer asks for it, so a slow consumer (the downloader, a
+//! network client) holds back the stream instead of a buffer growing in front of it. Batches
+//! share the index behind an [`Arc`] and borrow their posts from it, nothing is cloned.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use roaring::RoaringBitmap;
+
+use crate::{index::Index, models::PostSimplified, query::Query};
+
+/// Results per batch unless set with [`QueryStream::with_batch_size`]
+pub const DEFAULT_BATCH_SIZE: usize = 1_000;
+
+/// The results of a query in result order, as a [`Stream`] of [`QueryBatch`]es
+///
+/// The position in the results is kept like a search cursor, the id of the last post handed out,
+/// so the stream doesn't borrow the index and can be sent to another task.
+#[derive(Debug, Clone)]
+pub struct QueryStream {
+    index: Arc<Index>,
+    query: Query,
+    post_ids: RoaringBitmap,
+    after: Option<u32>,
+    batch_size: usize,
+    done: bool,
+}
+
+/// Consecutive results of a [`QueryStream`]
+#[derive(Debug, Clone)]
+pub struct QueryBatch {
+    index: Arc<Index>,
+    ids: Vec<u32>,
+}
+
+impl QueryStream {
+    /// Stream the posts matching `query`
+    pub fn search(index: Arc<Index>, query: Query) -> Self {
+        let post_ids = index.search(&query);
+        Self::new(index, query, post_ids)
+    }
+
+    /// Stream `post_ids`, a result of `query` which was already searched and possibly cut down,
+    /// in the order of `query`
+    pub fn new(index: Arc<Index>, query: Query, post_ids: RoaringBitmap) -> Self {
+        Self {
+            index,
+            query,
+            post_ids,
+            after: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            done: false,
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Number of results, including those already handed out
+    pub fn len(&self) -> u64 {
+        self.post_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.post_ids.is_empty()
+    }
+
+    /// The next batch, for consumers outside of an async runtime
+    pub fn next_batch(&mut self) -> Option<QueryBatch> {
+        if self.done {
+            return None;
+        }
+        let ids: Vec<u32> = self
+            .index
+            .ordered(&self.query, &self.post_ids, self.after)
+            .take(self.batch_size)
+            .collect();
+        self.done = ids.len() < self.batch_size;
+        self.after = ids.last().copied().or(self.after);
+        (!ids.is_empty()).then(|| QueryBatch {
+            index: self.index.clone(),
+            ids,
+        })
+    }
+}
+
+impl Stream for QueryStream {
+    type Item = QueryBatch;
+
+    fn poll_next(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // A batch is a few thousand lookups at most, computing it doesn't need to yield
+        Poll::Ready(self.get_mut().next_batch())
+    }
+}
+
+impl QueryBatch {
+    pub fn ids(&self) -> &[u32] {
+        &self.ids
+    }
+
+    /// The posts of the batch, in result order
+    pub fn posts(&self) -> impl Iterator<Item = &PostSimplified> {
+        self.ids
+            .iter()
+            .filter_map(|id| self.index.post_id_to_post.get(id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
@@ -0,0 +1,202 @@
+//! Spelling corrections of tag names, see [`SymSpell`]
+//!
+//! SymSpell finds every word within a small edit distance of a term without comparing the term to
+//! every word: the dictionary keeps the words under each of their deletes (the word with up to
+//! `max_distance` characters removed), so a lookup only generates the deletes of the term and
+//! checks the words stored under them. Only the first [`PREFIX_LENGTH`] characters of a word are
+//! used for the deletes, which bounds the size of the dictionary for long tag names, and every
+//! candidate is verified with its real distance to the term.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use serde::Serialize;
+
+/// Characters of a word its deletes are generated from
+pub const PREFIX_LENGTH: usize = 7;
+
+/// A dictionary of words for finding the ones close to a misspelled term
+#[derive(Debug, Clone, Default)]
+pub struct SymSpell {
+    max_distance: usize,
+    words: Vec<String>,
+    /// Hash of a delete and the word it belongs to, ordered by hash
+    deletes: Vec<(u64, u32)>,
+}
+
+/// A word close to the looked up term
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Correction {
+    pub name: String,
+    /// Edit distance to the term, counting a swap of two adjacent characters as one edit
+    pub distance: u32,
+    /// Posts with the tag, more used tags are ranked first among those at the same distance
+    pub count: u32,
+}
+
+impl SymSpell {
+    /// A dictionary of `words` for terms up to `max_distance` edits away from them
+    pub fn new<I: IntoIterator<Item = String>>(words: I, max_distance: usize) -> Self {
+        let words: Vec<String> = words.into_iter().collect();
+        let mut deletes = Vec::new();
+        for (position, word) in words.iter().enumerate() {
+            for delete in deletes_of(word, max_distance) {
+                deletes.push((hash(&delete), position as u32));
+            }
+        }
+        deletes.sort_unstable();
+        deletes.dedup();
+        Self {
+            max_distance,
+            words,
+            deletes,
+        }
+    }
+
+    pub fn max_distance(&self) -> usize {
+        self.max_distance
+    }
+
+    /// The words within `max_distance` edits of `term` with their distance, closest first
+    pub fn lookup(&self, term: &str) -> Vec<(&str, u32)> {
+        let length = term.chars().count();
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for delete in deletes_of(term, self.max_distance) {
+            let key = hash(&delete);
+            let start = self.deletes.partition_point(|(hash, _)| *hash < key);
+            for (_, position) in self.deletes[start..].iter().take_while(|(h, _)| *h == key) {
+                if !seen.insert(*position) {
+                    continue;
+                }
+                let word = &self.words[*position as usize];
+                if word.chars().count().abs_diff(length) > self.max_distance {
+                    continue;
+                }
+                let distance = osa_distance(term, word);
+                if distance <= self.max_distance {
+                    found.push((word.as_str(), distance as u32));
+                }
+            }
+        }
+        found.sort_unstable_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        found
+    }
+}
+
+/// The prefix of `word` with every combination of up to `max_distance` characters removed,
+/// including the prefix itself
+fn deletes_of(word: &str, max_distance: usize) -> HashSet<String> {
+    let prefix: String = word.chars().take(PREFIX_LENGTH).collect();
+    let mut deletes = HashSet::from([prefix.clone()]);
+    let mut current = vec![prefix];
+    for _ in 0..max_distance {
+        let mut next = Vec::new();
+        for candidate in &current {
+            let chars: Vec<char> = candidate.chars().collect();
+            for skip in 0..chars.len() {
+                let delete: String = chars
+                    .iter()
+                    .enumerate()
+                    .filter(|(position, _)| *position != skip)
+                    .map(|(_, c)| *c)
+                    .collect();
+                if deletes.insert(delete.clone()) {
+                    next.push(delete);
+                }
+            }
+        }
+        current = next;
+    }
+    deletes
+}
+
+fn hash(delete: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    delete.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and swaps of adjacent
+/// characters, each counting as one edit
+pub fn osa_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // Three rows of the distance matrix: two rows back, the previous row and the current one
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osa_distances() {
+        assert_eq!(osa_distance("", ""), 0);
+        assert_eq!(osa_distance("", "abc"), 3);
+        assert_eq!(osa_distance("abc", ""), 3);
+        assert_eq!(osa_distance("cat", "cat"), 0);
+        assert_eq!(osa_distance("kitten", "sitting"), 3);
+        // A swap of adjacent characters is one edit
+        assert_eq!(osa_distance("ab", "ba"), 1);
+        assert_eq!(osa_distance("cta", "cat"), 1);
+        // A swapped pair can't be edited again, unlike with the Damerau-Levenshtein distance
+        assert_eq!(osa_distance("ca", "abc"), 3);
+        // Characters, not bytes
+        assert_eq!(osa_distance("猫耳", "猫目"), 1);
+    }
+
+    fn words(list: &[&str]) -> SymSpell {
+        SymSpell::new(list.iter().map(|word| word.to_string()), 2)
+    }
+
+    #[test]
+    fn lookup_finds_words_within_the_distance() {
+        let spell = words(&["cat", "cats", "dog", "catgirl", "bat"]);
+        assert_eq!(spell.lookup("cta"), [("cat", 1), ("bat", 2), ("cats", 2)]);
+        assert_eq!(spell.lookup("dgo"), [("dog", 1)]);
+        assert!(spell.lookup("elephant").is_empty());
+    }
+
+    #[test]
+    fn lookup_includes_exact_matches() {
+        let spell = words(&["cat"]);
+        assert_eq!(spell.lookup("cat"), [("cat", 0)]);
+    }
+
+    #[test]
+    fn lookup_of_long_words_beyond_the_prefix() {
+        let spell = words(&["long_hair_ribbon", "long_hair_ribbons", "long_hairs"]);
+        assert_eq!(
+            spell.lookup("long_hair_ribbno"),
+            [("long_hair_ribbon", 1), ("long_hair_ribbons", 2)]
+        );
+        // The prefix of the term has the edit
+        assert_eq!(spell.lookup("lnog_hairs"), [("long_hairs", 1)]);
+    }
+
+    #[test]
+    fn lookup_with_a_smaller_distance() {
+        let spell = SymSpell::new(["cat", "cats"].map(String::from), 1);
+        assert_eq!(spell.max_distance(), 1);
+        assert_eq!(spell.lookup("cta"), [("cat", 1)]);
+    }
+}